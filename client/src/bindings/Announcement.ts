@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Severity } from "./Severity";
+
+/**
+ * Value of `announcement` messages, sent by the operators to every room
+ */
+export type Announcement = { message: string, severity: Severity, 
+/**
+ * Unix timestamp after which the announcement no longer applies, `None` if it is only
+ * sent to the clients connected when it is made
+ */
+expires_at: number | null, };
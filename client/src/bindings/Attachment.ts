@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * File attached to a room
+ */
+export type Attachment = { id: number, room_id: string, filename: string, content_type: string, size: number, 
+/**
+ * SHA-256 of the content, shared by identical files
+ */
+hash: string, created_at: number, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Clearing of the content of a room once nobody edited it for a while,
+ * for public pads that shouldn't keep the text of their last visitor
+ */
+export type AutoClear = { 
+/**
+ * Minutes without edits after which the content is cleared, `None` keeps it
+ */
+minutes: number | null, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BurnAfter } from "./BurnAfter";
+
+/**
+ * Wiping of the content of a room once it was read, for sharing secrets and tokens that
+ * shouldn't stay on the server
+ */
+export type Burn = { 
+/**
+ * `None` keeps the content
+ */
+after: BurnAfter | null, };
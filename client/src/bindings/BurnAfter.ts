@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Event after which the content of a room is wiped
+ */
+export type BurnAfter = "read" | "leave";
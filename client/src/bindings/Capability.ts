@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Optional protocol feature, used only when both ends support it
+ */
+export type Capability = "diff-sync" | "presence" | "compression" | "msgpack" | "documents" | "resume" | "ot" | "ranges";
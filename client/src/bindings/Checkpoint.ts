@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Content of a room saved under a label by a user, kept until removed unlike the versions
+ * of the history
+ */
+export type Checkpoint = { id: number, label: string, 
+/**
+ * Username of its author, `None` if saved anonymously through the API
+ */
+created_by: string | null, created_at: number, 
+/**
+ * Bytes of the content
+ */
+size: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Body of `POST /api/rooms/:room_id/checkpoints`
+ */
+export type CheckpointRequest = { label: string, };
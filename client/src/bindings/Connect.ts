@@ -0,0 +1,27 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EncryptionParams } from "./EncryptionParams";
+
+/**
+ * First message of a client, joining a room
+ */
+export type Connect = { username: string, 
+/**
+ * Id of the room
+ */
+channel: string, 
+/**
+ * Create the room end-to-end encrypted, or make sure it is
+ */
+encryption?: EncryptionParams, 
+/**
+ * Missing for clients predating the handshake, which get no `hello`
+ */
+protocol_version?: number, capabilities: Array<string> | undefined, 
+/**
+ * Token of a previous connection to the room, with the `resume` capability
+ */
+resume?: string, 
+/**
+ * Password of a private room, for those who are not its members
+ */
+password?: string, };
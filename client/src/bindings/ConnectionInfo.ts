@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Connection, as listed by `GET /api/admin/connections`
+ */
+export type ConnectionInfo = { id: number, username: string, room: string, ip: string, connected_at: number, };
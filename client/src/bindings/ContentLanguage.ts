@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ContentKind } from "./ContentKind";
+
+/**
+ * Type and natural language of the content of a room
+ */
+export type ContentLanguage = { kind: ContentKind, 
+/**
+ * ISO 639-1 code of the natural language, if recognized
+ */
+language: string | null, 
+/**
+ * Whether the kind was set by hand rather than detected
+ */
+kind_overridden: boolean, 
+/**
+ * Whether the language was set by hand rather than detected
+ */
+language_overridden: boolean, };
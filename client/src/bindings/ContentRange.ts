@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A chunk of a document, answering a `get-range` message
+ */
+export type ContentRange = { 
+/**
+ * Byte offset of the chunk in the document
+ */
+offset: number, 
+/**
+ * Byte offset right after the chunk, where the next one starts
+ */
+end: number, 
+/**
+ * Bytes of the whole document
+ */
+total: number, content: string, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Document of a room, as listed by `GET /api/rooms/:room_id/documents`
+ */
+export type DocumentInfo = { id: string, 
+/**
+ * Size of the content in bytes
+ */
+size: number, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What a destructive operation would affect, returned instead of performing it in dry-run mode
+ */
+export type DryRunReport = { rooms: Array<string>, 
+/**
+ * Attachment blobs, by hash
+ */
+blobs: Array<string>, users_disconnected: number, bytes_freed: number, };
\ No newline at end of file
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Key derivation parameters of an end-to-end encrypted room, chosen by its creator.
+ * Clients derive the key from a passphrase shared out of band, the server only stores the salt.
+ */
+export type EncryptionParams = { 
+/**
+ * Base64 encoded salt
+ */
+salt: string, };
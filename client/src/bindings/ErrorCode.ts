@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What went wrong, sent along the `error` messages so that clients can tell errors apart
+ * without parsing their text, which is meant for people and may change
+ */
+export type ErrorCode = "invalid-json" | "protocol-mismatch" | "invalid-room-id" | "invalid-username" | "username-taken" | "unauthorized" | "forbidden" | "invalid-encryption" | "encryption-mismatch" | "room-full" | "too-many-connections" | "too-many-rooms" | "reserved-room-id" | "room-trashed" | "room-archived" | "room-closing" | "room-frozen" | "room-locked" | "line-claimed" | "rate-limited" | "muted" | "payload-too-large" | "edit-conflict" | "operation-rejected" | "invalid-document" | "invalid-language" | "invalid-checkpoint" | "invalid-range" | "internal";
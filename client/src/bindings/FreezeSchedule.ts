@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FreezeWindow } from "./FreezeWindow";
+
+/**
+ * Read-only windows of a room, repeated every week
+ */
+export type FreezeSchedule = { windows: Array<FreezeWindow>, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FreezeSchedule } from "./FreezeSchedule";
+
+/**
+ * Freeze schedule of a room and whether it is currently frozen
+ */
+export type FreezeStatus = { schedule: FreezeSchedule, 
+/**
+ * Unix timestamp at which the room becomes writable again, if frozen
+ */
+frozen_until: number | null, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Weekday } from "./Weekday";
+
+/**
+ * Weekly read-only window of a room, `start` and `end` are `HH:MM` times in UTC
+ */
+export type FreezeWindow = { day: Weekday, start: string, end: string, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Capability } from "./Capability";
+
+/**
+ * Reply of the server to a versioned handshake, before the room content
+ */
+export type Hello = { 
+/**
+ * Version used for the rest of the connection
+ */
+protocol_version: number, min_protocol_version: number, max_protocol_version: number, 
+/**
+ * Capabilities requested by the client that the server supports
+ */
+capabilities: Array<Capability>, };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Saved version of the content of a room
+ */
+export type HistoryVersion = { 
+/**
+ * Compared with another version with `GET /api/rooms/:room_id/diff`
+ */
+id: number, 
+/**
+ * Unix timestamp of when the version was first saved, it holds the last content saved
+ * in the 5 minutes after
+ */
+created_at: number, 
+/**
+ * Bytes of the content
+ */
+size: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Banned IP address, as listed by `GET /api/admin/bans`
+ */
+export type IpBan = { ip: string, reason: string | null, banned_at: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Lines of a document, counted from 0 with `end` excluded
+ */
+export type LineRange = { start: number, end: number, };
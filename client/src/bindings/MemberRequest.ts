@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RoomRole } from "./RoomRole";
+
+/**
+ * Body of `PUT /api/rooms/:room_id/members/:username`
+ */
+export type MemberRequest = { role: RoomRole, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Value of `presence` messages, the users of the room sorted
+ */
+export type Presence = Array<string>;
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Body of `PATCH /api/rooms/:room_id`
+ */
+export type RenameRequest = { 
+/**
+ * New id of the room
+ */
+id: string, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Value of the `resume` message sent to the clients with the `resume` capability,
+ * before the room content
+ */
+export type ResumeInfo = { 
+/**
+ * Sent in the `resume` field of the next join message to pick up from this connection
+ */
+token: string, 
+/**
+ * The previous session was resumed, documents the client already has are not sent again
+ */
+resumed: boolean, };
@@ -1,6 +1,39 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RoomPin } from "./RoomPin";
 
 /**
  * Room
  */
-export type Room = { id: string, users: Array<string>, };
+export type Room = { id: string, users: Array<string>, 
+/**
+ * The content can't be saved to the database at the moment
+ */
+persistence_degraded: boolean, 
+/**
+ * End-to-end encrypted, the content is ciphertext
+ */
+encrypted: boolean, 
+/**
+ * Language used to highlight the content
+ */
+language: string | null, 
+/**
+ * Pinned at the top of the list, for everyone or the current user
+ */
+pin: RoomPin | null, 
+/**
+ * Unix timestamp of the creation, unknown for rooms older than this field
+ */
+created_at: number | null, 
+/**
+ * Unix timestamp of the last edit, or of the loading of a room not edited since, if known
+ */
+updated_at: number | null, 
+/**
+ * Size of the main content in bytes, ciphertext included for encrypted rooms
+ */
+content_length: number, 
+/**
+ * Out of memory until unarchived, only listed with `?include=archived`
+ */
+archived: boolean, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RoomRole } from "./RoomRole";
+
+/**
+ * Member of a room, as listed by `GET /api/rooms/:room_id/members`
+ */
+export type RoomMember = { username: string, role: RoomRole, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PinScope } from "./PinScope";
+
+/**
+ * Room pinned at the top of the rooms list
+ */
+export type RoomPin = { scope: PinScope, 
+/**
+ * Rooms with the lowest positions come first
+ */
+position: number, };
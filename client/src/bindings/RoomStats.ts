@@ -0,0 +1,22 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Activity of a loaded room
+ */
+export type RoomStats = { room_id: string, connections: number, 
+/**
+ * Size of the main content and of the documents, in bytes
+ */
+content_length: number, 
+/**
+ * Messages received since the room was loaded
+ */
+messages: number, 
+/**
+ * Messages received over the last minute
+ */
+messages_per_minute: number, 
+/**
+ * Writes of the documents of the room to the database since it was loaded
+ */
+db_writes: number, };
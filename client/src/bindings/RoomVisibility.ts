@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Visibility } from "./Visibility";
+
+/**
+ * Visibility of a room, as answered by `GET /api/rooms/:room_id/visibility`
+ */
+export type RoomVisibility = { visibility: Visibility, 
+/**
+ * Whether a password lets non-members join the room when it is private
+ */
+password: boolean, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Webhook registered on a room, as listed by `GET /api/rooms/:room_id/webhooks`
+ */
+export type RoomWebhook = { id: number, url: string, created_at: number, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A room whose content matches a search
+ */
+export type SearchResult = { room_id: string, 
+/**
+ * Excerpt of the content around the matches, HTML-escaped, the matches in `<mark>` tags
+ */
+snippet: string, };
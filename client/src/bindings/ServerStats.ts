@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RoomStats } from "./RoomStats";
+
+/**
+ * Totals of the loaded rooms, with the busiest ones
+ */
+export type ServerStats = { rooms: number, connections: number, content_length: number, messages_per_minute: number, 
+/**
+ * Documents written to the database since the server started
+ */
+db_writes: number, 
+/**
+ * Ranked by the `sort` query parameter
+ */
+top_rooms: Array<RoomStats>, };
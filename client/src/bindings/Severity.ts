@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How much an announcement matters, for clients to pick how they show it
+ */
+export type Severity = "info" | "warning" | "critical";
@@ -1,4 +1,19 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ErrorCode } from "./ErrorCode";
 import type { SocketMessageType } from "./SocketMessageType";
 
-export type SocketMessage = { type: SocketMessageType, value: string | undefined, username: string | undefined, };
+export type SocketMessage = { 
+/**
+ * Document of the room the message is about, the main one if unset.
+ * Must stay the first field, see [`documents::is_document_message`].
+ */
+doc_id: string | undefined, type: SocketMessageType, value: string | undefined, 
+/**
+ * What went wrong, in `error` messages
+ */
+code?: ErrorCode, 
+/**
+ * Revision of the document a content message brings it to, sent back with the edits made
+ * on it to merge them with the changes of the others
+ */
+revision: number | undefined, username: string | undefined, };
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type SocketMessageType = "join" | "leave" | "message" | "error" | "update-rooms-list";
+export type SocketMessageType = "join" | "leave" | "message" | "error" | "update-rooms-list" | "freeze" | "unfreeze" | "announcement" | "persistence-degraded" | "persistence-restored" | "encrypted" | "hello" | "document-removed" | "file-added" | "redirect" | "language-changed" | "room-closing" | "username-assigned" | "presence" | "room-renamed" | "resume" | "server-shutdown" | "merge-conflict" | "kicked" | "muted" | "unmuted" | "request-lock" | "grant-lock" | "lines-claimed" | "lines-released" | "checkpoint" | "persisted" | "content-size" | "range";
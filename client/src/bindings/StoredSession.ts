@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Connection to a room recorded in the database, as listed by `GET /api/admin/sessions`
+ */
+export type StoredSession = { connection_id: number, room_id: string, username: string, connected_at: number, };
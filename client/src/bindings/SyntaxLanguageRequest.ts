@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Body of `PUT /api/rooms/:room_id/syntax-language`
+ */
+export type SyntaxLanguageRequest = { 
+/**
+ * `None` to unset it
+ */
+language: string | null, };
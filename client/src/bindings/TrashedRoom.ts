@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A removed room waiting in the trash
+ */
+export type TrashedRoom = { id: string, 
+/**
+ * When the room was removed, in seconds since the epoch
+ */
+deleted_at: number, 
+/**
+ * When the room will be deleted for good, `None` if the trash was disabled since
+ */
+purge_at: number | null, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Visibility } from "./Visibility";
+
+/**
+ * Body of `PUT /api/rooms/:room_id/visibility`
+ */
+export type VisibilityRequest = { visibility: Visibility, 
+/**
+ * Password of a private room, none to only let its members in
+ */
+password?: string, };
@@ -5,7 +5,7 @@
  */
 
 // Styles
-import { mdiClose, mdiDotsVertical, mdiForumPlusOutline, mdiMagnify, mdiPencil, mdiRefresh, mdiThemeLightDark, mdiTrashCan } from '@mdi/js'
+import { mdiClose, mdiDotsVertical, mdiForumPlusOutline, mdiMagnify, mdiPencil, mdiPin, mdiRefresh, mdiThemeLightDark, mdiTrashCan } from '@mdi/js'
 import { aliases, mdi } from 'vuetify/iconsets/mdi-svg'
 import 'vuetify/styles'
 // Composables
@@ -61,6 +61,7 @@ export default createVuetify({
       'forum-plus-outline': mdiForumPlusOutline,
       'theme-light-dark': mdiThemeLightDark,
       'refresh': mdiRefresh,
+      'pin': mdiPin,
     },
   },
   defaults: {
@@ -4,13 +4,14 @@
  * Automatic routes for `./src/pages/*.vue`
  */
 
+import { basePath } from '@/utils/basePath'
 import { setupLayouts } from 'virtual:generated-layouts'
 // Composables
 import { createRouter, createWebHistory } from 'vue-router/auto'
 import { routes } from 'vue-router/auto-routes'
 
 const router = createRouter({
-  history: createWebHistory(import.meta.env.BASE_URL),
+  history: createWebHistory(`${basePath}/`),
   routes: setupLayouts(routes),
 })
 
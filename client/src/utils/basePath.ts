@@ -0,0 +1,2 @@
+// Prefix the server is reached under (`BASE_PATH`), set as the `<base>` of the page, without trailing slash
+export const basePath = new URL(document.baseURI).pathname.replace(/\/$/, '')
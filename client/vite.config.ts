@@ -20,6 +20,8 @@ try {
 
 // https://vitejs.dev/config/
 export default defineConfig({
+  // Relative to the `<base>` set by the server, so the app can be served under `BASE_PATH`
+  base: './',
   plugins: [
     VueRouter({
       dts: 'src/typed-router.d.ts',
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_oidc_subject ON users (oidc_subject);
@@ -0,0 +1,3 @@
+ALTER TABLE rooms ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+ALTER TABLE rooms ADD COLUMN freeze_schedule TEXT;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS blobs (
+    hash TEXT PRIMARY KEY NOT NULL,
+    size INTEGER NOT NULL,
+    last_used_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS attachments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    room_id TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    blob_hash TEXT NOT NULL REFERENCES blobs (hash),
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attachments_room_id ON attachments (room_id);
+
+CREATE INDEX IF NOT EXISTS attachments_blob_hash ON attachments (blob_hash);
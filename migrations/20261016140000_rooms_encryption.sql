@@ -0,0 +1,3 @@
+ALTER TABLE rooms ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE rooms ADD COLUMN encryption_salt TEXT;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS documents (
+    room_id TEXT NOT NULL,
+    doc_id TEXT NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (room_id, doc_id)
+);
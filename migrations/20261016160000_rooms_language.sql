@@ -0,0 +1,4 @@
+-- Manual content type and natural language of a room, detected when NULL
+ALTER TABLE rooms ADD COLUMN content_kind TEXT;
+
+ALTER TABLE rooms ADD COLUMN language TEXT;
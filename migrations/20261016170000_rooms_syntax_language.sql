@@ -0,0 +1,2 @@
+-- Language used to highlight the content of a room, like rust or python
+ALTER TABLE rooms ADD COLUMN syntax_language TEXT;
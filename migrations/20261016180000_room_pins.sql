@@ -0,0 +1,8 @@
+ALTER TABLE rooms ADD COLUMN pin_position INTEGER;
+
+CREATE TABLE IF NOT EXISTS user_pins (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    room_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (user_id, room_id)
+);
@@ -0,0 +1,2 @@
+-- Minutes without edits after which the content of a room is cleared, NULL keeps it
+ALTER TABLE rooms ADD COLUMN auto_clear_minutes INTEGER;
@@ -0,0 +1,2 @@
+-- Unix timestamp of the last write of the content of a room
+ALTER TABLE rooms ADD COLUMN updated_at INTEGER;
@@ -0,0 +1,10 @@
+-- Unix timestamp of the creation of a room, unknown for rooms older than this column
+ALTER TABLE rooms ADD COLUMN created_at INTEGER;
+
+-- Rooms are inserted from many places, date them all here
+CREATE TRIGGER IF NOT EXISTS rooms_created_at AFTER INSERT ON rooms
+WHEN NEW.created_at IS NULL
+BEGIN
+    UPDATE rooms SET created_at = CAST(strftime('%s', 'now') AS INTEGER)
+    WHERE room_id = NEW.room_id;
+END;
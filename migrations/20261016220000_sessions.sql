@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    connection_id INTEGER PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    connected_at INTEGER NOT NULL
+);
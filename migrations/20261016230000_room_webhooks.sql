@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS room_webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS room_webhooks_room_id ON room_webhooks (room_id);
@@ -0,0 +1,6 @@
+ALTER TABLE rooms ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public'
+    CHECK (visibility IN ('public', 'unlisted', 'private'));
+ALTER TABLE rooms ADD COLUMN password_hash TEXT;
+
+-- Rooms with members were private until now
+UPDATE rooms SET visibility = 'private' WHERE room_id IN (SELECT room_id FROM room_members);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS ip_bans (
+    ip TEXT PRIMARY KEY NOT NULL,
+    reason TEXT,
+    banned_at INTEGER NOT NULL
+);
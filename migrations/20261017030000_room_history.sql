@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS room_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS room_history_room_id ON room_history (room_id, id);
+CREATE INDEX IF NOT EXISTS room_history_created_at ON room_history (created_at);
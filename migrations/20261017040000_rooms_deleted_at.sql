@@ -0,0 +1,2 @@
+-- Set when a room is moved to the trash, it is deleted for good TRASH_RETENTION_DAYS later
+ALTER TABLE rooms ADD COLUMN deleted_at INTEGER;
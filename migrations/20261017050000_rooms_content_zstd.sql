@@ -0,0 +1,3 @@
+-- Large contents are stored zstd-compressed in content_zstd, content is then empty
+ALTER TABLE rooms ADD COLUMN content_zstd BLOB;
+ALTER TABLE rooms ADD COLUMN compressed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- 'read' or 'leave', the content is wiped after that event
+ALTER TABLE rooms ADD COLUMN burn_after TEXT;
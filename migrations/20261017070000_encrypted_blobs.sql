@@ -0,0 +1,9 @@
+-- Opaque payloads encrypted by the clients, independent of rooms
+CREATE TABLE IF NOT EXISTS encrypted_blobs (
+    id TEXT PRIMARY KEY NOT NULL,
+    content BLOB NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS encrypted_blobs_expires_at ON encrypted_blobs (expires_at);
@@ -0,0 +1,2 @@
+-- Archived rooms stay out of memory and of the rooms list until unarchived
+ALTER TABLE rooms ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
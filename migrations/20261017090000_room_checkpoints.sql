@@ -0,0 +1,11 @@
+-- Contents of rooms saved under a label, kept until removed
+CREATE TABLE IF NOT EXISTS room_checkpoints (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL,
+    label TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_by TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS room_checkpoints_room_id ON room_checkpoints (room_id, id);
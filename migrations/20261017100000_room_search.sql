@@ -0,0 +1,29 @@
+-- Full-text index of the contents of rooms. Plain contents are indexed as they are written,
+-- compressed ones by the server, which decodes them. Encrypted contents are never indexed.
+CREATE VIRTUAL TABLE IF NOT EXISTS room_search USING fts5(
+    room_id UNINDEXED,
+    content,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS room_search_insert AFTER INSERT ON rooms
+WHEN NOT NEW.compressed AND NOT NEW.encrypted
+BEGIN
+    INSERT INTO room_search (room_id, content) VALUES (NEW.room_id, NEW.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS room_search_update
+AFTER UPDATE OF room_id, content, compressed, encrypted ON rooms
+BEGIN
+    DELETE FROM room_search WHERE room_id = OLD.room_id;
+    INSERT INTO room_search (room_id, content)
+    SELECT NEW.room_id, NEW.content WHERE NOT NEW.compressed AND NOT NEW.encrypted;
+END;
+
+CREATE TRIGGER IF NOT EXISTS room_search_delete AFTER DELETE ON rooms
+BEGIN
+    DELETE FROM room_search WHERE room_id = OLD.room_id;
+END;
+
+INSERT INTO room_search (room_id, content)
+SELECT room_id, content FROM rooms WHERE NOT compressed AND NOT encrypted;
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN external_name TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_external_name ON users (external_name);
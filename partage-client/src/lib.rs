@@ -0,0 +1,646 @@
+//! Async client for [partage](https://github.com/kernoeb/partage/) rooms.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), partage_client::Error> {
+//! use partage_client::{Client, Event, JoinOptions};
+//!
+//! let mut client = Client::connect("ws://localhost:3000/ws", JoinOptions::new("bot", "notes")).await?;
+//! client.edit("Hello from Rust").await?;
+//! while let Some(event) = client.next_event().await? {
+//!     if let Event::Content { doc_id, value, .. } = event {
+//!         println!("{doc_id}: {} bytes", value.len());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(clippy::multiple_crate_versions, clippy::missing_errors_doc)]
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+/// Version of the WebSocket protocol spoken by this client
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// WebSocket subprotocol spoken by this client, where every message is tagged by its `type`
+pub const SUBPROTOCOL: &str = "partage.v2";
+
+/// Document every room has
+pub const MAIN_DOCUMENT: &str = "main";
+
+/// Capabilities requested from the server, see the server `protocol` module
+const CAPABILITIES: &[&str] = &["presence", "compression", "documents"];
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Error of a connection to a room
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(tungstenite::Error),
+    /// The server sent a message this client doesn't understand
+    InvalidMessage(String),
+    /// The server refused the connection, with its reason
+    Rejected(String),
+    /// The connection was closed before the server answered the handshake
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSocket(e) => write!(f, "WebSocket error: {e}"),
+            Self::InvalidMessage(e) => write!(f, "Invalid message from the server: {e}"),
+            Self::Rejected(reason) => write!(f, "Connection rejected: {reason}"),
+            Self::Closed => write!(f, "Connection closed during the handshake"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::WebSocket(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Self::WebSocket(e)
+    }
+}
+
+/// Room to join and under which name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinOptions {
+    /// Ignored by the server for logged in users, who always use their account name
+    pub username: String,
+    pub channel: String,
+    /// Session token, as returned by the login endpoint
+    pub token: Option<String>,
+}
+
+impl JoinOptions {
+    #[must_use]
+    pub fn new(username: impl Into<String>, channel: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            channel: channel.into(),
+            token: None,
+        }
+    }
+
+    /// Authenticate the connection with a session token
+    #[must_use]
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+}
+
+/// Reply of the server to the handshake
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+    /// Capabilities requested by the client that the server supports
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    #[must_use]
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Message received from a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Content of a document, sent on join and whenever someone edits it
+    Content {
+        doc_id: String,
+        /// Author of the edit, `Server` for the contents sent on join
+        username: String,
+        value: String,
+    },
+    /// Content of an end-to-end encrypted room, as base64 ciphertext
+    Encrypted {
+        username: String,
+        value: String,
+    },
+    Join {
+        username: String,
+    },
+    Leave {
+        username: String,
+    },
+    /// Everyone in the room, sorted, sent after every join and leave and on request
+    Presence {
+        users: Vec<String>,
+    },
+    /// A file was attached to the room, downloadable from `/api/rooms/:room_id/files/:id`
+    FileAdded {
+        /// Empty for anonymous uploads
+        username: String,
+        file: FileInfo,
+    },
+    /// A document other than the main one was deleted
+    DocumentRemoved {
+        doc_id: String,
+    },
+    /// The room was merged into another one and removed, rejoin under the new room id
+    Redirect {
+        room: String,
+    },
+    /// The room got a new id, rejoin it under `room`
+    RoomRenamed {
+        room: String,
+    },
+    /// The name asked for was taken in the room, the client joined under `username` instead
+    UsernameAssigned {
+        username: String,
+    },
+    /// The room is being deleted, in `seconds` or as soon as its users left
+    RoomClosing {
+        seconds: u64,
+    },
+    /// The highlighting language of the room changed, `None` once unset
+    LanguageChanged {
+        language: Option<String>,
+    },
+    /// The room was frozen, edits are refused until it is unfrozen
+    Frozen {
+        notice: String,
+    },
+    Unfrozen,
+    Announcement {
+        message: String,
+    },
+    /// Changes can't be saved by the server for now
+    PersistenceDegraded {
+        message: String,
+    },
+    PersistenceRestored,
+    /// The list of rooms changed
+    RoomsListUpdated,
+    Error {
+        message: String,
+    },
+    /// The server closed the connection
+    Closed {
+        code: u16,
+        reason: String,
+    },
+}
+
+/// File attached to a room
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FileInfo {
+    pub id: i64,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+}
+
+/// Message as sent by the server
+#[derive(Debug, Deserialize)]
+struct RawMessage {
+    #[serde(default)]
+    doc_id: Option<String>,
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    compression: Option<String>,
+}
+
+impl RawMessage {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let mut message: Self =
+            serde_json::from_str(text).map_err(|e| Error::InvalidMessage(e.to_string()))?;
+        if let Some(compression) = message.compression.take() {
+            let value = message.value.take().unwrap_or_default();
+            message.value = Some(decompress(&compression, &value)?);
+        }
+        Ok(message)
+    }
+
+    /// `None` for messages of types this client doesn't know about
+    fn into_event(self) -> Option<Event> {
+        if self.message_type == "language-changed" {
+            return Some(Event::LanguageChanged {
+                language: self.value,
+            });
+        }
+        let value = self.value.unwrap_or_default();
+        Some(match self.message_type.as_str() {
+            "message" => Event::Content {
+                doc_id: self.doc_id.unwrap_or_else(|| MAIN_DOCUMENT.to_string()),
+                username: self.username,
+                value,
+            },
+            "encrypted" => Event::Encrypted {
+                username: self.username,
+                value,
+            },
+            "join" => Event::Join {
+                username: self.username,
+            },
+            "leave" => Event::Leave {
+                username: self.username,
+            },
+            "presence" => Event::Presence {
+                users: serde_json::from_str(&value).ok()?,
+            },
+            "document-removed" => Event::DocumentRemoved {
+                doc_id: self.doc_id?,
+            },
+            "file-added" => Event::FileAdded {
+                username: self.username,
+                file: serde_json::from_str(&value).ok()?,
+            },
+            "redirect" => Event::Redirect { room: value },
+            "room-renamed" => Event::RoomRenamed { room: value },
+            "username-assigned" => Event::UsernameAssigned { username: value },
+            "room-closing" => Event::RoomClosing {
+                seconds: value.parse().ok()?,
+            },
+            "freeze" => Event::Frozen { notice: value },
+            "unfreeze" => Event::Unfrozen,
+            "announcement" => Event::Announcement { message: value },
+            "persistence-degraded" => Event::PersistenceDegraded { message: value },
+            "persistence-restored" => Event::PersistenceRestored,
+            "update-rooms-list" => Event::RoomsListUpdated,
+            "error" => Event::Error { message: value },
+            _ => return None,
+        })
+    }
+}
+
+fn decompress(compression: &str, value: &str) -> Result<String, Error> {
+    if compression != "deflate" {
+        return Err(Error::InvalidMessage(format!(
+            "Unknown compression {compression}"
+        )));
+    }
+    let compressed = STANDARD
+        .decode(value)
+        .map_err(|e| Error::InvalidMessage(e.to_string()))?;
+    let mut decompressed = String::new();
+    ZlibDecoder::new(&compressed[..])
+        .read_to_string(&mut decompressed)
+        .map_err(|e| Error::InvalidMessage(e.to_string()))?;
+    Ok(decompressed)
+}
+
+/// Event carried by a frame, `None` for frames that carry none
+fn decode(message: Message) -> Result<Option<Event>, Error> {
+    match message {
+        Message::Text(text) => Ok(RawMessage::parse(&text)?.into_event()),
+        Message::Close(frame) => Ok(Some(Event::Closed {
+            code: frame.as_ref().map_or(1005, |frame| frame.code.into()),
+            reason: frame
+                .map(|frame| frame.reason.into_owned())
+                .unwrap_or_default(),
+        })),
+        // Heartbeat replies and WebSocket control frames
+        Message::Binary(_) | Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Ok(None),
+    }
+}
+
+/// Connection to a room
+#[derive(Debug)]
+pub struct Client {
+    sender: Sender,
+    updates: Updates,
+    hello: Hello,
+}
+
+impl Client {
+    /// Connect to the WebSocket endpoint of a server, `ws://host/ws`, and join a room.
+    /// The contents of the room come as the first [`Event::Content`] events.
+    pub async fn connect(url: &str, options: JoinOptions) -> Result<Self, Error> {
+        let mut request = url.into_client_request()?;
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            HeaderValue::from_static(SUBPROTOCOL),
+        );
+        if let Some(token) = &options.token {
+            let header = HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|e| Error::WebSocket(tungstenite::Error::HttpFormat(e.into())))?;
+            request.headers_mut().insert("authorization", header);
+        }
+        let (socket, _) = connect_async(request).await?;
+        let (mut sink, mut stream) = socket.split();
+
+        sink.send(Message::Text(
+            json!({
+                "username": options.username,
+                "channel": options.channel,
+                "protocol_version": PROTOCOL_VERSION,
+                "capabilities": CAPABILITIES,
+            })
+            .to_string(),
+        ))
+        .await?;
+
+        let hello = loop {
+            match stream.next().await.ok_or(Error::Closed)?? {
+                Message::Text(text) => {
+                    let message = RawMessage::parse(&text)?;
+                    match message.message_type.as_str() {
+                        "hello" => {
+                            break serde_json::from_str(&message.value.unwrap_or_default())
+                                .map_err(|e| Error::InvalidMessage(e.to_string()))?
+                        }
+                        "error" => return Err(Error::Rejected(message.value.unwrap_or_default())),
+                        other => {
+                            return Err(Error::InvalidMessage(format!(
+                                "Expected hello, got {other}"
+                            )))
+                        }
+                    }
+                }
+                Message::Close(_) => return Err(Error::Closed),
+                _ => {}
+            }
+        };
+
+        Ok(Self {
+            sender: Sender { sink },
+            updates: Updates { stream },
+            hello,
+        })
+    }
+
+    /// Version and capabilities agreed on with the server
+    #[must_use]
+    pub const fn hello(&self) -> &Hello {
+        &self.hello
+    }
+
+    /// Replace the content of the main document
+    pub async fn edit(&mut self, content: &str) -> Result<(), Error> {
+        self.sender.edit(content).await
+    }
+
+    /// Replace the content of a document, creating it if needed
+    pub async fn edit_document(&mut self, doc_id: &str, content: &str) -> Result<(), Error> {
+        self.sender.edit_document(doc_id, content).await
+    }
+
+    /// Change the highlighting language of the room for all its members, `None` to unset it
+    pub async fn set_language(&mut self, language: Option<&str>) -> Result<(), Error> {
+        self.sender.set_language(language).await
+    }
+
+    /// Ask for an [`Event::Presence`] listing the users of the room
+    pub async fn request_presence(&mut self) -> Result<(), Error> {
+        self.sender.request_presence().await
+    }
+
+    /// Next event of the room, `None` once the connection is closed
+    pub async fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        self.updates.next().await.transpose()
+    }
+
+    /// Separate the editing half from the updates, to use them from different tasks
+    #[must_use]
+    pub fn split(self) -> (Sender, Updates) {
+        (self.sender, self.updates)
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.sender.close().await
+    }
+}
+
+/// Editing half of a [`Client`]
+#[derive(Debug)]
+pub struct Sender {
+    sink: SplitSink<Socket, Message>,
+}
+
+impl Sender {
+    /// Replace the content of the main document
+    pub async fn edit(&mut self, content: &str) -> Result<(), Error> {
+        self.edit_document(MAIN_DOCUMENT, content).await
+    }
+
+    /// Replace the content of a document, creating it if needed
+    pub async fn edit_document(&mut self, doc_id: &str, content: &str) -> Result<(), Error> {
+        self.sink
+            .send(Message::Text(
+                serde_json::to_string(&DocumentEdit {
+                    doc_id,
+                    value: content,
+                })
+                .map_err(|e| Error::InvalidMessage(e.to_string()))?,
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Change the highlighting language of the room for all its members, `None` to unset it
+    pub async fn set_language(&mut self, language: Option<&str>) -> Result<(), Error> {
+        self.sink
+            .send(Message::Text(
+                json!({ "type": "set-language", "value": language }).to_string(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Ask for an [`Event::Presence`] listing the users of the room
+    pub async fn request_presence(&mut self) -> Result<(), Error> {
+        self.sink
+            .send(Message::Text(json!({ "type": "get-presence" }).to_string()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.sink.close().await?;
+        Ok(())
+    }
+}
+
+/// Edit as sent with the [`SUBPROTOCOL`]
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "edit")]
+struct DocumentEdit<'a> {
+    doc_id: &'a str,
+    value: &'a str,
+}
+
+/// Events half of a [`Client`]
+#[derive(Debug)]
+pub struct Updates {
+    stream: SplitStream<Socket>,
+}
+
+impl Stream for Updates {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let Some(message) = ready!(self.stream.poll_next_unpin(cx)) else {
+                return Poll::Ready(None);
+            };
+            match message.map_err(Error::from).and_then(decode) {
+                Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Event, FileInfo, MAIN_DOCUMENT};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn event(json: &str) -> Option<Event> {
+        decode(Message::Text(json.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            event(r#"{"type":"message","value":"hi","username":"alice"}"#),
+            Some(Event::Content {
+                doc_id: MAIN_DOCUMENT.to_string(),
+                username: "alice".to_string(),
+                value: "hi".to_string(),
+            })
+        );
+        assert_eq!(
+            event(r#"{"doc_id":"notes","type":"document-removed"}"#),
+            Some(Event::DocumentRemoved {
+                doc_id: "notes".to_string()
+            })
+        );
+        assert_eq!(
+            event(r#"{"type":"join","username":"bob"}"#),
+            Some(Event::Join {
+                username: "bob".to_string()
+            })
+        );
+        assert_eq!(event(r#"{"type":"unfreeze"}"#), Some(Event::Unfrozen));
+        assert_eq!(
+            event(
+                r#"{"type":"file-added","value":"{\"id\":3,\"room_id\":\"r\",\"filename\":\"a.txt\",\"content_type\":\"text/plain\",\"size\":2,\"hash\":\"ab\",\"created_at\":0}"}"#
+            ),
+            Some(Event::FileAdded {
+                username: String::new(),
+                file: FileInfo {
+                    id: 3,
+                    filename: "a.txt".to_string(),
+                    content_type: "text/plain".to_string(),
+                    size: 2,
+                },
+            })
+        );
+        assert_eq!(
+            event(r#"{"type":"language-changed","value":"rust"}"#),
+            Some(Event::LanguageChanged {
+                language: Some("rust".to_string())
+            })
+        );
+        assert_eq!(
+            event(r#"{"type":"language-changed"}"#),
+            Some(Event::LanguageChanged { language: None })
+        );
+        assert_eq!(
+            event(r#"{"type":"room-closing","value":"60"}"#),
+            Some(Event::RoomClosing { seconds: 60 })
+        );
+        assert_eq!(
+            event(r#"{"type":"room-renamed","value":"notes"}"#),
+            Some(Event::RoomRenamed {
+                room: "notes".to_string()
+            })
+        );
+        assert_eq!(
+            event(r#"{"type":"username-assigned","value":"alice-2"}"#),
+            Some(Event::UsernameAssigned {
+                username: "alice-2".to_string()
+            })
+        );
+        assert_eq!(
+            event(r#"{"type":"presence","value":"[\"alice\",\"bob\"]"}"#),
+            Some(Event::Presence {
+                users: vec!["alice".to_string(), "bob".to_string()]
+            })
+        );
+        assert_eq!(event(r#"{"type":"telepathy"}"#), None);
+        assert!(decode(Message::Text("not json".to_string())).is_err());
+
+        // Heartbeat replies
+        assert_eq!(decode(Message::Binary(vec![0xA])).unwrap(), None);
+        assert_eq!(
+            decode(Message::Close(Some(CloseFrame {
+                code: CloseCode::Restart,
+                reason: "later".into(),
+            })))
+            .unwrap(),
+            Some(Event::Closed {
+                code: 1012,
+                reason: "later".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_compressed() {
+        let content = "All work and no play makes Jack a dull boy. ".repeat(1000);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let compressed = STANDARD.encode(encoder.finish().unwrap());
+
+        let json = serde_json::json!({
+            "doc_id": "notes",
+            "type": "message",
+            "value": compressed,
+            "compression": "deflate",
+        });
+        assert_eq!(
+            event(&json.to_string()),
+            Some(Event::Content {
+                doc_id: "notes".to_string(),
+                username: String::new(),
+                value: content,
+            })
+        );
+    }
+}
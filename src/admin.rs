@@ -0,0 +1,541 @@
+use crate::announcements::{Announcement, Severity};
+use crate::attachments;
+use crate::backup;
+use crate::connections::ConnectionInfo;
+use crate::history;
+use crate::ip_filter;
+use crate::pins::{self, PinRequest};
+use crate::sessions::{self, StoredSession};
+use crate::stats;
+use crate::{
+    auth, collect_attachment_garbage, delete_room, ensure_room_loaded, get_assets, get_metrics,
+    store_room_owner, stored_size, unix_timestamp, AppState, CustomError, DryRunQuery,
+    DryRunReport, SocketMessage, SocketMessageType, DEFAULT_ROOM,
+};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Routes nested under `/api/admin`, see [`require_admin`]
+pub(crate) fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/assets", get(get_assets))
+        .route("/metrics", get(get_metrics))
+        .route("/stats", get(stats::get_stats))
+        .route("/connections", get(list_connections))
+        .route("/sessions", get(list_sessions))
+        .route("/rooms/:room_id", delete(force_remove_room))
+        .route("/rooms/:room_id/kick", post(kick))
+        .route("/rooms/:room_id/mute", post(mute))
+        .route("/rooms/:room_id/mute/:username", delete(unmute))
+        .route("/rooms/:room_id/history", delete(history::purge_history))
+        .route("/rooms/:room_id/owner", put(set_room_owner))
+        .route(
+            "/rooms/:room_id/pin",
+            put(pin_room_for_everyone).delete(unpin_room_for_everyone),
+        )
+        .route(
+            "/rooms/:room_id/trace",
+            get(download_trace).put(start_trace).delete(stop_trace),
+        )
+        .route("/announce", post(announce))
+        .route("/attachments/gc", post(attachments_gc))
+        .route("/backup", get(backup::download_backup))
+        .route("/bans", get(ip_filter::list_bans).post(ip_filter::ban_ip))
+        .route("/bans/:ip", delete(ip_filter::unban_ip))
+}
+
+/// Only let requests bearing `ADMIN_TOKEN` through, the admin API is disabled without it
+pub(crate) async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.config.admin_token.is_none() {
+        return CustomError::not_found("Admin API is disabled.").into_response();
+    }
+    if !is_admin(&state, request.headers()) {
+        return CustomError::new(StatusCode::UNAUTHORIZED, "Invalid admin token.").into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Whether the request bears `ADMIN_TOKEN`, never when it is unset
+pub(crate) fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(admin_token) = &state.config.admin_token else {
+        return false;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| tokens_match(token.trim(), admin_token))
+}
+
+/// Compare tokens in constant time, hashing them first so their length doesn't leak either
+fn tokens_match(given: &str, expected: &str) -> bool {
+    Sha256::digest(given.as_bytes())
+        .iter()
+        .zip(Sha256::digest(expected.as_bytes()).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// List the clients connected to rooms
+async fn list_connections(State(state): State<Arc<AppState>>) -> Json<Vec<ConnectionInfo>> {
+    Json(state.connections.list())
+}
+
+/// List the connections recorded in the database, which outlive the server process
+async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<StoredSession>>, CustomError> {
+    let db = &state.db;
+    sessions::list(db).await.map(Json).map_err(|e| {
+        eprintln!("Failed to list sessions: {e:#}");
+        auth::internal_error()
+    })
+}
+
+/// Remove a room whatever its number of users, disconnecting them
+async fn force_remove_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Query(query): Query<DryRunQuery>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if room_id == DEFAULT_ROOM {
+        return Err(CustomError::bad_request("Cannot remove the default room."));
+    }
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+
+    if query.dry_run {
+        let room = &rooms[&room_id];
+        let users_disconnected = room.users.lock().await.len();
+        let contents_size = room.contents_size().await;
+        drop(rooms);
+        let report = DryRunReport {
+            rooms: vec![room_id.clone()],
+            blobs: Vec::new(),
+            users_disconnected,
+            bytes_freed: contents_size + stored_size(&state, &room_id).await,
+        };
+        return Ok(Json(json!({
+            "type": "dry-run",
+            "value": report
+        })));
+    }
+
+    delete_room(&state, &mut rooms, &room_id).await?;
+    drop(rooms);
+
+    let disconnected = state.connections.disconnect(&room_id, None);
+    println!("Admin removed room {room_id}, disconnected {disconnected} clients");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room removed."
+    })))
+}
+
+/// Body of `PUT /api/admin/rooms/:room_id/owner`
+#[derive(Debug, Deserialize)]
+struct OwnerRequest {
+    /// Account taking over the room, `None` to leave it ownerless
+    username: Option<String>,
+}
+
+/// Reassign a room, typically one orphaned by the deletion of its owner account
+async fn set_room_owner(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Json(body): Json<OwnerRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    let owner_id = match &body.username {
+        Some(username) => Some(
+            sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+                .bind(username)
+                .fetch_optional(db)
+                .await
+                .map_err(|e| {
+                    eprintln!("Failed to read user from database: {e}");
+                    auth::internal_error()
+                })?
+                .ok_or_else(|| CustomError::not_found("User not found."))?,
+        ),
+        None => None,
+    };
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    store_room_owner(&state, &rooms[&room_id], &room_id, owner_id, false).await?;
+    drop(rooms);
+
+    println!(
+        "Admin set the owner of room {room_id} to {}",
+        body.username.as_deref().unwrap_or("nobody")
+    );
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": body.username
+    })))
+}
+
+/// Pin a room at the top of the rooms list of every user
+async fn pin_room_for_everyone(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    body: Option<Json<PinRequest>>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let position = body.map(|Json(body)| body.position).unwrap_or_default();
+    store_global_pin(&state, &room_id, Some(position)).await?;
+    println!("Admin pinned room {room_id} at position {position}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room pinned."
+    })))
+}
+
+/// Unpin a room pinned for every user
+async fn unpin_room_for_everyone(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    store_global_pin(&state, &room_id, None).await?;
+    println!("Admin unpinned room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room unpinned."
+    })))
+}
+
+/// Store the pin of a room for everyone and tell every client to refresh its rooms list
+async fn store_global_pin(
+    state: &AppState,
+    room_id: &str,
+    position: Option<i64>,
+) -> Result<(), CustomError> {
+    let db = &state.db;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(state, &mut rooms, room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let content = rooms[room_id].content_rx.borrow().clone();
+    if let Err(e) = pins::store_global(db, room_id, &content, position).await {
+        eprintln!("Failed to store room pin in database: {e:#}");
+        return Err(auth::internal_error());
+    }
+
+    let update = json!(SocketMessage {
+        doc_id: None,
+        message_type: SocketMessageType::UpdateRoomsList,
+        value: None,
+        code: None,
+        revision: None,
+        username: String::new(),
+    })
+    .to_string();
+    for room in rooms.values() {
+        let _ = room.tx.send(update.clone());
+    }
+    drop(rooms);
+    Ok(())
+}
+
+/// Body of `POST /api/admin/rooms/:room_id/kick`
+#[derive(Debug, Deserialize)]
+struct KickRequest {
+    username: String,
+}
+
+/// Disconnect every connection of a user from a room, telling it was kicked
+async fn kick(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Json(body): Json<KickRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let kicked = state.connections.kick(&room_id, &body.username);
+    if kicked == 0 {
+        return Err(CustomError::not_found("User not connected to this room."));
+    }
+
+    println!("Admin kicked {} from room {room_id}", body.username);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": kicked
+    })))
+}
+
+/// Body of `POST /api/admin/rooms/:room_id/mute`
+#[derive(Debug, Deserialize)]
+struct MuteRequest {
+    username: String,
+    /// Length of the mute, `MUTE_DURATION_MINUTES` if unset
+    minutes: Option<u32>,
+}
+
+/// Tell the members of a room that a user was muted until `until`, or unmuted if `None`
+async fn send_mute(state: &AppState, room_id: &str, username: &str, until: Option<i64>) {
+    let message = json!(SocketMessage {
+        doc_id: None,
+        message_type: match until {
+            Some(_) => SocketMessageType::Muted,
+            None => SocketMessageType::Unmuted,
+        },
+        value: until.map(|until| until.to_string()),
+        code: None,
+        revision: None,
+        username: username.to_string(),
+    })
+    .to_string();
+    if let Some(room) = state.rooms.lock().await.get(room_id) {
+        let _ = room.tx.send(message);
+    }
+}
+
+/// Drop what a user writes to a room for a while, its connections staying open
+async fn mute(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Json(body): Json<MuteRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let duration = match body.minutes {
+        Some(0) => return Err(CustomError::bad_request("A mute lasts at least 1 minute.")),
+        Some(minutes) => u64::from(minutes) * 60,
+        None => state.config.mute_duration.as_secs(),
+    };
+    let until = unix_timestamp().saturating_add(i64::try_from(duration).unwrap_or(i64::MAX));
+    let muted = state.connections.mute(&room_id, &body.username, until);
+    if muted == 0 {
+        return Err(CustomError::not_found("User not connected to this room."));
+    }
+    send_mute(&state, &room_id, &body.username, Some(until)).await;
+
+    println!(
+        "Admin muted {} in room {room_id} for {duration} seconds",
+        body.username
+    );
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": until
+    })))
+}
+
+/// Let a muted user write to a room again
+async fn unmute(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, username)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if !state.connections.unmute(&room_id, &username) {
+        return Err(CustomError::not_found("User not muted in this room."));
+    }
+    send_mute(&state, &room_id, &username, None).await;
+
+    println!("Admin unmuted {username} in room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "User unmuted."
+    })))
+}
+
+/// Body of `PUT /api/admin/rooms/:room_id/trace`
+#[derive(Debug, Default, Deserialize)]
+struct TraceRequest {
+    /// Record the messages themselves rather than only their sizes.
+    /// Traces are meant to be shared to debug the server, leave the content of users out of them.
+    #[serde(default)]
+    payloads: bool,
+}
+
+/// Start recording the protocol messages of a room, restarting its trace if already recording
+async fn start_trace(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    body: Option<Json<TraceRequest>>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let body = body.map(|Json(body)| body).unwrap_or_default();
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    state.traces.start(&room_id, body.payloads);
+    drop(rooms);
+
+    println!(
+        "Admin started tracing room {room_id}{}",
+        if body.payloads { " with payloads" } else { "" }
+    );
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Tracing started."
+    })))
+}
+
+/// Download the trace of a room as JSON lines
+async fn download_trace(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+) -> Result<Response, CustomError> {
+    let trace = state
+        .traces
+        .export(&room_id)
+        .ok_or_else(|| CustomError::not_found("Room not traced."))?;
+    let filename: String = room_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"trace-{filename}.jsonl\""),
+            ),
+        ],
+        trace,
+    )
+        .into_response())
+}
+
+/// Stop tracing a room, dropping its trace
+async fn stop_trace(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if !state.traces.stop(&room_id) {
+        return Err(CustomError::not_found("Room not traced."));
+    }
+
+    println!("Admin stopped tracing room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Tracing stopped."
+    })))
+}
+
+/// Body of `POST /api/admin/announce`
+#[derive(Debug, Deserialize)]
+struct AnnounceRequest {
+    message: String,
+    #[serde(default)]
+    severity: Severity,
+    /// Seconds the announcement applies for, clients joining meanwhile get it too
+    expires_in: Option<u64>,
+}
+
+/// Send an announcement to the users of every room
+async fn announce(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<AnnounceRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let message = body.message.trim();
+    if message.is_empty() {
+        return Err(CustomError::bad_request("Announcement message is empty."));
+    }
+    if body.expires_in == Some(0) {
+        return Err(CustomError::bad_request(
+            "Announcements must last at least 1 second.",
+        ));
+    }
+
+    let announcement = Announcement {
+        message: message.to_string(),
+        severity: body.severity,
+        expires_at: body.expires_in.map(|seconds| {
+            unix_timestamp().saturating_add(i64::try_from(seconds).unwrap_or(i64::MAX))
+        }),
+    };
+    state.announcement.remember(&announcement);
+    let socket_message = announcement.socket_message();
+
+    let rooms = state.rooms.lock().await;
+    let mut recipients = 0;
+    for room in rooms.values() {
+        recipients += room.tx.send(socket_message.clone()).unwrap_or(0);
+    }
+    drop(rooms);
+
+    println!("Admin announcement to {recipients} clients: {message}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": recipients
+    })))
+}
+
+/// Delete unreferenced attachment blobs right away
+async fn attachments_gc(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DryRunQuery>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if query.dry_run {
+        let garbage = state
+            .attachments
+            .garbage(attachments::GC_GRACE_PERIOD)
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to list attachment garbage: {e:#}");
+                auth::internal_error()
+            })?;
+        let report = DryRunReport {
+            bytes_freed: garbage
+                .iter()
+                .map(|(_, size)| usize::try_from(*size).unwrap_or(0))
+                .sum(),
+            blobs: garbage.into_iter().map(|(hash, _)| hash).collect(),
+            ..DryRunReport::default()
+        };
+        return Ok(Json(json!({
+            "type": "dry-run",
+            "value": report
+        })));
+    }
+
+    let report = collect_attachment_garbage(&state).await.ok_or_else(|| {
+        CustomError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Attachment garbage collection failed.",
+        )
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": report
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokens_match;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3cret", "s3cre"));
+        assert!(!tokens_match("", "s3cret"));
+    }
+}
@@ -0,0 +1,96 @@
+use rand::Rng;
+use std::sync::Mutex;
+use tokio::time::{self, Duration, Instant};
+
+/// Paces WebSocket upgrades so a crowd of reconnecting clients is spread over time.
+/// Upgrades are admitted at a fixed rate, with a bounded number of them waiting for their turn.
+#[derive(Debug)]
+pub(crate) struct UpgradeGate {
+    /// Delay between two upgrades, `None` disables pacing
+    interval: Option<Duration>,
+    queue_size: u32,
+    next_slot: Mutex<Instant>,
+}
+
+impl UpgradeGate {
+    pub(crate) fn new(per_second: u32, queue_size: u32) -> Self {
+        Self {
+            interval: (per_second > 0).then(|| Duration::from_secs(1) / per_second),
+            queue_size,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserve the next upgrade slot, `None` if the waiting queue is full
+    fn reserve(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let Some(interval) = self.interval else {
+            return Some(now);
+        };
+
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let slot = (*next_slot).max(now);
+        if slot.duration_since(now) > interval * self.queue_size {
+            return None;
+        }
+        *next_slot = slot + interval;
+        drop(next_slot);
+
+        Some(slot)
+    }
+
+    /// Wait for an upgrade slot, returns `false` if the server is overloaded
+    pub(crate) async fn admit(&self) -> bool {
+        match self.reserve() {
+            Some(slot) => {
+                time::sleep_until(slot).await;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Suggested reconnection delay: at least a second, plus a random share of `jitter`
+/// so that clients don't all come back at the same time
+pub(crate) fn reconnect_delay(jitter: Duration) -> Duration {
+    let jitter_ms = u64::try_from(jitter.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_secs(1) + Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconnect_delay, UpgradeGate};
+    use tokio::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_upgrade_gate_queue() {
+        let gate = UpgradeGate::new(10, 2);
+        let now = Instant::now();
+
+        // First upgrade right away, then one every 100ms
+        assert!(gate.reserve().unwrap() <= now + Duration::from_millis(10));
+        assert!(gate.reserve().unwrap() >= now + Duration::from_millis(100));
+        assert!(gate.reserve().unwrap() >= now + Duration::from_millis(200));
+
+        // The queue is full
+        assert!(gate.reserve().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_gate_disabled() {
+        let gate = UpgradeGate::new(0, 0);
+        for _ in 0..100 {
+            assert!(gate.admit().await);
+        }
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        for _ in 0..100 {
+            let delay = reconnect_delay(Duration::from_secs(5));
+            assert!(delay >= Duration::from_secs(1));
+            assert!(delay <= Duration::from_secs(6));
+        }
+    }
+}
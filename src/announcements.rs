@@ -0,0 +1,111 @@
+use crate::{unix_timestamp, SocketMessage, SocketMessageType};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use ts_rs::TS;
+
+/// How much an announcement matters, for clients to pick how they show it
+#[derive(TS, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub(crate) enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Value of `announcement` messages, sent by the operators to every room
+#[derive(TS, Serialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct Announcement {
+    pub(crate) message: String,
+    pub(crate) severity: Severity,
+    /// Unix timestamp after which the announcement no longer applies, `None` if it is only
+    /// sent to the clients connected when it is made
+    #[ts(type = "number | null")]
+    pub(crate) expires_at: Option<i64>,
+}
+
+impl Announcement {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub(crate) fn socket_message(&self) -> String {
+        json!(SocketMessage {
+            doc_id: None,
+            message_type: SocketMessageType::Announcement,
+            value: serde_json::to_string(self).ok(),
+            code: None,
+            revision: None,
+            username: "Server".to_string(),
+        })
+        .to_string()
+    }
+}
+
+/// The last announcement with an expiry, sent to the clients joining a room until then
+#[derive(Debug, Default)]
+pub(crate) struct CurrentAnnouncement(Mutex<Option<Announcement>>);
+
+impl CurrentAnnouncement {
+    /// Keep an announcement for the clients joining later, if it expires
+    pub(crate) fn remember(&self, announcement: &Announcement) {
+        if announcement.expires_at.is_some() {
+            *self.0.lock().unwrap() = Some(announcement.clone());
+        }
+    }
+
+    /// The announcement for a client joining now, unless it expired
+    pub(crate) fn active(&self) -> Option<Announcement> {
+        let mut current = self.0.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|announcement| announcement.is_expired(unix_timestamp()))
+        {
+            *current = None;
+        }
+        current.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Announcement, CurrentAnnouncement, Severity};
+    use crate::unix_timestamp;
+
+    fn announcement(expires_at: Option<i64>) -> Announcement {
+        Announcement {
+            message: "Maintenance at noon".to_string(),
+            severity: Severity::Warning,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_current_announcement() {
+        let current = CurrentAnnouncement::default();
+        let later = announcement(Some(unix_timestamp() + 60));
+        current.remember(&later);
+        assert_eq!(current.active(), Some(later.clone()));
+
+        // Announcements without expiry don't replace it for the next clients
+        current.remember(&announcement(None));
+        assert_eq!(current.active(), Some(later));
+
+        current.remember(&announcement(Some(unix_timestamp() - 1)));
+        assert_eq!(current.active(), None);
+    }
+
+    #[test]
+    fn test_socket_message() {
+        let message: serde_json::Value =
+            serde_json::from_str(&announcement(None).socket_message()).unwrap();
+        assert_eq!(message["type"], "announcement");
+        let value: serde_json::Value =
+            serde_json::from_str(message["value"].as_str().unwrap()).unwrap();
+        assert_eq!(value["severity"], "warning");
+        assert_eq!(value["expires_at"], serde_json::Value::Null);
+    }
+}
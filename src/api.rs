@@ -0,0 +1,1302 @@
+use crate::burn::BurnAfter;
+use crate::client_ip::ClientIp;
+use crate::documents::{DocumentInfo, MAIN_DOCUMENT, MAX_DOCUMENTS};
+use crate::format::{self, Formatter};
+use crate::freeze::FreezeSchedule;
+use crate::language::{self, LanguagePatch};
+use crate::metrics::SaturationSnapshot;
+use crate::pins::{self, PinRequest, Pins, RoomPin};
+use crate::rooms::{broadcast_capacity, ensure_room_loaded, RoomState, DEFAULT_ROOM};
+use crate::storage::{delete_stored_room, get_room_owner, store_room_owner, store_user_pin};
+use crate::webhooks::WebhookEvent;
+use crate::{
+    attachments, auth, checkpoints, compression, history, http_cache, members, revisions, trash,
+    unix_timestamp, visibility, AppState, CustomError, SocketMessage, SocketMessageType,
+};
+use anyhow::Result;
+use axum::extract::{Multipart, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::time::{self, Duration, Instant};
+use ts_rs::TS;
+
+/// Delay before an occupied room is deleted, for its users to leave or save its content
+const ROOM_CLOSING_DELAY: Duration = Duration::from_secs(60);
+
+/// Throttle REST API requests per client IP
+pub(crate) async fn rate_limit_api(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.api_rate_limiter.check(ip) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({ "error": "Too many requests." })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Reject anonymous requests to rooms when `REQUIRE_AUTH` is set
+pub(crate) async fn require_auth(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.config.require_auth
+        && auth::current_user(&state, request.headers())
+            .await
+            .is_none()
+    {
+        return CustomError::new(StatusCode::UNAUTHORIZED, "Authentication required.")
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// `?dry_run=true` query parameter accepted by destructive endpoints
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct DryRunQuery {
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+}
+
+/// What a destructive operation would affect, returned instead of performing it in dry-run mode
+#[derive(TS, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct DryRunReport {
+    pub(crate) rooms: Vec<String>,
+    /// Attachment blobs, by hash
+    pub(crate) blobs: Vec<String>,
+    pub(crate) users_disconnected: usize,
+    pub(crate) bytes_freed: usize,
+}
+
+/// Remove a room by id
+pub(crate) async fn remove_room(
+    State(state): State<Arc<AppState>>,
+    room: axum::extract::Path<String>,
+    Query(query): Query<DryRunQuery>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    // If general, forbid removal
+    if room.0 == DEFAULT_ROOM {
+        return Err(CustomError::bad_request("Cannot remove the default room."));
+    }
+
+    let mut rooms = state.rooms.lock().await;
+
+    // If already removed, fail silently
+    // Evicted rooms only live in the database, restore them to run the usual checks
+    if !ensure_room_loaded(&state, &mut rooms, &room.0).await {
+        println!("Room already removed.");
+        return Ok(Json(json!({ "message": "Room already removed." })));
+    }
+    check_room_owner(&state, &headers, &room.0).await?;
+
+    // If only 1 room exists, don't remove it, return an error
+    if rooms.len() == 1 {
+        return Err(CustomError::bad_request("Cannot remove the last room."));
+    }
+
+    let room_state = rooms.get(&room.0).unwrap();
+    let users_count = room_state.users.lock().await.len();
+
+    // Report what would be removed, without touching anything
+    if query.dry_run {
+        drop(rooms);
+        // The room goes to the trash, nothing is freed before it is purged
+        let report = DryRunReport {
+            rooms: vec![room.0.clone()],
+            blobs: Vec::new(),
+            users_disconnected: users_count,
+            bytes_freed: 0,
+        };
+        return Ok(Json(json!({
+            "type": "dry-run",
+            "value": report
+        })));
+    }
+
+    // Give the other users of the room time to leave, or to save its content
+    if users_count > 1 {
+        let mut closing_at = room_state.closing_at.lock().await;
+        let deadline = *closing_at.get_or_insert_with(|| {
+            let deadline = Instant::now() + ROOM_CLOSING_DELAY;
+            let _ = room_state.tx.send(room_closing_message(ROOM_CLOSING_DELAY));
+            tokio::spawn(close_room_later(state.clone(), room.0.clone(), deadline));
+            println!("Room {} will be removed in {ROOM_CLOSING_DELAY:?}", room.0);
+            deadline
+        });
+        drop(closing_at);
+        drop(rooms);
+        return Ok(Json(json!({
+            "type": "scheduled",
+            "value": seconds_left(deadline.saturating_duration_since(Instant::now()))
+        })));
+    }
+
+    trash::discard_room(&state, &mut rooms, &room.0).await?;
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room removed."
+    })))
+}
+
+/// Bytes the saved versions, checkpoints and attachments of a room take
+pub(crate) async fn stored_size(state: &AppState, room_id: &str) -> usize {
+    let history_bytes = history::size(&state.db, room_id)
+        .await
+        .map(|(_, bytes)| bytes)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to measure room history: {e:#}");
+            0
+        });
+    let checkpoint_bytes = checkpoints::size(&state.db, room_id)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to measure room checkpoints: {e:#}");
+            0
+        });
+    let attachment_bytes = state
+        .attachments
+        .room_usage(room_id)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to measure room attachments: {e:#}");
+            0
+        });
+    usize::try_from(history_bytes + checkpoint_bytes)
+        .unwrap_or(0)
+        .saturating_add(usize::try_from(attachment_bytes).unwrap_or(usize::MAX))
+}
+
+/// Seconds before a closing room is deleted, rounded up so it never announces 0 before that
+fn seconds_left(remaining: Duration) -> u64 {
+    remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
+}
+
+fn room_closing_message(remaining: Duration) -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::RoomClosing,
+        value: Some(seconds_left(remaining).to_string()),
+    })
+    .to_string()
+}
+
+/// Delete a room counting down to its deletion once its users left or the countdown ran out,
+/// reminding them of the time left every 10 seconds
+async fn close_room_later(state: Arc<AppState>, room_id: String, deadline: Instant) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    interval.tick().await;
+    for tick in 1_u64.. {
+        interval.tick().await;
+        let mut rooms = state.rooms.lock().await;
+        // Removed meanwhile, and maybe created again since
+        let Some(room) = rooms.get(&room_id) else {
+            return;
+        };
+        if room.closing_at.lock().await.is_none() {
+            return;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let empty = room.users.lock().await.is_empty();
+        if !empty && !remaining.is_zero() {
+            if tick % 10 == 0 {
+                let _ = room.tx.send(room_closing_message(remaining));
+            }
+            drop(rooms);
+            continue;
+        }
+
+        let result = trash::discard_room(&state, &mut rooms, &room_id).await;
+        drop(rooms);
+        if result.is_ok() {
+            let disconnected = state.connections.disconnect(&room_id, None);
+            println!("Removed closing room {room_id}, disconnected {disconnected} clients");
+        }
+        return;
+    }
+}
+
+/// Remove a room from memory and from the database for good, and notify the users of the other rooms
+pub(crate) async fn delete_room(
+    state: &AppState,
+    rooms: &mut HashMap<String, RoomState>,
+    room_id: &str,
+) -> Result<(), CustomError> {
+    if let Some(room_state) = rooms.remove(room_id) {
+        room_state.shutdown();
+    }
+    state.traces.stop(room_id);
+    let listed = visibility::is_listed(&state.db, room_id).await;
+    // Before the webhooks of the room are forgotten
+    state
+        .webhooks
+        .emit(WebhookEvent::RoomDeleted, room_id, json!({}))
+        .await;
+
+    delete_stored_room(state, room_id).await?;
+
+    // Notify all users that the room has been removed, unless they couldn't see it
+    if listed {
+        for room_state in rooms.values() {
+            let _ = room_state.tx.send(
+                json!(SocketMessage! {
+                    message_type: SocketMessageType::UpdateRoomsList,
+                })
+                .to_string(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Body of `POST /api/rooms/:room_id/merge`
+#[derive(Debug, Deserialize)]
+pub(crate) struct MergeRequest {
+    /// Room merged into the target, removed afterwards
+    source: String,
+}
+
+/// Content of two rooms put one after the other, separated by a blank line
+fn append_content(target: &str, source: &str) -> String {
+    if target.is_empty() {
+        return source.to_string();
+    }
+    if source.is_empty() {
+        return target.to_string();
+    }
+    format!("{}\n\n{source}", target.trim_end_matches('\n'))
+}
+
+/// Append the content, documents and attachments of a room to another one and remove it,
+/// redirecting its members to the target
+pub(crate) async fn merge_room(
+    State(state): State<Arc<AppState>>,
+    Path(target): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<MergeRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let source = body.source;
+    if source == target {
+        return Err(CustomError::bad_request("Cannot merge a room into itself."));
+    }
+    if source == DEFAULT_ROOM {
+        return Err(CustomError::bad_request(
+            "Cannot merge the default room into another room.",
+        ));
+    }
+
+    let username = auth::current_user(&state, &headers)
+        .await
+        .map(|user| user.username)
+        .unwrap_or_default();
+    let mut rooms = state.rooms.lock().await;
+    for room_id in [&target, &source] {
+        if !ensure_room_loaded(&state, &mut rooms, room_id).await {
+            return Err(CustomError::not_found(format!("Room {room_id} not found.")));
+        }
+        check_room_owner(&state, &headers, room_id).await?;
+
+        let room = &rooms[room_id];
+        if room.encryption.is_some() {
+            return Err(CustomError::bad_request(
+                "Encrypted rooms can't be merged by the server.",
+            ));
+        }
+        revisions::check_writable(room, &username).await?;
+    }
+
+    let source_room = &rooms[&source];
+    let target_room = &rooms[&target];
+    let source_documents: Vec<(String, String)> = source_room
+        .documents
+        .lock()
+        .await
+        .iter()
+        .map(|(doc_id, document)| (doc_id.clone(), document.content_rx.borrow().clone()))
+        .collect();
+    let documents = target_room.documents.lock().await;
+    let new_documents = source_documents
+        .iter()
+        .filter(|(doc_id, _)| !documents.contains_key(doc_id))
+        .count();
+    let too_many_documents = documents.len() + new_documents > MAX_DOCUMENTS;
+    drop(documents);
+    if too_many_documents {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            format!("A room has at most {MAX_DOCUMENTS} documents besides the main one."),
+        ));
+    }
+
+    let merged = append_content(
+        &target_room.content_rx.borrow().clone(),
+        &source_room.content_rx.borrow().clone(),
+    );
+    let mut updates = vec![(None, merged)];
+    for (doc_id, content) in source_documents {
+        let existing = target_room.content_of(Some(&doc_id)).await;
+        updates.push((
+            Some(doc_id),
+            append_content(&existing.unwrap_or_default(), &content),
+        ));
+    }
+    for (scope, content) in &updates {
+        revisions::check_edit(target_room, &username, scope.as_deref(), content).await?;
+    }
+    for (scope, content) in updates {
+        target_room
+            .update_content(&state, &target, scope.as_deref(), &content)
+            .await
+            .map_err(CustomError::bad_request)?;
+        let _ = target_room.tx.send(
+            json!(SocketMessage! {
+                doc_id: scope,
+                message_type: SocketMessageType::Message,
+                value: Some(content),
+                username: "Server".to_string(),
+            })
+            .to_string(),
+        );
+    }
+
+    if let Err(e) = state.attachments.move_room(&source, &target).await {
+        eprintln!("Failed to move room attachments: {e:#}");
+        return Err(auth::internal_error());
+    }
+
+    let _ = source_room.tx.send(
+        json!(SocketMessage! {
+            message_type: SocketMessageType::Redirect,
+            value: Some(target.clone()),
+        })
+        .to_string(),
+    );
+    delete_room(&state, &mut rooms, &source).await?;
+    drop(rooms);
+
+    println!("Merged room {source} into {target}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Rooms merged."
+    })))
+}
+
+/// Body of `POST /api/rooms/:room_id/format`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct FormatRequest {
+    #[serde(default)]
+    formatter: Formatter,
+}
+
+/// Format the content of a room and broadcast the result to its members
+pub(crate) async fn format_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    body: Option<Json<FormatRequest>>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let username = revisions::check_editor(&state, &headers, &room_id).await?;
+    let formatter = body.map(|Json(body)| body.formatter).unwrap_or_default();
+
+    let (content, burned) = {
+        let mut rooms = state.rooms.lock().await;
+        if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+            return Err(CustomError::not_found("Room not found."));
+        }
+        let room = &rooms[&room_id];
+        if room.encryption.is_some() {
+            return Err(CustomError::bad_request(
+                "Encrypted rooms can't be formatted by the server.",
+            ));
+        }
+        let burned = room.burns_after(BurnAfter::Read).await;
+        let content = room.read_content(&state, &room_id).await?;
+        drop(rooms);
+        (content, burned)
+    };
+
+    // The room is not locked while formatting, an external command may be slow
+    let formatted = match formatter {
+        Formatter::Markdown => format::normalize_markdown(&content),
+        Formatter::Command => {
+            let Some(command) = &state.config.formatter_command else {
+                return Err(CustomError::bad_request("No formatter command configured."));
+            };
+            format::run_command(command, &content).await.map_err(|e| {
+                eprintln!("Failed to format room {room_id}: {e:#}");
+                CustomError::new(StatusCode::INTERNAL_SERVER_ERROR, "Formatter failed.")
+            })?
+        }
+    };
+
+    // A room burned after reading keeps nothing, the formatted content is only for the reader
+    if burned {
+        return Ok(Json(json!({
+            "type": "success",
+            "value": formatted
+        })));
+    }
+
+    let rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get(&room_id) else {
+        return Err(CustomError::not_found("Room not found."));
+    };
+    revisions::check_writable(room, &username).await?;
+
+    // Don't overwrite edits made while the formatter was running
+    if *room.content_rx.borrow() != content {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            "Room content changed while formatting, try again.",
+        ));
+    }
+
+    if formatted != content {
+        revisions::check_edit(room, &username, None, &formatted).await?;
+        room.update_content(&state, &room_id, None, &formatted)
+            .await
+            .map_err(CustomError::bad_request)?;
+        let revision = room.revision(None).await;
+        let _ = room.tx.send(
+            json!(SocketMessage! {
+                message_type: SocketMessageType::content(room.encryption.is_some()),
+                value: Some(formatted.clone()),
+                revision: Some(revision),
+                username: "Server".to_string(),
+            })
+            .to_string(),
+        );
+    }
+
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": formatted
+    })))
+}
+
+/// Make sure the request comes from an owner of the room, the account owning it or a member
+/// with the owner role.
+/// Rooms without an owner can be managed by anyone, like the rest of the API.
+pub(crate) async fn check_room_owner(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<(), CustomError> {
+    let owners = members::owners(&state.db, room_id).await;
+    if owners.is_empty() {
+        return Ok(());
+    }
+    match auth::current_user(state, headers).await {
+        Some(user) if owners.contains(&user.id) => Ok(()),
+        Some(_) => Err(CustomError::new(
+            StatusCode::FORBIDDEN,
+            "Only the owner of the room can do this.",
+        )),
+        None => Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Log in as the owner of the room to do this.",
+        )),
+    }
+}
+
+/// Take ownership of a room that has none, such as the rooms created before accounts existed.
+/// Anyone can already manage an ownerless room, so claiming one grants nothing over others.
+pub(crate) async fn claim_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if room_id == DEFAULT_ROOM {
+        return Err(CustomError::bad_request(
+            "The default room can't be claimed.",
+        ));
+    }
+    let Some(user) = auth::current_user(&state, &headers).await else {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Log in to claim a room.",
+        ));
+    };
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let claimed = store_room_owner(&state, &rooms[&room_id], &room_id, Some(user.id), true).await?;
+    drop(rooms);
+
+    if !claimed && get_room_owner(&state.db, &room_id).await != Some(user.id) {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            "This room already has an owner.",
+        ));
+    }
+    if claimed {
+        println!("{} claimed room {room_id}", user.username);
+    }
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room claimed."
+    })))
+}
+
+/// Pin a room at the top of the rooms list of the current user
+pub(crate) async fn pin_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    body: Option<Json<PinRequest>>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let position = body.map(|Json(body)| body.position).unwrap_or_default();
+    store_user_pin(&state, &headers, &room_id, Some(position)).await?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room pinned."
+    })))
+}
+
+/// Unpin a room pinned by the current user
+pub(crate) async fn unpin_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    store_user_pin(&state, &headers, &room_id, None).await?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room unpinned."
+    })))
+}
+
+/// Freeze schedule of a room and whether it is currently frozen
+#[derive(TS, Serialize, Deserialize, Debug)]
+#[ts(export)]
+pub(crate) struct FreezeStatus {
+    pub(crate) schedule: FreezeSchedule,
+    /// Unix timestamp at which the room becomes writable again, if frozen
+    #[ts(type = "number | null")]
+    pub(crate) frozen_until: Option<i64>,
+}
+
+/// List the documents of a room, the main one first
+pub(crate) async fn list_documents(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[&room_id];
+    let mut documents: Vec<DocumentInfo> = room
+        .documents
+        .lock()
+        .await
+        .iter()
+        .map(|(doc_id, document)| DocumentInfo {
+            id: doc_id.clone(),
+            size: document.content_rx.borrow().len(),
+        })
+        .collect();
+    documents.sort_by(|a, b| a.id.cmp(&b.id));
+    documents.insert(
+        0,
+        DocumentInfo {
+            id: MAIN_DOCUMENT.to_string(),
+            size: room.content_rx.borrow().len(),
+        },
+    );
+    drop(rooms);
+
+    Ok(http_cache::cached_json(
+        &state.validators,
+        &format!("documents:{room_id}"),
+        &headers,
+        &json!({
+            "type": "success",
+            "value": documents
+        }),
+    ))
+}
+
+/// Body of `PUT /api/rooms/:room_id/syntax-language`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct SyntaxLanguageRequest {
+    /// `None` to unset it
+    pub(crate) language: Option<String>,
+}
+
+/// Change the highlighting language of a room, announced to its members with `language-changed`
+pub(crate) async fn set_room_syntax_language(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<SyntaxLanguageRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    rooms[&room_id]
+        .set_syntax_language(&state, &room_id, body.language)
+        .await
+        .map_err(CustomError::bad_request)?;
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Language updated."
+    })))
+}
+
+/// Type and natural language of the content of a room, detected unless set by hand
+pub(crate) async fn get_language(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[&room_id];
+    let content = room.content_rx.borrow().clone();
+    // Nothing to detect in ciphertext
+    let detect = state.config.language_detection && room.encryption.is_none();
+    drop(rooms);
+
+    let settings = language::load_override(&state.db, &room_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read room language from database: {e:#}");
+            auth::internal_error()
+        })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": settings.resolve(&content, detect)
+    })))
+}
+
+/// Set the type or natural language of the content of a room by hand
+pub(crate) async fn set_language(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<LanguagePatch>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    let room = &rooms[&room_id];
+    let content = room.content_rx.borrow().clone();
+    let detect = state.config.language_detection && room.encryption.is_none();
+
+    let current = language::load_override(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to read room language from database: {e:#}");
+        auth::internal_error()
+    })?;
+    let settings = patch.apply(current).map_err(CustomError::bad_request)?;
+    if let Err(e) = language::store_override(db, &room_id, &content, &settings).await {
+        eprintln!("Failed to store room language in database: {e:#}");
+        return Err(auth::internal_error());
+    }
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": settings.resolve(&content, detect)
+    })))
+}
+
+/// Remove a document of a room, the main one can't be
+pub(crate) async fn remove_document(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, doc_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if doc_id == MAIN_DOCUMENT {
+        return Err(CustomError::bad_request(
+            "The main document can't be removed.",
+        ));
+    }
+
+    let username = auth::current_user(&state, &headers)
+        .await
+        .map(|user| user.username)
+        .unwrap_or_default();
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    let room = &rooms[&room_id];
+    revisions::check_edit(room, &username, Some(&doc_id), "").await?;
+
+    if room.documents.lock().await.remove(&doc_id).is_none() {
+        return Err(CustomError::not_found("Document not found."));
+    }
+    room.sequencers.lock().await.remove(&doc_id);
+    if let Err(e) = state.storage.delete_document(&room_id, &doc_id).await {
+        eprintln!("Failed to remove document from storage: {e:#}");
+        return Err(auth::internal_error());
+    }
+    if let Some(mirror) = &state.mirror {
+        mirror.remove_document(&room_id, &doc_id);
+    }
+    let _ = room.tx.send(
+        json!(SocketMessage! {
+            doc_id: Some(doc_id.clone()),
+            message_type: SocketMessageType::DocumentRemoved,
+        })
+        .to_string(),
+    );
+    drop(rooms);
+
+    println!("Removed document {doc_id} of room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Document removed."
+    })))
+}
+
+/// Attach the `file` field of a multipart upload to a room, announcing it with `file-added`
+pub(crate) async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let username = revisions::check_editor(&state, &headers, &room_id).await?;
+    let store = &state.attachments;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    revisions::check_writable(&rooms[&room_id], &username).await?;
+    drop(rooms);
+
+    let too_large = || {
+        CustomError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Files are limited to {} bytes.",
+                state.config.max_attachment_size
+            ),
+        )
+    };
+    let invalid = |e: axum::extract::multipart::MultipartError| {
+        CustomError::bad_request(format!("Invalid upload: {e}"))
+    };
+
+    let mut file = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(invalid)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let filename = attachments::sanitize_filename(field.file_name().unwrap_or_default());
+        let content_type = field.content_type().map_or_else(
+            || {
+                mime_guess::from_path(&filename)
+                    .first_or_octet_stream()
+                    .to_string()
+            },
+            ToString::to_string,
+        );
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(|e| {
+            if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                too_large()
+            } else {
+                invalid(e)
+            }
+        })? {
+            bytes.extend_from_slice(&chunk);
+            if u64::try_from(bytes.len()).unwrap_or(u64::MAX) > state.config.max_attachment_size {
+                return Err(too_large());
+            }
+        }
+        file = Some((filename, content_type, bytes));
+        break;
+    }
+    let Some((filename, content_type, bytes)) = file else {
+        return Err(CustomError::bad_request("Missing file field."));
+    };
+
+    let usage = store.room_usage(&room_id).await.map_err(|e| {
+        eprintln!("Failed to read room attachments size: {e:#}");
+        auth::internal_error()
+    })?;
+    let size = u64::try_from(bytes.len()).unwrap_or(u64::MAX);
+    if usage.saturating_add(size) > state.config.room_attachments_quota {
+        return Err(CustomError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "The files of a room are limited to {} bytes in total.",
+                state.config.room_attachments_quota
+            ),
+        ));
+    }
+
+    let attachment = store
+        .attach(&room_id, &filename, &content_type, &bytes)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to store attachment: {e:#}");
+            auth::internal_error()
+        })?;
+    println!(
+        "Attached {} ({} bytes) to room {room_id}",
+        attachment.filename, attachment.size
+    );
+
+    let uploader = auth::current_user(&state, &headers)
+        .await
+        .map(|user| user.username)
+        .unwrap_or_default();
+    let rooms = state.rooms.lock().await;
+    if let Some(room) = rooms.get(&room_id) {
+        let _ = room.tx.send(
+            json!(SocketMessage! {
+                message_type: SocketMessageType::FileAdded,
+                value: serde_json::to_string(&attachment).ok(),
+                username: uploader,
+            })
+            .to_string(),
+        );
+    }
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": attachment
+    })))
+}
+
+/// Download a file attached to a room
+pub(crate) async fn download_file(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, file_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let store = &state.attachments;
+    let Some((attachment, bytes)) = store.get(&room_id, file_id).await.map_err(|e| {
+        eprintln!("Failed to read attachment: {e:#}");
+        auth::internal_error()
+    })?
+    else {
+        return Err(CustomError::not_found("File not found."));
+    };
+
+    // Never rendered inline, an uploaded HTML page would run on the origin of the server
+    let disposition = format!(
+        "attachment; filename=\"{}\"",
+        attachment
+            .filename
+            .chars()
+            .map(|c| if c.is_ascii() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            })
+            .collect::<String>()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+/// Get the freeze schedule of a room
+pub(crate) async fn get_freeze_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let schedule = rooms[&room_id].freeze_schedule.lock().await.clone();
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": FreezeStatus {
+            frozen_until: schedule.frozen_until(unix_timestamp()),
+            schedule,
+        }
+    })))
+}
+
+/// Replace the freeze schedule of a room, announcing the change if it freezes or unfreezes it
+pub(crate) async fn set_freeze_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(schedule): Json<FreezeSchedule>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    schedule.validate().map_err(CustomError::bad_request)?;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    let room = &rooms[&room_id];
+
+    let stored = serde_json::to_string(&schedule).unwrap_or_default();
+    let content = room.content_rx.borrow().clone();
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, freeze_schedule) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET freeze_schedule = excluded.freeze_schedule
+        ",
+    )
+    .bind(&room_id)
+    .bind(content)
+    .bind(stored)
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store freeze schedule in database: {e}");
+        return Err(CustomError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to store freeze schedule.",
+        ));
+    }
+
+    let now = unix_timestamp();
+    *room.freeze_schedule.lock().await = schedule.clone();
+    room.update_frozen(now).await;
+    drop(rooms);
+
+    println!("Updated freeze schedule of room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": FreezeStatus {
+            frozen_until: schedule.frozen_until(now),
+            schedule,
+        }
+    })))
+}
+
+/// Room
+#[derive(TS, Serialize, Deserialize)]
+#[ts(export)]
+pub(crate) struct Room {
+    pub(crate) id: String,
+    pub(crate) users: Vec<String>,
+    /// The content can't be saved to the database at the moment
+    pub(crate) persistence_degraded: bool,
+    /// End-to-end encrypted, the content is ciphertext
+    pub(crate) encrypted: bool,
+    /// Language used to highlight the content
+    #[ts(type = "string | null")]
+    pub(crate) language: Option<String>,
+    /// Pinned at the top of the list, for everyone or the current user
+    pub(crate) pin: Option<RoomPin>,
+    /// Unix timestamp of the creation, unknown for rooms older than this field
+    #[ts(type = "number | null")]
+    pub(crate) created_at: Option<i64>,
+    /// Unix timestamp of the last edit, or of the loading of a room not edited since, if known
+    #[ts(type = "number | null")]
+    pub(crate) updated_at: Option<i64>,
+    /// Size of the main content in bytes, ciphertext included for encrypted rooms
+    #[ts(type = "number")]
+    pub(crate) content_length: usize,
+    /// Out of memory until unarchived, only listed with `?include=archived`
+    pub(crate) archived: bool,
+}
+
+/// Row of a room in the database, as listed by `GET /api/rooms`
+#[derive(sqlx::FromRow)]
+struct StoredRoom {
+    room_id: String,
+    encrypted: bool,
+    syntax_language: Option<String>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    /// Length of the content when stored uncompressed
+    content_length: i64,
+    /// Start of the compressed content, which holds its length
+    frame_header: Option<Vec<u8>>,
+    archived: bool,
+}
+
+/// Order of the rooms list, after the pinned rooms
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RoomSort {
+    /// Alphabetical
+    #[default]
+    Id,
+    /// Most users first
+    Users,
+    /// Most recently edited first
+    UpdatedAt,
+}
+
+/// Rooms listed by `GET /api/rooms` besides the active ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RoomsInclude {
+    Archived,
+}
+
+/// Query parameters of `GET /api/rooms`, which lists every room without `per_page`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct RoomsQuery {
+    include: Option<RoomsInclude>,
+    /// Only the rooms whose id contains this, ignoring case
+    search: Option<String>,
+    #[serde(default)]
+    sort: RoomSort,
+    /// Page number, from 1
+    page: Option<usize>,
+    /// Rooms per page, at most [`MAX_ROOMS_PER_PAGE`]
+    per_page: Option<usize>,
+}
+
+const MAX_ROOMS_PER_PAGE: usize = 100;
+
+impl RoomsQuery {
+    /// Filter, sort and paginate a rooms list, returning the rooms of the page
+    /// and how many rooms matched
+    pub(crate) fn apply(&self, mut rooms: Vec<Room>) -> Result<(Vec<Room>, usize), String> {
+        if let Some(search) = self.search.as_deref().map(str::to_lowercase) {
+            rooms.retain(|room| room.id.to_lowercase().contains(&search));
+        }
+
+        rooms.sort_by(|a, b| {
+            let order = match self.sort {
+                RoomSort::Id => std::cmp::Ordering::Equal,
+                RoomSort::Users => b.users.len().cmp(&a.users.len()),
+                RoomSort::UpdatedAt => b.updated_at.cmp(&a.updated_at),
+            };
+            pins::sort_key(a.pin)
+                .cmp(&pins::sort_key(b.pin))
+                .then(order)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        let total = rooms.len();
+        let Some(per_page) = self.per_page else {
+            return Ok((rooms, total));
+        };
+        if per_page == 0 || per_page > MAX_ROOMS_PER_PAGE {
+            return Err(format!(
+                "per_page must be between 1 and {MAX_ROOMS_PER_PAGE}."
+            ));
+        }
+        let page = self.page.unwrap_or(1);
+        if page == 0 {
+            return Err("Pages are numbered from 1.".to_string());
+        }
+        let page_rooms = rooms
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+        Ok((page_rooms, total))
+    }
+}
+
+/// Get a list of the rooms, pinned ones first, with the number of matching rooms
+/// in the `X-Total-Count` header
+pub(crate) async fn get_rooms(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<RoomsQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    // Only logged in users have pins of their own
+    let user_id = auth::current_user(&state, &headers)
+        .await
+        .map(|account| account.id);
+    let pins = Pins::load(&state.db, user_id).await.unwrap_or_else(|e| {
+        eprintln!("Failed to read room pins from database: {e:#}");
+        Pins::default()
+    });
+    // Private rooms are only listed for their members
+    let hidden = members::hidden_rooms(&state.db, user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read room members from database: {e:#}");
+            auth::internal_error()
+        })?;
+
+    let mut stored_rooms: HashMap<String, StoredRoom> = sqlx::query_as::<_, StoredRoom>(
+        r"
+        SELECT room_id, encrypted, syntax_language, created_at, updated_at,
+            LENGTH(CAST(content AS BLOB)) AS content_length,
+            CASE WHEN compressed THEN SUBSTR(content_zstd, 1, ?) END AS frame_header, archived
+        FROM rooms WHERE deleted_at IS NULL AND (? OR NOT archived)
+        ",
+    )
+    .bind(compression::FRAME_HEADER_MAX)
+    .bind(query.include == Some(RoomsInclude::Archived))
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to list rooms from database: {e}");
+        Vec::new()
+    })
+    .into_iter()
+    .filter(|room| !hidden.contains(&room.room_id))
+    .map(|room| (room.room_id.clone(), room))
+    .collect();
+
+    let rooms = state.rooms.lock().await;
+    let mut room_list = Vec::new();
+
+    let now = unix_timestamp();
+    for (id, room) in rooms.iter().filter(|(id, _)| !hidden.contains(*id)) {
+        let users = room.users.lock().await;
+        let idle = room.last_edit.lock().await.elapsed().as_secs();
+        let last_edit = now.saturating_sub(i64::try_from(idle).unwrap_or(i64::MAX));
+        // Edits are written to the database a moment later, rooms in memory only have no row
+        let (created_at, updated_at) = match stored_rooms.remove(id) {
+            Some(stored) => (stored.created_at, stored.updated_at.unwrap_or(last_edit)),
+            None => (Some(room.created_at), last_edit),
+        };
+        let content_length = room.content_rx.borrow().len();
+        room_list.push(Room {
+            id: id.clone(),
+            users: users.names().cloned().collect(),
+            persistence_degraded: room.persistence_degraded.load(Ordering::Relaxed),
+            encrypted: room.encryption.is_some(),
+            language: room.syntax_language.lock().await.clone(),
+            pin: pins.get(id),
+            created_at,
+            updated_at: Some(updated_at),
+            content_length,
+            archived: false,
+        });
+    }
+
+    // Evicted rooms are only in the database
+    for (id, stored) in stored_rooms {
+        room_list.push(Room {
+            pin: pins.get(&id),
+            id,
+            users: vec![],
+            persistence_degraded: false,
+            encrypted: stored.encrypted,
+            language: stored.syntax_language,
+            created_at: stored.created_at,
+            updated_at: stored.updated_at,
+            content_length: match &stored.frame_header {
+                Some(header) => compression::content_length(header)
+                    .and_then(|length| usize::try_from(length).ok())
+                    .unwrap_or_default(),
+                None => usize::try_from(stored.content_length).unwrap_or_default(),
+            },
+            archived: stored.archived,
+        });
+    }
+    drop(rooms);
+
+    let (room_list, total) = query.apply(room_list).map_err(CustomError::bad_request)?;
+
+    // Pins make the list differ between users
+    let resource = format!(
+        "rooms:{}?{}",
+        user_id.map_or_else(String::new, |id| id.to_string()),
+        uri.query().unwrap_or_default()
+    );
+    let mut response = http_cache::cached_json(&state.validators, &resource, &headers, &room_list);
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::VARY, HeaderValue::from_static("cookie"));
+    response_headers.insert("x-total-count", HeaderValue::from(total));
+    Ok(response)
+}
+
+/// Measure how close the rooms are to dropping messages
+async fn saturation_snapshot(state: &AppState) -> SaturationSnapshot {
+    let mut snapshot = SaturationSnapshot {
+        connections: state.connections.list().len(),
+        broadcast_capacity: broadcast_capacity(),
+        ..SaturationSnapshot::default()
+    };
+
+    let rooms = state.rooms.lock().await;
+    snapshot.rooms = rooms.len();
+    for room in rooms.values() {
+        // Messages are retained until the slowest member received them
+        let queued = room.tx.len();
+        snapshot.outbound_queue_depth_sum += queued;
+        snapshot.outbound_queue_depth_max = snapshot.outbound_queue_depth_max.max(queued);
+        if room.unsaved.load(Ordering::Relaxed)
+            || room
+                .documents
+                .lock()
+                .await
+                .values()
+                .any(|document| document.unsaved.load(Ordering::Relaxed))
+        {
+            snapshot.db_write_queue_length += 1;
+        }
+    }
+    drop(rooms);
+
+    #[allow(clippy::cast_precision_loss)]
+    {
+        snapshot.broadcast_fill_ratio_max =
+            snapshot.outbound_queue_depth_max as f64 / snapshot.broadcast_capacity as f64;
+    }
+    snapshot
+}
+
+/// Saturation gauges in the Prometheus text format
+pub(crate) async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = saturation_snapshot(&state).await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        snapshot.to_prometheus(&state.saturation_metrics),
+    )
+}
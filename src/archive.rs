@@ -0,0 +1,188 @@
+use crate::trash::notify_rooms_list;
+use crate::{
+    auth, check_room_owner, flush_room, get_stored_content, visibility, AppState, CustomError,
+    DEFAULT_ROOM,
+};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+/// What joining an archived room does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ArchivedJoin {
+    /// The client is refused until the room is unarchived
+    #[default]
+    Reject,
+    /// The room is unarchived and loaded as usual
+    Unarchive,
+}
+
+impl ArchivedJoin {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "unarchive" => Some(Self::Unarchive),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a room is archived
+pub(crate) async fn is_archived(db: &SqlitePool, room_id: &str) -> bool {
+    match sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM rooms WHERE room_id = ? AND archived AND deleted_at IS NULL",
+    )
+    .bind(room_id)
+    .fetch_one(db)
+    .await
+    {
+        Ok(count) => count > 0,
+        Err(e) => {
+            eprintln!("Failed to read room archive state from database: {e}");
+            false
+        }
+    }
+}
+
+/// Take a room out of the archive, returning whether it was archived.
+/// It is loaded again by the next user joining it.
+pub(crate) async fn unarchive(db: &SqlitePool, room_id: &str) -> Result<bool> {
+    let unarchived = sqlx::query(
+        "UPDATE rooms SET archived = FALSE WHERE room_id = ? AND archived AND deleted_at IS NULL",
+    )
+    .bind(room_id)
+    .execute(db)
+    .await?
+    .rows_affected();
+    Ok(unarchived > 0)
+}
+
+/// `POST /api/rooms/:room_id/archive`, move an unused room out of memory, its content
+/// staying in the database
+pub(crate) async fn archive_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if room_id == DEFAULT_ROOM {
+        return Err(CustomError::bad_request(
+            "The default room can't be archived.",
+        ));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+
+    let mut rooms = state.rooms.lock().await;
+    if is_archived(&state.db, &room_id).await {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            "This room is already archived.",
+        ));
+    }
+    match rooms.get(&room_id) {
+        Some(room) => {
+            if !room.users.lock().await.is_empty() {
+                return Err(CustomError::new(
+                    StatusCode::CONFLICT,
+                    "Users are still in this room.",
+                ));
+            }
+            let room = rooms.remove(&room_id).expect("room is loaded");
+            room.shutdown();
+            flush_room(&state, &room_id, &room).await;
+        }
+        None => {
+            if get_stored_content(&state, &room_id).await.is_none() {
+                return Err(CustomError::not_found("Room not found."));
+            }
+        }
+    }
+    // Rooms never written to are stored too, so that their settings are archived with them
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, archived) VALUES (?, '', TRUE)
+        ON CONFLICT (room_id) DO UPDATE SET archived = TRUE
+        ",
+    )
+    .bind(&room_id)
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to archive room: {e}");
+        return Err(auth::internal_error());
+    }
+    println!("Room {room_id} archived");
+
+    if visibility::is_listed(&state.db, &room_id).await {
+        notify_rooms_list(&rooms);
+    }
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room archived."
+    })))
+}
+
+/// `POST /api/rooms/:room_id/unarchive`, take a room out of the archive
+pub(crate) async fn unarchive_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    check_room_owner(&state, &headers, &room_id).await?;
+
+    let rooms = state.rooms.lock().await;
+    match unarchive(&state.db, &room_id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(CustomError::not_found("Room not archived.")),
+        Err(e) => {
+            eprintln!("Failed to unarchive room: {e:#}");
+            return Err(auth::internal_error());
+        }
+    }
+    println!("Room {room_id} unarchived");
+
+    if visibility::is_listed(&state.db, &room_id).await {
+        notify_rooms_list(&rooms);
+    }
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room unarchived."
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_archived, unarchive, ArchivedJoin};
+    use crate::database;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ArchivedJoin::parse("reject"), Some(ArchivedJoin::Reject));
+        assert_eq!(
+            ArchivedJoin::parse("unarchive"),
+            Some(ArchivedJoin::Unarchive)
+        );
+        assert_eq!(ArchivedJoin::parse("restore"), None);
+    }
+
+    #[tokio::test]
+    async fn test_unarchive() {
+        let db = database::memory().await.unwrap();
+        sqlx::query("INSERT INTO rooms (room_id, content, archived) VALUES ('old', '', TRUE)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert!(is_archived(&db, "old").await);
+        assert!(unarchive(&db, "old").await.unwrap());
+        assert!(!is_archived(&db, "old").await);
+        assert!(!unarchive(&db, "old").await.unwrap());
+    }
+}
@@ -0,0 +1,476 @@
+use crate::metrics::to_hex;
+use crate::mirror::Mirror;
+use crate::unix_timestamp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+use ts_rs::TS;
+
+/// Unreferenced blobs are kept this long, so an upload in progress isn't collected
+pub(crate) const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+const MAX_FILENAME_LENGTH: usize = 255;
+
+/// File attached to a room
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct Attachment {
+    pub(crate) id: i64,
+    pub(crate) room_id: String,
+    pub(crate) filename: String,
+    pub(crate) content_type: String,
+    pub(crate) size: i64,
+    /// SHA-256 of the content, shared by identical files
+    pub(crate) hash: String,
+    pub(crate) created_at: i64,
+}
+
+/// Outcome of a garbage collection
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct GcReport {
+    pub(crate) blobs_removed: u64,
+    pub(crate) bytes_freed: u64,
+}
+
+/// Content-addressable attachment storage.
+/// Blobs are written once on disk under their SHA-256 and shared by every attachment with
+/// the same content; a blob is referenced as long as an attachment row points to it.
+#[derive(Debug)]
+pub(crate) struct AttachmentStore {
+    dir: PathBuf,
+    db: SqlitePool,
+    /// Gets a copy of every blob, and gives back those missing on disk
+    mirror: Option<Mirror>,
+    /// Held for writing by the garbage collector, so it never races with an upload
+    gc_lock: RwLock<()>,
+}
+
+impl AttachmentStore {
+    pub(crate) fn new(dir: impl Into<PathBuf>, db: SqlitePool, mirror: Option<Mirror>) -> Self {
+        Self {
+            dir: dir.into(),
+            db,
+            mirror,
+            gc_lock: RwLock::new(()),
+        }
+    }
+
+    /// Path of a blob, sharded by the first byte of its hash
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(hash)
+    }
+
+    /// Store a blob if it isn't already, returning its hash.
+    /// Must be called with `gc_lock` held for reading.
+    async fn put_blob(&self, bytes: &[u8]) -> Result<String> {
+        let hash = to_hex(&Sha256::digest(bytes));
+        let path = self.blob_path(&hash);
+
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            write_atomically(&path, bytes).await?;
+            if let Some(mirror) = &self.mirror {
+                mirror.blob(&hash, bytes);
+            }
+        }
+
+        // Refreshing `last_used_at` protects the blob until the attachment row is written
+        sqlx::query(
+            r"
+            INSERT INTO blobs (hash, size, last_used_at) VALUES (?, ?, ?)
+            ON CONFLICT (hash) DO UPDATE SET last_used_at = excluded.last_used_at
+            ",
+        )
+        .bind(&hash)
+        .bind(i64::try_from(bytes.len()).unwrap_or(i64::MAX))
+        .bind(unix_timestamp())
+        .execute(&self.db)
+        .await?;
+
+        Ok(hash)
+    }
+
+    /// Attach a file to a room
+    pub(crate) async fn attach(
+        &self,
+        room_id: &str,
+        filename: &str,
+        content_type: &str,
+        bytes: &[u8],
+    ) -> Result<Attachment> {
+        let upload = self.gc_lock.read().await;
+        let hash = self.put_blob(bytes).await?;
+        let created_at = unix_timestamp();
+
+        let id = sqlx::query(
+            r"
+            INSERT INTO attachments (room_id, filename, content_type, blob_hash, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(room_id)
+        .bind(filename)
+        .bind(content_type)
+        .bind(&hash)
+        .bind(created_at)
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid();
+        drop(upload);
+
+        Ok(Attachment {
+            id,
+            room_id: room_id.to_string(),
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            size: i64::try_from(bytes.len()).unwrap_or(i64::MAX),
+            hash,
+            created_at,
+        })
+    }
+
+    /// Get an attachment of a room, with its content
+    pub(crate) async fn get(
+        &self,
+        room_id: &str,
+        id: i64,
+    ) -> Result<Option<(Attachment, Vec<u8>)>> {
+        let row = sqlx::query_as::<_, (String, String, i64, String, i64)>(
+            r"
+            SELECT attachments.filename, attachments.content_type, blobs.size,
+                   attachments.blob_hash, attachments.created_at
+            FROM attachments JOIN blobs ON blobs.hash = attachments.blob_hash
+            WHERE attachments.room_id = ? AND attachments.id = ?
+            ",
+        )
+        .bind(room_id)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let Some((filename, content_type, size, hash, created_at)) = row else {
+            return Ok(None);
+        };
+        let bytes = self.read_blob(&hash).await?;
+
+        Ok(Some((
+            Attachment {
+                id,
+                room_id: room_id.to_string(),
+                filename,
+                content_type,
+                size,
+                hash,
+                created_at,
+            },
+            bytes,
+        )))
+    }
+
+    /// Content of a blob, downloaded from the mirror if it is missing on disk
+    async fn read_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        let path = self.blob_path(hash);
+        let error = match fs::read(&path).await {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) => e,
+        };
+        if error.kind() != std::io::ErrorKind::NotFound {
+            return Err(error).with_context(|| format!("Failed to read blob {hash}"));
+        }
+        let Some(mirror) = &self.mirror else {
+            return Err(error).with_context(|| format!("Missing blob {hash}"));
+        };
+        let bytes = mirror
+            .get_blob(hash)
+            .await?
+            .with_context(|| format!("Missing blob {hash}"))?;
+        // Kept on disk for the next downloads
+        if let Err(e) = write_atomically(&path, &bytes).await {
+            eprintln!("Failed to restore blob {hash} from the mirror: {e:#}");
+        }
+        Ok(bytes)
+    }
+
+    /// Total size of the files attached to a room, counting shared blobs once per attachment
+    pub(crate) async fn room_usage(&self, room_id: &str) -> Result<u64> {
+        let size: i64 = sqlx::query_scalar(
+            r"
+            SELECT COALESCE(SUM(blobs.size), 0)
+            FROM attachments JOIN blobs ON blobs.hash = attachments.blob_hash
+            WHERE attachments.room_id = ?
+            ",
+        )
+        .bind(room_id)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(u64::try_from(size).unwrap_or(0))
+    }
+
+    /// Move the attachments of a room to another one
+    pub(crate) async fn move_room(&self, from: &str, to: &str) -> Result<u64> {
+        Ok(
+            sqlx::query("UPDATE attachments SET room_id = ? WHERE room_id = ?")
+                .bind(to)
+                .bind(from)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    /// Drop the attachments of a removed room, their blobs are left to the garbage collector
+    pub(crate) async fn remove_room(&self, room_id: &str) -> Result<u64> {
+        Ok(sqlx::query("DELETE FROM attachments WHERE room_id = ?")
+            .bind(room_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Hashes and sizes of the blobs no attachment refers to anymore, unused since `cutoff`
+    async fn unreferenced(&self, cutoff: i64) -> Result<Vec<(String, i64)>> {
+        Ok(sqlx::query_as(
+            r"
+            SELECT hash, size FROM blobs
+            WHERE last_used_at <= ?
+            AND NOT EXISTS (SELECT 1 FROM attachments WHERE attachments.blob_hash = blobs.hash)
+            ",
+        )
+        .bind(cutoff)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Hashes and sizes of the blobs the next garbage collection would delete
+    pub(crate) async fn garbage(&self, grace_period: Duration) -> Result<Vec<(String, i64)>> {
+        self.unreferenced(gc_cutoff(grace_period)).await
+    }
+
+    /// Delete the blobs no attachment refers to anymore
+    pub(crate) async fn collect_garbage(&self, grace_period: Duration) -> Result<GcReport> {
+        let cutoff = gc_cutoff(grace_period);
+        let mut report = GcReport::default();
+        let gc = self.gc_lock.write().await;
+
+        for (hash, size) in self.unreferenced(cutoff).await? {
+            let deleted = sqlx::query(
+                r"
+                DELETE FROM blobs WHERE hash = ? AND last_used_at <= ?
+                AND NOT EXISTS (SELECT 1 FROM attachments WHERE attachments.blob_hash = blobs.hash)
+                ",
+            )
+            .bind(&hash)
+            .bind(cutoff)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+            if deleted == 0 {
+                continue;
+            }
+
+            if let Some(mirror) = &self.mirror {
+                mirror.remove_blob(&hash);
+            }
+            match fs::remove_file(self.blob_path(&hash)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Failed to remove blob {hash}: {e}"),
+            }
+            report.blobs_removed += 1;
+            report.bytes_freed += u64::try_from(size).unwrap_or(0);
+        }
+        drop(gc);
+
+        Ok(report)
+    }
+}
+
+/// Blobs unused since this timestamp are past the grace period
+fn gc_cutoff(grace_period: Duration) -> i64 {
+    unix_timestamp() - i64::try_from(grace_period.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// Name under which an uploaded file is stored: no directories, no control characters
+pub(crate) fn sanitize_filename(filename: &str) -> String {
+    let name: String = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME_LENGTH)
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "file".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Write a file through a temporary file, so a crash never leaves a truncated blob behind
+async fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = path.parent().context("Blob path has no parent")?;
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let tmp = path.with_extension(format!("tmp-{}", crate::auth::generate_token()));
+    fs::write(&tmp, bytes)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    if let Err(e) = fs::rename(&tmp, path).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sanitize_filename, AttachmentStore};
+    use crate::database;
+    use crate::mirror::Mirror;
+    use object_store::memory::InMemory;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::time::Duration;
+
+    async fn setup_store() -> (AttachmentStore, PathBuf) {
+        let db = database::memory().await.unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "partage-attachments-{}",
+            crate::auth::generate_token()
+        ));
+        (AttachmentStore::new(&dir, db, None), dir)
+    }
+
+    #[tokio::test]
+    async fn test_deduplication() {
+        let (store, dir) = setup_store().await;
+
+        let first = store
+            .attach("room_a", "screenshot.png", "image/png", b"pixels")
+            .await
+            .unwrap();
+        let second = store
+            .attach("room_b", "copy.png", "image/png", b"pixels")
+            .await
+            .unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.hash, second.hash);
+
+        // A single blob on disk
+        let shard = dir.join(&first.hash[..2]);
+        assert_eq!(std::fs::read_dir(&shard).unwrap().count(), 1);
+
+        let (attachment, bytes) = store.get("room_b", second.id).await.unwrap().unwrap();
+        assert_eq!(attachment.filename, "copy.png");
+        assert_eq!(bytes, b"pixels");
+
+        // Attachments are scoped to their room
+        assert!(store.get("room_a", second.id).await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_room_usage() {
+        let (store, dir) = setup_store().await;
+
+        assert_eq!(store.room_usage("room_a").await.unwrap(), 0);
+        store
+            .attach("room_a", "a.txt", "text/plain", b"shared")
+            .await
+            .unwrap();
+        store
+            .attach("room_a", "b.txt", "text/plain", b"shared")
+            .await
+            .unwrap();
+        store
+            .attach("room_b", "c.txt", "text/plain", b"other room")
+            .await
+            .unwrap();
+        assert_eq!(store.room_usage("room_a").await.unwrap(), 12);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("notes.txt"), "notes.txt");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\cv.pdf"), "cv.pdf");
+        assert_eq!(sanitize_filename("evil\r\nname"), "evilname");
+        assert_eq!(sanitize_filename(".."), "file");
+        assert_eq!(sanitize_filename(""), "file");
+        assert_eq!(sanitize_filename(&"a".repeat(300)).len(), 255);
+    }
+
+    #[tokio::test]
+    async fn test_garbage_collection() {
+        let (store, dir) = setup_store().await;
+
+        let shared = store
+            .attach("room_a", "a.txt", "text/plain", b"shared")
+            .await
+            .unwrap();
+        store
+            .attach("room_b", "b.txt", "text/plain", b"shared")
+            .await
+            .unwrap();
+        let own = store
+            .attach("room_a", "c.txt", "text/plain", b"only a")
+            .await
+            .unwrap();
+
+        // Recently used blobs are kept
+        assert_eq!(store.remove_room("room_a").await.unwrap(), 2);
+        let report = store
+            .collect_garbage(Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(report.blobs_removed, 0);
+
+        // The shared blob is still referenced by room_b
+        assert_eq!(
+            store.garbage(Duration::ZERO).await.unwrap(),
+            [(own.hash.clone(), 6)]
+        );
+        let report = store.collect_garbage(Duration::ZERO).await.unwrap();
+        assert_eq!(report.blobs_removed, 1);
+        assert_eq!(report.bytes_freed, 6);
+        assert!(dir.join(&shared.hash[..2]).join(&shared.hash).exists());
+        assert!(!dir.join(&own.hash[..2]).join(&own.hash).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    #[tokio::test]
+    async fn test_missing_blobs_come_back_from_the_mirror() {
+        let (store, dir) = setup_store().await;
+        let store = AttachmentStore {
+            mirror: Some(Mirror::spawn(Arc::new(InMemory::new()))),
+            ..store
+        };
+        let attachment = store
+            .attach("room", "notes.txt", "text/plain", b"notes")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The disk is gone
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (_, bytes) = store.get("room", attachment.id).await.unwrap().unwrap();
+        assert_eq!(bytes, b"notes");
+        assert!(dir
+            .join(&attachment.hash[..2])
+            .join(&attachment.hash)
+            .exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::authenticator::{Handshake, Identity};
+use crate::config::Config;
+use crate::metrics::to_hex;
+use crate::username;
+use crate::{unix_timestamp, AppState, CustomError};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Name of the session cookie
+pub(crate) const SESSION_COOKIE: &str = "partage_session";
+
+/// Sessions expire after 30 days
+pub(crate) const SESSION_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 1024;
+
+/// Authenticated account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AuthUser {
+    pub(crate) id: i64,
+    pub(crate) username: String,
+}
+
+/// Body of the register and login endpoints
+#[derive(TS, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub(crate) struct Credentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+/// Account of the current user
+#[derive(TS, Serialize, Deserialize, Debug)]
+#[ts(export)]
+pub(crate) struct Account {
+    pub(crate) username: String,
+}
+
+/// Routes nested under `/api/auth`
+pub(crate) fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/register", post(register))
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/me", get(me))
+        .nest("/oidc", crate::oidc::router())
+}
+
+/// Resolve the account of a request, anonymous when its authenticator failed
+pub(crate) async fn current_user(state: &AppState, headers: &HeaderMap) -> Option<AuthUser> {
+    authenticate(state, headers).await.unwrap_or_else(|e| {
+        eprintln!("Failed to authenticate request: {e:#}");
+        None
+    })
+}
+
+/// Resolve the account of a request from its session cookie or bearer token, or else from
+/// the authenticator of the server
+pub(crate) async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> anyhow::Result<Option<AuthUser>> {
+    if let Some(user) = session_user(state, headers).await {
+        return Ok(Some(user));
+    }
+    match state
+        .authenticator
+        .authenticate(Handshake { headers })
+        .await?
+    {
+        Identity::Anonymous => Ok(None),
+        Identity::Account(username) => find_or_create_user(&state.db, &username).await.map(Some),
+    }
+}
+
+/// The account of a name vouched for by the authenticator, created on first sight without a
+/// password so that it can only be used through the authenticator.
+/// Accounts are keyed on the name as given: a password or OIDC account already holding the
+/// username is refused rather than taken over.
+async fn find_or_create_user(db: &SqlitePool, name: &str) -> anyhow::Result<AuthUser> {
+    let existing = sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, username FROM users WHERE external_name = ?",
+    )
+    .bind(name)
+    .fetch_optional(db)
+    .await?;
+    if let Some((id, username)) = existing {
+        return Ok(AuthUser { id, username });
+    }
+
+    let username = username::sanitize(name).map_err(anyhow::Error::msg)?;
+    match sqlx::query(
+        "INSERT INTO users (username, password_hash, created_at, external_name) VALUES (?, '', ?, ?)",
+    )
+    .bind(&username)
+    .bind(unix_timestamp())
+    .bind(name)
+    .execute(db)
+    .await
+    {
+        Ok(result) => Ok(AuthUser {
+            id: result.last_insert_rowid(),
+            username,
+        }),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            anyhow::bail!("Username {username} already taken by another account")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The account of the session cookie or bearer token of a request
+async fn session_user(state: &AppState, headers: &HeaderMap) -> Option<AuthUser> {
+    let token = session_token(headers)?;
+
+    match sqlx::query_as::<_, (i64, String)>(
+        r"
+        SELECT users.id, users.username FROM auth_sessions
+        JOIN users ON users.id = auth_sessions.user_id
+        WHERE auth_sessions.token = ? AND auth_sessions.expires_at > ?
+        ",
+    )
+    .bind(token)
+    .bind(unix_timestamp())
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(user) => user.map(|(id, username)| AuthUser { id, username }),
+        Err(e) => {
+            eprintln!("Failed to read session from database: {e}");
+            None
+        }
+    }
+}
+
+/// The names among `usernames` that belong to accounts, anonymous users can't take them
+pub(crate) async fn registered(
+    state: &AppState,
+    usernames: &[String],
+) -> sqlx::Result<HashSet<String>> {
+    let registered = sqlx::query_scalar::<_, String>(
+        "SELECT username FROM users WHERE username IN (SELECT value FROM json_each(?))",
+    )
+    .bind(serde_json::json!(usernames).to_string())
+    .fetch_all(&state.db)
+    .await?;
+    Ok(registered.into_iter().collect())
+}
+
+/// Extract the session token from the `Authorization: Bearer` header or the session cookie
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim().to_string());
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then(|| value.to_string())
+        })
+}
+
+/// Cookie of a session, sent back to the app only, under `BASE_PATH`, and over TLS only when
+/// the server serves it
+pub(crate) fn session_cookie(config: &Config, token: &str, max_age: i64) -> String {
+    let path = if config.base_path.is_empty() {
+        "/"
+    } else {
+        &config.base_path
+    };
+    let secure = if config.tls.is_some() { "; Secure" } else { "" };
+    format!(
+        "{SESSION_COOKIE}={token}; Path={path}; HttpOnly; SameSite=Lax; Max-Age={max_age}{secure}"
+    )
+}
+
+pub(crate) fn internal_error() -> CustomError {
+    CustomError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal error.")
+}
+
+pub(crate) fn validate_credentials(username: &str, password: &str) -> Result<(), CustomError> {
+    // Account names are taken as they are, not sanitized behind the user's back
+    match username::sanitize(username) {
+        Ok(sanitized) if sanitized == username => {}
+        Ok(_) => return Err(CustomError::bad_request("Invalid username.")),
+        Err(e) => return Err(CustomError::bad_request(e)),
+    }
+    if password.len() < MIN_PASSWORD_LENGTH || password.len() > MAX_PASSWORD_LENGTH {
+        return Err(CustomError::bad_request(format!(
+            "Password must be between {MIN_PASSWORD_LENGTH} and {MAX_PASSWORD_LENGTH} bytes."
+        )));
+    }
+    Ok(())
+}
+
+/// Hash a password with argon2, off the async runtime
+pub(crate) async fn hash_password(password: String) -> Result<String, CustomError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .ok()
+    .and_then(Result::ok)
+    .ok_or_else(internal_error)
+}
+
+/// Check a password against its argon2 hash, off the async runtime
+pub(crate) async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Random token suitable for sessions and CSRF protection
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Create a session for a user, returning its token
+pub(crate) async fn create_session(db: &SqlitePool, user_id: i64) -> Result<String, CustomError> {
+    let token = generate_token();
+
+    let now = unix_timestamp();
+    let expires_at = now + SESSION_TTL_SECS;
+
+    // Good time to forget expired sessions
+    let _ = sqlx::query!("DELETE FROM auth_sessions WHERE expires_at <= ?", now)
+        .execute(db)
+        .await;
+
+    sqlx::query!(
+        "INSERT INTO auth_sessions (token, user_id, expires_at) VALUES (?, ?, ?)",
+        token,
+        user_id,
+        expires_at
+    )
+    .execute(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to create session: {e}");
+        internal_error()
+    })?;
+
+    Ok(token)
+}
+
+/// Create an account and log it in
+async fn register(
+    State(state): State<Arc<AppState>>,
+    Json(credentials): Json<Credentials>,
+) -> Result<Response, CustomError> {
+    let db = &state.db;
+    let username = credentials.username.trim().to_string();
+    validate_credentials(&username, &credentials.password)?;
+
+    let password_hash = hash_password(credentials.password).await?;
+    let now = unix_timestamp();
+
+    let user_id = match sqlx::query!(
+        "INSERT INTO users (username, password_hash, created_at) VALUES (?, ?, ?)",
+        username,
+        password_hash,
+        now
+    )
+    .execute(db)
+    .await
+    {
+        Ok(result) => result.last_insert_rowid(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(CustomError::new(
+                StatusCode::CONFLICT,
+                "Username already taken.",
+            ));
+        }
+        Err(e) => {
+            eprintln!("Failed to create user: {e}");
+            return Err(internal_error());
+        }
+    };
+
+    println!("Account created: {username}");
+
+    let token = create_session(db, user_id).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(
+            header::SET_COOKIE,
+            session_cookie(&state.config, &token, SESSION_TTL_SECS),
+        )],
+        Json(Account { username }),
+    )
+        .into_response())
+}
+
+/// Log in with a username and a password
+async fn login(
+    State(state): State<Arc<AppState>>,
+    Json(credentials): Json<Credentials>,
+) -> Result<Response, CustomError> {
+    let db = &state.db;
+
+    let user = sqlx::query_as::<_, (i64, String, String)>(
+        "SELECT id, username, password_hash FROM users WHERE username = ?",
+    )
+    .bind(credentials.username.trim())
+    .fetch_optional(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to read user from database: {e}");
+        internal_error()
+    })?;
+
+    let Some((user_id, username, password_hash)) = user else {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid username or password.",
+        ));
+    };
+    if !verify_password(credentials.password, password_hash).await {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid username or password.",
+        ));
+    }
+
+    let token = create_session(db, user_id).await?;
+    Ok((
+        [(
+            header::SET_COOKIE,
+            session_cookie(&state.config, &token, SESSION_TTL_SECS),
+        )],
+        Json(Account { username }),
+    )
+        .into_response())
+}
+
+/// Log out, invalidating the current session
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    let db = &state.db;
+
+    if let Some(token) = session_token(&headers) {
+        sqlx::query!("DELETE FROM auth_sessions WHERE token = ?", token)
+            .execute(db)
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to delete session: {e}");
+                internal_error()
+            })?;
+    }
+
+    Ok((
+        [(header::SET_COOKIE, session_cookie(&state.config, "", 0))],
+        Json(serde_json::json!({ "type": "success", "value": "Logged out." })),
+    )
+        .into_response())
+}
+
+/// Get the account of the current user
+async fn me(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Account>, CustomError> {
+    current_user(&state, &headers)
+        .await
+        .map(|user| {
+            Json(Account {
+                username: user.username,
+            })
+        })
+        .ok_or_else(|| CustomError::new(StatusCode::UNAUTHORIZED, "Not logged in."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_or_create_user, session_cookie, session_token, validate_credentials, SESSION_COOKIE,
+    };
+    use crate::config::Config;
+    use crate::database;
+    use crate::tls::TlsConfig;
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    #[test]
+    fn test_session_cookie() {
+        let cookie = session_cookie(&Config::default(), "abc", 60);
+        assert_eq!(
+            cookie,
+            format!("{SESSION_COOKIE}=abc; Path=/; HttpOnly; SameSite=Lax; Max-Age=60")
+        );
+
+        let config = Config {
+            base_path: "/partage".to_string(),
+            tls: Some(TlsConfig {
+                cert_path: "cert.pem".into(),
+                key_path: "key.pem".into(),
+            }),
+            ..Config::default()
+        };
+        let cookie = session_cookie(&config, "abc", 60);
+        assert!(cookie.contains("; Path=/partage;"));
+        assert!(cookie.ends_with("; Secure"));
+    }
+
+    #[tokio::test]
+    async fn test_find_or_create_user() {
+        let db = database::memory().await.unwrap();
+        let alice = find_or_create_user(&db, "alice").await.unwrap();
+        assert_eq!(alice.username, "alice");
+        assert_eq!(find_or_create_user(&db, "alice").await.unwrap(), alice);
+
+        // Names held by other accounts aren't taken over
+        sqlx::query(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', 0)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert!(find_or_create_user(&db, "bob").await.is_err());
+        assert!(find_or_create_user(&db, " alice").await.is_err());
+    }
+
+    #[test]
+    fn test_session_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_token(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; {SESSION_COOKIE}=abc123")).unwrap(),
+        );
+        assert_eq!(session_token(&headers).as_deref(), Some("abc123"));
+
+        // Bearer tokens take precedence
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer def456"),
+        );
+        assert_eq!(session_token(&headers).as_deref(), Some("def456"));
+    }
+
+    #[test]
+    fn test_validate_credentials() {
+        assert!(validate_credentials("alice", "correct horse").is_ok());
+        assert!(validate_credentials("", "correct horse").is_err());
+        assert!(validate_credentials(&"a".repeat(33), "correct horse").is_err());
+        assert!(validate_credentials("Server", "correct horse").is_err());
+        assert!(validate_credentials("alice", "short").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+
+/// The request a client opens its socket or calls the API with
+#[derive(Debug, Clone, Copy)]
+pub struct Handshake<'a> {
+    pub headers: &'a HeaderMap,
+}
+
+/// Who a client is, according to an [`Authenticator`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    /// No account, the client picks a name on joining
+    Anonymous,
+    /// The account of this username, created on its first request
+    Account(String),
+}
+
+/// Hook telling who the clients without a partage session are, such as the users an LDAP
+/// server or the headers of an authenticating proxy vouch for.
+///
+/// ```
+/// use partage::{Authenticator, Handshake, Identity};
+///
+/// struct ProxyHeader;
+///
+/// #[async_trait::async_trait]
+/// impl Authenticator for ProxyHeader {
+///     async fn authenticate(&self, handshake: Handshake<'_>) -> anyhow::Result<Identity> {
+///         Ok(handshake
+///             .headers
+///             .get("x-forwarded-user")
+///             .and_then(|user| user.to_str().ok())
+///             .map_or(Identity::Anonymous, |user| Identity::Account(user.to_string())))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// The identity of the client of `handshake`. An error refuses its socket, its API calls
+    /// being made anonymously.
+    async fn authenticate(&self, handshake: Handshake<'_>) -> Result<Identity>;
+}
+
+/// The default authenticator: clients are anonymous unless logged in to a partage account
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Anonymous;
+
+#[async_trait]
+impl Authenticator for Anonymous {
+    async fn authenticate(&self, _handshake: Handshake<'_>) -> Result<Identity> {
+        Ok(Identity::Anonymous)
+    }
+}
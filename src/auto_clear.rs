@@ -0,0 +1,235 @@
+use crate::{
+    check_room_owner, ensure_room_loaded, revisions, AppState, CustomError, RoomState,
+    SocketMessage, SocketMessageType,
+};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tokio::time::{self, Duration, Instant};
+use ts_rs::TS;
+
+/// Longest delay before clearing, a week
+const MAX_AUTO_CLEAR_MINUTES: u32 = 7 * 24 * 60;
+
+/// Clearing of the content of a room once nobody edited it for a while,
+/// for public pads that shouldn't keep the text of their last visitor
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct AutoClear {
+    /// Minutes without edits after which the content is cleared, `None` keeps it
+    pub(crate) minutes: Option<u32>,
+}
+
+impl AutoClear {
+    fn validate(self) -> Result<(), String> {
+        match self.minutes {
+            Some(minutes) if minutes == 0 || minutes > MAX_AUTO_CLEAR_MINUTES => Err(format!(
+                "The content can be cleared after 1 to {MAX_AUTO_CLEAR_MINUTES} minutes."
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn delay(self) -> Option<Duration> {
+        self.minutes
+            .map(|minutes| Duration::from_secs(u64::from(minutes) * 60))
+    }
+}
+
+/// Get the auto-clear delay of a room stored in the database
+pub(crate) async fn load(db: &SqlitePool, room_id: &str) -> AutoClear {
+    let minutes = sqlx::query_scalar::<_, Option<u32>>(
+        "SELECT auto_clear_minutes FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to read room auto-clear from database: {e}");
+        None
+    })
+    .flatten();
+    AutoClear { minutes }
+}
+
+impl RoomState {
+    /// Set the auto-clear delay of a room that has no members yet
+    pub(crate) fn with_auto_clear(mut self, auto_clear: AutoClear) -> Self {
+        self.auto_clear = tokio::sync::Mutex::new(auto_clear);
+        self
+    }
+
+    /// Whether the content is due to be cleared
+    async fn is_stale(&self) -> bool {
+        let auto_clear = *self.auto_clear.lock().await;
+        let Some(delay) = auto_clear.delay() else {
+            return false;
+        };
+        let idle = self.last_edit.lock().await.elapsed();
+        idle >= delay && !self.is_empty().await
+    }
+
+    /// Whether the room holds no content at all, in any document
+    pub(crate) async fn is_empty(&self) -> bool {
+        let main_empty = self.content_rx.borrow().is_empty();
+        main_empty
+            && self
+                .documents
+                .lock()
+                .await
+                .values()
+                .all(|document| document.content_rx.borrow().is_empty())
+    }
+
+    /// Bytes the main content and the documents of the room take
+    pub(crate) async fn contents_size(&self) -> usize {
+        let main = self.content_rx.borrow().len();
+        main + self
+            .documents
+            .lock()
+            .await
+            .values()
+            .map(|document| document.content_rx.borrow().len())
+            .sum::<usize>()
+    }
+
+    /// Empty every document of the room and send the empty contents to its members
+    pub(crate) async fn clear(&self) {
+        let encrypted = self.encryption.is_some();
+        let message = |doc_id: Option<String>, revision: u64| {
+            json!(SocketMessage {
+                doc_id,
+                message_type: SocketMessageType::content(encrypted),
+                value: Some(String::new()),
+                code: None,
+                revision: Some(revision),
+                username: "Server".to_string(),
+            })
+            .to_string()
+        };
+
+        self.content_tx.send_replace(String::new());
+        let _ = self.tx.send(message(None, self.revision(None).await));
+        let doc_ids: Vec<String> = {
+            let documents = self.documents.lock().await;
+            for document in documents.values() {
+                document.content_tx.send_replace(String::new());
+            }
+            documents.keys().cloned().collect()
+        };
+        for doc_id in doc_ids {
+            let revision = self.revision(Some(&doc_id)).await;
+            let _ = self.tx.send(message(Some(doc_id), revision));
+        }
+        *self.last_edit.lock().await = Instant::now();
+    }
+}
+
+/// Clear the loaded rooms nobody edited for longer than their auto-clear delay, every minute.
+/// Rooms waiting to be cleared are never evicted, so this covers them all.
+pub(crate) async fn clear_stale_rooms(state: Arc<AppState>) {
+    let mut interval = time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        clear_stale_rooms_once(&state).await;
+    }
+}
+
+pub(crate) async fn clear_stale_rooms_once(state: &AppState) {
+    let rooms = state.rooms.lock().await;
+    for (room_id, room) in rooms.iter() {
+        if room.is_stale().await {
+            room.clear().await;
+            println!("Cleared the content of idle room {room_id}");
+        }
+    }
+    drop(rooms);
+}
+
+/// Get the auto-clear delay of a room
+pub(crate) async fn get_auto_clear(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let auto_clear = *rooms[&room_id].auto_clear.lock().await;
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": auto_clear
+    })))
+}
+
+/// Change the auto-clear delay of a room, counted from its last edit
+pub(crate) async fn set_auto_clear(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(auto_clear): Json<AutoClear>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    auto_clear.validate().map_err(CustomError::bad_request)?;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    let room = &rooms[&room_id];
+
+    let content = room.content_rx.borrow().clone();
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, auto_clear_minutes) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET auto_clear_minutes = excluded.auto_clear_minutes
+        ",
+    )
+    .bind(&room_id)
+    .bind(content)
+    .bind(auto_clear.minutes)
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store room auto-clear in database: {e}");
+        return Err(CustomError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to store auto-clear.",
+        ));
+    }
+
+    *room.auto_clear.lock().await = auto_clear;
+    drop(rooms);
+
+    println!("Updated auto-clear of room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": auto_clear
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoClear, MAX_AUTO_CLEAR_MINUTES};
+
+    #[test]
+    fn test_validate() {
+        assert!(AutoClear { minutes: None }.validate().is_ok());
+        assert!(AutoClear { minutes: Some(1) }.validate().is_ok());
+        assert!(AutoClear { minutes: Some(0) }.validate().is_err());
+        assert!(AutoClear {
+            minutes: Some(MAX_AUTO_CLEAR_MINUTES + 1)
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{auth, unix_timestamp, AppState, CustomError};
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use tokio_util::io::ReaderStream;
+
+const BACKUP_PREFIX: &str = "partage-";
+const BACKUP_EXTENSION: &str = ".db";
+
+/// Write a consistent copy of the database to `path`, which must not exist yet.
+/// Writes keep going while it runs, the copy is a snapshot of when it started.
+pub(crate) async fn write_backup(db: &SqlitePool, path: &Path) -> Result<()> {
+    let path = path.to_str().context("Backup path is not valid UTF-8")?;
+    sqlx::query("VACUUM INTO ?").bind(path).execute(db).await?;
+    Ok(())
+}
+
+fn backup_file_name(timestamp: i64) -> String {
+    format!("{BACKUP_PREFIX}{timestamp}{BACKUP_EXTENSION}")
+}
+
+/// Timestamp of a backup written by [`backup_to_dir`], from its file name
+fn backup_timestamp(file_name: &str) -> Option<i64> {
+    file_name
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(BACKUP_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+/// Backups found in a directory, oldest first
+async fn list_backups(dir: &Path) -> Result<Vec<(i64, PathBuf)>> {
+    let mut backups = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(timestamp) = entry.file_name().to_str().and_then(backup_timestamp) {
+            backups.push((timestamp, entry.path()));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// Write a timestamped backup to a directory, then remove the oldest ones past `keep`.
+/// `keep` 0 keeps them all.
+pub(crate) async fn backup_to_dir(
+    db: &SqlitePool,
+    dir: &Path,
+    keep: usize,
+    now: i64,
+) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    // Renamed once complete, a failed backup is never taken for a good one
+    let path = dir.join(backup_file_name(now));
+    let partial = dir.join(format!(".{}.partial", backup_file_name(now)));
+    let _ = tokio::fs::remove_file(&partial).await;
+    write_backup(db, &partial).await?;
+    tokio::fs::rename(&partial, &path).await?;
+
+    if keep > 0 {
+        let backups = list_backups(dir).await?;
+        for (_, old) in backups.iter().take(backups.len().saturating_sub(keep)) {
+            tokio::fs::remove_file(old)
+                .await
+                .with_context(|| format!("Failed to remove {}", old.display()))?;
+        }
+    }
+    Ok(path)
+}
+
+/// Back up the database to `dir` every `interval`, starting now
+pub(crate) async fn run_scheduled(
+    state: Arc<AppState>,
+    dir: PathBuf,
+    interval: Duration,
+    keep: usize,
+) {
+    let db = &state.db;
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        match backup_to_dir(db, &dir, keep, unix_timestamp()).await {
+            Ok(path) => println!("Database backed up to {}", path.display()),
+            Err(e) => eprintln!("Failed to back up the database: {e:#}"),
+        }
+    }
+}
+
+/// Download a backup of the whole database
+pub(crate) async fn download_backup(
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, CustomError> {
+    let db = &state.db;
+    let path = std::env::temp_dir().join(format!("partage-backup-{}.db", auth::generate_token()));
+    if let Err(e) = write_backup(db, &path).await {
+        eprintln!("Failed to back up the database: {e:#}");
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(auth::internal_error());
+    }
+
+    let file = tokio::fs::File::open(&path).await;
+    // Still readable through the open file where unlinking an open file is allowed
+    let _ = tokio::fs::remove_file(&path).await;
+    let file = file.map_err(|e| {
+        eprintln!("Failed to read database backup: {e}");
+        auth::internal_error()
+    })?;
+    let size = file.metadata().await.map(|metadata| metadata.len()).ok();
+
+    let filename = backup_file_name(unix_timestamp());
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response();
+    if let Some(size) = size {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, size.into());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backup_file_name, backup_timestamp, backup_to_dir, list_backups};
+    use crate::database;
+    use sqlx::SqlitePool;
+
+    #[test]
+    fn test_backup_timestamp() {
+        assert_eq!(
+            backup_timestamp(&backup_file_name(1_792_171_800)),
+            Some(1_792_171_800)
+        );
+        assert_eq!(backup_timestamp("partage-abc.db"), None);
+        assert_eq!(backup_timestamp(".partage-1.db.partial"), None);
+        assert_eq!(backup_timestamp("notes.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn test_backup_to_dir() {
+        let db = database::memory().await.unwrap();
+        sqlx::query("INSERT INTO rooms (room_id, content) VALUES ('notes', 'Hello')")
+            .execute(&db)
+            .await
+            .unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("partage-backups-{}", crate::auth::generate_token()));
+
+        for now in 1..=3 {
+            backup_to_dir(&db, &dir, 2, now).await.unwrap();
+        }
+        let backups = list_backups(&dir).await.unwrap();
+        assert_eq!(
+            backups.iter().map(|(now, _)| *now).collect::<Vec<_>>(),
+            [2, 3]
+        );
+
+        // Backups are usable databases
+        let backup = SqlitePool::connect(&format!("sqlite://{}", backups[1].1.display()))
+            .await
+            .unwrap();
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'notes'")
+                .fetch_one(&backup)
+                .await
+                .unwrap();
+        assert_eq!(content, "Hello");
+        backup.close().await;
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{bail, Result};
+
+/// Normalize the prefix the app is served under, `/partage/` becoming `/partage`.
+/// The root is the empty string.
+pub(crate) fn normalize(base_path: &str) -> Result<String> {
+    let base_path = base_path.trim().trim_end_matches('/');
+    if base_path.is_empty() {
+        return Ok(String::new());
+    }
+    if !base_path.starts_with('/') {
+        bail!("must start with /");
+    }
+    if base_path.contains(['?', '#', ':']) || base_path.contains("//") {
+        bail!("must be a plain path");
+    }
+    Ok(base_path.to_string())
+}
+
+/// Point the absolute URLs of the frontend page to `base_path`, and make it the base of the
+/// relative ones so that they resolve the same from every client-side route
+pub(crate) fn rewrite_index_html(html: &str, base_path: &str) -> String {
+    let mut html = html
+        .replace("href=\"/", &format!("href=\"{base_path}/"))
+        .replace("src=\"/", &format!("src=\"{base_path}/"))
+        // Protocol-relative URLs aren't ours
+        .replace(&format!("=\"{base_path}//"), "=\"//");
+    if let Some(head) = html.find("<head>") {
+        html.insert_str(
+            head + "<head>".len(),
+            &format!("<base href=\"{base_path}/\">"),
+        );
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, rewrite_index_html};
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("").unwrap(), "");
+        assert_eq!(normalize("/").unwrap(), "");
+        assert_eq!(normalize("/partage/").unwrap(), "/partage");
+        assert_eq!(normalize("/tools/partage").unwrap(), "/tools/partage");
+        assert!(normalize("partage").is_err());
+        assert!(normalize("/partage?x").is_err());
+        assert!(normalize("//partage").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_index_html() {
+        let html = r#"<html><head><link rel="icon" href="/favicon.ico"><script src="./assets/index.js"></script><script src="//cdn.example/x.js"></script></head></html>"#;
+        assert_eq!(
+            rewrite_index_html(html, "/partage"),
+            r#"<html><head><base href="/partage/"><link rel="icon" href="/partage/favicon.ico"><script src="./assets/index.js"></script><script src="//cdn.example/x.js"></script></head></html>"#
+        );
+        assert_eq!(
+            rewrite_index_html(html, ""),
+            r#"<html><head><base href="/"><link rel="icon" href="/favicon.ico"><script src="./assets/index.js"></script><script src="//cdn.example/x.js"></script></head></html>"#
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::announcements::{Announcement, Severity};
+use crate::api::{DryRunReport, FreezeStatus, Room, SyntaxLanguageRequest};
+use crate::attachments::Attachment;
+use crate::auth::{Account, Credentials};
+use crate::auto_clear::AutoClear;
+use crate::burn::{Burn, BurnAfter};
+use crate::checkpoints::{Checkpoint, CheckpointRequest};
+use crate::connections::ConnectionInfo;
+use crate::documents::DocumentInfo;
+use crate::encryption::EncryptionParams;
+use crate::error_code::ErrorCode;
+use crate::freeze::{FreezeSchedule, FreezeWindow, Weekday};
+use crate::history::HistoryVersion;
+use crate::ip_filter::{BanRequest, IpBan};
+use crate::language::{ContentKind, ContentLanguage};
+use crate::line_claims::LineRange;
+use crate::members::{MemberRequest, RoomMember, RoomRole};
+use crate::pins::{PinRequest, PinScope, RoomPin};
+use crate::protocol::{Capability, Hello};
+use crate::ranges::ContentRange;
+use crate::rename::RenameRequest;
+use crate::resume::ResumeInfo;
+use crate::search::SearchResult;
+use crate::sessions::StoredSession;
+use crate::stats::{RoomStats, ServerStats};
+use crate::trash::TrashedRoom;
+use crate::visibility::{RoomVisibility, Visibility, VisibilityRequest};
+use crate::webhooks::{RoomWebhook, WebhookRequest};
+use crate::ws::{Connect, Presence};
+use crate::{SocketMessage, SocketMessageType};
+use ts_rs::TS;
+
+/// Types whose committed bindings differ from the ones they generate, the committed ones
+/// being read when the tests are built, before the `#[ts(export)]` tests rewrite them
+macro_rules! stale_bindings {
+    ($($name:ident),* $(,)?) => {{
+        let mut stale = Vec::new();
+        $(
+            let committed = include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/client/src/bindings/",
+                stringify!($name),
+                ".ts"
+            ));
+            if <$name as TS>::export_to_string().unwrap() != committed {
+                stale.push(stringify!($name));
+            }
+        )*
+        stale
+    }};
+}
+
+/// The bindings are committed so that the client builds without the server, the
+/// `#[ts(export)]` tests write them and this one makes sure they were committed since
+#[test]
+fn test_bindings_are_current() {
+    let stale = stale_bindings![
+        Account,
+        Announcement,
+        Attachment,
+        AutoClear,
+        BanRequest,
+        Burn,
+        BurnAfter,
+        Capability,
+        Checkpoint,
+        CheckpointRequest,
+        Connect,
+        ConnectionInfo,
+        ContentKind,
+        ContentLanguage,
+        ContentRange,
+        Credentials,
+        DocumentInfo,
+        DryRunReport,
+        EncryptionParams,
+        ErrorCode,
+        FreezeSchedule,
+        FreezeStatus,
+        FreezeWindow,
+        Hello,
+        HistoryVersion,
+        IpBan,
+        LineRange,
+        MemberRequest,
+        PinRequest,
+        PinScope,
+        Presence,
+        RenameRequest,
+        ResumeInfo,
+        Room,
+        RoomMember,
+        RoomPin,
+        RoomRole,
+        RoomStats,
+        RoomVisibility,
+        RoomWebhook,
+        SearchResult,
+        ServerStats,
+        Severity,
+        SocketMessage,
+        SocketMessageType,
+        StoredSession,
+        SyntaxLanguageRequest,
+        TrashedRoom,
+        Visibility,
+        VisibilityRequest,
+        WebhookRequest,
+        Weekday,
+    ];
+    assert!(
+        stale.is_empty(),
+        "Outdated bindings in client/src/bindings: {stale:?}, commit the ones `cargo test` wrote"
+    );
+}
@@ -0,0 +1,358 @@
+use crate::{check_room_owner, ensure_room_loaded, revisions, AppState, CustomError, RoomState};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use ts_rs::TS;
+use zeroize::Zeroize;
+
+/// Event after which the content of a room is wiped
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub(crate) enum BurnAfter {
+    /// Read once: through `/r/:room_id/raw`, `/r/:room_id/html`, the content API, GraphQL, the
+    /// events, formatting or a download, or by a client that joined the room holding it, once
+    /// it leaves
+    Read,
+    /// Every member left the room
+    Leave,
+}
+
+impl BurnAfter {
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Leave => "leave",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(Self::Read),
+            "leave" => Some(Self::Leave),
+            _ => None,
+        }
+    }
+}
+
+/// Wiping of the content of a room once it was read, for sharing secrets and tokens that
+/// shouldn't stay on the server
+#[derive(TS, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct Burn {
+    /// `None` keeps the content
+    pub(crate) after: Option<BurnAfter>,
+}
+
+impl Burn {
+    /// The setting stored in the `burn_after` column
+    pub(crate) fn from_stored(stored: Option<&str>) -> Self {
+        Self {
+            after: stored.and_then(BurnAfter::parse),
+        }
+    }
+}
+
+/// Get the burn setting of a room stored in the database
+pub(crate) async fn load(db: &SqlitePool, room_id: &str) -> Burn {
+    let stored =
+        sqlx::query_scalar::<_, Option<String>>("SELECT burn_after FROM rooms WHERE room_id = ?")
+            .bind(room_id)
+            .fetch_optional(db)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read room burn setting from database: {e}");
+                None
+            })
+            .flatten();
+    Burn::from_stored(stored.as_deref())
+}
+
+impl RoomState {
+    /// Set the burn setting of a room that has no members yet
+    pub(crate) fn with_burn(mut self, burn: Burn) -> Self {
+        self.burn = tokio::sync::Mutex::new(burn);
+        self
+    }
+
+    /// Whether the content is wiped after `event`
+    pub(crate) async fn burns_after(&self, event: BurnAfter) -> bool {
+        self.burn.lock().await.after == Some(event)
+    }
+
+    /// Whether the room was already read and wiped, the next readers don't find it
+    pub(crate) async fn burned(&self) -> bool {
+        self.burns_after(BurnAfter::Read).await && self.is_empty().await
+    }
+
+    /// Wipe the room if it burns after reading, once its contents were given to a reader
+    pub(crate) async fn burn_on_read(&self, state: &AppState, room_id: &str) {
+        if self.burns_after(BurnAfter::Read).await && !self.is_empty().await {
+            self.burn(state, room_id).await;
+        }
+    }
+
+    /// Main content for a reader, the room is wiped right after if it burns after reading
+    pub(crate) async fn read_content(
+        &self,
+        state: &AppState,
+        room_id: &str,
+    ) -> Result<String, CustomError> {
+        if self.burned().await {
+            return Err(CustomError::not_found("Room not found."));
+        }
+        let content = self.content_rx.borrow().clone();
+        self.burn_on_read(state, room_id).await;
+        Ok(content)
+    }
+
+    /// Wipe every document of the room, overwriting their contents in memory, in its storage
+    /// and in the database, and send the empty contents to its members
+    pub(crate) async fn burn(&self, state: &AppState, room_id: &str) {
+        self.content_tx.send_replace(String::new()).zeroize();
+        let doc_ids: Vec<String> = {
+            let documents = self.documents.lock().await;
+            for document in documents.values() {
+                document.content_tx.send_replace(String::new()).zeroize();
+            }
+            documents.keys().cloned().collect()
+        };
+        self.clear().await;
+        self.forget_operations().await;
+
+        // A flush may be writing the old contents, they are wiped once written
+        let paused = state.write_behind.pause().await;
+        if let Err(e) = state.storage.wipe_room(room_id).await {
+            eprintln!("Failed to wipe room {room_id} from storage: {e:#}");
+        }
+        if let Err(e) = wipe_stored(&state.db, room_id).await {
+            eprintln!("Failed to wipe room {room_id} from database: {e:#}");
+        }
+        drop(paused);
+        if let Some(mirror) = &state.mirror {
+            let empty = String::new();
+            mirror.snapshot_room(room_id, "", doc_ids.iter().map(|doc_id| (doc_id, &empty)));
+        }
+        println!("Burned the content of room {room_id}");
+    }
+}
+
+/// Empty the copy of the content a room got its settings stored with, its saved versions and
+/// checkpoints
+async fn wipe_stored(db: &SqlitePool, room_id: &str) -> Result<()> {
+    wipe(
+        db,
+        room_id,
+        &[
+            "UPDATE rooms SET content = '', content_zstd = NULL, compressed = FALSE WHERE room_id = ?",
+            "DELETE FROM room_history WHERE room_id = ?",
+            "DELETE FROM room_checkpoints WHERE room_id = ?",
+        ],
+    )
+    .await
+}
+
+/// Run `statements` for a room, with SQLite overwriting the freed space rather than leaving
+/// the old contents in the file
+pub(crate) async fn wipe(db: &SqlitePool, room_id: &str, statements: &[&str]) -> Result<()> {
+    let mut connection = db.acquire().await?;
+    sqlx::query("PRAGMA secure_delete = ON")
+        .execute(&mut *connection)
+        .await?;
+    let wiped = async {
+        for statement in statements {
+            sqlx::query(statement)
+                .bind(room_id)
+                .execute(&mut *connection)
+                .await?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    sqlx::query("PRAGMA secure_delete = OFF")
+        .execute(&mut *connection)
+        .await?;
+    wiped
+}
+
+/// Get the burn setting of a room
+pub(crate) async fn get_burn(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let burn = *rooms[&room_id].burn.lock().await;
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": burn
+    })))
+}
+
+/// Change the burn setting of a room
+pub(crate) async fn set_burn(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(burn): Json<Burn>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    let room = &rooms[&room_id];
+
+    let content = room.content_rx.borrow().clone();
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, burn_after) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET burn_after = excluded.burn_after
+        ",
+    )
+    .bind(&room_id)
+    .bind(content)
+    .bind(burn.after.map(BurnAfter::as_str))
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store room burn setting in database: {e}");
+        return Err(CustomError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to store the burn setting.",
+        ));
+    }
+
+    *room.burn.lock().await = burn;
+    drop(rooms);
+
+    println!("Updated burn setting of room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": burn
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, Burn, BurnAfter};
+    use crate::config::Config;
+    use crate::storage::{SqliteStorage, Storage};
+    use crate::write_behind::{Write, WriteBehind};
+    use crate::{database, AppState, RoomState};
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::{watch, Notify};
+    use tokio::time::{self, Duration};
+
+    /// Storage whose writes wait for `released`
+    struct GatedStorage {
+        inner: SqliteStorage,
+        /// Notified when a write starts
+        entered: Notify,
+        released: watch::Sender<bool>,
+    }
+
+    #[async_trait]
+    impl Storage for GatedStorage {
+        async fn content(&self, room_id: &str) -> anyhow::Result<Option<String>> {
+            self.inner.content(room_id).await
+        }
+        async fn documents(&self, room_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+            self.inner.documents(room_id).await
+        }
+        async fn write(&self, writes: &[Write]) -> anyhow::Result<()> {
+            self.entered.notify_one();
+            let _ = self
+                .released
+                .subscribe()
+                .wait_for(|released| *released)
+                .await;
+            self.inner.write(writes).await
+        }
+        async fn delete_document(&self, room_id: &str, doc_id: &str) -> anyhow::Result<()> {
+            self.inner.delete_document(room_id, doc_id).await
+        }
+        async fn delete_room(&self, room_id: &str) -> anyhow::Result<()> {
+            self.inner.delete_room(room_id).await
+        }
+        async fn wipe_room(&self, room_id: &str) -> anyhow::Result<()> {
+            self.inner.wipe_room(room_id).await
+        }
+    }
+
+    #[test]
+    fn test_from_stored() {
+        assert_eq!(Burn::from_stored(None), Burn::default());
+        assert_eq!(Burn::from_stored(Some("read")).after, Some(BurnAfter::Read));
+        assert_eq!(
+            Burn::from_stored(Some("leave")).after,
+            Some(BurnAfter::Leave)
+        );
+        assert_eq!(Burn::from_stored(Some("never")), Burn::default());
+    }
+
+    #[tokio::test]
+    async fn test_burn_during_flush() {
+        let db = database::memory().await.unwrap();
+        sqlx::query(
+            "INSERT INTO rooms (room_id, content, burn_after) VALUES ('secret', '', 'read')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let storage = Arc::new(GatedStorage {
+            inner: SqliteStorage::new(db.clone()),
+            entered: Notify::new(),
+            released: watch::channel(false).0,
+        });
+        let write_behind = WriteBehind::spawn(db.clone(), storage.clone(), None);
+        let room = RoomState::new("secret", &write_behind).with_burn(load(&db, "secret").await);
+        let state = AppState::new(
+            HashMap::new(),
+            db.clone(),
+            write_behind,
+            None,
+            Config::default(),
+        );
+
+        time::sleep(Duration::from_millis(50)).await;
+        room.content_tx.send_replace("hunter2".to_string());
+        time::timeout(Duration::from_secs(5), storage.entered.notified())
+            .await
+            .unwrap();
+        // Burned while the flush still holds the secret
+        let release = async {
+            time::sleep(Duration::from_millis(50)).await;
+            storage.released.send_replace(true);
+        };
+        tokio::join!(room.burn(&state, "secret"), release);
+        drop(state.write_behind.pause().await);
+
+        let stored: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'secret'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(stored, "");
+        let versions: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM room_history WHERE room_id = 'secret'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(versions, 0);
+    }
+}
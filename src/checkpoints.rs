@@ -0,0 +1,292 @@
+use crate::rooms::RoomState;
+use crate::{
+    auth, ensure_room_loaded, revisions, unix_timestamp, AppState, CustomError, SocketMessage,
+    SocketMessageType,
+};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Checkpoints kept per room, older ones must be removed to add more
+const MAX_CHECKPOINTS: usize = 50;
+
+const MAX_LABEL_LENGTH: usize = 100;
+
+/// Content of a room saved under a label by a user, kept until removed unlike the versions
+/// of the history
+#[derive(TS, sqlx::FromRow, Debug, Clone, PartialEq, Eq, Serialize)]
+#[ts(export)]
+pub(crate) struct Checkpoint {
+    #[ts(type = "number")]
+    pub(crate) id: i64,
+    pub(crate) label: String,
+    /// Username of its author, `None` if saved anonymously through the API
+    pub(crate) created_by: Option<String>,
+    #[ts(type = "number")]
+    pub(crate) created_at: i64,
+    /// Bytes of the content
+    #[ts(type = "number")]
+    pub(crate) size: i64,
+}
+
+/// Body of `POST /api/rooms/:room_id/checkpoints`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct CheckpointRequest {
+    label: String,
+}
+
+fn validate_label(label: &str) -> Result<(), String> {
+    if label.trim().is_empty() || label.chars().count() > MAX_LABEL_LENGTH {
+        return Err(format!(
+            "Checkpoint labels are 1 to {MAX_LABEL_LENGTH} characters."
+        ));
+    }
+    Ok(())
+}
+
+/// Checkpoints of a room, the latest first
+async fn list(db: &SqlitePool, room_id: &str) -> Result<Vec<Checkpoint>> {
+    Ok(sqlx::query_as::<_, Checkpoint>(
+        r"
+        SELECT id, label, created_by, created_at, LENGTH(CAST(content AS BLOB)) AS size
+        FROM room_checkpoints WHERE room_id = ? ORDER BY id DESC
+        ",
+    )
+    .bind(room_id)
+    .fetch_all(db)
+    .await?)
+}
+
+/// Total size in bytes of the checkpoints of a room
+pub(crate) async fn size(db: &SqlitePool, room_id: &str) -> Result<i64> {
+    Ok(sqlx::query_scalar(
+        "SELECT COALESCE(SUM(LENGTH(CAST(content AS BLOB))), 0) FROM room_checkpoints WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_one(db)
+    .await?)
+}
+
+/// Forget the checkpoints of a room
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM room_checkpoints WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+impl RoomState {
+    /// Save the main content of the room under `label`, and tell its members
+    pub(crate) async fn save_checkpoint(
+        &self,
+        state: &AppState,
+        room_id: &str,
+        label: &str,
+        username: Option<&str>,
+    ) -> Result<Checkpoint, String> {
+        let label = label.trim();
+        validate_label(label)?;
+        let count = list(&state.db, room_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to list room checkpoints: {e:#}");
+                "Failed to save the checkpoint.".to_string()
+            })?
+            .len();
+        if count >= MAX_CHECKPOINTS {
+            return Err(format!(
+                "A room has at most {MAX_CHECKPOINTS} checkpoints, remove some to add more."
+            ));
+        }
+
+        let content = self.content_rx.borrow().clone();
+        let checkpoint = sqlx::query_as::<_, Checkpoint>(
+            r"
+            INSERT INTO room_checkpoints (room_id, label, content, created_by, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING id, label, created_by, created_at, LENGTH(CAST(content AS BLOB)) AS size
+            ",
+        )
+        .bind(room_id)
+        .bind(label)
+        .bind(content)
+        .bind(username)
+        .bind(unix_timestamp())
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to store room checkpoint: {e}");
+            "Failed to save the checkpoint.".to_string()
+        })?;
+
+        let _ = self.tx.send(
+            json!(SocketMessage {
+                doc_id: None,
+                message_type: SocketMessageType::Checkpoint,
+                value: Some(json!(checkpoint).to_string()),
+                code: None,
+                revision: None,
+                username: username.unwrap_or_default().to_string(),
+            })
+            .to_string(),
+        );
+        Ok(checkpoint)
+    }
+}
+
+/// `GET /api/rooms/:room_id/checkpoints`
+pub(crate) async fn list_checkpoints(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    drop(rooms);
+
+    let checkpoints = list(&state.db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to list room checkpoints: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": checkpoints
+    })))
+}
+
+/// `POST /api/rooms/:room_id/checkpoints`, save the current content under a label
+pub(crate) async fn create_checkpoint(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<CheckpointRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), CustomError> {
+    revisions::check_editor(&state, &headers, &room_id).await?;
+    let username = auth::current_user(&state, &headers)
+        .await
+        .map(|user| user.username);
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let checkpoint = rooms[&room_id]
+        .save_checkpoint(&state, &room_id, &body.label, username.as_deref())
+        .await
+        .map_err(CustomError::bad_request)?;
+    drop(rooms);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "type": "success",
+            "value": checkpoint
+        })),
+    ))
+}
+
+/// `POST /api/rooms/:room_id/checkpoints/:checkpoint_id/restore`, put the content of a
+/// checkpoint back, the current one staying in the history
+pub(crate) async fn restore_checkpoint(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, checkpoint_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let username = revisions::check_editor(&state, &headers, &room_id).await?;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let content: String =
+        sqlx::query_scalar("SELECT content FROM room_checkpoints WHERE id = ? AND room_id = ?")
+            .bind(checkpoint_id)
+            .bind(&room_id)
+            .fetch_optional(&state.db)
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to read room checkpoint: {e}");
+                auth::internal_error()
+            })?
+            .ok_or_else(|| CustomError::not_found("Checkpoint not found."))?;
+
+    let room = &rooms[&room_id];
+    revisions::check_edit(room, &username, None, &content).await?;
+    room.update_content(&state, &room_id, None, &content)
+        .await
+        .map_err(CustomError::bad_request)?;
+    let revision = room.revision(None).await;
+    let _ = room.tx.send(
+        json!(SocketMessage {
+            doc_id: None,
+            message_type: SocketMessageType::content(room.encryption.is_some()),
+            value: Some(content),
+            code: None,
+            revision: Some(revision),
+            username: "Server".to_string(),
+        })
+        .to_string(),
+    );
+    drop(rooms);
+
+    println!("Restored checkpoint {checkpoint_id} of room {room_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": revision
+    })))
+}
+
+/// `DELETE /api/rooms/:room_id/checkpoints/:checkpoint_id`
+pub(crate) async fn remove_checkpoint(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, checkpoint_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    revisions::check_editor(&state, &headers, &room_id).await?;
+
+    let removed = sqlx::query("DELETE FROM room_checkpoints WHERE id = ? AND room_id = ?")
+        .bind(checkpoint_id)
+        .bind(&room_id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to remove room checkpoint: {e}");
+            auth::internal_error()
+        })?
+        .rows_affected();
+    if removed == 0 {
+        return Err(CustomError::not_found("Checkpoint not found."));
+    }
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": checkpoint_id
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_label;
+
+    #[test]
+    fn test_validate_label() {
+        assert!(validate_label("before refactor").is_ok());
+        assert!(validate_label("").is_err());
+        assert!(validate_label("   ").is_err());
+        assert!(validate_label(&"é".repeat(100)).is_ok());
+        assert!(validate_label(&"é".repeat(101)).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::ip_filter::Cidr;
+use crate::AppState;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Address of the client of a request, as seen by the trusted proxies in front of the server.
+/// Without trusted proxies, it is the address of the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientIp(pub(crate) IpAddr);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        Ok(Self(resolve(
+            peer.ip(),
+            &parts.headers,
+            &state.config.trusted_proxies,
+        )))
+    }
+}
+
+/// Client address of a request received from `peer`.
+/// The forwarding headers are only read from trusted proxies, and the client is the closest
+/// hop that isn't one of them, since the hops before it could have been made up.
+fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let peer = peer.to_canonical();
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    // `Forwarded` is the standard, `X-Forwarded-For` what most proxies send
+    let hops = forwarded_hops(headers);
+    let hops = if hops.is_empty() {
+        x_forwarded_for_hops(headers)
+    } else {
+        hops
+    };
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        // An unknown or obfuscated hop can't be resolved further
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop.to_canonical();
+        if !is_trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// `for` addresses of the `Forwarded` headers, first hop first
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim().trim_matches('"')))
+            })
+        })
+        .collect()
+}
+
+/// Addresses of the `X-Forwarded-For` headers, first hop first
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Address of a node, which can come with a port: `192.0.2.1:8080` or `[2001:db8::1]:8080`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+    use crate::ip_filter::parse_list;
+    use axum::http::{HeaderMap, HeaderValue};
+    use std::net::IpAddr;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_resolve() {
+        let trusted = parse_list("127.0.0.1, 10.0.0.0/8").unwrap();
+        let forwarded = headers("x-forwarded-for", "203.0.113.9, 198.51.100.1, 10.0.0.2");
+
+        // Only trusted proxies can tell who the client is
+        assert_eq!(
+            resolve(ip("198.51.100.7"), &forwarded, &trusted),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            resolve(ip("127.0.0.1"), &forwarded, &trusted),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            resolve(ip("127.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("127.0.0.1")
+        );
+        assert_eq!(resolve(ip("127.0.0.1"), &forwarded, &[]), ip("127.0.0.1"));
+
+        let forwarded = headers(
+            "forwarded",
+            "for=203.0.113.9;proto=https, for=\"[2001:db8::1]:4711\"",
+        );
+        assert_eq!(
+            resolve(ip("10.1.1.1"), &forwarded, &trusted),
+            ip("2001:db8::1")
+        );
+        let forwarded = headers("forwarded", "for=unknown");
+        assert_eq!(
+            resolve(ip("10.1.1.1"), &forwarded, &trusted),
+            ip("10.1.1.1")
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use crate::documents::{DocumentEdit, MAIN_DOCUMENT};
+use crate::ot::SubmittedOperation;
+use crate::protocol::Subprotocol;
+use serde::Deserialize;
+
+/// Message of a client, translated from the subprotocol it speaks.
+/// This is also the wire format of v2, where every message is tagged by its `type`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum ClientMessage {
+    /// Replace the content of a document, the main one if `doc_id` is `None`
+    Edit {
+        #[serde(default)]
+        doc_id: Option<String>,
+        value: String,
+        /// Revision of the document the content was edited from, to merge it with the changes
+        /// made since instead of overwriting them
+        #[serde(default)]
+        revision: Option<u64>,
+    },
+    /// Change the highlighting language of the room, `None` to unset it
+    SetLanguage {
+        #[serde(default)]
+        value: Option<String>,
+    },
+    /// Ask for a `presence` message listing the users of the room
+    GetPresence,
+    /// Take the write lock of the room if it is free, or ask its holder for it
+    RequestLock,
+    /// Hand the write lock over to the member `value`, or release it if `None`, as its holder
+    GrantLock {
+        #[serde(default)]
+        value: Option<String>,
+    },
+    /// Claim the lines `start` to `end` of a document, the main one if `doc_id` is `None`, for
+    /// the others not to change them. Lines are counted from 0, `end` excluded.
+    ClaimLines {
+        #[serde(default)]
+        doc_id: Option<String>,
+        start: usize,
+        end: usize,
+    },
+    /// Give up the lines claimed
+    ReleaseLines,
+    /// Save the main content under the label `value`
+    Checkpoint { value: String },
+    /// The client handled the messages of the room up to the one numbered `seq`
+    Ack { seq: u64 },
+    /// Ask for a `range` message with about `length` bytes of a document from `offset`, the
+    /// main one if `doc_id` is `None`
+    GetRange {
+        #[serde(default)]
+        doc_id: Option<String>,
+        offset: usize,
+        length: usize,
+    },
+    /// Transform a revision of a document, with the `ot` capability
+    Op(SubmittedOperation),
+}
+
+/// Translate a text frame of a client into a [`ClientMessage`].
+/// `documents`, `operations` and `ranges` tell whether the client negotiated the `documents`,
+/// `ot` and `ranges` capabilities.
+pub(crate) fn decode(
+    subprotocol: Subprotocol,
+    documents: bool,
+    operations: bool,
+    ranges: bool,
+    text: String,
+) -> Result<ClientMessage, String> {
+    let message = match subprotocol {
+        Subprotocol::V2 => serde_json::from_str(&text).map_err(|_| "Invalid JSON".to_string())?,
+        // Clients handling several documents or operations send JSON, which leaves room for
+        // commands
+        Subprotocol::V1 if documents || operations => {
+            match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(message) => message,
+                Err(_) => {
+                    let edit: DocumentEdit =
+                        serde_json::from_str(&text).map_err(|_| "Invalid JSON".to_string())?;
+                    ClientMessage::Edit {
+                        doc_id: edit.scope(),
+                        value: edit.value,
+                        revision: None,
+                    }
+                }
+            }
+        }
+        // Only ranges are asked for in JSON, the content is still sent as is
+        Subprotocol::V1 if ranges => match serde_json::from_str(&text) {
+            Ok(range @ ClientMessage::GetRange { .. }) => range,
+            _ => ClientMessage::Edit {
+                doc_id: None,
+                value: text,
+                revision: None,
+            },
+        },
+        // Other v1 clients send the content as is
+        Subprotocol::V1 => ClientMessage::Edit {
+            doc_id: None,
+            value: text,
+            revision: None,
+        },
+    };
+
+    match message {
+        ClientMessage::Edit {
+            doc_id: Some(doc_id),
+            value,
+            revision,
+        } if doc_id == MAIN_DOCUMENT => Ok(ClientMessage::Edit {
+            doc_id: None,
+            value,
+            revision,
+        }),
+        ClientMessage::ClaimLines {
+            doc_id: Some(doc_id),
+            start,
+            end,
+        } if doc_id == MAIN_DOCUMENT => Ok(ClientMessage::ClaimLines {
+            doc_id: None,
+            start,
+            end,
+        }),
+        ClientMessage::GetRange {
+            doc_id: Some(doc_id),
+            offset,
+            length,
+        } if doc_id == MAIN_DOCUMENT => Ok(ClientMessage::GetRange {
+            doc_id: None,
+            offset,
+            length,
+        }),
+        ClientMessage::Op(mut operation) if operation.doc_id.as_deref() == Some(MAIN_DOCUMENT) => {
+            operation.doc_id = None;
+            Ok(ClientMessage::Op(operation))
+        }
+        // It would never get the edits of the others
+        ClientMessage::Edit {
+            doc_id: Some(_), ..
+        } if !documents => Err("Editing documents requires the documents capability.".to_string()),
+        ClientMessage::ClaimLines {
+            doc_id: Some(_), ..
+        } if !documents => Err("Editing documents requires the documents capability.".to_string()),
+        ClientMessage::GetRange {
+            doc_id: Some(_), ..
+        } if !documents => Err("Reading documents requires the documents capability.".to_string()),
+        ClientMessage::Op(operation) if operation.doc_id.is_some() && !documents => {
+            Err("Editing documents requires the documents capability.".to_string())
+        }
+        ClientMessage::Op(_) if !operations => {
+            Err("Operations require the ot capability.".to_string())
+        }
+        message => Ok(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, ClientMessage};
+    use crate::protocol::Subprotocol;
+
+    fn edit(doc_id: Option<&str>, value: &str) -> ClientMessage {
+        ClientMessage::Edit {
+            doc_id: doc_id.map(ToString::to_string),
+            value: value.to_string(),
+            revision: None,
+        }
+    }
+
+    #[test]
+    fn test_v1() {
+        let decode = |documents, text: &str| {
+            decode(Subprotocol::V1, documents, false, false, text.to_string())
+        };
+
+        // Raw content, even when it looks like JSON
+        assert_eq!(decode(false, "hello"), Ok(edit(None, "hello")));
+        assert_eq!(
+            decode(false, r#"{"type":"set-language"}"#),
+            Ok(edit(None, r#"{"type":"set-language"}"#))
+        );
+
+        assert_eq!(
+            decode(true, r#"{"doc_id":"notes","value":"todo"}"#),
+            Ok(edit(Some("notes"), "todo"))
+        );
+        assert_eq!(
+            decode(true, r#"{"doc_id":"main","value":"hello"}"#),
+            Ok(edit(None, "hello"))
+        );
+        assert_eq!(
+            decode(true, r#"{"type":"set-language","value":"rust"}"#),
+            Ok(ClientMessage::SetLanguage {
+                value: Some("rust".to_string())
+            })
+        );
+        assert!(decode(true, "hello").is_err());
+    }
+
+    #[test]
+    fn test_v2() {
+        let decode = |documents, text: &str| {
+            decode(Subprotocol::V2, documents, false, false, text.to_string())
+        };
+
+        assert_eq!(
+            decode(false, r#"{"type":"edit","value":"hello"}"#),
+            Ok(edit(None, "hello"))
+        );
+        assert_eq!(
+            decode(
+                false,
+                r#"{"type":"edit","doc_id":"main","value":"hello","revision":3}"#
+            ),
+            Ok(ClientMessage::Edit {
+                doc_id: None,
+                value: "hello".to_string(),
+                revision: Some(3),
+            })
+        );
+        assert_eq!(
+            decode(true, r#"{"type":"edit","doc_id":"notes","value":"todo"}"#),
+            Ok(edit(Some("notes"), "todo"))
+        );
+        assert!(decode(false, r#"{"type":"edit","doc_id":"notes","value":"todo"}"#).is_err());
+        assert_eq!(
+            decode(false, r#"{"type":"set-language","value":null}"#),
+            Ok(ClientMessage::SetLanguage { value: None })
+        );
+        assert_eq!(
+            decode(false, r#"{"type":"get-presence"}"#),
+            Ok(ClientMessage::GetPresence)
+        );
+        assert_eq!(
+            decode(
+                false,
+                r#"{"type":"claim-lines","doc_id":"main","start":1,"end":3}"#
+            ),
+            Ok(ClientMessage::ClaimLines {
+                doc_id: None,
+                start: 1,
+                end: 3,
+            })
+        );
+        assert!(decode(
+            false,
+            r#"{"type":"claim-lines","doc_id":"notes","start":1,"end":3}"#
+        )
+        .is_err());
+        assert!(decode(false, "hello").is_err());
+        assert!(decode(false, r#"{"doc_id":"main","value":"hello"}"#).is_err());
+    }
+
+    #[test]
+    fn test_operations() {
+        let op = r#"{"type":"op","doc_id":"main","revision":3,"ops":[2,"a"]}"#;
+        let Ok(ClientMessage::Op(operation)) =
+            decode(Subprotocol::V1, false, true, false, op.to_string())
+        else {
+            panic!("operation not decoded");
+        };
+        assert_eq!(operation.doc_id, None);
+        assert_eq!(operation.revision, 3);
+        assert_eq!(operation.ops.apply("xy"), Ok("xya".to_string()));
+
+        // Raw content without the capability
+        assert_eq!(
+            decode(Subprotocol::V1, false, false, false, op.to_string()),
+            Ok(edit(None, op))
+        );
+        assert!(decode(Subprotocol::V2, false, false, false, op.to_string()).is_err());
+        let notes = r#"{"type":"op","doc_id":"notes","revision":0,"ops":["a"]}"#;
+        assert!(decode(Subprotocol::V2, false, true, false, notes.to_string()).is_err());
+        assert!(decode(Subprotocol::V2, true, true, false, notes.to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let range = r#"{"type":"get-range","doc_id":"main","offset":10,"length":5}"#;
+        let expected = ClientMessage::GetRange {
+            doc_id: None,
+            offset: 10,
+            length: 5,
+        };
+        assert_eq!(
+            decode(Subprotocol::V2, false, false, false, range.to_string()),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            decode(Subprotocol::V1, false, false, true, range.to_string()),
+            Ok(expected)
+        );
+        // Other messages stay contents
+        assert_eq!(
+            decode(
+                Subprotocol::V1,
+                false,
+                false,
+                true,
+                r#"{"type":"get-presence"}"#.to_string()
+            ),
+            Ok(edit(None, r#"{"type":"get-presence"}"#))
+        );
+        assert_eq!(
+            decode(Subprotocol::V1, false, false, false, range.to_string()),
+            Ok(edit(None, range))
+        );
+        let notes = r#"{"type":"get-range","doc_id":"notes","offset":0,"length":5}"#;
+        assert!(decode(Subprotocol::V2, false, false, false, notes.to_string()).is_err());
+        assert!(decode(Subprotocol::V2, true, false, false, notes.to_string()).is_ok());
+    }
+}
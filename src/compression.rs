@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+
+/// Contents longer than this are stored compressed, in bytes
+const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// zstd level, fast enough to compress on every write
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Longest zstd frame header, which holds the size of the content it compresses
+pub(crate) const FRAME_HEADER_MAX: u32 = 18;
+
+/// Content of a room as stored in the `content`, `content_zstd` and `compressed` columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredContent {
+    /// Empty when compressed
+    pub(crate) content: String,
+    pub(crate) content_zstd: Option<Vec<u8>>,
+    pub(crate) compressed: bool,
+}
+
+impl StoredContent {
+    /// Compress `content` if it is large enough for it to pay off
+    pub(crate) fn encode(content: &str) -> Result<Self> {
+        if content.len() <= COMPRESSION_THRESHOLD {
+            return Ok(Self {
+                content: content.to_string(),
+                content_zstd: None,
+                compressed: false,
+            });
+        }
+        // Unlike the streaming encoder, this records the size of the content in the frame
+        let content_zstd = zstd::bulk::compress(content.as_bytes(), COMPRESSION_LEVEL)
+            .context("Failed to compress room content")?;
+        Ok(Self {
+            content: String::new(),
+            content_zstd: Some(content_zstd),
+            compressed: true,
+        })
+    }
+
+    /// The content as it was before being stored
+    pub(crate) fn decode(self) -> Result<String> {
+        if !self.compressed {
+            return Ok(self.content);
+        }
+        let content_zstd = self
+            .content_zstd
+            .context("Compressed room content is missing")?;
+        let content =
+            zstd::decode_all(content_zstd.as_slice()).context("Corrupted room content")?;
+        String::from_utf8(content).context("Corrupted room content")
+    }
+}
+
+/// Size of a compressed content in bytes, read from the start of its frame
+pub(crate) fn content_length(frame_header: &[u8]) -> Option<u64> {
+    zstd::zstd_safe::get_frame_content_size(frame_header)
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_length, StoredContent, COMPRESSION_THRESHOLD, FRAME_HEADER_MAX};
+    use crate::database;
+    use crate::storage::{SqliteStorage, Storage};
+    use crate::write_behind::Write;
+
+    #[test]
+    fn test_large_contents_are_compressed() {
+        let small = "Short notes";
+        let stored = StoredContent::encode(small).unwrap();
+        assert!(!stored.compressed);
+        assert_eq!(stored.content, small);
+        assert_eq!(stored.decode().unwrap(), small);
+
+        let large = "All work and no play makes Jack a dull boy. ".repeat(1000);
+        assert!(large.len() > COMPRESSION_THRESHOLD);
+        let stored = StoredContent::encode(&large).unwrap();
+        assert!(stored.compressed);
+        assert!(stored.content.is_empty());
+        let content_zstd = stored.content_zstd.as_ref().unwrap();
+        assert!(content_zstd.len() < large.len() / 10);
+        assert_eq!(
+            content_length(&content_zstd[..usize::try_from(FRAME_HEADER_MAX).unwrap()]),
+            Some(large.len() as u64)
+        );
+        assert_eq!(stored.decode().unwrap(), large);
+
+        let corrupted = StoredContent {
+            content: String::new(),
+            content_zstd: Some(b"not zstd".to_vec()),
+            compressed: true,
+        };
+        assert!(corrupted.decode().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stored_compressed() {
+        let db = database::memory().await.unwrap();
+        let large = "0123456789abcdef".repeat(COMPRESSION_THRESHOLD);
+        let storage = SqliteStorage::new(db.clone());
+        storage
+            .write(&Write::room("large", &large, []))
+            .await
+            .unwrap();
+
+        let (content, compressed): (String, bool) =
+            sqlx::query_as("SELECT content, compressed FROM rooms WHERE room_id = 'large'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(content.is_empty());
+        assert!(compressed);
+        assert_eq!(
+            storage.content("large").await.unwrap().as_deref(),
+            Some(large.as_str())
+        );
+    }
+}
@@ -0,0 +1,894 @@
+use crate::archive::ArchivedJoin;
+use crate::base_path;
+use crate::content_log::ContentLog;
+use crate::database::SqliteTuning;
+use crate::history::Retention;
+use crate::ip_filter::{self, Cidr};
+use crate::mirror::MirrorConfig;
+use crate::oidc::{self, OidcConfig};
+use crate::rate_limit::RateLimit;
+use crate::telemetry::OtlpConfig;
+use crate::tls::TlsConfig;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Configuration file read when `CONFIG_FILE` is unset, if it exists
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Server tunables, read from environment variables and the configuration file
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// HTTP port
+    pub(crate) port: u16,
+    /// Unix socket listened on instead of `port`
+    pub(crate) unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix socket, so that the reverse proxy can connect to it
+    pub(crate) unix_socket_mode: u32,
+    /// Prefix every route is served under, such as `/partage`, empty at the root
+    pub(crate) base_path: String,
+    /// SQLite database URL, `None` disables persistence
+    pub(crate) database_url: Option<String>,
+    /// Pragmas and pool size of the database connections
+    pub(crate) sqlite: SqliteTuning,
+    /// Delay between writes of a changed room content to the database
+    pub(crate) persist_interval: Duration,
+    /// Content of the rooms created by joining an unknown id, instead of an empty pad
+    pub(crate) default_room_content: Option<String>,
+    /// Messages a room keeps for its slowest member, clients falling further behind are resynced
+    pub(crate) broadcast_capacity: usize,
+    /// Rooms without users are evicted from memory after this delay, `None` disables eviction
+    pub(crate) idle_room_timeout: Option<Duration>,
+    /// Limit of WebSocket text frames per client IP
+    pub(crate) ws_rate_limit: RateLimit,
+    /// Limit of REST API requests per client IP
+    pub(crate) api_rate_limit: RateLimit,
+    /// Requests taking longer are answered 408, `None` lets them run
+    pub(crate) request_timeout: Option<Duration>,
+    /// Requests handled at once, the others wait for their turn, 0 disables the limit
+    pub(crate) max_concurrent_requests: usize,
+    /// Largest request body, in bytes, attachments having their own limit
+    pub(crate) max_body_size: u64,
+    /// Shell command used by the `command` formatter, reading stdin and writing stdout
+    pub(crate) formatter_command: Option<String>,
+    /// WebSocket upgrades accepted per second, 0 disables pacing
+    pub(crate) upgrades_per_second: u32,
+    /// Upgrades allowed to wait for their turn before answering 503
+    pub(crate) upgrade_queue_size: u32,
+    /// WebSocket connections open at once, 0 disables the limit
+    pub(crate) max_connections: usize,
+    /// WebSocket connections open at once from a single IP, 0 disables the limit
+    pub(crate) max_connections_per_ip: usize,
+    /// Users in a room at once, whatever their number of tabs, 0 disables the limit
+    pub(crate) max_room_users: usize,
+    /// Rooms a client can create per minute, once its burst is spent, 0 disables the limit
+    pub(crate) room_creations_per_minute: u32,
+    pub(crate) room_creation_burst: u32,
+    /// Rooms stored at most, trashed ones included, 0 disables the limit
+    pub(crate) max_rooms: usize,
+    /// Ids of the rooms that can't be created, matched as a whole
+    pub(crate) reserved_room_names: Option<Regex>,
+    /// Turn the room ids into lowercase ASCII-friendly slugs, `Café Notes` being `cafe-notes`
+    pub(crate) slugify_room_ids: bool,
+    /// Spread of the reconnection delay suggested to clients
+    pub(crate) reconnect_jitter: Duration,
+    /// Time given to clients to disconnect on their own when the server shuts down
+    pub(crate) shutdown_grace: Duration,
+    /// Delay between the pings sent to each client, `None` disables them
+    pub(crate) heartbeat_interval: Option<Duration>,
+    /// Pings in a row a client can leave unanswered before being disconnected
+    pub(crate) heartbeat_max_missed: u32,
+    /// Single sign-on provider, enabled by `OIDC_ISSUER_URL`
+    pub(crate) oidc: Option<OidcConfig>,
+    /// Only logged in users can access rooms
+    pub(crate) require_auth: bool,
+    /// Bearer token of the `/api/admin` routes, `None` disables them
+    pub(crate) admin_token: Option<String>,
+    /// How long users muted through the admin API can't write, unless the request says
+    pub(crate) mute_duration: Duration,
+    /// How long lines claimed by a member stay theirs without them claiming or editing lines
+    pub(crate) line_claim_timeout: Duration,
+    /// Webhooks called for the events of every room
+    pub(crate) webhook_urls: Vec<String>,
+    /// Key of the webhook delivery signatures, `None` leaves them unsigned
+    pub(crate) webhook_secret: Option<String>,
+    /// Directory of the attachment blobs
+    pub(crate) attachments_dir: PathBuf,
+    /// Largest file that can be attached to a room, in bytes
+    pub(crate) max_attachment_size: u64,
+    /// Total size of the files attached to a room, in bytes
+    pub(crate) room_attachments_quota: u64,
+    /// Largest encrypted blob, in bytes
+    pub(crate) max_blob_size: u64,
+    /// Longest time an encrypted blob is kept
+    pub(crate) blob_max_ttl: Duration,
+    /// What is logged about room contents
+    pub(crate) content_log: ContentLog,
+    /// Collector the request and WebSocket spans are exported to, `None` only logs them
+    pub(crate) otlp: Option<OtlpConfig>,
+    /// Detect the type and natural language of room contents
+    pub(crate) language_detection: bool,
+    /// Seed applied at startup, creating the rooms and accounts it lists if missing
+    pub(crate) seed_file: Option<PathBuf>,
+    /// Directory of the scheduled database backups, `None` disables them
+    pub(crate) backup_dir: Option<PathBuf>,
+    /// Delay between scheduled backups
+    pub(crate) backup_interval: Duration,
+    /// Scheduled backups kept, the oldest ones are removed past that, 0 keeps them all
+    pub(crate) backup_keep: usize,
+    /// Bucket the rooms and attachments are mirrored to, `None` disables the mirror
+    pub(crate) mirror: Option<MirrorConfig>,
+    /// Saved versions of the room contents kept
+    pub(crate) history_retention: Retention,
+    /// How long removed rooms stay in the trash, `None` deletes them right away
+    pub(crate) trash_retention: Option<Duration>,
+    /// What joining an archived room does
+    pub(crate) archived_join: ArchivedJoin,
+    /// Delay between two `VACUUM` of the database, `None` disables them
+    pub(crate) vacuum_interval: Option<Duration>,
+    /// Only these client IPs are served, every one if empty
+    pub(crate) ip_allowlist: Vec<Cidr>,
+    /// Client IPs refused even if allowed
+    pub(crate) ip_denylist: Vec<Cidr>,
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers tell the client IP.
+    /// On a Unix socket, the reverse proxy in front of it by default.
+    pub(crate) trusted_proxies: Vec<Cidr>,
+    /// Serve HTTPS and WSS directly, `None` serves plain HTTP
+    pub(crate) tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 3001,
+            unix_socket: None,
+            unix_socket_mode: 0o660,
+            base_path: String::new(),
+            database_url: None,
+            sqlite: SqliteTuning::default(),
+            persist_interval: Duration::from_secs(2),
+            default_room_content: None,
+            broadcast_capacity: 100,
+            idle_room_timeout: Some(Duration::from_secs(30 * 60)),
+            ws_rate_limit: RateLimit {
+                per_second: 30,
+                burst: 60,
+            },
+            api_rate_limit: RateLimit {
+                per_second: 10,
+                burst: 30,
+            },
+            request_timeout: Some(Duration::from_secs(60)),
+            max_concurrent_requests: 1024,
+            max_body_size: 2 * MIB,
+            formatter_command: None,
+            upgrades_per_second: 100,
+            upgrade_queue_size: 500,
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            max_room_users: 0,
+            room_creations_per_minute: 10,
+            room_creation_burst: 20,
+            max_rooms: 0,
+            reserved_room_names: None,
+            slugify_room_ids: false,
+            reconnect_jitter: Duration::from_secs(10),
+            shutdown_grace: Duration::from_secs(10),
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            heartbeat_max_missed: 2,
+            oidc: None,
+            require_auth: false,
+            admin_token: None,
+            mute_duration: Duration::from_secs(10 * 60),
+            line_claim_timeout: Duration::from_secs(60),
+            webhook_urls: Vec::new(),
+            webhook_secret: None,
+            attachments_dir: PathBuf::from("attachments"),
+            max_attachment_size: 10 * MIB,
+            max_blob_size: MIB,
+            blob_max_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            room_attachments_quota: 100 * MIB,
+            content_log: ContentLog::default(),
+            otlp: None,
+            language_detection: true,
+            seed_file: None,
+            backup_dir: None,
+            backup_interval: Duration::from_secs(24 * 60 * 60),
+            backup_keep: 7,
+            mirror: None,
+            history_retention: Retention::default(),
+            trash_retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            archived_join: ArchivedJoin::default(),
+            vacuum_interval: Some(Duration::from_secs(24 * 60 * 60)),
+            ip_allowlist: Vec::new(),
+            ip_denylist: Vec::new(),
+            trusted_proxies: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from the environment, then the configuration file at
+    /// `CONFIG_FILE` (`config.toml` by default), then the defaults
+    ///
+    /// # Errors
+    ///
+    /// When the configuration file can't be read, or a setting is invalid
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("CONFIG_FILE").ok().map(PathBuf::from);
+        let file = match &path {
+            Some(path) => Some(ConfigFile::read(path)?),
+            None => {
+                let path = Path::new(DEFAULT_CONFIG_FILE);
+                path.exists().then(|| ConfigFile::read(path)).transpose()?
+            }
+        };
+        Self::from_sources(&Sources { file })
+    }
+
+    fn from_sources(sources: &Sources) -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(port) = sources.parse("PORT")? {
+            config.port = port;
+        }
+        if let Some(listen) = sources.string("LISTEN")? {
+            let Some(path) = listen.strip_prefix("unix:") else {
+                bail!("LISTEN must be unix:<path>, use PORT to listen on TCP");
+            };
+            config.unix_socket = Some(PathBuf::from(path));
+        }
+        if let Some(path) = sources.string("BASE_PATH")? {
+            config.base_path = base_path::normalize(&path)
+                .with_context(|| format!("Invalid value for BASE_PATH: {path}"))?;
+        }
+        if let Some(mode) = sources.string("UNIX_SOCKET_MODE")? {
+            config.unix_socket_mode = u32::from_str_radix(&mode, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .with_context(|| format!("Invalid value for UNIX_SOCKET_MODE: {mode}"))?;
+        }
+        config.database_url = sources.string("DATABASE_URL")?;
+        if let Some(mode) = sources.string("SQLITE_JOURNAL_MODE")? {
+            config.sqlite.journal_mode = SqliteJournalMode::from_str(&mode).ok().with_context(|| {
+                format!(
+                    "Invalid value for SQLITE_JOURNAL_MODE: {mode}, expected wal, delete, truncate, persist, memory or off"
+                )
+            })?;
+        }
+        if let Some(synchronous) = sources.string("SQLITE_SYNCHRONOUS")? {
+            config.sqlite.synchronous = SqliteSynchronous::from_str(&synchronous)
+                .ok()
+                .with_context(|| {
+                    format!(
+                        "Invalid value for SQLITE_SYNCHRONOUS: {synchronous}, expected off, normal, full or extra"
+                    )
+                })?;
+        }
+        if let Some(seconds) = sources.parse("SQLITE_BUSY_TIMEOUT_SECONDS")? {
+            config.sqlite.busy_timeout = Duration::from_secs(seconds);
+        }
+        if let Some(max) = sources.parse("DATABASE_MAX_CONNECTIONS")? {
+            if max == 0 {
+                bail!("DATABASE_MAX_CONNECTIONS must be at least 1");
+            }
+            config.sqlite.max_connections = max;
+        }
+        if let Some(seconds) = sources.parse::<u64>("PERSIST_INTERVAL_SECONDS")? {
+            if seconds == 0 {
+                bail!("PERSIST_INTERVAL_SECONDS must be at least 1");
+            }
+            config.persist_interval = Duration::from_secs(seconds);
+        }
+
+        config.default_room_content = sources
+            .string("DEFAULT_ROOM_CONTENT")?
+            .filter(|content| !content.is_empty());
+
+        if let Some(capacity) = sources.parse("BROADCAST_CAPACITY")? {
+            if capacity == 0 {
+                bail!("BROADCAST_CAPACITY must be at least 1");
+            }
+            config.broadcast_capacity = capacity;
+        }
+
+        // 0 disables eviction
+        if let Some(minutes) = sources.parse::<u64>("IDLE_ROOM_TIMEOUT_MINUTES")? {
+            config.idle_room_timeout =
+                (minutes > 0).then(|| Duration::from_secs(minutes.saturating_mul(60)));
+        }
+
+        if let Some(per_second) = sources.parse("WS_RATE_LIMIT_PER_SECOND")? {
+            config.ws_rate_limit.per_second = per_second;
+        }
+        if let Some(burst) = sources.parse("WS_RATE_LIMIT_BURST")? {
+            config.ws_rate_limit.burst = burst;
+        }
+        if let Some(per_second) = sources.parse("API_RATE_LIMIT_PER_SECOND")? {
+            config.api_rate_limit.per_second = per_second;
+        }
+        if let Some(burst) = sources.parse("API_RATE_LIMIT_BURST")? {
+            config.api_rate_limit.burst = burst;
+        }
+
+        if let Some(seconds) = sources.parse::<u64>("REQUEST_TIMEOUT_SECONDS")? {
+            config.request_timeout = (seconds > 0).then(|| Duration::from_secs(seconds));
+        }
+        if let Some(max) = sources.parse("MAX_CONCURRENT_REQUESTS")? {
+            config.max_concurrent_requests = max;
+        }
+        if let Some(megabytes) = sources.parse::<u64>("MAX_BODY_SIZE_MB")? {
+            if megabytes == 0 {
+                bail!("MAX_BODY_SIZE_MB must be at least 1");
+            }
+            config.max_body_size = megabytes.saturating_mul(MIB);
+        }
+
+        config.formatter_command = sources.string("FORMATTER_COMMAND")?;
+
+        if let Some(per_second) = sources.parse("UPGRADES_PER_SECOND")? {
+            config.upgrades_per_second = per_second;
+        }
+        if let Some(queue_size) = sources.parse("UPGRADE_QUEUE_SIZE")? {
+            config.upgrade_queue_size = queue_size;
+        }
+        if let Some(max) = sources.parse("MAX_CONNECTIONS")? {
+            config.max_connections = max;
+        }
+        if let Some(max) = sources.parse("MAX_CONNECTIONS_PER_IP")? {
+            config.max_connections_per_ip = max;
+        }
+        if let Some(max) = sources.parse("MAX_ROOM_USERS")? {
+            config.max_room_users = max;
+        }
+        if let Some(per_minute) = sources.parse("ROOM_CREATIONS_PER_MINUTE")? {
+            config.room_creations_per_minute = per_minute;
+        }
+        if let Some(burst) = sources.parse("ROOM_CREATION_BURST")? {
+            config.room_creation_burst = burst;
+        }
+        if let Some(max) = sources.parse("MAX_ROOMS")? {
+            config.max_rooms = max;
+        }
+        if let Some(slugify) = sources.parse("SLUGIFY_ROOM_IDS")? {
+            config.slugify_room_ids = slugify;
+        }
+        if let Some(pattern) = sources
+            .string("RESERVED_ROOM_NAMES")?
+            .filter(|pattern| !pattern.is_empty())
+        {
+            config.reserved_room_names =
+                Some(Regex::new(&format!("^(?:{pattern})$")).with_context(|| {
+                    format!("Invalid value for RESERVED_ROOM_NAMES: {pattern}")
+                })?);
+        }
+        if let Some(seconds) = sources.parse("RECONNECT_JITTER_SECONDS")? {
+            config.reconnect_jitter = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = sources.parse("SHUTDOWN_GRACE_SECONDS")? {
+            config.shutdown_grace = Duration::from_secs(seconds);
+        }
+        // 0 disables the pings
+        if let Some(seconds) = sources.parse::<u64>("HEARTBEAT_INTERVAL_SECONDS")? {
+            config.heartbeat_interval = (seconds > 0).then(|| Duration::from_secs(seconds));
+        }
+        if let Some(max_missed) = sources.parse("HEARTBEAT_MAX_MISSED")? {
+            if max_missed == 0 {
+                bail!("HEARTBEAT_MAX_MISSED must be at least 1");
+            }
+            config.heartbeat_max_missed = max_missed;
+        }
+
+        if let Some(issuer_url) = sources.string("OIDC_ISSUER_URL")? {
+            let (Some(client_id), Some(redirect_url)) = (
+                sources.string("OIDC_CLIENT_ID")?,
+                sources.string("OIDC_REDIRECT_URL")?,
+            ) else {
+                bail!("OIDC_ISSUER_URL requires OIDC_CLIENT_ID and OIDC_REDIRECT_URL");
+            };
+            oidc::check_https(&issuer_url).context("OIDC_ISSUER_URL must be https")?;
+            config.oidc = Some(OidcConfig {
+                issuer_url,
+                client_id,
+                client_secret: sources.string("OIDC_CLIENT_SECRET")?,
+                redirect_url,
+                username_claim: sources
+                    .string("OIDC_USERNAME_CLAIM")?
+                    .unwrap_or_else(|| "preferred_username".to_string()),
+            });
+        }
+        if let Some(require_auth) = sources.parse("REQUIRE_AUTH")? {
+            config.require_auth = require_auth;
+        }
+        config.admin_token = sources
+            .string("ADMIN_TOKEN")?
+            .filter(|token| !token.is_empty());
+        if let Some(minutes) = sources.parse::<u64>("MUTE_DURATION_MINUTES")? {
+            if minutes == 0 {
+                bail!("MUTE_DURATION_MINUTES must be at least 1");
+            }
+            config.mute_duration = Duration::from_secs(minutes.saturating_mul(60));
+        }
+        if let Some(seconds) = sources.parse::<u64>("LINE_CLAIM_TIMEOUT_SECONDS")? {
+            if seconds == 0 {
+                bail!("LINE_CLAIM_TIMEOUT_SECONDS must be at least 1");
+            }
+            config.line_claim_timeout = Duration::from_secs(seconds);
+        }
+        if let Some(urls) = sources.string("WEBHOOK_URLS")? {
+            config.webhook_urls = urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(ToString::to_string)
+                .collect();
+        }
+        config.webhook_secret = sources
+            .string("WEBHOOK_SECRET")?
+            .filter(|secret| !secret.is_empty());
+        if let Some(dir) = sources.string("ATTACHMENTS_DIR")? {
+            config.attachments_dir = PathBuf::from(dir);
+        }
+        if let Some(megabytes) = sources.parse::<u64>("MAX_ATTACHMENT_SIZE_MB")? {
+            config.max_attachment_size = megabytes.saturating_mul(MIB);
+        }
+        if let Some(megabytes) = sources.parse::<u64>("ROOM_ATTACHMENTS_QUOTA_MB")? {
+            config.room_attachments_quota = megabytes.saturating_mul(MIB);
+        }
+        if let Some(megabytes) = sources.parse::<u64>("MAX_BLOB_SIZE_MB")? {
+            config.max_blob_size = megabytes.saturating_mul(MIB);
+        }
+        if let Some(hours) = sources.parse::<u64>("BLOB_MAX_TTL_HOURS")? {
+            if hours == 0 {
+                bail!("BLOB_MAX_TTL_HOURS must be at least 1");
+            }
+            config.blob_max_ttl = Duration::from_secs(hours.saturating_mul(60 * 60));
+        }
+        if let Some(level) = sources.string("CONTENT_LOG")? {
+            config.content_log = ContentLog::parse(&level).with_context(|| {
+                format!("Invalid value for CONTENT_LOG: {level}, expected off or metadata")
+            })?;
+        }
+        if let Some(endpoint) = sources
+            .string("OTEL_EXPORTER_OTLP_ENDPOINT")?
+            .filter(|endpoint| !endpoint.is_empty())
+        {
+            config.otlp = Some(OtlpConfig {
+                endpoint,
+                service_name: sources
+                    .string("OTEL_SERVICE_NAME")?
+                    .unwrap_or_else(|| "partage".to_string()),
+            });
+        }
+
+        if let Some(language_detection) = sources.parse("LANGUAGE_DETECTION")? {
+            config.language_detection = language_detection;
+        }
+        config.seed_file = sources.string("SEED_FILE")?.map(PathBuf::from);
+
+        config.backup_dir = sources.string("BACKUP_DIR")?.map(PathBuf::from);
+        if let Some(hours) = sources.parse::<u64>("BACKUP_INTERVAL_HOURS")? {
+            if hours == 0 {
+                bail!("BACKUP_INTERVAL_HOURS must be at least 1");
+            }
+            config.backup_interval = Duration::from_secs(hours.saturating_mul(60 * 60));
+        }
+        if let Some(keep) = sources.parse("BACKUP_KEEP")? {
+            config.backup_keep = keep;
+        }
+        if let Some(bucket) = sources
+            .string("S3_BUCKET")?
+            .filter(|bucket| !bucket.is_empty())
+        {
+            config.mirror = Some(MirrorConfig {
+                bucket,
+                endpoint: sources.string("S3_ENDPOINT")?,
+                region: sources
+                    .string("S3_REGION")?
+                    .unwrap_or_else(|| "us-east-1".to_string()),
+                access_key_id: sources.string("S3_ACCESS_KEY_ID")?,
+                secret_access_key: sources.string("S3_SECRET_ACCESS_KEY")?,
+            });
+        }
+
+        // 0 keeps the versions whatever their age
+        if let Some(days) = sources.parse::<u64>("HISTORY_MAX_AGE_DAYS")? {
+            config.history_retention.max_age =
+                (days > 0).then(|| Duration::from_secs(days.saturating_mul(24 * 60 * 60)));
+        }
+        if let Some(max_rows) = sources.parse("HISTORY_MAX_ROWS")? {
+            config.history_retention.max_rows = max_rows;
+        }
+        // 0 disables the trash, removed rooms are deleted right away
+        if let Some(days) = sources.parse::<u64>("TRASH_RETENTION_DAYS")? {
+            config.trash_retention =
+                (days > 0).then(|| Duration::from_secs(days.saturating_mul(24 * 60 * 60)));
+        }
+        if let Some(join) = sources.string("ARCHIVED_ROOM_JOIN")? {
+            config.archived_join = ArchivedJoin::parse(&join).with_context(|| {
+                format!(
+                    "Invalid value for ARCHIVED_ROOM_JOIN: {join}, expected reject or unarchive"
+                )
+            })?;
+        }
+        if let Some(hours) = sources.parse::<u64>("VACUUM_INTERVAL_HOURS")? {
+            config.vacuum_interval =
+                (hours > 0).then(|| Duration::from_secs(hours.saturating_mul(60 * 60)));
+        }
+
+        if let Some(list) = sources.string("IP_ALLOWLIST")? {
+            config.ip_allowlist = ip_filter::parse_list(&list)
+                .with_context(|| format!("Invalid value for IP_ALLOWLIST: {list}"))?;
+        }
+        if let Some(list) = sources.string("IP_DENYLIST")? {
+            config.ip_denylist = ip_filter::parse_list(&list)
+                .with_context(|| format!("Invalid value for IP_DENYLIST: {list}"))?;
+        }
+        if let Some(list) = sources.string("TRUSTED_PROXIES")? {
+            config.trusted_proxies = ip_filter::parse_list(&list)
+                .with_context(|| format!("Invalid value for TRUSTED_PROXIES: {list}"))?;
+        } else if config.unix_socket.is_some() {
+            // Every client of the socket is seen as the loopback address, it's the reverse
+            // proxy that tells them apart
+            config.trusted_proxies = ip_filter::parse_list("127.0.0.1")?;
+        }
+
+        match (
+            sources.string("TLS_CERT_PATH")?,
+            sources.string("TLS_KEY_PATH")?,
+        ) {
+            (Some(cert_path), Some(key_path)) => {
+                config.tls = Some(TlsConfig {
+                    cert_path: PathBuf::from(cert_path),
+                    key_path: PathBuf::from(key_path),
+                });
+            }
+            (None, None) => {}
+            _ => bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+        }
+        if config.tls.is_some() && config.unix_socket.is_some() {
+            bail!("TLS isn't supported on Unix sockets, leave it to the reverse proxy");
+        }
+
+        Ok(config)
+    }
+}
+
+/// Settings of the configuration file, keyed by the name of their environment variable
+/// in lowercase, e.g. `port` or `ws_rate_limit_burst`
+#[derive(Debug)]
+struct ConfigFile {
+    path: PathBuf,
+    values: HashMap<String, toml::Value>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(path, &text)
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<Self> {
+        let values: HashMap<String, toml::Value> =
+            toml::from_str(text).with_context(|| format!("Invalid {}", path.display()))?;
+        // A typo would otherwise silently leave the default
+        if let Some(key) = values
+            .keys()
+            .find(|key| !SETTINGS.contains(&key.to_uppercase().as_str()))
+        {
+            bail!("Unknown setting {key} in {}", path.display());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            values,
+        })
+    }
+}
+
+/// Settings that can be set in the configuration file
+const SETTINGS: &[&str] = &[
+    "PORT",
+    "LISTEN",
+    "UNIX_SOCKET_MODE",
+    "BASE_PATH",
+    "DATABASE_URL",
+    "SQLITE_JOURNAL_MODE",
+    "SQLITE_SYNCHRONOUS",
+    "SQLITE_BUSY_TIMEOUT_SECONDS",
+    "DATABASE_MAX_CONNECTIONS",
+    "PERSIST_INTERVAL_SECONDS",
+    "DEFAULT_ROOM_CONTENT",
+    "BROADCAST_CAPACITY",
+    "IDLE_ROOM_TIMEOUT_MINUTES",
+    "WS_RATE_LIMIT_PER_SECOND",
+    "WS_RATE_LIMIT_BURST",
+    "API_RATE_LIMIT_PER_SECOND",
+    "API_RATE_LIMIT_BURST",
+    "REQUEST_TIMEOUT_SECONDS",
+    "MAX_CONCURRENT_REQUESTS",
+    "MAX_BODY_SIZE_MB",
+    "FORMATTER_COMMAND",
+    "UPGRADES_PER_SECOND",
+    "UPGRADE_QUEUE_SIZE",
+    "MAX_CONNECTIONS",
+    "MAX_CONNECTIONS_PER_IP",
+    "MAX_ROOM_USERS",
+    "ROOM_CREATIONS_PER_MINUTE",
+    "ROOM_CREATION_BURST",
+    "MAX_ROOMS",
+    "RESERVED_ROOM_NAMES",
+    "SLUGIFY_ROOM_IDS",
+    "RECONNECT_JITTER_SECONDS",
+    "SHUTDOWN_GRACE_SECONDS",
+    "HEARTBEAT_INTERVAL_SECONDS",
+    "HEARTBEAT_MAX_MISSED",
+    "OIDC_ISSUER_URL",
+    "OIDC_CLIENT_ID",
+    "OIDC_CLIENT_SECRET",
+    "OIDC_REDIRECT_URL",
+    "OIDC_USERNAME_CLAIM",
+    "REQUIRE_AUTH",
+    "ADMIN_TOKEN",
+    "MUTE_DURATION_MINUTES",
+    "LINE_CLAIM_TIMEOUT_SECONDS",
+    "WEBHOOK_URLS",
+    "WEBHOOK_SECRET",
+    "ATTACHMENTS_DIR",
+    "MAX_ATTACHMENT_SIZE_MB",
+    "ROOM_ATTACHMENTS_QUOTA_MB",
+    "MAX_BLOB_SIZE_MB",
+    "BLOB_MAX_TTL_HOURS",
+    "CONTENT_LOG",
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_SERVICE_NAME",
+    "LANGUAGE_DETECTION",
+    "SEED_FILE",
+    "BACKUP_DIR",
+    "BACKUP_INTERVAL_HOURS",
+    "BACKUP_KEEP",
+    "S3_BUCKET",
+    "S3_ENDPOINT",
+    "S3_REGION",
+    "S3_ACCESS_KEY_ID",
+    "S3_SECRET_ACCESS_KEY",
+    "HISTORY_MAX_AGE_DAYS",
+    "HISTORY_MAX_ROWS",
+    "TRASH_RETENTION_DAYS",
+    "ARCHIVED_ROOM_JOIN",
+    "VACUUM_INTERVAL_HOURS",
+    "IP_ALLOWLIST",
+    "IP_DENYLIST",
+    "TRUSTED_PROXIES",
+    "TLS_CERT_PATH",
+    "TLS_KEY_PATH",
+];
+
+/// Where settings are read from, environment variables winning over the configuration file
+#[derive(Debug, Default)]
+struct Sources {
+    file: Option<ConfigFile>,
+}
+
+impl Sources {
+    /// Raw value of a setting
+    fn string(&self, name: &str) -> Result<Option<String>> {
+        if let Ok(value) = std::env::var(name) {
+            return Ok(Some(value));
+        }
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        let key = name.to_lowercase();
+        match file.values.get(&key) {
+            None => Ok(None),
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(value @ (toml::Value::Integer(_) | toml::Value::Boolean(_))) => {
+                Ok(Some(value.to_string()))
+            }
+            Some(_) => bail!(
+                "Invalid value for {key} in {}, expected a string, number or boolean",
+                file.path.display()
+            ),
+        }
+    }
+
+    /// Parsed value of a setting
+    fn parse<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        if let Some(value) = env_var(name)? {
+            return Ok(Some(value));
+        }
+        let (Some(file), Some(value)) = (&self.file, self.string(name)?) else {
+            return Ok(None);
+        };
+        value.parse::<T>().map(Some).with_context(|| {
+            format!(
+                "Invalid value for {} in {}: {value}",
+                name.to_lowercase(),
+                file.path.display()
+            )
+        })
+    }
+}
+
+/// Parse an optional environment variable
+pub(crate) fn env_var<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    std::env::var(name).ok().map_or(Ok(None), |val| {
+        val.parse::<T>()
+            .map(Some)
+            .with_context(|| format!("Invalid value for {name}: {val}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{env_var, Config, ConfigFile, Sources};
+    use crate::ip_filter;
+    use crate::telemetry::OtlpConfig;
+    use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    #[test]
+    fn test_env_var_parsing() {
+        std::env::set_var("PARTAGE_TEST_ENV_VAR_OK", "42");
+        std::env::set_var("PARTAGE_TEST_ENV_VAR_BAD", "forty-two");
+
+        assert_eq!(env_var::<u64>("PARTAGE_TEST_ENV_VAR_OK").unwrap(), Some(42));
+        assert!(env_var::<u64>("PARTAGE_TEST_ENV_VAR_BAD").is_err());
+        assert_eq!(
+            env_var::<u64>("PARTAGE_TEST_ENV_VAR_MISSING").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_config_file() {
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            r#"
+            port = 8080
+            database_url = "sqlite://partage.db"
+            persist_interval_seconds = 5
+            require_auth = true
+            default_room_content = "This pad is public."
+            ip_denylist = "192.0.2.0/24, 2001:db8::/32"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.database_url.as_deref(), Some("sqlite://partage.db"));
+        assert_eq!(config.persist_interval, Duration::from_secs(5));
+        assert!(config.require_auth);
+        assert_eq!(
+            config.default_room_content.as_deref(),
+            Some("This pad is public.")
+        );
+        assert_eq!(config.ip_denylist.len(), 2);
+
+        let unknown = ConfigFile::parse(Path::new("config.toml"), "prot = 8080");
+        assert!(unknown.unwrap_err().to_string().contains("prot"));
+
+        let file = ConfigFile::parse(Path::new("config.toml"), "port = \"eighty\"").unwrap();
+        let error = Config::from_sources(&Sources { file: Some(file) }).unwrap_err();
+        assert!(error.to_string().contains("port"));
+
+        let file = ConfigFile::parse(Path::new("config.toml"), "port = [80]").unwrap();
+        assert!(Config::from_sources(&Sources { file: Some(file) }).is_err());
+
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "sqlite_journal_mode = \"delete\"\nsqlite_busy_timeout_seconds = 30",
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        assert_eq!(config.sqlite.journal_mode, SqliteJournalMode::Delete);
+        assert_eq!(config.sqlite.synchronous, SqliteSynchronous::Normal);
+        assert_eq!(config.sqlite.busy_timeout, Duration::from_secs(30));
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "sqlite_synchronous = \"sometimes\"",
+        )
+        .unwrap();
+        assert!(Config::from_sources(&Sources { file: Some(file) }).is_err());
+
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "s3_bucket = \"partage\"\ns3_endpoint = \"http://minio:9000\"",
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        let mirror = config.mirror.unwrap();
+        assert_eq!(mirror.bucket, "partage");
+        assert_eq!(mirror.endpoint.as_deref(), Some("http://minio:9000"));
+        assert_eq!(mirror.region, "us-east-1");
+
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "reserved_room_names = \"admin|api\"",
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        let reserved = config.reserved_room_names.unwrap();
+        assert!(reserved.is_match("api"));
+        assert!(!reserved.is_match("rapid"));
+        let file =
+            ConfigFile::parse(Path::new("config.toml"), "reserved_room_names = \"(\"").unwrap();
+        assert!(Config::from_sources(&Sources { file: Some(file) }).is_err());
+
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "otel_exporter_otlp_endpoint = \"http://tempo:4317\"",
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        assert_eq!(
+            config.otlp,
+            Some(OtlpConfig {
+                endpoint: "http://tempo:4317".to_string(),
+                service_name: "partage".to_string(),
+            })
+        );
+
+        let file = ConfigFile::parse(Path::new("config.toml"), "tls_cert_path = \"cert.pem\"");
+        let error = Config::from_sources(&Sources {
+            file: Some(file.unwrap()),
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("TLS_KEY_PATH"));
+
+        let file = ConfigFile::parse(
+            Path::new("config.toml"),
+            "listen = \"unix:/run/partage.sock\"\nunix_socket_mode = \"600\"",
+        )
+        .unwrap();
+        let config = Config::from_sources(&Sources { file: Some(file) }).unwrap();
+        assert_eq!(config.unix_socket, Some(PathBuf::from("/run/partage.sock")));
+        assert_eq!(config.unix_socket_mode, 0o600);
+        assert_eq!(
+            config.trusted_proxies,
+            ip_filter::parse_list("127.0.0.1").unwrap()
+        );
+        let file = ConfigFile::parse(Path::new("config.toml"), "listen = \"0.0.0.0:80\"").unwrap();
+        assert!(Config::from_sources(&Sources { file: Some(file) }).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let sources = Sources {
+            file: Some(ConfigFile {
+                path: PathBuf::from("config.toml"),
+                values: HashMap::from([(
+                    "partage_test_config_keep".to_string(),
+                    toml::Value::Integer(3),
+                )]),
+            }),
+        };
+        assert_eq!(
+            sources.parse::<usize>("PARTAGE_TEST_CONFIG_KEEP").unwrap(),
+            Some(3)
+        );
+        std::env::set_var("PARTAGE_TEST_CONFIG_KEEP", "5");
+        assert_eq!(
+            sources.parse::<usize>("PARTAGE_TEST_CONFIG_KEEP").unwrap(),
+            Some(5)
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use ts_rs::TS;
+
+/// WebSocket connection that joined a room
+#[derive(Debug)]
+struct Connection {
+    username: String,
+    room: String,
+    ip: IpAddr,
+    connected_at: i64,
+    /// Cancelled to disconnect the client
+    disconnect: CancellationToken,
+    /// Disconnected by a kick, which the client is told about
+    kicked: bool,
+}
+
+/// Connection, as listed by `GET /api/admin/connections`
+#[derive(TS, Debug, Clone, PartialEq, Eq, Serialize)]
+#[ts(export)]
+pub(crate) struct ConnectionInfo {
+    #[ts(type = "number")]
+    pub(crate) id: u64,
+    pub(crate) username: String,
+    pub(crate) room: String,
+    #[ts(type = "string")]
+    pub(crate) ip: IpAddr,
+    #[ts(type = "number")]
+    pub(crate) connected_at: i64,
+}
+
+/// Open WebSocket connections, whether they joined a room or not
+#[derive(Debug, Default)]
+struct OpenSockets {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Registry of the connected clients, so they can be listed, disconnected and muted
+#[derive(Debug, Default)]
+pub(crate) struct Connections {
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<u64, Connection>>,
+    open: Mutex<OpenSockets>,
+    /// Unix timestamp until which a user can't write to a room, by room and username.
+    /// Kept across reconnections.
+    mutes: Mutex<HashMap<(String, String), i64>>,
+}
+
+/// Open WebSocket connection, counted against the limits until dropped
+#[derive(Debug)]
+pub(crate) struct OpenSocket<'a> {
+    connections: &'a Connections,
+    ip: IpAddr,
+}
+
+impl Drop for OpenSocket<'_> {
+    fn drop(&mut self) {
+        let mut open = self.connections.open.lock().unwrap();
+        open.total -= 1;
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+        drop(open);
+    }
+}
+
+impl Connections {
+    /// Count a new WebSocket connection from `ip`, `None` if there are already `max` of them,
+    /// or `max_per_ip` from this IP. A limit of 0 is no limit.
+    pub(crate) fn open(&self, ip: IpAddr, max: usize, max_per_ip: usize) -> Option<OpenSocket<'_>> {
+        let ip = ip.to_canonical();
+        let mut open = self.open.lock().unwrap();
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or_default();
+        if (max > 0 && open.total >= max) || (max_per_ip > 0 && from_ip >= max_per_ip) {
+            return None;
+        }
+        open.total += 1;
+        *open.per_ip.entry(ip).or_default() += 1;
+        drop(open);
+        Some(OpenSocket {
+            connections: self,
+            ip,
+        })
+    }
+
+    /// Register a client that joined a room.
+    /// Returns its id and a token cancelled when it must be disconnected.
+    pub(crate) fn register(
+        &self,
+        username: &str,
+        room: &str,
+        ip: IpAddr,
+        connected_at: i64,
+    ) -> (u64, CancellationToken) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let disconnect = CancellationToken::new();
+        self.connections.lock().unwrap().insert(
+            id,
+            Connection {
+                username: username.to_string(),
+                room: room.to_string(),
+                ip,
+                connected_at,
+                disconnect: disconnect.clone(),
+                kicked: false,
+            },
+        );
+        (id, disconnect)
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+
+    /// Connected clients, oldest first
+    pub(crate) fn list(&self) -> Vec<ConnectionInfo> {
+        let mut list: Vec<_> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, connection)| ConnectionInfo {
+                id: *id,
+                username: connection.username.clone(),
+                room: connection.room.clone(),
+                ip: connection.ip,
+                connected_at: connection.connected_at,
+            })
+            .collect();
+        list.sort_by_key(|connection| connection.id);
+        list
+    }
+
+    /// Disconnect the clients of a room, only those of `username` if set.
+    /// Returns the number of disconnected clients.
+    pub(crate) fn disconnect(&self, room: &str, username: Option<&str>) -> usize {
+        let connections = self.connections.lock().unwrap();
+        let mut count = 0;
+        for connection in connections.values().filter(|connection| {
+            connection.room == room
+                && (username.is_none() || username == Some(connection.username.as_str()))
+        }) {
+            connection.disconnect.cancel();
+            count += 1;
+        }
+        drop(connections);
+        count
+    }
+
+    /// Disconnect the clients of `username` from a room, telling them they were kicked.
+    /// Returns the number of disconnected clients.
+    pub(crate) fn kick(&self, room: &str, username: &str) -> usize {
+        let mut connections = self.connections.lock().unwrap();
+        let mut count = 0;
+        for connection in connections
+            .values_mut()
+            .filter(|connection| connection.room == room && connection.username == username)
+        {
+            connection.kicked = true;
+            connection.disconnect.cancel();
+            count += 1;
+        }
+        drop(connections);
+        count
+    }
+
+    /// Whether a connection was disconnected by a kick
+    pub(crate) fn was_kicked(&self, id: u64) -> bool {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|connection| connection.kicked)
+    }
+
+    /// Drop what `username` writes to a room until `until`, if it is connected to it.
+    /// Returns the number of its clients connected to the room.
+    pub(crate) fn mute(&self, room: &str, username: &str, until: i64) -> usize {
+        let count = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|connection| connection.room == room && connection.username == username)
+            .count();
+        if count > 0 {
+            self.mutes
+                .lock()
+                .unwrap()
+                .insert((room.to_string(), username.to_string()), until);
+        }
+        count
+    }
+
+    /// Let `username` write to a room again, `false` if it wasn't muted
+    pub(crate) fn unmute(&self, room: &str, username: &str) -> bool {
+        self.mutes
+            .lock()
+            .unwrap()
+            .remove(&(room.to_string(), username.to_string()))
+            .is_some()
+    }
+
+    /// Until when `username` can't write to a room at `now`, `None` if it can
+    pub(crate) fn muted_until(&self, room: &str, username: &str, now: i64) -> Option<i64> {
+        let mut mutes = self.mutes.lock().unwrap();
+        let key = (room.to_string(), username.to_string());
+        let until = *mutes.get(&key)?;
+        if until <= now {
+            mutes.remove(&key);
+            return None;
+        }
+        Some(until)
+    }
+
+    /// Disconnect every client connected from `ip`, returns the number of disconnected clients
+    pub(crate) fn disconnect_ip(&self, ip: IpAddr) -> usize {
+        let connections = self.connections.lock().unwrap();
+        let mut count = 0;
+        for connection in connections
+            .values()
+            .filter(|connection| connection.ip.to_canonical() == ip)
+        {
+            connection.disconnect.cancel();
+            count += 1;
+        }
+        drop(connections);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Connections;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn test_connections() {
+        let connections = Connections::default();
+        let (alice, alice_token) = connections.register("alice", "room", IP, 1);
+        let (_, bob_token) = connections.register("bob", "room", IP, 2);
+        let (_, carol_token) = connections.register("carol", "other", IP, 3);
+
+        let list = connections.list();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].username, "alice");
+
+        assert_eq!(connections.disconnect("room", Some("bob")), 1);
+        assert!(bob_token.is_cancelled());
+        assert!(!alice_token.is_cancelled());
+
+        assert_eq!(connections.disconnect("room", None), 2);
+        assert!(alice_token.is_cancelled());
+        assert!(!carol_token.is_cancelled());
+
+        connections.unregister(alice);
+        assert_eq!(connections.list().len(), 2);
+        assert_eq!(connections.disconnect("room", Some("alice")), 0);
+
+        let (_, dave_token) = connections.register("dave", "other", OTHER_IP, 4);
+        assert_eq!(connections.disconnect_ip(OTHER_IP), 1);
+        assert!(dave_token.is_cancelled());
+    }
+
+    #[test]
+    fn test_kick() {
+        let connections = Connections::default();
+        let (alice, alice_token) = connections.register("alice", "room", IP, 1);
+        let (bob, bob_token) = connections.register("bob", "room", IP, 2);
+
+        assert_eq!(connections.kick("room", "alice"), 1);
+        assert!(alice_token.is_cancelled());
+        assert!(connections.was_kicked(alice));
+        assert_eq!(connections.kick("other", "bob"), 0);
+        assert!(!bob_token.is_cancelled());
+
+        connections.disconnect("room", None);
+        assert!(!connections.was_kicked(bob));
+    }
+
+    #[test]
+    fn test_mutes() {
+        let connections = Connections::default();
+        connections.register("alice", "room", IP, 1);
+
+        assert_eq!(connections.mute("room", "alice", 100), 1);
+        assert_eq!(connections.mute("room", "bob", 100), 0);
+        assert_eq!(connections.muted_until("room", "alice", 50), Some(100));
+        assert_eq!(connections.muted_until("other", "alice", 50), None);
+        assert_eq!(connections.muted_until("room", "bob", 50), None);
+        assert!(!connections.unmute("room", "bob"));
+
+        assert!(connections.unmute("room", "alice"));
+        assert_eq!(connections.muted_until("room", "alice", 50), None);
+
+        // Over once it expires
+        connections.mute("room", "alice", 100);
+        assert_eq!(connections.muted_until("room", "alice", 100), None);
+        assert!(!connections.unmute("room", "alice"));
+    }
+
+    #[test]
+    fn test_open_sockets_are_limited() {
+        let connections = Connections::default();
+        let first = connections.open(IP, 3, 2).unwrap();
+        let second = connections.open(IP, 3, 2).unwrap();
+        assert!(connections.open(IP, 3, 2).is_none());
+        let other = connections.open(OTHER_IP, 3, 2).unwrap();
+        // Full, whatever the IP
+        assert!(connections.open(OTHER_IP, 3, 2).is_none());
+
+        drop(first);
+        let third = connections.open(IP, 3, 2).unwrap();
+        drop((second, third, other));
+        assert!(connections.open(IP, 0, 0).is_some());
+    }
+}
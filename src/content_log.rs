@@ -0,0 +1,111 @@
+use crate::metrics::to_hex;
+use sha2::{Digest, Sha256};
+use tracing::Level;
+
+/// Contents that grow past this size at once are reported
+const LARGE_CONTENT_BYTES: usize = 1024 * 1024;
+
+/// Contents this large that shrink at once are reported, typically wiped pads
+const SHRINK_MIN_BYTES: usize = 4 * 1024;
+
+/// Change of size between two edits considered abnormal
+const SIZE_CHANGE_FACTOR: usize = 4;
+
+/// What the server logs about room contents, which are never logged themselves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ContentLog {
+    /// Nothing
+    Off,
+    /// Room id, size and hash of the edits, and warnings on abnormal size changes
+    #[default]
+    Metadata,
+}
+
+impl ContentLog {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "metadata" => Some(Self::Metadata),
+            _ => None,
+        }
+    }
+}
+
+/// Abnormal change of size of a room content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anomaly {
+    Grew,
+    Shrank,
+}
+
+fn anomaly(previous_bytes: usize, bytes: usize) -> Option<Anomaly> {
+    if bytes >= LARGE_CONTENT_BYTES && bytes >= previous_bytes.saturating_mul(SIZE_CHANGE_FACTOR) {
+        Some(Anomaly::Grew)
+    } else if previous_bytes >= SHRINK_MIN_BYTES
+        && bytes.saturating_mul(SIZE_CHANGE_FACTOR) <= previous_bytes
+    {
+        Some(Anomaly::Shrank)
+    } else {
+        None
+    }
+}
+
+/// Short fingerprint of a content, enough to tell versions apart in logs
+fn fingerprint(content: &str) -> String {
+    to_hex(&Sha256::digest(content.as_bytes())[..8])
+}
+
+/// Log an edit of a room content
+pub(crate) fn edit(level: ContentLog, room_id: &str, previous_bytes: usize, content: &str) {
+    if level == ContentLog::Off {
+        return;
+    }
+
+    let bytes = content.len();
+    match anomaly(previous_bytes, bytes) {
+        Some(anomaly) => tracing::warn!(
+            room_id,
+            previous_bytes,
+            bytes,
+            sha256 = %fingerprint(content),
+            ?anomaly,
+            "Abnormal room content size change"
+        ),
+        // Hashing every keystroke of a large pad is only worth it when someone reads it
+        None if tracing::enabled!(Level::DEBUG) => tracing::debug!(
+            room_id,
+            bytes,
+            sha256 = %fingerprint(content),
+            "Room content updated"
+        ),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anomaly, fingerprint, Anomaly, ContentLog, LARGE_CONTENT_BYTES};
+
+    #[test]
+    fn test_anomaly() {
+        assert_eq!(anomaly(0, 10), None);
+        assert_eq!(anomaly(10_000, 10_010), None);
+        assert_eq!(anomaly(10, LARGE_CONTENT_BYTES), Some(Anomaly::Grew));
+        assert_eq!(anomaly(LARGE_CONTENT_BYTES, LARGE_CONTENT_BYTES + 1), None);
+        assert_eq!(anomaly(10_000, 0), Some(Anomaly::Shrank));
+        assert_eq!(anomaly(100, 0), None);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint("").len(), 16);
+        assert_ne!(fingerprint("a"), fingerprint("b"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ContentLog::parse("off"), Some(ContentLog::Off));
+        assert_eq!(ContentLog::parse("metadata"), Some(ContentLog::Metadata));
+        assert_eq!(ContentLog::parse("full"), None);
+    }
+}
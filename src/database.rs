@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous,
+};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// SQLite settings of every connection of the pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SqliteTuning {
+    /// `wal` lets readers go on while a room is written
+    pub(crate) journal_mode: SqliteJournalMode,
+    /// `normal` is safe with `wal`, only the last commits can be lost on a power failure
+    pub(crate) synchronous: SqliteSynchronous,
+    /// Time a connection waits for a lock before failing with `database is locked`
+    pub(crate) busy_timeout: Duration,
+    /// Connections of the pool at most
+    pub(crate) max_connections: u32,
+}
+
+impl Default for SqliteTuning {
+    fn default() -> Self {
+        Self {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout: Duration::from_secs(5),
+            max_connections: 10,
+        }
+    }
+}
+
+/// Open a pool of connections to the database at `url`, with the pragmas of `tuning`
+pub(crate) async fn connect(url: &str, tuning: SqliteTuning) -> Result<SqlitePool> {
+    let SqliteTuning {
+        journal_mode,
+        synchronous,
+        busy_timeout,
+        max_connections,
+    } = tuning;
+    let options = SqliteConnectOptions::from_str(url)
+        .with_context(|| format!("Invalid database URL: {url}"))?
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .busy_timeout(busy_timeout);
+    let db = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await?;
+    Ok(db)
+}
+
+/// Open a migrated database living in memory, for servers without `DATABASE_URL`.
+/// It holds the settings, accounts and saved versions of the rooms, their contents being in a
+/// [`MemoryStorage`](crate::MemoryStorage).
+/// The connections of the pool share it, and it is gone once the last one closes, so one is
+/// kept open for good.
+pub(crate) async fn memory() -> Result<SqlitePool> {
+    let db = SqlitePoolOptions::new()
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await?;
+    sqlx::migrate!().run(&db).await?;
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connect, memory, SqliteTuning};
+
+    #[tokio::test]
+    async fn test_connect_applies_pragmas() {
+        let path =
+            std::env::temp_dir().join(format!("partage-{}.db", crate::auth::generate_token()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let db = connect(&url, SqliteTuning::default()).await.unwrap();
+
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+        let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(synchronous, 1); // NORMAL
+        let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(busy_timeout, 5000);
+        db.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+    #[tokio::test]
+    async fn test_memory_is_shared_by_connections() {
+        let db = memory().await.unwrap();
+        let mut writer = db.acquire().await.unwrap();
+        sqlx::query("INSERT INTO rooms (room_id, content) VALUES ('general', 'Hello')")
+            .execute(&mut *writer)
+            .await
+            .unwrap();
+
+        // Another connection of the pool sees the same database
+        let mut reader = db.acquire().await.unwrap();
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'general'")
+                .fetch_one(&mut *reader)
+                .await
+                .unwrap();
+        assert_eq!(content, "Hello");
+    }
+}
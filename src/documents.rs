@@ -0,0 +1,193 @@
+use crate::room_handle::RoomHandle;
+use crate::write_behind::{Listeners, WriteBehind};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteExecutor, SqlitePool};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::sync::watch;
+use ts_rs::TS;
+
+/// Document every room has, stored with the room itself
+pub(crate) const MAIN_DOCUMENT: &str = "main";
+
+/// Documents of a room besides the main one
+pub(crate) const MAX_DOCUMENTS: usize = 16;
+
+const MAX_DOC_ID_LENGTH: usize = 64;
+
+/// Named document of a room, besides its main one
+#[derive(Debug)]
+pub(crate) struct Document {
+    pub(crate) content_tx: watch::Sender<String>,
+    pub(crate) content_rx: watch::Receiver<String>,
+    /// Stops writing the document when it is removed, or with its room
+    _handle: RoomHandle,
+    /// Set while the content has changes not written to the database yet
+    pub(crate) unsaved: Arc<AtomicBool>,
+}
+
+impl Document {
+    pub(crate) fn new(
+        write_behind: &WriteBehind,
+        room_id: &str,
+        doc_id: &str,
+        content: String,
+        listeners: &Listeners,
+        room_handle: &RoomHandle,
+    ) -> Self {
+        let (content_tx, content_rx) = watch::channel(content);
+        let handle = room_handle.child();
+        let unsaved = write_behind.track(room_id, Some(doc_id), &content_rx, listeners, &handle);
+
+        Self {
+            content_tx,
+            content_rx,
+            _handle: handle,
+            unsaved,
+        }
+    }
+}
+
+/// Document of a room, as listed by `GET /api/rooms/:room_id/documents`
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct DocumentInfo {
+    pub(crate) id: String,
+    /// Size of the content in bytes
+    pub(crate) size: usize,
+}
+
+/// Edit sent by the clients that negotiated the `documents` capability
+#[derive(Debug, Deserialize)]
+pub(crate) struct DocumentEdit {
+    pub(crate) doc_id: String,
+    pub(crate) value: String,
+}
+
+impl DocumentEdit {
+    /// Document other than the main one targeted by the edit, if any
+    pub(crate) fn scope(&self) -> Option<String> {
+        (self.doc_id != MAIN_DOCUMENT).then(|| self.doc_id.clone())
+    }
+}
+
+pub(crate) fn validate_doc_id(doc_id: &str) -> Result<(), String> {
+    if doc_id.is_empty()
+        || doc_id.len() > MAX_DOC_ID_LENGTH
+        || !doc_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Document ids are 1 to {MAX_DOC_ID_LENGTH} letters, digits, dashes or underscores."
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a broadcast message is scoped to a document other than the main one.
+/// Clients without the `documents` capability don't get those, they would take them for
+/// the main content. `doc_id` is serialized first, being both the first field of
+/// `SocketMessage` and the first key in alphabetical order.
+pub(crate) fn is_document_message(msg: &str) -> bool {
+    msg.starts_with(r#"{"doc_id":"#)
+}
+
+/// Documents of a room stored in the database, besides the main one
+pub(crate) async fn load(db: &SqlitePool, room_id: &str) -> Result<Vec<(String, String)>> {
+    Ok(sqlx::query_as::<_, (String, String)>(
+        "SELECT doc_id, content FROM documents WHERE room_id = ? ORDER BY doc_id",
+    )
+    .bind(room_id)
+    .fetch_all(db)
+    .await?)
+}
+
+pub(crate) async fn store(
+    db: impl SqliteExecutor<'_>,
+    room_id: &str,
+    doc_id: &str,
+    content: &str,
+) -> Result<()> {
+    sqlx::query(
+        r"
+        INSERT INTO documents (room_id, doc_id, content) VALUES (?, ?, ?)
+        ON CONFLICT (room_id, doc_id) DO UPDATE SET content = excluded.content
+        ",
+    )
+    .bind(room_id)
+    .bind(doc_id)
+    .bind(content)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn delete(db: &SqlitePool, room_id: &str, doc_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM documents WHERE room_id = ? AND doc_id = ?")
+        .bind(room_id)
+        .bind(doc_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM documents WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_document_message, validate_doc_id, DocumentEdit};
+    use crate::{SocketMessage, SocketMessageType};
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_doc_id() {
+        assert!(validate_doc_id("notes").is_ok());
+        assert!(validate_doc_id("chapter_1-draft").is_ok());
+        assert!(validate_doc_id("").is_err());
+        assert!(validate_doc_id("../etc").is_err());
+        assert!(validate_doc_id(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_is_document_message() {
+        let scoped = json!(SocketMessage {
+            doc_id: Some("notes".to_string()),
+            message_type: SocketMessageType::Message,
+            value: Some("{\"doc_id\":".to_string()),
+            code: None,
+            revision: None,
+            username: "alice".to_string(),
+        })
+        .to_string();
+        assert!(is_document_message(&scoped));
+
+        let main = json!(SocketMessage {
+            doc_id: None,
+            message_type: SocketMessageType::Message,
+            value: Some("{\"doc_id\":".to_string()),
+            code: None,
+            revision: None,
+            username: "alice".to_string(),
+        })
+        .to_string();
+        assert!(!is_document_message(&main));
+    }
+
+    #[test]
+    fn test_scope() {
+        let edit = |doc_id: &str| DocumentEdit {
+            doc_id: doc_id.to_string(),
+            value: String::new(),
+        };
+        assert_eq!(edit("main").scope(), None);
+        assert_eq!(edit("notes").scope(), Some("notes".to_string()));
+    }
+}
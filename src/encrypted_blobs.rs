@@ -0,0 +1,171 @@
+use crate::{auth, unix_timestamp, AppState, CustomError};
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+
+/// Lifetime of a blob stored without `?ttl=`, unless `BLOB_MAX_TTL_HOURS` is shorter
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Delay between two purges of the expired blobs
+const PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Query parameters of `POST /api/blobs`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct BlobQuery {
+    /// Seconds the blob is kept for
+    #[serde(default)]
+    ttl: Option<u64>,
+}
+
+/// When a blob stored at `now` expires, `ttl` being capped to `max_ttl`
+fn expires_at(now: i64, ttl: Option<u64>, max_ttl: Duration) -> i64 {
+    let ttl = ttl.map_or(DEFAULT_TTL, Duration::from_secs).min(max_ttl);
+    now.saturating_add(i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX))
+}
+
+/// Store the request body as is, for clients sharing a payload they encrypted themselves:
+/// the server never sees the key, and forgets the payload once it expires
+pub(crate) async fn create_blob(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BlobQuery>,
+    content: Bytes,
+) -> Result<(StatusCode, Json<serde_json::Value>), CustomError> {
+    if content.is_empty() {
+        return Err(CustomError::bad_request("Empty blob."));
+    }
+    if query.ttl == Some(0) {
+        return Err(CustomError::bad_request(
+            "The TTL must be at least 1 second.",
+        ));
+    }
+
+    let id = auth::generate_token();
+    let now = unix_timestamp();
+    let expires_at = expires_at(now, query.ttl, state.config.blob_max_ttl);
+    if let Err(e) = sqlx::query(
+        "INSERT INTO encrypted_blobs (id, content, created_at, expires_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(content.as_ref())
+    .bind(now)
+    .bind(expires_at)
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store blob in database: {e}");
+        return Err(auth::internal_error());
+    }
+
+    println!("Stored blob of {} bytes", content.len());
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "type": "success",
+            "value": { "id": id, "expires_at": expires_at }
+        })),
+    ))
+}
+
+/// Get a blob as it was stored, until it expires
+pub(crate) async fn get_blob(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Response, CustomError> {
+    let content = sqlx::query_scalar::<_, Vec<u8>>(
+        "SELECT content FROM encrypted_blobs WHERE id = ? AND expires_at > ?",
+    )
+    .bind(&id)
+    .bind(unix_timestamp())
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to read blob from database: {e}");
+        auth::internal_error()
+    })?
+    .ok_or_else(|| CustomError::not_found("Blob not found."))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+/// Delete the blobs expired at `now`, returning how many there were
+async fn purge_expired(db: &SqlitePool, now: i64) -> Result<u64> {
+    Ok(
+        sqlx::query("DELETE FROM encrypted_blobs WHERE expires_at <= ?")
+            .bind(now)
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
+
+/// Delete the expired blobs every few minutes, they are already out of reach
+pub(crate) async fn run_purge(state: Arc<AppState>) {
+    let mut interval = time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_expired(&state.db, unix_timestamp()).await {
+            Ok(0) => {}
+            Ok(purged) => println!("Purged {purged} expired blobs"),
+            Err(e) => eprintln!("Failed to purge expired blobs: {e:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expires_at, purge_expired, DEFAULT_TTL};
+    use crate::database;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_expires_at() {
+        let max_ttl = Duration::from_secs(3600);
+        assert_eq!(expires_at(1000, Some(60), max_ttl), 1060);
+        assert_eq!(expires_at(1000, Some(7200), max_ttl), 4600);
+        assert_eq!(expires_at(1000, None, max_ttl), 4600);
+        assert_eq!(
+            expires_at(1000, None, Duration::from_secs(u64::MAX)),
+            1000 + i64::try_from(DEFAULT_TTL.as_secs()).unwrap()
+        );
+        assert_eq!(expires_at(1000, Some(u64::MAX), Duration::MAX), i64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let db = database::memory().await.unwrap();
+
+        for (id, expires_at) in [("old", 100), ("fresh", 2000)] {
+            sqlx::query(
+                "INSERT INTO encrypted_blobs (id, content, created_at, expires_at) VALUES (?, x'00', 0, ?)",
+            )
+            .bind(id)
+            .bind(expires_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        assert_eq!(purge_expired(&db, 1000).await.unwrap(), 1);
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM encrypted_blobs")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, ["fresh"]);
+    }
+}
@@ -0,0 +1,68 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+const MIN_SALT_LENGTH: usize = 16;
+const MAX_SALT_LENGTH: usize = 64;
+
+/// Smallest ciphertext: a 12 bytes nonce and a 16 bytes authentication tag
+const MIN_CIPHERTEXT_LENGTH: usize = 12 + 16;
+
+/// Key derivation parameters of an end-to-end encrypted room, chosen by its creator.
+/// Clients derive the key from a passphrase shared out of band, the server only stores the salt.
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct EncryptionParams {
+    /// Base64 encoded salt
+    pub(crate) salt: String,
+}
+
+impl EncryptionParams {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let salt = STANDARD
+            .decode(&self.salt)
+            .map_err(|_| "Encryption salt must be base64 encoded.".to_string())?;
+        if !(MIN_SALT_LENGTH..=MAX_SALT_LENGTH).contains(&salt.len()) {
+            return Err(format!(
+                "Encryption salt must be between {MIN_SALT_LENGTH} and {MAX_SALT_LENGTH} bytes."
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether the content sent to an encrypted room looks like ciphertext:
+/// empty, or the base64 encoding of a nonce followed by the encrypted content
+pub(crate) fn is_ciphertext(payload: &str) -> bool {
+    payload.is_empty()
+        || STANDARD
+            .decode(payload)
+            .is_ok_and(|bytes| bytes.len() >= MIN_CIPHERTEXT_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_ciphertext, EncryptionParams};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    #[test]
+    fn test_validate_params() {
+        let params = |salt: &str| EncryptionParams {
+            salt: salt.to_string(),
+        };
+        assert!(params(&STANDARD.encode([7u8; 16])).validate().is_ok());
+        assert!(params(&STANDARD.encode([7u8; 8])).validate().is_err());
+        assert!(params(&STANDARD.encode([7u8; 65])).validate().is_err());
+        assert!(params("not base64!").validate().is_err());
+    }
+
+    #[test]
+    fn test_is_ciphertext() {
+        assert!(is_ciphertext(""));
+        assert!(is_ciphertext(&STANDARD.encode([1u8; 40])));
+        assert!(!is_ciphertext(&STANDARD.encode([1u8; 10])));
+        assert!(!is_ciphertext("my secret password"));
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+/// What went wrong, sent along the `error` messages so that clients can tell errors apart
+/// without parsing their text, which is meant for people and may change
+#[derive(TS, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum ErrorCode {
+    /// The message is not the JSON expected
+    InvalidJson,
+    /// The protocol version asked for is not supported
+    ProtocolMismatch,
+    InvalidRoomId,
+    /// Empty, too long or reserved
+    InvalidUsername,
+    /// The username belongs to an account the client is not logged in as
+    UsernameTaken,
+    /// Logging in is required
+    Unauthorized,
+    /// Private room, or a viewer trying to edit
+    Forbidden,
+    /// The encryption parameters are invalid
+    InvalidEncryption,
+    /// Plaintext sent to an encrypted room, or encryption asked for an existing plaintext room
+    EncryptionMismatch,
+    RoomFull,
+    TooManyConnections,
+    /// The server holds as many rooms as it can
+    TooManyRooms,
+    /// The room id can't be created
+    ReservedRoomId,
+    RoomTrashed,
+    RoomArchived,
+    /// The room is being deleted
+    RoomClosing,
+    /// The room can't be edited for now, the client gets its content back
+    RoomFrozen,
+    /// Another member holds the write lock, the client gets its content back
+    RoomLocked,
+    /// The edit changes lines another member claimed, the client gets its content back, or
+    /// the lines claimed overlap theirs
+    LineClaimed,
+    /// Too many messages, or rooms created
+    RateLimited,
+    /// The messages of the client are dropped until its mute ends
+    Muted,
+    PayloadTooLarge,
+    /// An edit could not be merged with the changes made since, the client gets the content back
+    EditConflict,
+    /// An operation was refused, the client gets the document back
+    OperationRejected,
+    /// Invalid document id, or too many documents
+    InvalidDocument,
+    InvalidLanguage,
+    /// Invalid label, or too many checkpoints
+    InvalidCheckpoint,
+    /// Range of a document out of its bounds, or of a document that doesn't exist
+    InvalidRange,
+    Internal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorCode;
+
+    #[test]
+    fn test_codes_are_kebab_case() {
+        assert_eq!(
+            serde_json::to_value(ErrorCode::RoomFull).unwrap(),
+            "room-full"
+        );
+        assert_eq!(
+            serde_json::to_value(ErrorCode::InvalidJson).unwrap(),
+            "invalid-json"
+        );
+    }
+}
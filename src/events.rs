@@ -0,0 +1,104 @@
+use crate::{ensure_room_loaded, resync_messages, revisions, AppState, CustomError};
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Types of the room messages streamed to the event consumers
+const STREAMED_TYPES: &[&str] = &["message", "encrypted", "presence"];
+
+/// Type of a room message, if it is streamed
+fn streamed_type(message: &str) -> Option<String> {
+    let message: serde_json::Value = serde_json::from_str(message).ok()?;
+    let message_type = message["type"].as_str()?;
+    STREAMED_TYPES
+        .contains(&message_type)
+        .then(|| message_type.to_string())
+}
+
+struct EventsState {
+    state: Arc<AppState>,
+    room_id: String,
+    rx: broadcast::Receiver<String>,
+    /// Messages to send before the next broadcast ones
+    pending: VecDeque<String>,
+}
+
+/// Stream the content updates and the presence of a room as Server-Sent Events, named after
+/// the type of their WebSocket message which is their data.
+/// The stream starts with the current contents and users, and ends when the room is unloaded.
+pub(crate) async fn room_events(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, CustomError> {
+    revisions::request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    if rooms[&room_id].burned().await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let rx = rooms[&room_id].tx.subscribe();
+    drop(rooms);
+    let pending = resync_messages(&state, &room_id).await.into();
+    // The stream starts with the contents, a room burning after reading is wiped then
+    if let Some(room) = state.rooms.lock().await.get(&room_id) {
+        room.burn_on_read(&state, &room_id).await;
+    }
+
+    let events = stream::unfold(
+        EventsState {
+            state,
+            room_id,
+            rx,
+            pending,
+        },
+        |mut events| async move {
+            loop {
+                let message = match events.pending.pop_front() {
+                    Some(message) => message,
+                    None => match events.rx.recv().await {
+                        Ok(message) => message,
+                        // Catch up with the whole state of the room
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            events.pending =
+                                resync_messages(&events.state, &events.room_id).await.into();
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    },
+                };
+                if let Some(message_type) = streamed_type(&message) {
+                    let event = Event::default().event(message_type).data(message);
+                    return Some((Ok(event), events));
+                }
+            }
+        },
+    );
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::streamed_type;
+
+    #[test]
+    fn test_streamed_type() {
+        assert_eq!(
+            streamed_type(r#"{"type":"message","value":"hi","username":"alice"}"#).as_deref(),
+            Some("message")
+        );
+        assert_eq!(
+            streamed_type(r#"{"type":"presence","value":"[]","username":""}"#).as_deref(),
+            Some("presence")
+        );
+        assert_eq!(streamed_type(r#"{"type":"join","username":"alice"}"#), None);
+        assert_eq!(streamed_type("not json"), None);
+    }
+}
@@ -0,0 +1,433 @@
+use crate::client_ip::ClientIp;
+use crate::documents::{self, MAIN_DOCUMENT, MAX_DOCUMENTS};
+use crate::encryption::{self, EncryptionParams};
+use crate::freeze::FreezeSchedule;
+use crate::language::{self, ContentKind, LanguageOverride, LanguagePatch};
+use crate::paste::readable_content;
+use crate::room_id;
+use crate::storage;
+use crate::webhooks::WebhookEvent;
+use crate::write_behind::Write;
+use crate::{
+    auth, check_room_owner, ensure_room_loaded, get_stored_content, trash, unix_timestamp,
+    validate_syntax_language, AppState, CustomError, RoomState, SocketMessage, SocketMessageType,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Version of the bundle format, bundles of later versions are refused
+const EXPORT_VERSION: u32 = 1;
+
+/// Everything needed to recreate a room on this server or another one.
+/// Attachments and the owner are not part of it, accounts differ between servers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RoomExport {
+    version: u32,
+    room_id: String,
+    /// Unix timestamp
+    exported_at: i64,
+    /// Ciphertext for encrypted rooms
+    content: String,
+    /// Documents besides the main one, by id
+    #[serde(default)]
+    documents: BTreeMap<String, String>,
+    #[serde(default)]
+    encryption: Option<EncryptionParams>,
+    #[serde(default)]
+    freeze_schedule: FreezeSchedule,
+    /// Highlighting language
+    #[serde(default)]
+    syntax_language: Option<String>,
+    /// Type of the content when set by hand
+    #[serde(default)]
+    content_kind: Option<ContentKind>,
+    /// Natural language of the content when set by hand
+    #[serde(default)]
+    language: Option<String>,
+}
+
+impl RoomExport {
+    fn validate(&self) -> Result<(), String> {
+        if self.version > EXPORT_VERSION {
+            return Err(format!(
+                "Unsupported export version {}, this server reads up to {EXPORT_VERSION}.",
+                self.version
+            ));
+        }
+        room_id::validate(&self.room_id)?;
+        if self.documents.len() > MAX_DOCUMENTS {
+            return Err(format!(
+                "A room has at most {MAX_DOCUMENTS} documents besides the main one."
+            ));
+        }
+        for doc_id in self.documents.keys() {
+            if doc_id == MAIN_DOCUMENT {
+                return Err("The main document is the content of the room.".to_string());
+            }
+            documents::validate_doc_id(doc_id)?;
+        }
+        if let Some(params) = &self.encryption {
+            params.validate()?;
+            let plaintext = std::iter::once(&self.content)
+                .chain(self.documents.values())
+                .any(|content| !encryption::is_ciphertext(content));
+            if plaintext {
+                return Err("Encrypted rooms only hold ciphertext.".to_string());
+            }
+        }
+        self.freeze_schedule.validate()?;
+        if let Some(language) = &self.syntax_language {
+            validate_syntax_language(language)?;
+        }
+        self.language_override()?;
+        Ok(())
+    }
+
+    fn language_override(&self) -> Result<LanguageOverride, String> {
+        LanguagePatch {
+            kind: Some(self.content_kind),
+            language: Some(self.language.clone()),
+        }
+        .apply(LanguageOverride::default())
+    }
+}
+
+/// Download a room as a JSON bundle, to back it up or move it to another server
+pub(crate) async fn export_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    // Checked before locking the rooms, for the other rooms not to wait on the database
+    check_room_owner(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[&room_id];
+
+    let content = room.content_rx.borrow().clone();
+    let documents = room
+        .documents
+        .lock()
+        .await
+        .iter()
+        .map(|(doc_id, document)| (doc_id.clone(), document.content_rx.borrow().clone()))
+        .collect();
+    let freeze_schedule = room.freeze_schedule.lock().await.clone();
+    let syntax_language = room.syntax_language.lock().await.clone();
+    let encryption = room.encryption.clone();
+    drop(rooms);
+
+    let settings = language::load_override(&state.db, &room_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read room language from database: {e:#}");
+            auth::internal_error()
+        })?;
+
+    let export = RoomExport {
+        version: EXPORT_VERSION,
+        room_id: room_id.clone(),
+        exported_at: unix_timestamp(),
+        content,
+        documents,
+        encryption,
+        freeze_schedule,
+        syntax_language,
+        content_kind: settings.kind,
+        language: settings.language,
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.json\"", file_stem(&room_id)),
+        )],
+        Json(export),
+    )
+        .into_response())
+}
+
+/// `room_id` as a file name without extension, ASCII for every browser to keep it
+fn file_stem(room_id: &str) -> String {
+    room_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Type of file a room is downloaded as
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DownloadFormat {
+    #[default]
+    Txt,
+    Md,
+}
+
+impl DownloadFormat {
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::Txt => "text/plain; charset=utf-8",
+            Self::Md => "text/markdown; charset=utf-8",
+        }
+    }
+
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Txt => "txt",
+            Self::Md => "md",
+        }
+    }
+}
+
+/// Query parameters of `GET /api/rooms/:room_id/download`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct DownloadQuery {
+    #[serde(default)]
+    format: DownloadFormat,
+}
+
+/// Name of the file a room is downloaded as at `timestamp`, so that successive downloads
+/// don't overwrite each other
+fn download_file_name(room_id: &str, timestamp: i64, format: DownloadFormat) -> String {
+    format!("{}-{timestamp}.{}", file_stem(room_id), format.extension())
+}
+
+/// Download the content of a room as a text or Markdown file
+pub(crate) async fn download_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    let content = readable_content(&state, &headers, &room_id).await?;
+    let filename = download_file_name(&room_id, unix_timestamp(), query.format);
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                query.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+/// Recreate a room from a bundle made by [`export_room`], under the same id
+pub(crate) async fn import_room(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(export): Json<RoomExport>,
+) -> Result<Response, CustomError> {
+    export.validate().map_err(CustomError::bad_request)?;
+    let settings = export
+        .language_override()
+        .map_err(CustomError::bad_request)?;
+    // Imported rooms belong to the account importing them, if any
+    let owner_id = auth::current_user(&state, &headers)
+        .await
+        .map(|account| account.id);
+    let room_id = export.room_id;
+
+    let mut rooms = state.rooms.lock().await;
+    if rooms.contains_key(&room_id)
+        || get_stored_content(&state, &room_id).await.is_some()
+        || trash::is_trashed(&state.db, &room_id).await
+    {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            format!("Room {room_id} already exists."),
+        ));
+    }
+    state.room_creation.check(&state.db, ip, &room_id).await?;
+
+    let db = &state.db;
+    let freeze_schedule = serde_json::to_string(&export.freeze_schedule).map_err(|e| {
+        eprintln!("Failed to serialize freeze schedule: {e}");
+        auth::internal_error()
+    })?;
+    let stored = sqlx::query(
+        r"
+        INSERT INTO rooms (
+            room_id, content, owner_id, encrypted, encryption_salt, freeze_schedule,
+            syntax_language, content_kind, language
+        )
+        VALUES (?, '', ?, ?, ?, ?, ?, ?, ?)
+        ",
+    )
+    .bind(&room_id)
+    .bind(owner_id)
+    .bind(export.encryption.is_some())
+    .bind(
+        export
+            .encryption
+            .as_ref()
+            .map(|params| params.salt.as_str()),
+    )
+    .bind(freeze_schedule)
+    .bind(export.syntax_language.as_deref())
+    .bind(settings.kind.map(ContentKind::as_str))
+    .bind(settings.language.as_deref())
+    .execute(db)
+    .await;
+    if let Err(e) = stored {
+        eprintln!("Failed to store imported room in database: {e}");
+        return Err(auth::internal_error());
+    }
+    let writes = Write::room(&room_id, &export.content, &export.documents);
+    if let Err(e) = storage::store(db, state.storage.as_ref(), &writes).await {
+        eprintln!("Failed to store imported room contents: {e:#}");
+        return Err(auth::internal_error());
+    }
+    if let Some(mirror) = &state.mirror {
+        mirror.snapshot_room(&room_id, &export.content, &export.documents);
+    }
+
+    let room_state = RoomState::new(&room_id, &state.write_behind)
+        .with_freeze_schedule(export.freeze_schedule)
+        .with_encryption(export.encryption)
+        .with_syntax_language(export.syntax_language)
+        .with_documents(
+            &state.write_behind,
+            &room_id,
+            export.documents.into_iter().collect(),
+        );
+    let _ = room_state.content_tx.send(export.content);
+    rooms.insert(room_id.clone(), room_state);
+
+    for room_state in rooms.values() {
+        let _ = room_state.tx.send(
+            json!(SocketMessage {
+                doc_id: None,
+                message_type: SocketMessageType::UpdateRoomsList,
+                value: None,
+                code: None,
+                revision: None,
+                username: String::new(),
+            })
+            .to_string(),
+        );
+    }
+    drop(rooms);
+
+    println!("Imported room {room_id}");
+    state
+        .webhooks
+        .emit(WebhookEvent::RoomCreated, &room_id, json!({}))
+        .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "type": "success",
+            "value": room_id
+        })),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{download_file_name, DownloadFormat, RoomExport, EXPORT_VERSION};
+    use crate::freeze::FreezeSchedule;
+    use std::collections::BTreeMap;
+
+    fn export() -> RoomExport {
+        RoomExport {
+            version: EXPORT_VERSION,
+            room_id: "notes".to_string(),
+            exported_at: 0,
+            content: "Hello".to_string(),
+            documents: BTreeMap::from([("todo".to_string(), "- [ ] a".to_string())]),
+            encryption: None,
+            freeze_schedule: FreezeSchedule::default(),
+            syntax_language: Some("markdown".to_string()),
+            content_kind: None,
+            language: Some("en".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(export().validate().is_ok());
+
+        let invalid = [
+            RoomExport {
+                version: EXPORT_VERSION + 1,
+                ..export()
+            },
+            RoomExport {
+                room_id: String::new(),
+                ..export()
+            },
+            RoomExport {
+                room_id: "a/b".to_string(),
+                ..export()
+            },
+            RoomExport {
+                documents: BTreeMap::from([("../x".to_string(), String::new())]),
+                ..export()
+            },
+            RoomExport {
+                documents: BTreeMap::from([("main".to_string(), String::new())]),
+                ..export()
+            },
+            RoomExport {
+                syntax_language: Some("<script>".to_string()),
+                ..export()
+            },
+            RoomExport {
+                language: Some("english".to_string()),
+                ..export()
+            },
+        ];
+        for export in invalid {
+            assert!(export.validate().is_err(), "{export:?}");
+        }
+    }
+
+    #[test]
+    fn test_minimal_bundle() {
+        // Only what every version of the format has is required
+        let export: RoomExport = serde_json::from_str(
+            r#"{"version": 1, "room_id": "a", "exported_at": 0, "content": "x"}"#,
+        )
+        .unwrap();
+        assert!(export.documents.is_empty());
+        assert!(export.validate().is_ok());
+    }
+
+    #[test]
+    fn test_download_file_name() {
+        assert_eq!(
+            download_file_name("notes", 1_700_000_000, DownloadFormat::Md),
+            "notes-1700000000.md"
+        );
+        assert_eq!(
+            download_file_name("café", 1, DownloadFormat::Txt),
+            "caf_-1.txt"
+        );
+    }
+}
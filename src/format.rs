@@ -0,0 +1,141 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+
+/// Maximum time an external formatter may run
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Formatter applied by `POST /api/rooms/:room_id/format`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Formatter {
+    /// Built-in markdown normalizer
+    #[default]
+    Markdown,
+    /// External command configured with `FORMATTER_COMMAND`
+    Command,
+}
+
+/// Normalize markdown: unix line endings, no trailing whitespace (except hard breaks),
+/// `-` bullets, at most one blank line in a row and a single final newline.
+/// Fenced code blocks are left untouched.
+pub(crate) fn normalize_markdown(content: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            lines.push(line.to_string());
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some(marker);
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+
+        // Collapse consecutive blank lines
+        if line.trim().is_empty() {
+            if lines.last().is_some_and(|last| !last.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        let hard_break = trimmed.ends_with("  ");
+        let mut text = trimmed.trim_end().to_string();
+
+        // `*` and `+` bullets become `-`
+        if let Some(rest) = text.strip_prefix("* ").or_else(|| text.strip_prefix("+ ")) {
+            text = format!("- {rest}");
+        }
+
+        if hard_break {
+            text.push_str("  ");
+        }
+        lines.push(format!("{indent}{text}"));
+    }
+
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+
+    let mut formatted = lines.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    formatted
+}
+
+/// Run an external formatter, passing the content on stdin and reading the result from stdout
+pub(crate) async fn run_command(command: &str, content: &str) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to spawn formatter")?;
+
+    let mut stdin = child.stdin.take().context("Formatter has no stdin")?;
+    let input = content.to_string();
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(input.as_bytes()).await;
+    });
+
+    let output = time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+        .await
+        .context("Formatter timed out")??;
+    let _ = writer.await;
+
+    if !output.status.success() {
+        bail!(
+            "Formatter exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    String::from_utf8(output.stdout).context("Formatter output is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_markdown, run_command};
+
+    #[test]
+    fn test_normalize_markdown() {
+        let input =
+            "\r\n# Title \r\n\r\n\r\n\r\n* one \n+ two\n  * nested\nhard break  \nend\n\n\n";
+        let expected = "# Title\n\n- one\n- two\n  - nested\nhard break  \nend\n";
+        assert_eq!(normalize_markdown(input), expected);
+
+        // Already normalized content is left as is
+        assert_eq!(normalize_markdown(expected), expected);
+        assert_eq!(normalize_markdown(""), "");
+    }
+
+    #[test]
+    fn test_normalize_markdown_keeps_code_blocks() {
+        let input = "```rust\n#[derive(Debug)]\n* not a bullet   \n\n\n\n```\n* bullet";
+        let expected = "```rust\n#[derive(Debug)]\n* not a bullet   \n\n\n\n```\n- bullet\n";
+        assert_eq!(normalize_markdown(input), expected);
+    }
+
+    #[tokio::test]
+    async fn test_run_command() {
+        assert_eq!(run_command("tr a-z A-Z", "hello").await.unwrap(), "HELLO");
+        assert!(run_command("exit 3", "hello").await.is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+/// Maximum number of windows in a schedule
+const MAX_WINDOWS: usize = 64;
+
+/// Day of the week
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub(crate) enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    /// Days since Monday
+    const fn index(self) -> u32 {
+        match self {
+            Self::Monday => 0,
+            Self::Tuesday => 1,
+            Self::Wednesday => 2,
+            Self::Thursday => 3,
+            Self::Friday => 4,
+            Self::Saturday => 5,
+            Self::Sunday => 6,
+        }
+    }
+}
+
+/// Weekly read-only window of a room, `start` and `end` are `HH:MM` times in UTC
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct FreezeWindow {
+    pub(crate) day: Weekday,
+    pub(crate) start: String,
+    pub(crate) end: String,
+}
+
+impl FreezeWindow {
+    /// Start and end of the window, in minutes since Monday 00:00
+    fn bounds(&self) -> Option<(u32, u32)> {
+        let start = parse_time(&self.start)?;
+        let end = parse_time(&self.end)?;
+        let day = self.day.index() * MINUTES_PER_DAY;
+        (start < end).then_some((day + start, day + end))
+    }
+}
+
+/// Read-only windows of a room, repeated every week
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct FreezeSchedule {
+    pub(crate) windows: Vec<FreezeWindow>,
+}
+
+impl FreezeSchedule {
+    /// Parse a schedule stored in the database, an invalid one is treated as empty
+    pub(crate) fn from_stored(stored: Option<&str>) -> Self {
+        stored
+            .and_then(|json| serde_json::from_str::<Self>(json).ok())
+            .filter(|schedule| schedule.validate().is_ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.windows.len() > MAX_WINDOWS {
+            return Err(format!("A schedule has at most {MAX_WINDOWS} windows."));
+        }
+        for window in &self.windows {
+            if window.bounds().is_none() {
+                return Err(format!(
+                    "Invalid window {:?} {}-{}, expected HH:MM times with start before end.",
+                    window.day, window.start, window.end
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// End of the window containing `minute` (since Monday 00:00), if any
+    fn window_end(&self, minute: u32) -> Option<u32> {
+        self.windows
+            .iter()
+            .filter_map(FreezeWindow::bounds)
+            .filter(|(start, end)| (*start..*end).contains(&minute))
+            .map(|(_, end)| end)
+            .max()
+    }
+
+    /// If the room is frozen at `timestamp`, when it will be writable again.
+    /// Back-to-back windows count as a single freeze.
+    pub(crate) fn frozen_until(&self, timestamp: i64) -> Option<i64> {
+        let minute = minute_of_week(timestamp);
+        let mut until = self.window_end(minute)?;
+
+        // Follow adjacent windows, possibly into the next week
+        while until - minute < MINUTES_PER_WEEK {
+            let week_start = until - until % MINUTES_PER_WEEK;
+            match self.window_end(until % MINUTES_PER_WEEK) {
+                Some(end) if week_start + end > until => until = week_start + end,
+                _ => break,
+            }
+        }
+
+        let minute_start = timestamp - timestamp.rem_euclid(60);
+        Some(minute_start + i64::from(until - minute) * 60)
+    }
+}
+
+/// Parse a `HH:MM` time into minutes since midnight, `24:00` being the end of the day
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    let time = hours * 60 + minutes;
+    (time <= MINUTES_PER_DAY).then_some(time)
+}
+
+/// Minutes elapsed since Monday 00:00 UTC
+fn minute_of_week(timestamp: i64) -> u32 {
+    // The Unix epoch was a Thursday
+    let minutes = (timestamp.div_euclid(60) + 3 * i64::from(MINUTES_PER_DAY))
+        .rem_euclid(i64::from(MINUTES_PER_WEEK));
+    u32::try_from(minutes).unwrap_or_default()
+}
+
+/// `HH:MM` time of a timestamp, in UTC
+pub(crate) fn format_time(timestamp: i64) -> String {
+    let minutes = timestamp
+        .div_euclid(60)
+        .rem_euclid(i64::from(MINUTES_PER_DAY));
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_time, parse_time, FreezeSchedule, FreezeWindow, Weekday};
+
+    /// Friday 2026-10-16 17:30:00 UTC
+    const FRIDAY_17_30: i64 = 1_792_171_800;
+
+    fn window(day: Weekday, start: &str, end: &str) -> FreezeWindow {
+        FreezeWindow {
+            day,
+            start: start.to_string(),
+            end: end.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("00:00"), Some(0));
+        assert_eq!(parse_time("16:30"), Some(990));
+        assert_eq!(parse_time("24:00"), Some(1440));
+        assert_eq!(parse_time("24:01"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("9:00"), None);
+        assert_eq!(parse_time("noon"), None);
+    }
+
+    #[test]
+    fn test_frozen_until() {
+        let schedule = FreezeSchedule {
+            windows: vec![window(Weekday::Friday, "16:00", "18:00")],
+        };
+        assert!(schedule.validate().is_ok());
+        assert_eq!(format_time(FRIDAY_17_30), "17:30");
+
+        let until = schedule.frozen_until(FRIDAY_17_30).unwrap();
+        assert_eq!(until, FRIDAY_17_30 + 30 * 60);
+        assert_eq!(format_time(until), "18:00");
+
+        // The end of a window is writable again
+        assert_eq!(schedule.frozen_until(until), None);
+        assert_eq!(schedule.frozen_until(FRIDAY_17_30 - 2 * 60 * 60), None);
+        assert!(schedule
+            .frozen_until(FRIDAY_17_30 + 7 * 24 * 60 * 60)
+            .is_some());
+        assert_eq!(FreezeSchedule::default().frozen_until(FRIDAY_17_30), None);
+    }
+
+    #[test]
+    fn test_adjacent_windows() {
+        // Friday evening to Monday morning, across the end of the week
+        let schedule = FreezeSchedule {
+            windows: vec![
+                window(Weekday::Friday, "17:00", "24:00"),
+                window(Weekday::Saturday, "00:00", "24:00"),
+                window(Weekday::Sunday, "00:00", "24:00"),
+                window(Weekday::Monday, "00:00", "08:00"),
+            ],
+        };
+        let until = schedule.frozen_until(FRIDAY_17_30).unwrap();
+        assert_eq!(
+            until,
+            FRIDAY_17_30 + (6 * 60 + 30 + 2 * 24 * 60 + 8 * 60) * 60
+        );
+
+        // Frozen all week long
+        let always = FreezeSchedule {
+            windows: [
+                Weekday::Monday,
+                Weekday::Tuesday,
+                Weekday::Wednesday,
+                Weekday::Thursday,
+                Weekday::Friday,
+                Weekday::Saturday,
+                Weekday::Sunday,
+            ]
+            .into_iter()
+            .map(|day| window(day, "00:00", "24:00"))
+            .collect(),
+        };
+        assert!(always.frozen_until(FRIDAY_17_30).is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = FreezeSchedule {
+            windows: vec![window(Weekday::Monday, "18:00", "16:00")],
+        };
+        assert!(invalid.validate().is_err());
+
+        // Invalid stored schedules are ignored rather than enforced
+        let stored = serde_json::to_string(&invalid).unwrap();
+        assert_eq!(
+            FreezeSchedule::from_stored(Some(&stored)),
+            FreezeSchedule::default()
+        );
+        assert_eq!(FreezeSchedule::from_stored(None), FreezeSchedule::default());
+    }
+}
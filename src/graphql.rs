@@ -0,0 +1,280 @@
+use crate::{auth, ensure_room_loaded, members, revisions, AppState, RoomState};
+use async_graphql::http::ALL_WEBSOCKET_PROTOCOLS;
+use async_graphql::{
+    Context, Data, EmptyMutation, Object, Result, Schema, SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQLBatchRequest, GraphQLProtocol, GraphQLResponse, GraphQLWebSocket};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::Extension;
+use futures::stream::{self, Stream};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, MutexGuard};
+
+/// Schema served at `/api/graphql`, subscriptions at `/api/graphql/ws`
+pub(crate) type PartageSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+pub(crate) fn schema(state: Arc<AppState>) -> PartageSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(state)
+        .finish()
+}
+
+/// `POST /api/graphql`, the headers of the request given to the resolvers for them to check
+/// the access to the rooms
+pub(crate) async fn execute(
+    Extension(schema): Extension<PartageSchema>,
+    headers: HeaderMap,
+    request: GraphQLBatchRequest,
+) -> GraphQLResponse {
+    schema
+        .execute_batch(request.into_inner().data(headers))
+        .await
+        .into()
+}
+
+/// `GET /api/graphql/ws`, subscriptions over a WebSocket, see [`execute`]
+pub(crate) async fn subscribe(
+    Extension(schema): Extension<PartageSchema>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            let mut data = Data::default();
+            data.insert(headers);
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .with_data(data)
+                .serve()
+        })
+}
+
+/// Fail unless the user of the request can read the room, private rooms are only read by
+/// their members
+async fn check_access(ctx: &Context<'_>, room_id: &str) -> Result<()> {
+    let state = ctx.data::<Arc<AppState>>()?;
+    let headers = ctx.data::<HeaderMap>()?;
+    revisions::request_access(state, headers, room_id)
+        .await
+        .map_err(|e| e.message)?;
+    Ok(())
+}
+
+/// Room, loaded from the database when one of its fields besides `id` is asked for.
+/// Only built for the rooms the user of the request can read.
+struct Room {
+    id: String,
+}
+
+#[derive(SimpleObject)]
+struct Document {
+    id: String,
+    content: String,
+}
+
+/// Edit of a document broadcast to the members of a room
+#[derive(SimpleObject, Deserialize)]
+struct ContentUpdate {
+    /// Document edited, the main one if unset
+    #[serde(default)]
+    doc_id: Option<String>,
+    /// Content of the document after the edit, ciphertext in encrypted rooms
+    value: String,
+    username: String,
+}
+
+impl ContentUpdate {
+    fn parse(message: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Tagged {
+            #[serde(rename = "type")]
+            message_type: String,
+            #[serde(flatten)]
+            update: ContentUpdate,
+        }
+        let tagged: Tagged = serde_json::from_str(message).ok()?;
+        matches!(tagged.message_type.as_str(), "message" | "encrypted").then_some(tagged.update)
+    }
+}
+
+impl Room {
+    /// Loaded rooms, this one included, `None` if it was removed
+    async fn loaded<'a>(
+        &self,
+        state: &'a AppState,
+    ) -> Option<MutexGuard<'a, HashMap<String, RoomState>>> {
+        let mut rooms = state.rooms.lock().await;
+        ensure_room_loaded(state, &mut rooms, &self.id)
+            .await
+            .then_some(rooms)
+    }
+}
+
+#[Object]
+impl Room {
+    async fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Names of the users in the room, sorted
+    async fn users(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        let Some(rooms) = self.loaded(ctx.data::<Arc<AppState>>()?).await else {
+            return Ok(Vec::new());
+        };
+        let mut users: Vec<String> = rooms[&self.id]
+            .users
+            .lock()
+            .await
+            .names()
+            .cloned()
+            .collect();
+        drop(rooms);
+        users.sort();
+        Ok(users)
+    }
+
+    /// End-to-end encrypted, the contents are ciphertext
+    async fn encrypted(&self, ctx: &Context<'_>) -> Result<bool> {
+        let rooms = self.loaded(ctx.data::<Arc<AppState>>()?).await;
+        Ok(rooms.is_some_and(|rooms| rooms[&self.id].encryption.is_some()))
+    }
+
+    /// Language used to highlight the content
+    async fn language(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        let Some(rooms) = self.loaded(ctx.data::<Arc<AppState>>()?).await else {
+            return Ok(None);
+        };
+        let language = rooms[&self.id].syntax_language.lock().await.clone();
+        drop(rooms);
+        Ok(language)
+    }
+
+    /// Main content, the room is wiped once read if it burns after reading
+    async fn content(&self, ctx: &Context<'_>) -> Result<String> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let Some(rooms) = self.loaded(state).await else {
+            return Ok(String::new());
+        };
+        let content = rooms[&self.id]
+            .read_content(state, &self.id)
+            .await
+            .map_err(|e| e.message)?;
+        drop(rooms);
+        Ok(content)
+    }
+
+    /// Documents besides the main one, sorted by id
+    async fn documents(&self, ctx: &Context<'_>) -> Result<Vec<Document>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let Some(rooms) = self.loaded(state).await else {
+            return Ok(Vec::new());
+        };
+        let room = &rooms[&self.id];
+        let mut documents: Vec<Document> = room
+            .documents
+            .lock()
+            .await
+            .iter()
+            .map(|(id, document)| Document {
+                id: id.clone(),
+                content: document.content_rx.borrow().clone(),
+            })
+            .collect();
+        room.burn_on_read(state, &self.id).await;
+        drop(rooms);
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(documents)
+    }
+}
+
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Rooms, sorted by id. Unlisted and private ones are only listed for their members.
+    async fn rooms(&self, ctx: &Context<'_>) -> Result<Vec<Room>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let user_id = auth::current_user(state, ctx.data::<HeaderMap>()?)
+            .await
+            .map(|account| account.id);
+        let hidden = members::hidden_rooms(&state.db, user_id).await?;
+        let mut ids: Vec<String> = state.rooms.lock().await.keys().cloned().collect();
+        // Evicted rooms only live in the database
+        let stored: Vec<String> = sqlx::query_scalar(
+            "SELECT room_id FROM rooms WHERE deleted_at IS NULL AND NOT archived",
+        )
+        .fetch_all(&state.db)
+        .await?;
+        ids.extend(stored);
+        ids.retain(|id| !hidden.contains(id));
+        ids.sort();
+        ids.dedup();
+        Ok(ids.into_iter().map(|id| Room { id }).collect())
+    }
+
+    async fn room(&self, ctx: &Context<'_>, id: String) -> Result<Option<Room>> {
+        check_access(ctx, &id).await?;
+        let state = ctx.data::<Arc<AppState>>()?;
+        let mut rooms = state.rooms.lock().await;
+        let exists = ensure_room_loaded(state, &mut rooms, &id).await;
+        drop(rooms);
+        Ok(exists.then_some(Room { id }))
+    }
+}
+
+pub(crate) struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Edits of the documents of a room, as they are broadcast to its members
+    async fn content_updates(
+        &self,
+        ctx: &Context<'_>,
+        room_id: String,
+    ) -> Result<impl Stream<Item = ContentUpdate>> {
+        check_access(ctx, &room_id).await?;
+        let state = ctx.data::<Arc<AppState>>()?;
+        let mut rooms = state.rooms.lock().await;
+        if !ensure_room_loaded(state, &mut rooms, &room_id).await {
+            return Err("Room not found.".into());
+        }
+        let rx = rooms[&room_id].tx.subscribe();
+        drop(rooms);
+
+        Ok(stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(message) => {
+                        if let Some(update) = ContentUpdate::parse(&message) {
+                            return Some((update, rx));
+                        }
+                    }
+                    // The next edit carries the whole content anyway
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentUpdate;
+
+    #[test]
+    fn test_content_update() {
+        let update = ContentUpdate::parse(
+            r#"{"doc_id":"notes","type":"message","value":"hi","username":"alice"}"#,
+        )
+        .unwrap();
+        assert_eq!(update.doc_id.as_deref(), Some("notes"));
+        assert_eq!(update.value, "hi");
+        assert!(ContentUpdate::parse(r#"{"type":"join","username":"alice"}"#).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::outbound::Outbound;
+use axum::extract::ws::Message;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+/// Liveness of a connection, pinged by the server.
+/// Connections closed abruptly never send a close frame, they would stay in their room otherwise.
+#[derive(Debug, Default)]
+pub(crate) struct Heartbeat {
+    /// Pings sent since the client was last heard from
+    unanswered: AtomicU32,
+}
+
+impl Heartbeat {
+    /// The client sent a frame, a pong or anything else
+    pub(crate) fn alive(&self) {
+        self.unanswered.store(0, Ordering::Relaxed);
+    }
+
+    /// Ping the client every `interval`, returning once `max_missed` pings in a row went
+    /// unanswered or the socket is closed
+    pub(crate) async fn run(&self, sender: &Outbound, interval: Duration, max_missed: u32) {
+        let mut ticks = time::interval_at(time::Instant::now() + interval, interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if self.unanswered.fetch_add(1, Ordering::Relaxed) >= max_missed {
+                return;
+            }
+            if !sender.send(Message::Ping(Vec::new())) {
+                return;
+            }
+        }
+    }
+}
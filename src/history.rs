@@ -0,0 +1,439 @@
+use crate::burn::BurnAfter;
+use crate::{
+    auth, ensure_room_loaded, revisions, unix_timestamp, AppState, CustomError, DryRunQuery,
+    DryRunReport,
+};
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use similar::TextDiff;
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::Acquire;
+use std::sync::{Arc, OnceLock};
+use tokio::time::{self, Duration, Instant};
+use ts_rs::TS;
+
+/// Delay between two prunings of the history
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Contents saved within this delay of the last version of a room replace it, rather than
+/// adding a version for every write of a room being edited
+const VERSION_INTERVAL: i64 = 5 * 60;
+
+/// Largest versions compared by `GET /api/rooms/:room_id/diff`, in bytes
+const MAX_DIFF_SIZE: usize = 1024 * 1024;
+
+/// Time spent looking for the smallest diff between two versions
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Retention of the history, set from the config when the server starts
+pub(crate) static RETENTION: OnceLock<Retention> = OnceLock::new();
+
+/// How long and how much history is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Retention {
+    /// Older versions are removed, `None` keeps them whatever their age
+    pub(crate) max_age: Option<Duration>,
+    /// Versions kept per room, the oldest are removed past that, 0 keeps them all
+    pub(crate) max_rows: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            max_rows: 100,
+        }
+    }
+}
+
+/// Saved version of the content of a room
+#[derive(TS, Serialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct HistoryVersion {
+    /// Compared with another version with `GET /api/rooms/:room_id/diff`
+    #[ts(type = "number")]
+    pub(crate) id: i64,
+    /// Unix timestamp of when the version was first saved, it holds the last content saved
+    /// in the 5 minutes after
+    #[ts(type = "number")]
+    pub(crate) created_at: i64,
+    /// Bytes of the content
+    #[ts(type = "number")]
+    pub(crate) size: i64,
+}
+
+/// `GET /api/rooms/:room_id/diff` query, `to` being the current content if unset
+#[derive(Deserialize, Debug)]
+pub(crate) struct DiffQuery {
+    from: i64,
+    to: Option<i64>,
+}
+
+/// Keep a saved version of the content of a room, or update its last one if it is recent or
+/// already holds that content, removing the oldest ones past `HISTORY_MAX_ROWS`
+pub(crate) async fn record(
+    db: impl Acquire<'_, Database = Sqlite>,
+    room_id: &str,
+    content: &str,
+    created_at: i64,
+) -> Result<()> {
+    let mut db = db.acquire().await?;
+    let updated = sqlx::query(
+        r"
+        UPDATE room_history SET content = ?
+        WHERE id = (SELECT MAX(id) FROM room_history WHERE room_id = ?)
+            AND (created_at > ? OR content = ?)
+        ",
+    )
+    .bind(content)
+    .bind(room_id)
+    .bind(created_at - VERSION_INTERVAL)
+    .bind(content)
+    .execute(&mut *db)
+    .await?
+    .rows_affected();
+    if updated > 0 {
+        return Ok(());
+    }
+
+    sqlx::query("INSERT INTO room_history (room_id, content, created_at) VALUES (?, ?, ?)")
+        .bind(room_id)
+        .bind(content)
+        .bind(created_at)
+        .execute(&mut *db)
+        .await?;
+    let max_rows = RETENTION.get().copied().unwrap_or_default().max_rows;
+    if max_rows > 0 {
+        sqlx::query(
+            r"
+            DELETE FROM room_history WHERE room_id = ? AND id NOT IN (
+                SELECT id FROM room_history WHERE room_id = ? ORDER BY id DESC LIMIT ?
+            )
+            ",
+        )
+        .bind(room_id)
+        .bind(room_id)
+        .bind(i64::try_from(max_rows).unwrap_or(i64::MAX))
+        .execute(&mut *db)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Remove the versions past the retention limits, returns the number of removed versions
+pub(crate) async fn prune(db: &SqlitePool, retention: Retention, now: i64) -> Result<u64> {
+    let mut removed = 0;
+    if let Some(max_age) = retention.max_age {
+        let max_age = i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX);
+        removed += sqlx::query("DELETE FROM room_history WHERE created_at < ?")
+            .bind(now.saturating_sub(max_age))
+            .execute(db)
+            .await?
+            .rows_affected();
+    }
+    if retention.max_rows > 0 {
+        removed += sqlx::query(
+            r"
+            DELETE FROM room_history WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (PARTITION BY room_id ORDER BY id DESC) AS rank
+                    FROM room_history
+                ) WHERE rank > ?
+            )
+            ",
+        )
+        .bind(i64::try_from(retention.max_rows).unwrap_or(i64::MAX))
+        .execute(db)
+        .await?
+        .rows_affected();
+    }
+    Ok(removed)
+}
+
+/// Number of versions of a room and their total size in bytes
+pub(crate) async fn size(db: &SqlitePool, room_id: &str) -> Result<(i64, i64)> {
+    Ok(sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(CAST(content AS BLOB))), 0) FROM room_history WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_one(db)
+    .await?)
+}
+
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<u64> {
+    Ok(sqlx::query("DELETE FROM room_history WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?
+        .rows_affected())
+}
+
+/// Saved versions of the content of a room, the latest first
+async fn list(db: &SqlitePool, room_id: &str) -> Result<Vec<HistoryVersion>> {
+    let versions = sqlx::query_as::<_, (i64, i64, i64)>(
+        "SELECT id, created_at, LENGTH(CAST(content AS BLOB)) FROM room_history WHERE room_id = ? ORDER BY id DESC",
+    )
+    .bind(room_id)
+    .fetch_all(db)
+    .await?;
+    Ok(versions
+        .into_iter()
+        .map(|(id, created_at, size)| HistoryVersion {
+            id,
+            created_at,
+            size,
+        })
+        .collect())
+}
+
+/// Content of a saved version of a room
+async fn version(db: &SqlitePool, room_id: &str, id: i64) -> Result<String, CustomError> {
+    sqlx::query_scalar("SELECT content FROM room_history WHERE room_id = ? AND id = ?")
+        .bind(room_id)
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read room history: {e}");
+            auth::internal_error()
+        })?
+        .ok_or_else(|| CustomError::not_found(format!("Version {id} not found.")))
+}
+
+/// Unified diff turning `old` into `new`, with 3 lines of context around the changes.
+/// Past `DIFF_TIMEOUT` it gives up looking for the smallest diff and returns a coarser one.
+fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Current content of a room whose history the user of a request can read: rooms burning
+/// after reading would be read without being burned
+async fn readable_history(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<String, CustomError> {
+    revisions::request_access(state, headers, room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(state, &mut rooms, room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[room_id];
+    if room.encryption.is_some() {
+        return Err(CustomError::bad_request(
+            "Encrypted rooms can't be read by the server.",
+        ));
+    }
+    if room.burns_after(BurnAfter::Read).await {
+        return Err(CustomError::bad_request(
+            "The history of rooms burning after reading can't be read.",
+        ));
+    }
+    let content = room.content_rx.borrow().clone();
+    drop(rooms);
+    Ok(content)
+}
+
+/// `GET /api/rooms/:room_id/history`, the saved versions of the content of a room
+pub(crate) async fn list_history(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    readable_history(&state, &headers, &room_id).await?;
+    let versions = list(&state.db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to list room history: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": versions
+    })))
+}
+
+/// `GET /api/rooms/:room_id/diff?from=..&to=..`, what changed between two saved versions of a
+/// room, or since one of them
+pub(crate) async fn get_diff(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Query(query): Query<DiffQuery>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    let current = readable_history(&state, &headers, &room_id).await?;
+    let old = version(&state.db, &room_id, query.from).await?;
+    let (new, new_name) = match query.to {
+        Some(to) => (
+            version(&state.db, &room_id, to).await?,
+            format!("{room_id}@{to}"),
+        ),
+        None => (current, format!("{room_id}@current")),
+    };
+    if old.len() > MAX_DIFF_SIZE || new.len() > MAX_DIFF_SIZE {
+        return Err(CustomError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Versions larger than {MAX_DIFF_SIZE} bytes can't be compared."),
+        ));
+    }
+    // Off the async runtime, a diff of large versions takes a while
+    let old_name = format!("{room_id}@{}", query.from);
+    let diff = tokio::task::spawn_blocking(move || unified_diff(&old, &new, &old_name, &new_name))
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to diff room versions: {e}");
+            auth::internal_error()
+        })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/x-diff; charset=utf-8"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        diff,
+    )
+        .into_response())
+}
+
+/// Prune the history every hour, and reclaim the space of the removed rows every
+/// `vacuum_interval`
+pub(crate) async fn run_retention(state: Arc<AppState>, vacuum_interval: Option<Duration>) {
+    let db = &state.db;
+    let mut last_vacuum = Instant::now();
+    let mut interval = time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match prune(db, state.config.history_retention, unix_timestamp()).await {
+            Ok(0) => {}
+            Ok(removed) => println!("Pruned {removed} versions from the room history"),
+            Err(e) => eprintln!("Failed to prune the room history: {e:#}"),
+        }
+
+        if vacuum_interval.is_some_and(|vacuum_interval| last_vacuum.elapsed() >= vacuum_interval) {
+            last_vacuum = Instant::now();
+            match sqlx::query("VACUUM").execute(db).await {
+                Ok(_) => println!("Database vacuumed"),
+                Err(e) => eprintln!("Failed to vacuum the database: {e}"),
+            }
+        }
+    }
+}
+
+/// `DELETE /api/admin/rooms/:room_id/history`, forget the saved versions of a room
+pub(crate) async fn purge_history(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Query(query): Query<DryRunQuery>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+
+    if query.dry_run {
+        let (_, bytes) = size(db, &room_id).await.map_err(|e| {
+            eprintln!("Failed to measure room history: {e:#}");
+            auth::internal_error()
+        })?;
+        return Ok(Json(json!({
+            "type": "dry-run",
+            "value": DryRunReport {
+                rooms: vec![room_id],
+                blobs: Vec::new(),
+                users_disconnected: 0,
+                bytes_freed: usize::try_from(bytes).unwrap_or(0),
+            }
+        })));
+    }
+
+    let removed = delete_room(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to purge room history: {e:#}");
+        auth::internal_error()
+    })?;
+    println!("Admin purged the history of room {room_id}, {removed} versions removed");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": format!("{removed} versions removed.")
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune, record, size, unified_diff, Retention};
+    use crate::database;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_prune() {
+        let db = database::memory().await.unwrap();
+
+        for (i, created_at) in [10, 400, 800, 2000, 2400].into_iter().enumerate() {
+            record(&db, "notes", &i.to_string(), created_at)
+                .await
+                .unwrap();
+        }
+        record(&db, "todo", "x", 2400).await.unwrap();
+
+        let retention = Retention {
+            max_age: Some(Duration::from_secs(1000)),
+            max_rows: 0,
+        };
+        assert_eq!(prune(&db, retention, 2500).await.unwrap(), 3);
+        assert_eq!(size(&db, "notes").await.unwrap(), (2, 2));
+
+        let retention = Retention {
+            max_age: None,
+            max_rows: 1,
+        };
+        assert_eq!(prune(&db, retention, 2500).await.unwrap(), 1);
+        let latest: String =
+            sqlx::query_scalar("SELECT content FROM room_history WHERE room_id = 'notes'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(latest, "4");
+        assert_eq!(size(&db, "todo").await.unwrap(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_record_coalesces_recent_versions() {
+        let db = database::memory().await.unwrap();
+        for (content, created_at) in [("a", 10), ("ab", 12), ("abc", 400)] {
+            record(&db, "notes", content, created_at).await.unwrap();
+        }
+        let versions: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT content, created_at FROM room_history WHERE room_id = 'notes' ORDER BY id",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(versions, [("ab".to_string(), 10), ("abc".to_string(), 400)]);
+
+        // Saving the same content again, as a retried write does, adds no version
+        record(&db, "notes", "abc", 4000).await.unwrap();
+        assert_eq!(size(&db, "notes").await.unwrap().0, 2);
+
+        // The oldest versions past the limit go as new ones come
+        for i in 1..=100 {
+            record(&db, "notes", "x", 400 + i * 1000).await.unwrap();
+        }
+        assert_eq!(size(&db, "notes").await.unwrap().0, 100);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "a\nB\nc\n", "notes@1", "notes@2"),
+            "--- notes@1\n+++ notes@2\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+        assert_eq!(unified_diff("same\n", "same\n", "notes@1", "notes@2"), "");
+    }
+}
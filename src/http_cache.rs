@@ -0,0 +1,130 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use headers::{CacheControl, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Representations remembered at most, forgotten all at once past that
+const MAX_VALIDATORS: usize = 1024;
+
+/// Latest representation served by the cacheable endpoints, by resource,
+/// with when it was first served to date it with `Last-Modified`
+#[derive(Debug, Default)]
+pub(crate) struct Validators {
+    seen: Mutex<HashMap<String, (String, SystemTime)>>,
+}
+
+impl Validators {
+    /// When the representation of a resource last changed, as far as this server knows
+    fn last_modified(&self, resource: &str, etag: &str) -> SystemTime {
+        let mut seen = self.seen.lock().unwrap();
+        if let Some((seen_etag, since)) = seen.get(resource) {
+            if seen_etag == etag {
+                return *since;
+            }
+        }
+        if seen.len() >= MAX_VALIDATORS {
+            seen.clear();
+        }
+        // HTTP dates have a resolution of a second
+        let now = UNIX_EPOCH
+            + Duration::from_secs(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_secs()),
+            );
+        seen.insert(resource.to_string(), (etag.to_string(), now));
+        drop(seen);
+        now
+    }
+}
+
+/// Answer with `value` as JSON, or with `304 Not Modified` if the client already has it.
+/// Clients may keep the response but must revalidate it every time, they poll for changes.
+pub(crate) fn cached_json<T: Serialize>(
+    validators: &Validators,
+    resource: &str,
+    headers: &HeaderMap,
+    value: &T,
+) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Failed to serialize response: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let digest = Sha256::digest(&body);
+    let tag = format!("\"{}\"", crate::metrics::to_hex(&digest[..16]));
+    let last_modified = validators.last_modified(resource, &tag);
+    let etag = tag.parse::<ETag>().ok();
+
+    // If-None-Match wins over If-Modified-Since when both are sent
+    let modified = match (
+        headers.typed_get::<IfNoneMatch>(),
+        headers.typed_get::<IfModifiedSince>(),
+        &etag,
+    ) {
+        (Some(if_none_match), _, Some(etag)) => if_none_match.precondition_passes(etag),
+        (None, Some(if_modified_since), _) => if_modified_since.is_modified(last_modified),
+        _ => true,
+    };
+
+    let mut response = if modified {
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            body,
+        )
+            .into_response()
+    } else {
+        StatusCode::NOT_MODIFIED.into_response()
+    };
+    let response_headers = response.headers_mut();
+    if let Some(etag) = etag {
+        response_headers.typed_insert(etag);
+    }
+    response_headers.typed_insert(LastModified::from(last_modified));
+    response_headers.typed_insert(CacheControl::new().with_no_cache());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cached_json, Validators};
+    use axum::http::{header, HeaderMap};
+    use serde_json::json;
+
+    #[test]
+    fn test_conditional_requests() {
+        let validators = Validators::default();
+        let response = cached_json(&validators, "rooms", &HeaderMap::new(), &json!(["a"]));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = cached_json(&validators, "rooms", &headers, &json!(["a"]));
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        let response = cached_json(&validators, "rooms", &headers, &json!(["a", "b"]));
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()[header::ETAG], etag);
+
+        // Clients without the tag revalidate with the date
+        let response = cached_json(&validators, "documents", &HeaderMap::new(), &json!([1]));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            response.headers()[header::LAST_MODIFIED].clone(),
+        );
+        let response = cached_json(&validators, "documents", &headers, &json!([1]));
+        assert_eq!(response.status(), 304);
+    }
+}
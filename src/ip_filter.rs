@@ -0,0 +1,327 @@
+use crate::client_ip::ClientIp;
+use crate::{auth, unix_timestamp, AppState, CustomError};
+use anyhow::{bail, Context, Result};
+use axum::extract::{Path, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use ts_rs::TS;
+
+/// Range of IP addresses, such as `10.0.0.0/8`, a single address being a range of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub(crate) fn parse(cidr: &str) -> Result<Self> {
+        let (address, prefix) = match cidr.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr, None),
+        };
+        let network = address
+            .trim()
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid IP address {address}"))?
+            .to_canonical();
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .with_context(|| format!("Invalid prefix length {prefix}"))?,
+            None => bits,
+        };
+        if prefix > bits {
+            bail!("Prefix length {prefix} is too long for {network}");
+        }
+        Ok(Self { network, prefix })
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(
+                u128::from(u32::from(network)),
+                u128::from(u32::from(ip)),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(network), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` of the `bits` bits of two addresses are the same
+const fn prefix_matches(a: u128, b: u128, bits: u8, prefix: u8) -> bool {
+    prefix == 0 || (a ^ b) >> (bits - prefix) == 0
+}
+
+/// Parse a comma-separated list of ranges
+pub(crate) fn parse_list(list: &str) -> Result<Vec<Cidr>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|cidr| !cidr.is_empty())
+        .map(Cidr::parse)
+        .collect()
+}
+
+/// Banned IP address, as listed by `GET /api/admin/bans`
+#[derive(TS, Debug, Clone, PartialEq, Eq, Serialize)]
+#[ts(export)]
+pub(crate) struct IpBan {
+    #[ts(type = "string")]
+    pub(crate) ip: IpAddr,
+    pub(crate) reason: Option<String>,
+    #[ts(type = "number")]
+    pub(crate) banned_at: i64,
+}
+
+/// Why a client is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Refusal {
+    Banned,
+    NotAllowed,
+}
+
+/// Allowed and denied ranges of client IPs, and the addresses banned at runtime
+#[derive(Debug, Default)]
+pub(crate) struct IpFilter {
+    /// Every address is allowed if empty
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    bans: Mutex<HashMap<IpAddr, IpBan>>,
+}
+
+impl IpFilter {
+    pub(crate) fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self {
+            allow,
+            deny,
+            bans: Mutex::default(),
+        }
+    }
+
+    /// Restore the bans stored in the database
+    pub(crate) fn restore(&self, bans: Vec<IpBan>) {
+        let mut current = self.bans.lock().unwrap();
+        for ban in bans {
+            current.insert(ban.ip, ban);
+        }
+    }
+
+    pub(crate) fn check(&self, ip: IpAddr) -> Result<(), Refusal> {
+        let ip = ip.to_canonical();
+        if self.bans.lock().unwrap().contains_key(&ip) {
+            return Err(Refusal::Banned);
+        }
+        if self.deny.iter().any(|cidr| cidr.contains(ip))
+            || !(self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+        {
+            return Err(Refusal::NotAllowed);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ban(&self, ban: IpBan) {
+        self.bans.lock().unwrap().insert(ban.ip, ban);
+    }
+
+    /// Returns `false` if the address wasn't banned
+    pub(crate) fn unban(&self, ip: IpAddr) -> bool {
+        self.bans.lock().unwrap().remove(&ip).is_some()
+    }
+
+    /// Banned addresses, most recent first
+    pub(crate) fn bans(&self) -> Vec<IpBan> {
+        let mut bans: Vec<_> = self.bans.lock().unwrap().values().cloned().collect();
+        bans.sort_by(|a, b| b.banned_at.cmp(&a.banned_at).then(a.ip.cmp(&b.ip)));
+        bans
+    }
+}
+
+/// Bans stored in the database
+pub(crate) async fn load_bans(db: &SqlitePool) -> Result<Vec<IpBan>> {
+    let rows = sqlx::query_as::<_, (String, Option<String>, i64)>(
+        "SELECT ip, reason, banned_at FROM ip_bans",
+    )
+    .fetch_all(db)
+    .await?;
+    rows.into_iter()
+        .map(|(ip, reason, banned_at)| {
+            Ok(IpBan {
+                ip: ip
+                    .parse()
+                    .with_context(|| format!("Invalid banned IP address {ip}"))?,
+                reason,
+                banned_at,
+            })
+        })
+        .collect()
+}
+
+/// Refuse the requests of banned clients and of those outside of the allowed ranges
+pub(crate) async fn filter_ip(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    match state.ip_filter.check(ip) {
+        Ok(()) => next.run(request).await,
+        Err(Refusal::Banned) => {
+            CustomError::new(StatusCode::FORBIDDEN, "Your IP address is banned.").into_response()
+        }
+        Err(Refusal::NotAllowed) => {
+            CustomError::new(StatusCode::FORBIDDEN, "Your IP address is not allowed.")
+                .into_response()
+        }
+    }
+}
+
+/// Body of `POST /api/admin/bans`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct BanRequest {
+    #[ts(type = "string")]
+    ip: IpAddr,
+    #[serde(default)]
+    #[ts(optional)]
+    reason: Option<String>,
+}
+
+/// List the banned IP addresses
+pub(crate) async fn list_bans(State(state): State<Arc<AppState>>) -> Json<Vec<IpBan>> {
+    Json(state.ip_filter.bans())
+}
+
+/// Ban an IP address, disconnecting its clients
+pub(crate) async fn ban_ip(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<BanRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let ban = IpBan {
+        ip: body.ip.to_canonical(),
+        reason: body.reason.filter(|reason| !reason.is_empty()),
+        banned_at: unix_timestamp(),
+    };
+
+    // Without `DATABASE_URL`, bans only last until the server restarts
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO ip_bans (ip, reason, banned_at) VALUES (?, ?, ?)
+        ON CONFLICT (ip) DO UPDATE SET reason = excluded.reason, banned_at = excluded.banned_at
+        ",
+    )
+    .bind(ban.ip.to_string())
+    .bind(&ban.reason)
+    .bind(ban.banned_at)
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store IP ban in database: {e}");
+        return Err(auth::internal_error());
+    }
+
+    let ip = ban.ip;
+    state.ip_filter.ban(ban.clone());
+    let disconnected = state.connections.disconnect_ip(ip);
+    println!("Admin banned {ip}, disconnected {disconnected} clients");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": ban
+    })))
+}
+
+/// Lift the ban of an IP address
+pub(crate) async fn unban_ip(
+    State(state): State<Arc<AppState>>,
+    Path(ip): Path<IpAddr>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let ip = ip.to_canonical();
+    if let Err(e) = sqlx::query("DELETE FROM ip_bans WHERE ip = ?")
+        .bind(ip.to_string())
+        .execute(&state.db)
+        .await
+    {
+        eprintln!("Failed to delete IP ban from database: {e}");
+        return Err(auth::internal_error());
+    }
+    if !state.ip_filter.unban(ip) {
+        return Err(CustomError::not_found("IP address not banned."));
+    }
+    println!("Admin lifted the ban of {ip}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Ban lifted."
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_list, Cidr, IpBan, IpFilter, Refusal};
+    use std::net::IpAddr;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(private.contains(ip("::ffff:10.0.0.1")));
+        assert!(!private.contains(ip("fd00::1")));
+
+        let single = Cidr::parse("192.168.1.7").unwrap();
+        assert!(single.contains(ip("192.168.1.7")));
+        assert!(!single.contains(ip("192.168.1.8")));
+
+        let v6 = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+        assert!(Cidr::parse("::/0").unwrap().contains(ip("2001:db8::1")));
+
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert_eq!(parse_list(" 10.0.0.0/8, ,::1 ").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = IpFilter::new(
+            parse_list("10.0.0.0/8").unwrap(),
+            parse_list("10.0.0.13").unwrap(),
+        );
+        assert_eq!(filter.check(ip("10.0.0.1")), Ok(()));
+        assert_eq!(filter.check(ip("10.0.0.13")), Err(Refusal::NotAllowed));
+        assert_eq!(filter.check(ip("127.0.0.1")), Err(Refusal::NotAllowed));
+
+        filter.ban(IpBan {
+            ip: ip("10.0.0.1"),
+            reason: None,
+            banned_at: 1,
+        });
+        assert_eq!(filter.check(ip("10.0.0.1")), Err(Refusal::Banned));
+        assert_eq!(filter.check(ip("::ffff:10.0.0.1")), Err(Refusal::Banned));
+        assert!(filter.unban(ip("10.0.0.1")));
+        assert!(!filter.unban(ip("10.0.0.1")));
+        assert_eq!(filter.check(ip("10.0.0.1")), Ok(()));
+
+        // Everyone is allowed without an allowlist
+        assert_eq!(IpFilter::default().check(ip("203.0.113.5")), Ok(()));
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::SqlitePool;
+use ts_rs::TS;
+
+/// Only the beginning of large contents is looked at
+const SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Share of the non-empty lines that must look like code
+const CODE_LINES_RATIO: f64 = 0.3;
+
+/// Share of the non-empty lines that must use Markdown syntax
+const MARKDOWN_LINES_RATIO: f64 = 0.1;
+
+/// Stop words recognized before a natural language is reported
+const MIN_STOP_WORDS: usize = 3;
+
+/// Most frequent words of the supported natural languages, by ISO 639-1 code
+const STOP_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "was", "of", "to", "in", "that", "it", "with", "for",
+            "this", "you", "not", "have",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "un", "du", "que", "qui", "dans", "pour",
+            "pas", "sur", "avec",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "mit", "den", "zu", "auf",
+            "ich", "sie", "es", "auch",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "y", "es", "que", "por", "una", "con", "para", "del", "como",
+            "pero", "muy", "su", "lo",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "che", "di", "non", "sono", "una", "gli", "per", "con", "della", "è", "questo",
+            "anche", "come", "ma", "nel",
+        ],
+    ),
+];
+
+const CODE_PREFIXES: &[&str] = &[
+    "fn ",
+    "pub ",
+    "use ",
+    "let ",
+    "const ",
+    "var ",
+    "def ",
+    "class ",
+    "import ",
+    "from ",
+    "function ",
+    "return ",
+    "#include",
+    "package ",
+    "public ",
+    "private ",
+    "if (",
+    "for (",
+    "while (",
+    "} else",
+    "SELECT ",
+    "//",
+    "/*",
+    "#!",
+];
+
+const MARKDOWN_PREFIXES: &[&str] = &["# ", "## ", "### ", "- ", "* ", "> ", "1. ", "- [ ]", "|"];
+
+/// Type of the content of a room
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum ContentKind {
+    Text,
+    Markdown,
+    Code,
+}
+
+impl ContentKind {
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Markdown => "markdown",
+            Self::Code => "code",
+        }
+    }
+
+    /// Parse a kind stored in the database
+    pub(crate) fn from_stored(stored: &str) -> Option<Self> {
+        match stored {
+            "text" => Some(Self::Text),
+            "markdown" => Some(Self::Markdown),
+            "code" => Some(Self::Code),
+            _ => None,
+        }
+    }
+}
+
+/// Type and natural language of the content of a room
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct ContentLanguage {
+    pub(crate) kind: ContentKind,
+    /// ISO 639-1 code of the natural language, if recognized
+    #[ts(type = "string | null")]
+    pub(crate) language: Option<String>,
+    /// Whether the kind was set by hand rather than detected
+    pub(crate) kind_overridden: bool,
+    /// Whether the language was set by hand rather than detected
+    pub(crate) language_overridden: bool,
+}
+
+/// Manual settings of a room, `None` fields are detected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LanguageOverride {
+    pub(crate) kind: Option<ContentKind>,
+    pub(crate) language: Option<String>,
+}
+
+impl LanguageOverride {
+    /// Resolve the settings of a room, detecting what wasn't set by hand if `detect` is set
+    pub(crate) fn resolve(&self, content: &str, detect: bool) -> ContentLanguage {
+        let (kind, language) = if detect && (self.kind.is_none() || self.language.is_none()) {
+            detect_content(content)
+        } else {
+            (ContentKind::Text, None)
+        };
+        ContentLanguage {
+            kind: self.kind.unwrap_or(kind),
+            language: self
+                .language
+                .clone()
+                .or_else(|| language.map(ToString::to_string)),
+            kind_overridden: self.kind.is_some(),
+            language_overridden: self.language.is_some(),
+        }
+    }
+}
+
+/// Body of `PATCH /api/rooms/:room_id/language`: missing fields are left as is,
+/// `null` goes back to automatic detection
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct LanguagePatch {
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) kind: Option<Option<ContentKind>>,
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) language: Option<Option<String>>,
+}
+
+/// Tell a `null` field apart from a missing one
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl LanguagePatch {
+    pub(crate) fn apply(self, mut current: LanguageOverride) -> Result<LanguageOverride, String> {
+        if let Some(kind) = self.kind {
+            current.kind = kind;
+        }
+        if let Some(language) = self.language {
+            if let Some(language) = &language {
+                validate_language(language)?;
+            }
+            current.language = language;
+        }
+        Ok(current)
+    }
+}
+
+fn validate_language(language: &str) -> Result<(), String> {
+    if (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_lowercase()) {
+        Ok(())
+    } else {
+        Err("Languages are ISO 639 codes, like en or fr.".to_string())
+    }
+}
+
+/// Manual settings of a room stored in the database
+pub(crate) async fn load_override(db: &SqlitePool, room_id: &str) -> Result<LanguageOverride> {
+    let row = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+        "SELECT content_kind, language FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await?;
+    let (kind, language) = row.unwrap_or_default();
+    Ok(LanguageOverride {
+        kind: kind.as_deref().and_then(ContentKind::from_stored),
+        language,
+    })
+}
+
+/// Store the manual settings of a room, `content` is used if it isn't stored yet
+pub(crate) async fn store_override(
+    db: &SqlitePool,
+    room_id: &str,
+    content: &str,
+    settings: &LanguageOverride,
+) -> Result<()> {
+    sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, content_kind, language) VALUES (?, ?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE
+        SET content_kind = excluded.content_kind, language = excluded.language
+        ",
+    )
+    .bind(room_id)
+    .bind(content)
+    .bind(settings.kind.map(ContentKind::as_str))
+    .bind(settings.language.as_deref())
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Beginning of a content, cut on a character boundary
+fn sample(content: &str) -> &str {
+    if content.len() <= SAMPLE_BYTES {
+        return content;
+    }
+    let mut end = SAMPLE_BYTES;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    &content[..end]
+}
+
+/// Guess the type of a content, and its natural language unless it is code
+pub(crate) fn detect_content(content: &str) -> (ContentKind, Option<&'static str>) {
+    let content = sample(content);
+    let kind = detect_kind(content);
+    let language = match kind {
+        ContentKind::Code => None,
+        ContentKind::Text | ContentKind::Markdown => detect_language(content),
+    };
+    (kind, language)
+}
+
+#[allow(clippy::cast_precision_loss)] // Line counts are far below 2^52
+fn detect_kind(content: &str) -> ContentKind {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return ContentKind::Text;
+    }
+    // Fenced code blocks only exist in Markdown
+    if lines.iter().any(|line| line.starts_with("```")) {
+        return ContentKind::Markdown;
+    }
+
+    let code_lines = lines
+        .iter()
+        .filter(|line| {
+            line.ends_with(';')
+                || line.ends_with('{')
+                || **line == "}"
+                || CODE_PREFIXES.iter().any(|prefix| line.starts_with(prefix))
+        })
+        .count();
+    let markdown_lines = lines
+        .iter()
+        .filter(|line| {
+            MARKDOWN_PREFIXES
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+                || line.contains("](")
+                || line.contains("**")
+        })
+        .count();
+
+    let total = lines.len() as f64;
+    if code_lines as f64 / total >= CODE_LINES_RATIO && code_lines > markdown_lines {
+        ContentKind::Code
+    } else if markdown_lines as f64 / total >= MARKDOWN_LINES_RATIO {
+        ContentKind::Markdown
+    } else {
+        ContentKind::Text
+    }
+}
+
+fn detect_language(content: &str) -> Option<&'static str> {
+    let mut counts = vec![0usize; STOP_WORDS.len()];
+    for word in content
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+    {
+        let word = word.to_lowercase();
+        for (count, (_, words)) in counts.iter_mut().zip(STOP_WORDS) {
+            if words.contains(&word.as_str()) {
+                *count += 1;
+            }
+        }
+    }
+
+    let (best, &best_count) = counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+    let ambiguous = counts
+        .iter()
+        .enumerate()
+        .any(|(i, count)| i != best && *count == best_count);
+    (best_count >= MIN_STOP_WORDS && !ambiguous).then_some(STOP_WORDS[best].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_content, ContentKind, LanguageOverride, LanguagePatch};
+
+    #[test]
+    fn test_detect_kind() {
+        assert_eq!(detect_content("").0, ContentKind::Text);
+        assert_eq!(
+            detect_content("fn main() {\n    println!(\"hi\");\n}\n").0,
+            ContentKind::Code
+        );
+        assert_eq!(
+            detect_content("# Notes\n\n- first\n- second\n\nSee [docs](https://example.com)").0,
+            ContentKind::Markdown
+        );
+        assert_eq!(
+            detect_content("Some text\n```\nlet a = 1;\n```\n").0,
+            ContentKind::Markdown
+        );
+        assert_eq!(
+            detect_content("Remember to buy milk and eggs.").0,
+            ContentKind::Text
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_content(
+                "The meeting is moved to Monday, and the notes are in the shared folder."
+            )
+            .1,
+            Some("en")
+        );
+        assert_eq!(
+            detect_content("La réunion est déplacée à lundi et les notes sont dans le dossier.").1,
+            Some("fr")
+        );
+        assert_eq!(
+            detect_content("let x = 1;\nlet y = 2;\nlet z = x + y;").1,
+            None
+        );
+        assert_eq!(detect_content("ok").1, None);
+    }
+
+    #[test]
+    fn test_override() {
+        let patch: LanguagePatch = serde_json::from_str(r#"{"kind":"code"}"#).unwrap();
+        let settings = patch.apply(LanguageOverride::default()).unwrap();
+        assert_eq!(settings.kind, Some(ContentKind::Code));
+
+        let resolved = settings.resolve("The cat and the dog are in the garden.", true);
+        assert_eq!(resolved.kind, ContentKind::Code);
+        assert!(resolved.kind_overridden);
+        assert_eq!(resolved.language.as_deref(), Some("en"));
+        assert!(!resolved.language_overridden);
+
+        // null goes back to detection, missing fields are kept
+        let patch: LanguagePatch =
+            serde_json::from_str(r#"{"kind":null,"language":"fr"}"#).unwrap();
+        let settings = patch.apply(settings).unwrap();
+        assert_eq!(settings.kind, None);
+        assert_eq!(settings.language.as_deref(), Some("fr"));
+        let patch: LanguagePatch = serde_json::from_str("{}").unwrap();
+        assert_eq!(patch.apply(settings.clone()).unwrap(), settings);
+
+        let patch: LanguagePatch = serde_json::from_str(r#"{"language":"French"}"#).unwrap();
+        assert!(patch.apply(settings).is_err());
+    }
+}
@@ -0,0 +1,6893 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(
+    clippy::multiple_crate_versions,
+    clippy::too_many_lines,
+    clippy::redundant_pub_crate
+)]
+
+mod admin;
+mod admission;
+mod announcements;
+mod archive;
+mod attachments;
+mod auth;
+mod authenticator;
+mod auto_clear;
+mod backup;
+mod base_path;
+#[cfg(test)]
+mod bindings;
+mod burn;
+mod checkpoints;
+mod client_ip;
+mod compat;
+mod compression;
+mod config;
+mod connections;
+mod content_log;
+mod database;
+mod documents;
+mod encrypted_blobs;
+mod encryption;
+mod error_code;
+mod events;
+mod export;
+mod format;
+mod freeze;
+mod graphql;
+mod heartbeat;
+mod history;
+mod http_cache;
+mod ip_filter;
+mod language;
+mod markdown;
+mod members;
+mod merge;
+mod metrics;
+mod mirror;
+mod oidc;
+mod openapi;
+mod ot;
+mod outbound;
+mod paste;
+mod pins;
+mod protocol;
+mod ranges;
+mod rate_limit;
+mod rename;
+mod resume;
+mod revisions;
+mod room_creation;
+mod room_handle;
+mod room_id;
+mod room_users;
+mod search;
+mod seed;
+mod sequence;
+mod server;
+mod sessions;
+mod stats;
+mod supervisor;
+mod telemetry;
+mod tls;
+mod trace;
+mod trash;
+#[cfg(unix)]
+mod unix_socket;
+mod username;
+mod visibility;
+mod webhooks;
+mod write_behind;
+
+use crate::admission::UpgradeGate;
+use crate::announcements::CurrentAnnouncement;
+use crate::api::{
+    check_room_owner, claim_room, delete_room, download_file, format_room, get_freeze_schedule,
+    get_language, get_metrics, get_rooms, list_documents, merge_room, pin_room, rate_limit_api,
+    remove_document, remove_room, require_auth, set_freeze_schedule, set_language,
+    set_room_syntax_language, stored_size, unpin_room, upload_file, DryRunQuery, DryRunReport,
+};
+use crate::attachments::AttachmentStore;
+pub use crate::authenticator::{Anonymous, Authenticator, Handshake, Identity};
+pub use crate::config::Config;
+use crate::connections::Connections;
+use crate::error_code::ErrorCode;
+use crate::http_cache::Validators;
+use crate::ip_filter::IpFilter;
+use crate::metrics::{AssetMetrics, SaturationMetrics};
+use crate::mirror::Mirror;
+use crate::oidc::OidcClient;
+use crate::rate_limit::RateLimiter;
+use crate::resume::ResumeSessions;
+use crate::room_creation::RoomCreation;
+use crate::rooms::{
+    apply_freeze_schedules, collect_attachment_garbage, drain, ensure_room_loaded,
+    evict_idle_rooms, flush_room, flush_rooms, validate_syntax_language, PersistenceHealth,
+    RoomState, BROADCAST_CAPACITY, DEFAULT_PERSIST_INTERVAL, DEFAULT_ROOM, PERSIST_INTERVAL,
+};
+pub use crate::server::{PartageServer, PartageServerBuilder};
+use crate::static_assets::{get_assets, static_handler};
+use crate::storage::{delete_stored_room, get_stored_content, store_room_owner};
+pub use crate::storage::{MemoryStorage, SqliteStorage, Storage};
+use crate::trace::Traces;
+use crate::webhooks::Webhooks;
+pub use crate::write_behind::Write;
+use crate::write_behind::WriteBehind;
+use crate::ws::{frozen_notice, handler, persisted_message, persistence_message, resync_messages};
+use anyhow::{Context, Result};
+use axum::extract::DefaultBodyLimit;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{middleware, Extension, Json, Router};
+use dotenvy::dotenv;
+use optional_default::OptionalDefault;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::signal;
+use tokio::sync::Mutex;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+use ts_rs::TS;
+
+/// State of the app
+struct AppState {
+    rooms: Mutex<HashMap<String, RoomState>>,
+    /// In memory when no `DATABASE_URL` is configured, see [`database::memory`]
+    db: SqlitePool,
+    /// Contents of the rooms
+    storage: Arc<dyn Storage>,
+    /// Writes the loaded rooms to `storage`
+    write_behind: WriteBehind,
+    /// Copy of the rooms and attachments in object storage, enabled by `S3_BUCKET`
+    mirror: Option<Mirror>,
+    config: Config,
+    ws_rate_limiter: RateLimiter,
+    api_rate_limiter: RateLimiter,
+    room_creation: RoomCreation,
+    asset_metrics: AssetMetrics,
+    saturation_metrics: SaturationMetrics,
+    upgrade_gate: UpgradeGate,
+    /// Cancelled when the server shuts down
+    shutdown: CancellationToken,
+    /// Cancelled when the shutdown starts, new connections are refused from then on
+    draining: CancellationToken,
+    oidc: Option<OidcClient>,
+    /// Tells who the clients without a session are
+    authenticator: Arc<dyn Authenticator>,
+    connections: Connections,
+    traces: Traces,
+    /// Validators of the responses clients revalidate, see [`http_cache::cached_json`]
+    validators: Validators,
+    /// Sessions of the clients that may reconnect where they left off
+    resume_sessions: ResumeSessions,
+    attachments: AttachmentStore,
+    webhooks: Webhooks,
+    ip_filter: IpFilter,
+    /// Sent to the clients joining a room until it expires
+    announcement: CurrentAnnouncement,
+}
+
+impl AppState {
+    fn new(
+        rooms: HashMap<String, RoomState>,
+        db: SqlitePool,
+        write_behind: WriteBehind,
+        mirror: Option<Mirror>,
+        config: Config,
+    ) -> Self {
+        let webhooks = Webhooks::new(
+            db.clone(),
+            config.webhook_urls.clone(),
+            config.webhook_secret.clone(),
+        );
+        Self {
+            rooms: Mutex::new(rooms),
+            storage: write_behind.storage().clone(),
+            write_behind,
+            attachments: AttachmentStore::new(&config.attachments_dir, db.clone(), mirror.clone()),
+            mirror,
+            db,
+            ws_rate_limiter: RateLimiter::new(config.ws_rate_limit),
+            api_rate_limiter: RateLimiter::new(config.api_rate_limit),
+            room_creation: RoomCreation::new(&config),
+            asset_metrics: AssetMetrics::default(),
+            saturation_metrics: SaturationMetrics::default(),
+            upgrade_gate: UpgradeGate::new(config.upgrades_per_second, config.upgrade_queue_size),
+            shutdown: CancellationToken::new(),
+            draining: CancellationToken::new(),
+            oidc: config.oidc.clone().map(OidcClient::new),
+            authenticator: Arc::new(Anonymous),
+            connections: Connections::default(),
+            traces: Traces::default(),
+            validators: Validators::default(),
+            resume_sessions: ResumeSessions::default(),
+            webhooks,
+            ip_filter: IpFilter::new(config.ip_allowlist.clone(), config.ip_denylist.clone()),
+            announcement: CurrentAnnouncement::default(),
+            config,
+        }
+    }
+}
+
+fn router(app_state: Arc<AppState>) -> Router {
+    let rooms = Router::new()
+        .route("/", get(get_rooms))
+        .route("/import", post(export::import_room))
+        .route("/trash", get(trash::list_trash))
+        .route("/:room_id", delete(remove_room).patch(rename::rename_room))
+        .route("/:room_id/format", post(format_room))
+        .route(
+            "/:room_id/content",
+            get(revisions::get_content).put(revisions::set_content),
+        )
+        .route("/:room_id/history", get(history::list_history))
+        .route("/:room_id/diff", get(history::get_diff))
+        .route(
+            "/:room_id/checkpoints",
+            get(checkpoints::list_checkpoints).post(checkpoints::create_checkpoint),
+        )
+        .route(
+            "/:room_id/checkpoints/:checkpoint_id",
+            delete(checkpoints::remove_checkpoint),
+        )
+        .route(
+            "/:room_id/checkpoints/:checkpoint_id/restore",
+            post(checkpoints::restore_checkpoint),
+        )
+        .route(
+            "/:room_id/freeze",
+            get(get_freeze_schedule).put(set_freeze_schedule),
+        )
+        .route(
+            "/:room_id/auto-clear",
+            get(auto_clear::get_auto_clear).put(auto_clear::set_auto_clear),
+        )
+        .route("/:room_id/burn", get(burn::get_burn).put(burn::set_burn))
+        .route("/:room_id/claim", post(claim_room))
+        .route("/:room_id/pin", put(pin_room).delete(unpin_room))
+        .route("/:room_id/export", get(export::export_room))
+        .route("/:room_id/download", get(export::download_room))
+        .route("/:room_id/merge", post(merge_room))
+        .route("/:room_id/restore", post(trash::restore_room))
+        .route("/:room_id/archive", post(archive::archive_room))
+        .route("/:room_id/unarchive", post(archive::unarchive_room))
+        .route("/:room_id/documents", get(list_documents))
+        .route("/:room_id/events", get(events::room_events))
+        .route("/:room_id/syntax-language", put(set_room_syntax_language))
+        .route("/:room_id/language", get(get_language).patch(set_language))
+        .route("/:room_id/documents/:doc_id", delete(remove_document))
+        .route(
+            "/:room_id/files",
+            // Leave room for the multipart framing, the file size is checked while reading it
+            post(upload_file).layer(DefaultBodyLimit::max(
+                usize::try_from(app_state.config.max_attachment_size)
+                    .unwrap_or(usize::MAX)
+                    .saturating_add(64 * 1024),
+            )),
+        )
+        .route("/:room_id/files/:file_id", get(download_file))
+        .route(
+            "/:room_id/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::add_webhook),
+        )
+        .route(
+            "/:room_id/webhooks/:webhook_id",
+            delete(webhooks::remove_webhook),
+        )
+        .route(
+            "/:room_id/visibility",
+            get(visibility::get_visibility).put(visibility::set_visibility),
+        )
+        .route("/:room_id/members", get(members::list_members))
+        .route(
+            "/:room_id/members/:username",
+            put(members::set_member).delete(members::remove_member),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            room_id::normalize_path,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_auth,
+        ));
+
+    let admin = admin::router().layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        admin::require_admin,
+    ));
+
+    let schema = graphql::schema(app_state.clone());
+    let graphql = Router::new()
+        .route("/", post(graphql::execute))
+        .route("/ws", get(graphql::subscribe))
+        .layer(Extension(schema))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_auth,
+        ));
+
+    let api = Router::new()
+        .nest("/rooms", rooms)
+        .route(
+            "/paste",
+            post(paste::create_paste).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                require_auth,
+            )),
+        )
+        .route(
+            "/blobs",
+            post(encrypted_blobs::create_blob)
+                .layer(DefaultBodyLimit::max(
+                    usize::try_from(app_state.config.max_blob_size).unwrap_or(usize::MAX),
+                ))
+                .layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_auth,
+                )),
+        )
+        .route("/blobs/:blob_id", get(encrypted_blobs::get_blob))
+        .route(
+            "/search",
+            get(search::search_rooms).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                require_auth,
+            )),
+        )
+        .nest("/auth", auth::router())
+        .nest("/admin", admin)
+        .nest("/graphql", graphql)
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::swagger_ui))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit_api,
+        ));
+
+    let raw = Router::new()
+        .route("/:room_id/raw", get(paste::get_raw))
+        .route("/:room_id/html", get(markdown::get_html))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            room_id::normalize_path,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_auth,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit_api,
+        ));
+
+    let base_path = app_state.config.base_path.clone();
+    let router = Router::new()
+        .route("/ws", get(handler))
+        .nest("/api", api)
+        .nest("/r", raw)
+        .fallback(static_handler)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            ip_filter::filter_ip,
+        ))
+        .layer(DefaultBodyLimit::max(
+            usize::try_from(app_state.config.max_body_size).unwrap_or(usize::MAX),
+        ));
+    // Upgraded WebSockets and event streams outlive the request, they aren't limited
+    let router = match app_state.config.request_timeout {
+        Some(timeout) => router.layer(TimeoutLayer::new(timeout)),
+        None => router,
+    };
+    let router = match app_state.config.max_concurrent_requests {
+        0 => router,
+        max => router.layer(GlobalConcurrencyLimitLayer::new(max)),
+    };
+    let router = router
+        .layer(TraceLayer::new_for_http())
+        .with_state(app_state);
+
+    if base_path.is_empty() {
+        router
+    } else {
+        Router::new().nest(&base_path, router)
+    }
+}
+
+/// Load the rooms of `db` with their contents from `storage`, then start the background tasks
+/// of the server
+async fn start(
+    config: Config,
+    db: SqlitePool,
+    storage: Arc<dyn Storage>,
+    authenticator: Arc<dyn Authenticator>,
+) -> Result<Arc<AppState>> {
+    let _ = PERSIST_INTERVAL.set(config.persist_interval);
+    let _ = history::RETENTION.set(config.history_retention);
+    let _ = BROADCAST_CAPACITY.set(config.broadcast_capacity);
+
+    if let Some(path) = &config.seed_file {
+        seed::run(&db, storage.as_ref(), path).await?;
+    }
+
+    // Connections still recorded were cut by a crash or a restart
+    for session in sessions::clear_stale(&db).await? {
+        println!(
+            "Connection of {} to room {} (since {}) ended with the previous run",
+            session.username, session.room_id, session.connected_at
+        );
+    }
+
+    let mirror = config.mirror.as_ref().map(Mirror::new).transpose()?;
+    if let Some(mirror) = &mirror {
+        // A new disk, the rooms are only left in the mirror
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rooms")
+            .fetch_one(&db)
+            .await?;
+        if stored == 0 {
+            let restored = mirror
+                .restore(&db, storage.as_ref())
+                .await
+                .context("Failed to restore rooms from the mirror")?;
+            println!("Restored {restored} rooms from the mirror");
+        }
+    }
+
+    let write_behind = WriteBehind::spawn(db.clone(), storage.clone(), mirror.clone());
+    let rooms = storage::load_rooms(&db, storage.as_ref(), &write_behind).await?;
+
+    let app_state = Arc::new(AppState {
+        authenticator,
+        ..AppState::new(rooms, db, write_behind, mirror, config)
+    });
+    app_state
+        .ip_filter
+        .restore(ip_filter::load_bans(&app_state.db).await?);
+
+    // Idle rooms are restored from the database when joined again
+    if let Some(timeout) = app_state.config.idle_room_timeout {
+        let state = app_state.clone();
+        supervisor::spawn_supervised("room eviction".to_string(), move |_| {
+            evict_idle_rooms(state.clone(), timeout)
+        });
+    }
+
+    let state = app_state.clone();
+    supervisor::spawn_supervised("freeze schedules".to_string(), move |_| {
+        apply_freeze_schedules(state.clone())
+    });
+    let state = app_state.clone();
+    supervisor::spawn_supervised("auto-clear".to_string(), move |_| {
+        auto_clear::clear_stale_rooms(state.clone())
+    });
+    let state = app_state.clone();
+    supervisor::spawn_supervised("line claims".to_string(), move |_| {
+        line_claims::expire_line_claims(state.clone())
+    });
+
+    if app_state.config.trash_retention.is_some() {
+        tokio::spawn(trash::run_purge(app_state.clone()));
+    }
+
+    tokio::spawn(encrypted_blobs::run_purge(app_state.clone()));
+
+    tokio::spawn(history::run_retention(
+        app_state.clone(),
+        app_state.config.vacuum_interval,
+    ));
+
+    if let Some(dir) = &app_state.config.backup_dir {
+        tokio::spawn(backup::run_scheduled(
+            app_state.clone(),
+            dir.clone(),
+            app_state.config.backup_interval,
+            app_state.config.backup_keep,
+        ));
+    }
+
+    // Delete attachment blobs no room refers to anymore
+    let gc_state = app_state.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            collect_attachment_garbage(&gc_state).await;
+        }
+    });
+
+    // Forget rate limiting buckets of clients that went away
+    {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                app_state.ws_rate_limiter.cleanup(Duration::from_secs(60));
+                app_state.api_rate_limiter.cleanup(Duration::from_secs(60));
+                app_state.room_creation.cleanup();
+            }
+        });
+    }
+
+    Ok(app_state)
+}
+
+/// Router of a partage server configured by `config`, to mount in another axum app.
+/// Rooms are written to the database in the background until the runtime stops.
+///
+/// # Errors
+///
+/// When the database can't be opened, or its rooms can't be loaded
+pub async fn app(config: Config) -> Result<Router> {
+    PartageServer::builder()
+        .config(config)
+        .build()
+        .router()
+        .await
+}
+
+/// Serve partage configured by `config` until a shutdown signal, then write the rooms to the
+/// database
+///
+/// # Errors
+///
+/// When the database can't be opened, its rooms can't be loaded or the listener can't be bound
+pub async fn serve(config: Config) -> Result<()> {
+    PartageServer::builder()
+        .config(config)
+        .build()
+        .serve()
+        .await
+}
+
+/// Serve the app of `app_state` until a shutdown signal, then write the rooms to the database
+async fn listen(app_state: Arc<AppState>) -> Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], app_state.config.port));
+    static_assets::log_embedded();
+
+    let shutdown_state = app_state.clone();
+    let tls = app_state.config.tls.clone();
+    let unix_socket_path = app_state.config.unix_socket.clone();
+    let unix_socket_mode = app_state.config.unix_socket_mode;
+    let app = router(app_state);
+
+    if let Some(path) = unix_socket_path {
+        #[cfg(unix)]
+        {
+            let listener = unix_socket::bind(&path, unix_socket_mode)?;
+            let shutdown = shutdown_state.shutdown.clone();
+            let drain_state = shutdown_state.clone();
+            tokio::spawn(async move {
+                shutdown_signal().await;
+                drain(&drain_state).await;
+            });
+
+            println!("listening on unix:{}", path.display());
+
+            unix_socket::serve(listener, &path, app, shutdown).await?;
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("Unix sockets aren't supported on this platform");
+    } else if let Some(tls) = tls {
+        let rustls = tls.load().await?;
+        #[cfg(unix)]
+        tokio::spawn(tls::reload_on_sighup(tls, rustls.clone()));
+
+        let handle = axum_server::Handle::new();
+        let shutdown_handle = handle.clone();
+        let drain_state = shutdown_state.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            drain(&drain_state).await;
+            shutdown_handle.graceful_shutdown(None);
+        });
+
+        println!("listening on {addr} (TLS)");
+
+        axum_server::bind_rustls(addr, rustls)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await?;
+    } else {
+        let listener = tokio::net::TcpListener::bind(addr.to_string()).await?;
+
+        println!("listening on {}", listener.local_addr()?);
+
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown({
+            let drain_state = shutdown_state.clone();
+            async move {
+                shutdown_signal().await;
+                drain(&drain_state).await;
+            }
+        })
+        .await?;
+    }
+
+    // Give the sockets a moment to send their close frames
+    time::sleep(Duration::from_millis(500)).await;
+    flush_rooms(&shutdown_state).await;
+
+    Ok(())
+}
+
+/// Command line of the `partage` binary: serve with the configuration of the environment,
+/// or seed the database with `partage seed --file <seed.yaml>`
+///
+/// # Errors
+///
+/// When the configuration is invalid, or serving or seeding fails
+pub async fn run() -> Result<()> {
+    let dotenv_found = dotenv().is_ok();
+    let config = Config::load().context("Invalid configuration")?;
+    let _telemetry = telemetry::init(config.otlp.as_ref())?;
+    if !dotenv_found {
+        eprintln!("No .env file found");
+    }
+
+    if let Some(path) = seed::seed_file_from_args(std::env::args().skip(1))? {
+        let db = storage::open_database(&config).await?;
+        seed::run(&db, &SqliteStorage::new(db.clone()), &path).await?;
+        return Ok(());
+    }
+
+    serve(config).await
+}
+
+/// Current time as seconds since the Unix epoch
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX)
+        })
+}
+
+#[derive(TS, Serialize, Debug)]
+enum SocketMessageType {
+    #[serde(rename = "join")]
+    Join,
+    #[serde(rename = "leave")]
+    Leave,
+    #[serde(rename = "message")]
+    Message,
+    #[serde(rename = "error")]
+    Error,
+    #[serde(rename = "update-rooms-list")]
+    UpdateRoomsList,
+    #[serde(rename = "freeze")]
+    Freeze,
+    #[serde(rename = "unfreeze")]
+    Unfreeze,
+    #[serde(rename = "announcement")]
+    Announcement,
+    #[serde(rename = "persistence-degraded")]
+    PersistenceDegraded,
+    #[serde(rename = "persistence-restored")]
+    PersistenceRestored,
+    #[serde(rename = "encrypted")]
+    Encrypted,
+    #[serde(rename = "hello")]
+    Hello,
+    #[serde(rename = "document-removed")]
+    DocumentRemoved,
+    #[serde(rename = "file-added")]
+    FileAdded,
+    #[serde(rename = "redirect")]
+    Redirect,
+    #[serde(rename = "language-changed")]
+    LanguageChanged,
+    #[serde(rename = "room-closing")]
+    RoomClosing,
+    #[serde(rename = "username-assigned")]
+    UsernameAssigned,
+    #[serde(rename = "presence")]
+    Presence,
+    #[serde(rename = "room-renamed")]
+    RoomRenamed,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "server-shutdown")]
+    ServerShutdown,
+    #[serde(rename = "merge-conflict")]
+    MergeConflict,
+    #[serde(rename = "kicked")]
+    Kicked,
+    #[serde(rename = "muted")]
+    Muted,
+    #[serde(rename = "unmuted")]
+    Unmuted,
+    #[serde(rename = "request-lock")]
+    RequestLock,
+    #[serde(rename = "grant-lock")]
+    GrantLock,
+    #[serde(rename = "lines-claimed")]
+    LinesClaimed,
+    #[serde(rename = "lines-released")]
+    LinesReleased,
+    #[serde(rename = "checkpoint")]
+    Checkpoint,
+    #[serde(rename = "persisted")]
+    Persisted,
+    #[serde(rename = "content-size")]
+    ContentSize,
+    #[serde(rename = "range")]
+    Range,
+}
+
+impl SocketMessageType {
+    /// Type of the messages carrying the content of a room, base64 ciphertext if encrypted
+    const fn content(encrypted: bool) -> Self {
+        if encrypted {
+            Self::Encrypted
+        } else {
+            Self::Message
+        }
+    }
+}
+
+#[derive(TS, Serialize, Debug, OptionalDefault)]
+#[ts(export)]
+struct SocketMessage {
+    /// Document of the room the message is about, the main one if unset.
+    /// Must stay the first field, see [`documents::is_document_message`].
+    #[optional(default = None)]
+    #[ts(type = "string | undefined")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    doc_id: Option<String>,
+    #[serde(rename = "type")]
+    message_type: SocketMessageType,
+    #[optional(default = None)]
+    #[ts(type = "string | undefined")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    /// What went wrong, in `error` messages
+    #[optional(default = None)]
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    /// Revision of the document a content message brings it to, sent back with the edits made
+    /// on it to merge them with the changes of the others
+    #[optional(default = None)]
+    #[ts(type = "number | undefined")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<u64>,
+    #[optional(default = String::new())]
+    #[ts(type = "string | undefined")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    username: String,
+}
+
+// Declared after `SocketMessage` to be in the scope of its `SocketMessage!` macro
+mod api;
+mod line_claims;
+mod rooms;
+mod static_assets;
+mod storage;
+mod write_lock;
+mod ws;
+
+/// Custom error type that can be converted into a JSON response
+#[derive(Debug)]
+struct CustomError {
+    status: StatusCode,
+    message: String,
+}
+
+impl CustomError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl IntoResponse for CustomError {
+    fn into_response(self) -> Response {
+        // Convert the custom error into a JSON response with a specific status code
+        let body = Json(json!({ "error": self.message }));
+        (self.status, body).into_response()
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
+    }
+}
+
+// Test functions
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    use crate::api::{DryRunReport, Room};
+    use crate::oidc::OidcConfig;
+    use crate::protocol::PROTOCOL_VERSION;
+    use crate::rate_limit::RateLimit;
+    use crate::rooms::{
+        drain, evict_idle_rooms_once, PersistenceHealth, RoomState, DEFAULT_BROADCAST_CAPACITY,
+    };
+    use crate::storage::update_room_content;
+    use crate::webhooks::sign;
+    use crate::write_behind::WriteBehind;
+    use crate::{
+        auto_clear, database, line_claims, router, AppState, Config, MemoryStorage, SqliteStorage,
+    };
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::Instant;
+    use tokio_tungstenite::tungstenite::Message;
+
+    async fn setup_test_server() -> (SocketAddr, Arc<AppState>) {
+        setup_test_server_with_config(Config::default()).await
+    }
+
+    async fn setup_test_server_with_config(config: Config) -> (SocketAddr, Arc<AppState>) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        // Create test app state similar to main() without a DATABASE_URL
+        let db = database::memory().await.unwrap();
+        let write_behind = WriteBehind::spawn(db.clone(), Arc::new(MemoryStorage::default()), None);
+        let mut rooms = HashMap::<String, RoomState>::new();
+        rooms.insert(
+            "general".to_string(),
+            RoomState::new("general", &write_behind),
+        );
+        let app_state = Arc::new(AppState::new(rooms, db, write_behind, None, config));
+
+        let app = router(app_state.clone());
+
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap();
+        });
+
+        (server_addr, app_state)
+    }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            base_path: "/partage".to_string(),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/partage/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let html = client
+            .get(format!("http://{addr}/partage/c/general"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(html.contains("<base href=\"/partage/\">"));
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/partage/ws"))
+            .await
+            .unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        assert!(ws.next().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_static_file_handling() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+
+        // Test root path
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Test index.html
+        let response = client
+            .get(format!("http://{addr}/index.html"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Test favicon.ico
+        let response = client
+            .get(format!("http://{addr}/favicon.ico"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Test non-existent file
+        let response = client
+            .get(format!("http://{addr}/nonexistent.js"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_asset_metrics() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        client
+            .get(format!("http://{addr}/favicon.ico"))
+            .send()
+            .await
+            .unwrap();
+        client
+            .get(format!("http://{addr}/missing.js"))
+            .send()
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!("http://{addr}/api/admin/assets"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let report: serde_json::Value = response.json().await.unwrap();
+
+        let assets = report["assets"].as_array().unwrap();
+        let index = assets.iter().find(|a| a["path"] == "index.html").unwrap();
+        assert_eq!(index["sha256"].as_str().unwrap().len(), 64);
+        let favicon = assets.iter().find(|a| a["path"] == "favicon.ico").unwrap();
+        assert_eq!(favicon["hits"], 1);
+        assert_eq!(report["not_found"], 1);
+        assert!(report["bytes_served"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_chat_flow() {
+        let (addr, _app) = setup_test_server().await;
+
+        // Connect two test users
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+
+        // User 1 joins general channel
+        let join_msg1 = json!({
+            "type": "join",
+            "channel": "general",
+            "username": "alice"
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg1)).await.unwrap();
+
+        // User 2 joins general channel
+        let join_msg2 = json!({
+            "type": "join",
+            "channel": "general",
+            "username": "bob"
+        })
+        .to_string();
+        ws2.send(Message::Text(join_msg2)).await.unwrap();
+
+        // Wait for initial messages on both connections
+        for _ in 0..3 {
+            if let Some(msg) = ws1.next().await {
+                let _ = msg.unwrap().into_text().unwrap();
+            }
+        }
+        for _ in 0..3 {
+            if let Some(msg) = ws2.next().await {
+                let _ = msg.unwrap().into_text().unwrap();
+            }
+        }
+
+        // Small delay to ensure all messages are processed
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        // User 1 sends a message
+        let chat_msg = json!({
+            "type": "message",
+            "channel": "general",
+            "username": "alice",
+            "content": "Hello, Bob!"
+        })
+        .to_string();
+        ws1.send(Message::Text(chat_msg)).await.unwrap();
+
+        // Verify Bob receives Alice's message
+        if let Some(msg) = ws2.next().await {
+            let received = msg.unwrap().into_text().unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+            let inner_msg: serde_json::Value =
+                serde_json::from_str(parsed["value"].as_str().unwrap()).unwrap();
+            assert_eq!(inner_msg["username"].as_str().unwrap(), "alice");
+            assert_eq!(inner_msg["content"].as_str().unwrap(), "Hello, Bob!");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_edge_cases() {
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        // Test connection with missing username
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let invalid_join = json!({
+            "channel": "test"
+        })
+        .to_string();
+
+        ws.send(Message::Text(invalid_join)).await.unwrap();
+
+        if let Some(msg) = ws.next().await {
+            let error_msg = msg.unwrap().into_text().unwrap();
+            assert!(error_msg.contains("error"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_room_management() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let base_url = format!("http://{addr}");
+
+        // Get initial rooms (should include general)
+        let response = client
+            .get(format!("{base_url}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let rooms: Vec<Room> = response.json().await.unwrap();
+        assert!(rooms.iter().any(|r| r.id == "general"));
+
+        // Try to delete general room (should fail)
+        let response = client
+            .delete(format!("{base_url}/api/rooms/general"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        // Connect user to a new room
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+
+        let join_msg = json!({
+            "username": "carol",
+            "channel": "new-room"
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        // Verify new room exists
+        let response = client
+            .get(format!("{base_url}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        let rooms: Vec<Room> = response.json().await.unwrap();
+        assert!(rooms.iter().any(|r| r.id == "new-room"));
+    }
+
+    #[tokio::test]
+    async fn test_room_removal_edge_cases() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+
+        // Test removing non-existent room
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/nonexistent"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Test removing room with active users
+        // First create and populate a room
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Join two users to the same room
+        let join_msg = json!({
+            "username": "test_user_1",
+            "channel": "test_room"
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        let join_msg = json!({
+            "username": "test_user_2",
+            "channel": "test_room"
+        })
+        .to_string();
+        ws2.send(Message::Text(join_msg)).await.unwrap();
+
+        // Rooms with active users are removed after a countdown
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/test_room"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["type"], "scheduled");
+        assert_eq!(body["value"], 60);
+    }
+
+    #[tokio::test]
+    async fn test_room_removal_dry_run() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "dry_runner",
+            "channel": "dry_run_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+
+        ws.send(Message::Text("12345".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let response = client
+            .post(format!("http://{addr}/api/rooms/dry_run_room/checkpoints"))
+            .json(&json!({ "label": "kept" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+
+        // Dry run reports the room and its user, nothing being freed before the trash is purged
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/dry_run_room?dry_run=true"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["type"], "dry-run");
+        let report: DryRunReport = serde_json::from_value(body["value"].clone()).unwrap();
+        assert_eq!(
+            report,
+            DryRunReport {
+                rooms: vec!["dry_run_room".to_string()],
+                blobs: Vec::new(),
+                users_disconnected: 1,
+                bytes_freed: 0,
+            }
+        );
+
+        // The room is still there
+        let rooms: Vec<Room> = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rooms.iter().any(|r| r.id == "dry_run_room"));
+
+        // Dry run still reports validation errors
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/general?dry_run=true"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_rate_limit() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            ws_rate_limit: RateLimit {
+                per_second: 1,
+                burst: 3,
+            },
+            ..Config::default()
+        })
+        .await;
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "spammer",
+            "channel": "spam_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+
+        for i in 0..5 {
+            ws.send(Message::Text(format!("spam {i}"))).await.unwrap();
+        }
+
+        // The join and the first two edits go through, then the client is throttled
+        let mut throttled = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            if parsed["type"] == "error" {
+                assert_eq!(parsed["value"], "Too many messages, slow down.");
+                throttled = true;
+                break;
+            }
+        }
+        assert!(throttled);
+    }
+
+    #[tokio::test]
+    async fn test_api_rate_limit() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            api_rate_limit: RateLimit {
+                per_second: 1,
+                burst: 2,
+            },
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        for _ in 0..2 {
+            let response = client
+                .get(format!("http://{addr}/api/rooms"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+        }
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+
+        // Static files are not throttled
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_format_room() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "writer",
+            "channel": "format_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+
+        ws.send(Message::Text("* a   \n\n\n\n* b".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = client
+            .post(format!("http://{addr}/api/rooms/format_room/format"))
+            .json(&json!({ "formatter": "markdown" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["value"], "- a\n\n- b\n");
+
+        // Members receive the formatted content from the server
+        let mut received_formatted = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            if parsed["username"] == "Server" && parsed["value"] == "- a\n\n- b\n" {
+                received_formatted = true;
+                break;
+            }
+        }
+        assert!(received_formatted);
+
+        // No command configured
+        let response = client
+            .post(format!("http://{addr}/api/rooms/format_room/format"))
+            .json(&json!({ "formatter": "command" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let response = client
+            .post(format!("http://{addr}/api/rooms/unknown_room/format"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "busy_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        for content in ["a", "ab", "abc"] {
+            ws.send(Message::Text(content.to_string())).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stats: crate::stats::ServerStats = client
+            .get(format!("http://{addr}/api/admin/stats?limit=1"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(stats.rooms, 2);
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.top_rooms.len(), 1);
+        let busy = &stats.top_rooms[0];
+        assert_eq!(busy.room_id, "busy_room");
+        assert_eq!(busy.connections, 1);
+        assert_eq!(busy.content_length, 3);
+        assert_eq!(busy.messages, 3);
+        assert!(busy.messages_per_minute > 2.9);
+
+        let response = client
+            .get(format!("http://{addr}/api/admin/stats?sort=loudest"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_admin_api() {
+        async fn next_announcement<S>(ws: &mut S) -> Option<serde_json::Value>
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            while let Ok(Some(Ok(msg))) =
+                tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+            {
+                let parsed: serde_json::Value =
+                    serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+                if parsed["type"] == "announcement" {
+                    return serde_json::from_str(parsed["value"].as_str().unwrap()).ok();
+                }
+            }
+            None
+        }
+
+        let (addr, _) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/api/admin/connections"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .get(format!("http://{addr}/api/admin/connections"))
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+            let join_msg = json!({
+                "username": username,
+                "channel": "admin_room"
+            })
+            .to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let _ = ws.next().await.unwrap();
+            sockets.push(ws);
+        }
+        let (mut general_ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "carol",
+            "channel": "general"
+        })
+        .to_string();
+        general_ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = general_ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let connections: Vec<serde_json::Value> = client
+            .get(format!("http://{addr}/api/admin/connections"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(connections.len(), 3);
+        assert_eq!(connections[0]["username"], "alice");
+        assert_eq!(connections[0]["room"], "admin_room");
+        assert_eq!(connections[0]["ip"], "127.0.0.1");
+
+        // Announcements reach every room
+        let response = client
+            .post(format!("http://{addr}/api/admin/announce"))
+            .bearer_auth("s3cret")
+            .json(&json!({
+                "message": "Maintenance at noon",
+                "severity": "warning",
+                "expires_in": 600
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let announcement = next_announcement(&mut general_ws).await.unwrap();
+        assert_eq!(announcement["message"], "Maintenance at noon");
+        assert_eq!(announcement["severity"], "warning");
+        assert!(
+            (announcement["expires_at"].as_i64().unwrap() - crate::unix_timestamp() - 600).abs()
+                <= 1
+        );
+        // Clients joining before it expires get it too
+        let (mut late_ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "carol", "channel": "late_room" }).to_string();
+        late_ws.send(Message::Text(join_msg)).await.unwrap();
+        let announcement = next_announcement(&mut late_ws).await.unwrap();
+        assert_eq!(announcement["message"], "Maintenance at noon");
+        late_ws.close(None).await.unwrap();
+
+        let response = client
+            .post(format!("http://{addr}/api/admin/announce"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "message": "Maintenance at noon", "severity": "urgent" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+
+        // Muting drops what the user writes, telling the room
+        let response = client
+            .post(format!("http://{addr}/api/admin/rooms/admin_room/mute"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "username": "bob", "minutes": 5 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        sockets[1]
+            .send(Message::Text("muted edit".to_string()))
+            .await
+            .unwrap();
+        let mut told = (false, false);
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), sockets[1].next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            assert_ne!(parsed["value"], "muted edit");
+            if parsed["type"] == "muted" {
+                assert_eq!(parsed["username"], "bob");
+                told.0 = true;
+            }
+            if parsed["code"] == "muted" {
+                told.1 = true;
+            }
+        }
+        assert_eq!(told, (true, true));
+        let content = reqwest::get(format!("http://{addr}/r/admin_room/raw"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_ne!(content, "muted edit");
+        let unmute_url = format!("http://{addr}/api/admin/rooms/admin_room/mute/bob");
+        let response = client
+            .delete(&unmute_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .delete(&unmute_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let response = client
+            .post(format!("http://{addr}/api/admin/rooms/admin_room/mute"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "username": "nobody" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        // Kicking closes the sockets of the user, telling it first
+        let response = client
+            .post(format!("http://{addr}/api/admin/rooms/admin_room/kick"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "username": "alice" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let (mut kicked, mut closed) = (false, false);
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), sockets[0].next()).await
+        {
+            match msg {
+                Message::Text(text) if text.contains(r#""type":"kicked""#) => kicked = true,
+                Message::Close(Some(frame)) => {
+                    assert_eq!(u16::from(frame.code), 1008);
+                    closed = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert!(kicked && closed);
+
+        // The user count check of `remove_room` doesn't apply
+        let response = client
+            .delete(format!("http://{addr}/api/admin/rooms/admin_room"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let rooms: Vec<Room> = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rooms.iter().all(|room| room.id != "admin_room"));
+
+        let response = client
+            .delete(format!("http://{addr}/api/admin/rooms/general"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_attachments_gc_dry_run() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        sqlx::query("INSERT INTO blobs (hash, size, last_used_at) VALUES ('0123', 6, 0)")
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!(
+                "http://{addr}/api/admin/attachments/gc?dry_run=true"
+            ))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["type"], "dry-run");
+        let report: DryRunReport = serde_json::from_value(body["value"].clone()).unwrap();
+        assert_eq!(
+            report,
+            DryRunReport {
+                blobs: vec!["0123".to_string()],
+                bytes_freed: 6,
+                ..DryRunReport::default()
+            }
+        );
+
+        // The blob is still there
+        let blobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM blobs")
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(blobs, 1);
+    }
+
+    #[tokio::test]
+    async fn test_saturation_metrics() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "metrics_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Fill the room channel as if its member stopped reading
+        let rooms = state.rooms.lock().await;
+        let room = &rooms["metrics_room"];
+        let mut slow = room.tx.subscribe();
+        for i in 0..DEFAULT_BROADCAST_CAPACITY / 4 {
+            room.tx.send(i.to_string()).unwrap();
+        }
+        drop(rooms);
+
+        let response = reqwest::Client::new()
+            .get(format!("http://{addr}/api/admin/metrics"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let text = response.text().await.unwrap();
+        assert!(text.contains("\npartage_connections 1\n"));
+        assert!(text.contains("\npartage_broadcast_fill_ratio_max 0.25\n"));
+        assert!(text.contains("\npartage_db_write_queue_length 0\n"));
+        assert!(text.contains("\npartage_lagged_receivers_total 0\n"));
+        assert!(slow.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_disabled() {
+        let (addr, _) = setup_test_server().await;
+        let response = reqwest::get(format!("http://{addr}/api/admin/connections"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_admin_bans() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/admin/bans"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "ip": "192.0.2.1", "reason": "spam" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let bans: Vec<serde_json::Value> = client
+            .get(format!("http://{addr}/api/admin/bans"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0]["ip"], "192.0.2.1");
+        assert_eq!(bans[0]["reason"], "spam");
+        let response = client
+            .delete(format!("http://{addr}/api/admin/bans/192.0.2.1"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .delete(format!("http://{addr}/api/admin/bans/192.0.2.1"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        // Banning an address closes the sockets opened from it
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "ban_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = client
+            .post(format!("http://{addr}/api/admin/bans"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "ip": "127.0.0.1" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let mut closed = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            if let Message::Close(_) = msg {
+                closed = true;
+                break;
+            }
+        }
+        assert!(closed);
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        assert!(state.ip_filter.unban("127.0.0.1".parse().unwrap()));
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            max_body_size: 1024,
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/paste"))
+            .body("a".repeat(2048))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 413);
+        let response = client
+            .post(format!("http://{addr}/api/paste"))
+            .body("a".repeat(512))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_trusted_proxies() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            trusted_proxies: crate::ip_filter::parse_list("127.0.0.1").unwrap(),
+            ip_denylist: crate::ip_filter::parse_list("203.0.113.0/24").unwrap(),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .header("x-forwarded-for", "203.0.113.9")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client
+            .get(format!("http://{addr}/api/rooms"))
+            .header("forwarded", "for=198.51.100.1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        ws.send(Message::Text(
+            json!({ "username": "alice", "channel": "general" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let _ = ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            state.connections.list()[0].ip,
+            "198.51.100.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            shutdown_grace: Duration::from_secs(5),
+            ..Config::default()
+        })
+        .await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = Instant::now();
+        let draining = tokio::spawn({
+            let state = state.clone();
+            async move { drain(&state).await }
+        });
+
+        // The client is told to leave, and leaves before the grace period ends
+        let mut announced = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let Message::Text(text) = msg else {
+                continue;
+            };
+            let parsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if parsed["type"] == "server-shutdown" {
+                let value: serde_json::Value =
+                    serde_json::from_str(parsed["value"].as_str().unwrap()).unwrap();
+                assert_eq!(value["grace_ms"], 5000);
+                announced = true;
+                break;
+            }
+        }
+        assert!(announced);
+
+        // New connections are refused meanwhile
+        assert!(connect_async(&ws_uri).await.is_err());
+
+        ws.close(None).await.unwrap();
+        draining.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(state.shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_protocol_handshake() {
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "handshake_room",
+            "protocol_version": PROTOCOL_VERSION,
+            "capabilities": ["presence", "diff-sync", "teleportation"]
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "hello");
+        let hello: serde_json::Value =
+            serde_json::from_str(parsed["value"].as_str().unwrap()).unwrap();
+        assert_eq!(hello["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(hello["capabilities"], json!(["presence"]));
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "message");
+
+        // Unsupported versions are refused with an explanation
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "bob",
+            "channel": "handshake_room",
+            "protocol_version": PROTOCOL_VERSION + 1
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "error");
+        match ws.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1002),
+            msg => panic!("Expected a close frame, got {msg:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_pack() {
+        let (addr, _) = setup_test_server().await;
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "msgpack_room",
+            "protocol_version": PROTOCOL_VERSION,
+            "capabilities": ["msgpack"]
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "hello");
+
+        // Everything after `hello` is MessagePack
+        let Message::Binary(bytes) = ws.next().await.unwrap().unwrap() else {
+            panic!("Expected a binary frame");
+        };
+        let parsed: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(parsed["type"], "message");
+        let _ = ws.next().await.unwrap(); // Join
+        let _ = ws.next().await.unwrap(); // Presence
+
+        ws.send(Message::Text("packed".to_string())).await.unwrap();
+        let Message::Binary(bytes) = ws.next().await.unwrap().unwrap() else {
+            panic!("Expected a binary frame");
+        };
+        let parsed: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(parsed["value"], "packed");
+        assert_eq!(parsed["username"], "alice");
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_room() {
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let salt = STANDARD.encode([7u8; 16]);
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "secret_room",
+            "encryption": { "salt": salt }
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "encrypted");
+        let _ = ws.next().await.unwrap(); // Join
+        let _ = ws.next().await.unwrap(); // Presence
+
+        // Plaintext is refused
+        ws.send(Message::Text("my password".to_string()))
+            .await
+            .unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "error");
+
+        // Ciphertext is relayed as is
+        let ciphertext = "c2VjcmV0IHNlY3JldCBzZWNyZXQgc2VjcmV0IHNlY3JldA==";
+        ws.send(Message::Text(ciphertext.to_string()))
+            .await
+            .unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "encrypted");
+        assert_eq!(parsed["value"], ciphertext);
+
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rooms
+            .iter()
+            .any(|room| room.id == "secret_room" && room.encrypted));
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}/api/rooms/secret_room/format"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        // Asking for encryption of an existing plaintext room fails
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "bob",
+            "channel": "general",
+            "encryption": { "salt": salt }
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "error");
+    }
+
+    /// Schedule covering the whole week
+    fn always_frozen() -> serde_json::Value {
+        let windows: Vec<_> = [
+            "monday",
+            "tuesday",
+            "wednesday",
+            "thursday",
+            "friday",
+            "saturday",
+            "sunday",
+        ]
+        .into_iter()
+        .map(|day| json!({ "day": day, "start": "00:00", "end": "24:00" }))
+        .collect();
+        json!({ "windows": windows })
+    }
+
+    #[tokio::test]
+    async fn test_freeze_schedule() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let freeze_url = format!("http://{addr}/api/rooms/frozen_room/freeze");
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "writer",
+            "channel": "frozen_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        ws.send(Message::Text("before".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = client
+            .put(&freeze_url)
+            .json(&always_frozen())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["value"]["frozen_until"].is_i64());
+
+        // Members are told about the freeze
+        let mut frozen = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            if parsed["type"] == "freeze" {
+                frozen = true;
+                break;
+            }
+        }
+        assert!(frozen);
+
+        // Edits are refused and the sender gets the current content back
+        ws.send(Message::Text("during".to_string())).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "error");
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["username"], "Server");
+        assert_eq!(parsed["value"], "before");
+
+        let response = client
+            .post(format!("http://{addr}/api/rooms/frozen_room/format"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 423);
+
+        // Late joiners know the room is frozen
+        let (mut late_ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "late",
+            "channel": "frozen_room"
+        })
+        .to_string();
+        late_ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = late_ws.next().await.unwrap();
+        let msg = late_ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "freeze");
+
+        // Clearing the schedule unfreezes the room
+        let response = client
+            .put(&freeze_url)
+            .json(&json!({ "windows": [] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let mut unfrozen = false;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            if parsed["type"] == "unfreeze" {
+                unfrozen = true;
+                break;
+            }
+        }
+        assert!(unfrozen);
+
+        let response = client
+            .put(&freeze_url)
+            .json(&json!({ "windows": [{ "day": "friday", "start": "18:00", "end": "16:00" }] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms/unknown_room/freeze"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_overload() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            upgrades_per_second: 1,
+            upgrade_queue_size: 0,
+            ..Config::default()
+        })
+        .await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (_ws, _) = connect_async(&ws_uri).await.unwrap();
+
+        // The next upgrade would have to wait, but there is no room in the queue
+        match connect_async(&ws_uri).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), 503);
+                assert!(response.headers().contains_key("retry-after"));
+            }
+            other => panic!("Expected an HTTP error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_close_frame() {
+        let (addr, state) = setup_test_server().await;
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "leaver",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+
+        state.shutdown.cancel();
+
+        // Skip the broadcasts until the close frame
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Close(Some(frame)) => {
+                    assert_eq!(u16::from(frame.code), 1012);
+                    let reason: serde_json::Value = serde_json::from_str(&frame.reason).unwrap();
+                    assert!(reason["retry_after_ms"].as_u64().unwrap() >= 1000);
+                    break;
+                }
+                Message::Text(_) => continue,
+                other => panic!("Unexpected message: {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_chat() {
+        let (addr, _) = setup_test_server().await;
+        let mut connections = HashMap::new();
+
+        // Connect multiple users
+        let ws_uri = format!("ws://{addr}/ws");
+        for i in 0..5 {
+            let (ws, _) = connect_async(&ws_uri).await.unwrap();
+            connections.insert(format!("user{i}"), ws);
+        }
+
+        // Join all users to the same room
+        for (username, ref mut ws) in &mut connections {
+            let join_msg = json!({
+                "type": "join",
+                "channel": "test_room",
+                "username": username,
+            })
+            .to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+        }
+
+        // Have each user send a message
+        for (username, ref mut ws) in &mut connections {
+            let msg = json!({
+                "type": "message",
+                "channel": "test_room",
+                "username": username,
+                "content": format!("Hello from {}", username),
+            })
+            .to_string();
+            ws.send(Message::Text(msg)).await.unwrap();
+        }
+
+        // Verify each user receives messages from others
+        for ref mut ws in connections.values_mut() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if let Some(msg) = ws.next().await {
+                let msg = msg.unwrap();
+                assert!(msg.is_text());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnection_scenario() {
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        // Initial connection
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Join and send a message
+        let join_msg = json!({
+            "username": "disconnector",
+            "channel": "test-room"
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        let msg = json!({
+            "type": "message",
+            "value": "Initial message",
+            "username": "disconnector"
+        })
+        .to_string();
+        ws1.send(Message::Text(msg)).await.unwrap();
+
+        // Simulate disconnection
+        drop(ws1);
+
+        // Reconnect
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Rejoin same room
+        let rejoin_msg = json!({
+            "username": "disconnector",
+            "channel": "test-room"
+        })
+        .to_string();
+        ws2.send(Message::Text(rejoin_msg)).await.unwrap();
+
+        // Verify we receive the current room state
+        if let Some(msg) = ws2.next().await {
+            let received = msg.unwrap().into_text().unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+            assert_eq!(parsed["type"], "message");
+            assert_eq!(parsed["username"], "Server");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_handling() {
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Send invalid JSON
+        ws.send(Message::Text("invalid json".to_string()))
+            .await
+            .unwrap();
+
+        // Should receive error message
+        if let Some(msg) = ws.next().await {
+            let msg = msg.unwrap();
+            assert!(msg.is_text());
+        }
+
+        // Try to delete non-existent room
+        let client = reqwest::Client::new();
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/nonexistent"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200); // Silently fails as specified
+    }
+
+    // Keep existing imports and add:
+    use sqlx::SqlitePool;
+
+    async fn setup_test_server_with_db() -> (SocketAddr, Arc<AppState>, SqlitePool) {
+        setup_test_server_with_db_and_config(Config::default()).await
+    }
+
+    async fn setup_test_server_with_db_and_config(
+        config: Config,
+    ) -> (SocketAddr, Arc<AppState>, SqlitePool) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        // Create in-memory SQLite database
+        let db = database::memory().await.unwrap();
+
+        // Initialize the general room in the database first
+        sqlx::query!(
+            "INSERT INTO rooms (room_id, content) VALUES (?, ?)",
+            "general",
+            ""
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let storage = Arc::new(SqliteStorage::new(db.clone()));
+        let write_behind = WriteBehind::spawn(db.clone(), storage, None);
+        let mut rooms = HashMap::<String, RoomState>::new();
+        rooms.insert(
+            "general".to_string(),
+            RoomState::new("general", &write_behind),
+        );
+        let app_state = Arc::new(AppState::new(rooms, db.clone(), write_behind, None, config));
+
+        let app = router(app_state.clone());
+
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap();
+        });
+
+        (server_addr, app_state, db)
+    }
+
+    #[tokio::test]
+    async fn test_database_persistence() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        // Connect and send messages
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Join general room
+        let join_msg = json!({
+            "username": "db_test_user",
+            "channel": "general"
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        // Wait for initial messages
+        let msg = ws1.next().await.unwrap();
+        let _ = msg.unwrap().into_text().unwrap();
+
+        // Send a test message
+        let test_content = "Test message for database persistence";
+        ws1.send(Message::Text(test_content.to_string()))
+            .await
+            .unwrap();
+
+        // Wait a bit for the database update interval
+        tokio::time::sleep(Duration::from_secs(6)).await;
+
+        // Verify content was saved to database
+        let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", "general")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(room.content, test_content);
+    }
+
+    #[tokio::test]
+    async fn test_room_persistence() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        // Create a new room by connecting to it
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        let new_room = "persistent_test_room";
+
+        let join_msg = json!({
+            "username": "room_creator",
+            "channel": new_room
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        // Send content to the new room
+        let test_content = "Content for persistent room";
+
+        // Wait for initial messages
+        let msg = ws1.next().await.unwrap();
+        let _ = msg.unwrap().into_text().unwrap();
+
+        ws1.send(Message::Text(test_content.to_string()))
+            .await
+            .unwrap();
+
+        // Wait for database update
+        tokio::time::sleep(Duration::from_secs(6)).await;
+
+        // Verify room exists in database
+        let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", new_room)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(room.content, test_content);
+
+        // Test room deletion
+        let client = reqwest::Client::new();
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/{new_room}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Verify room was moved to the trash, with its content
+        let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", new_room)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert!(room.deleted_at.is_some());
+        assert_eq!(room.content, test_content);
+    }
+
+    #[tokio::test]
+    async fn test_room_trash() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        let ws_uri = format!("ws://{addr}/ws");
+        let join_msg = json!({ "username": "alice", "channel": "trashed_room" }).to_string();
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws.next().await; // Content
+        ws.send(Message::Text("keep me".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state.write_behind.tracked("trashed_room"), 1);
+
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/trashed_room"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        // It is no longer written
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state.write_behind.tracked("trashed_room"), 0);
+
+        let listed = || async {
+            let rooms: Vec<serde_json::Value> = client
+                .get(format!("http://{addr}/api/rooms"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            rooms.iter().any(|room| room["id"] == "trashed_room")
+        };
+        assert!(!listed().await);
+
+        let trash: serde_json::Value = client
+            .get(format!("http://{addr}/api/rooms/trash"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let trashed = trash["value"].as_array().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0]["id"], "trashed_room");
+        assert_eq!(
+            trashed[0]["purge_at"].as_i64().unwrap() - trashed[0]["deleted_at"].as_i64().unwrap(),
+            30 * 24 * 60 * 60
+        );
+
+        // Not recreated under the same id
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["type"], "error");
+        assert_eq!(msg["value"], "This room is in the trash, restore it first.");
+
+        let restore_url = format!("http://{addr}/api/rooms/trashed_room/restore");
+        let response = client.post(&restore_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client.post(&restore_url).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        assert!(listed().await);
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'trashed_room'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(content, "keep me");
+    }
+
+    #[tokio::test]
+    async fn test_room_archive() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        let ws_uri = format!("ws://{addr}/ws");
+        let join_msg = json!({ "username": "alice", "channel": "archived_room" }).to_string();
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws.next().await; // Content
+        ws.send(Message::Text("old notes".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let archive_url = format!("http://{addr}/api/rooms/archived_room/archive");
+        let response = client.post(&archive_url).send().await.unwrap();
+        assert_eq!(response.status(), 409);
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = client.post(&archive_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(!state.rooms.lock().await.contains_key("archived_room"));
+        let response = client.post(&archive_url).send().await.unwrap();
+        assert_eq!(response.status(), 409);
+
+        let listed = |query: &'static str| {
+            let client = client.clone();
+            async move {
+                let rooms: Vec<serde_json::Value> = client
+                    .get(format!("http://{addr}/api/rooms{query}"))
+                    .send()
+                    .await
+                    .unwrap()
+                    .json()
+                    .await
+                    .unwrap();
+                rooms.into_iter().find(|room| room["id"] == "archived_room")
+            }
+        };
+        assert!(listed("").await.is_none());
+        let room = listed("?include=archived").await.unwrap();
+        assert_eq!(room["archived"], true);
+        assert_eq!(room["content_length"], "old notes".len());
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(join_msg.clone())).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["type"], "error");
+        assert_eq!(msg["code"], "room-archived");
+        // Read through the REST API, it is not loaded either
+        let response = reqwest::get(format!("http://{addr}/r/archived_room/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let unarchive_url = format!("http://{addr}/api/rooms/archived_room/unarchive");
+        let response = client.post(&unarchive_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client.post(&unarchive_url).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(listed("").await.unwrap()["archived"], false);
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["value"], "old notes");
+        let archived: bool =
+            sqlx::query_scalar("SELECT archived FROM rooms WHERE room_id = 'archived_room'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(!archived);
+    }
+
+    #[tokio::test]
+    async fn test_joining_unarchives() {
+        let (addr, _, db) = setup_test_server_with_db_and_config(Config {
+            archived_join: crate::archive::ArchivedJoin::Unarchive,
+            ..Config::default()
+        })
+        .await;
+        sqlx::query("INSERT INTO rooms (room_id, content, archived) VALUES ('cold', 'kept', TRUE)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "cold" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["value"], "kept");
+        let archived: bool =
+            sqlx::query_scalar("SELECT archived FROM rooms WHERE room_id = 'cold'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(!archived);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_room_persistence() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        // Create multiple rooms and send messages
+        let room_data = vec![
+            ("room1", "content1"),
+            ("room2", "content2"),
+            ("room3", "content3"),
+        ];
+
+        for (room_name, content) in &room_data {
+            let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+
+            let join_msg = json!({
+                "username": "multi_room_test",
+                "channel": room_name
+            })
+            .to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+
+            // Wait for initial messages
+            let msg = ws.next().await.unwrap();
+            let _ = msg.unwrap().into_text().unwrap();
+
+            ws.send(Message::Text((*content).to_string()))
+                .await
+                .unwrap();
+
+            // Wait for database update and close connection
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(ws);
+
+            // Wait a bit before creating next room
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        // Wait for all database updates to complete
+        tokio::time::sleep(Duration::from_secs(4)).await;
+
+        // Verify all rooms are in database with correct content
+        for (room_name, expected_content) in &room_data {
+            let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", room_name)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+            assert_eq!(room.content, *expected_content);
+        }
+
+        // Verify total room count
+        let count = sqlx::query!("SELECT COUNT(*) as count FROM rooms")
+            .fetch_one(&db)
+            .await
+            .unwrap()
+            .count;
+
+        // Count should be room_data.len() + 1 (including general room)
+        let count_usize = usize::try_from(count).expect("Failed to convert count to usize");
+        assert_eq!(count_usize, room_data.len() + 1);
+    }
+
+    #[tokio::test]
+    async fn test_content_update() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+
+        // Join test room
+        let room_name = "update_test_room";
+        let join_msg = json!({
+            "username": "content_updater",
+            "channel": room_name
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg)).await.unwrap();
+
+        // Wait for initial messages
+        let msg = ws1.next().await.unwrap();
+        let _ = msg.unwrap().into_text().unwrap();
+
+        // Send multiple messages and verify database updates
+        let messages = vec!["First message", "Second message", "Third message"];
+
+        for message in &messages {
+            ws1.send(Message::Text((*message).to_string()))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(6)).await;
+
+            let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", room_name)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+            assert_eq!(room.content, *message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_room_eviction() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let room_name = "idle_room";
+
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "idle_user",
+            "channel": room_name
+        })
+        .to_string();
+        ws1.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws1.next().await.unwrap();
+
+        ws1.send(Message::Text("Content kept across eviction".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Occupied rooms are never evicted
+        evict_idle_rooms_once(&state, Duration::ZERO).await;
+        assert!(state.rooms.lock().await.contains_key(room_name));
+        assert_eq!(state.write_behind.tracked(room_name), 1);
+
+        drop(ws1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        evict_idle_rooms_once(&state, Duration::ZERO).await;
+        assert!(!state.rooms.lock().await.contains_key(room_name));
+        assert!(state.rooms.lock().await.contains_key("general"));
+        // It is no longer written
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state.write_behind.tracked(room_name), 0);
+
+        // Content is flushed on eviction, without waiting for the write-behind
+        let room = sqlx::query!("SELECT * FROM rooms WHERE room_id = ?", room_name)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(room.content, "Content kept across eviction");
+
+        // Evicted rooms are still listed
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rooms.iter().any(|r| r.id == room_name));
+
+        // Joining restores the room from the database
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+        ws2.send(Message::Text(join_msg)).await.unwrap();
+        let received = ws2.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["value"], "Content kept across eviction");
+    }
+
+    #[tokio::test]
+    async fn test_idle_room_eviction_in_memory() {
+        let (addr, state) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join_msg = json!({
+            "username": "idle_user",
+            "channel": "memory_room"
+        })
+        .to_string();
+
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        ws1.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws1.next().await.unwrap();
+        ws1.send(Message::Text("Kept in memory".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(ws1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        evict_idle_rooms_once(&state, Duration::ZERO).await;
+        assert!(!state.rooms.lock().await.contains_key("memory_room"));
+        assert_eq!(
+            state
+                .storage
+                .content("memory_room")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("Kept in memory")
+        );
+
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+        ws2.send(Message::Text(join_msg)).await.unwrap();
+        let received = ws2.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["value"], "Kept in memory");
+    }
+
+    #[tokio::test]
+    async fn test_accounts() {
+        let (addr, _, _) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        let credentials = json!({ "username": "dave", "password": "correct horse" });
+
+        let response = client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+
+        // Usernames are unique
+        let response = client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+
+        let response = client
+            .post(format!("http://{addr}/api/auth/login"))
+            .json(&json!({ "username": "dave", "password": "wrong password" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .post(format!("http://{addr}/api/auth/login"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let cookie = response
+            .headers()
+            .get("set-cookie")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        let response = client
+            .get(format!("http://{addr}/api/auth/me"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        let account: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(account["username"], "dave");
+
+        // The account name is asserted server-side, whatever the client sends
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("cookie", cookie.parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({
+            "username": "not_dave",
+            "channel": "auth_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["type"], "join");
+        assert_eq!(parsed["username"], "dave");
+
+        // Anonymous users can't impersonate an account
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "dave",
+            "channel": "auth_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["type"], "error");
+
+        // Logging out invalidates the session
+        let response = client
+            .post(format!("http://{addr}/api/auth/logout"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .get(format!("http://{addr}/api/auth/me"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    #[test]
+    fn test_persistence_health() {
+        let mut health = PersistenceHealth::default();
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(false), Some(true));
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(true), Some(false));
+        assert_eq!(health.record(true), None);
+    }
+
+    #[tokio::test]
+    async fn test_persistence_degraded() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "writer",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+
+        // Every write fails from now on
+        db.close().await;
+        ws.send(Message::Text("unsaved".to_string())).await.unwrap();
+
+        let mut degraded = false;
+        while let Ok(Some(Ok(msg))) = tokio::time::timeout(Duration::from_secs(8), ws.next()).await
+        {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            if parsed["type"] == "persistence-degraded" {
+                degraded = true;
+                break;
+            }
+        }
+        assert!(degraded);
+
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let general = rooms.iter().find(|room| room.id == "general").unwrap();
+        assert!(general.persistence_degraded);
+    }
+
+    #[tokio::test]
+    async fn test_persisted() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({ "username": "writer", "channel": "general" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        next_of_type(&mut ws, "message").await;
+
+        let mut revision = serde_json::Value::Null;
+        for value in ["first", "second"] {
+            ws.send(Message::Text(
+                json!({ "type": "edit", "value": value }).to_string(),
+            ))
+            .await
+            .unwrap();
+            revision = next_of_type(&mut ws, "message").await["revision"].clone();
+        }
+
+        // Acknowledged up to the last revision, once it is in the database
+        let persisted = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let msg = next_of_type(&mut ws, "persisted").await;
+                if msg["revision"] == revision {
+                    return msg;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let at: i64 = persisted["value"].as_str().unwrap().parse().unwrap();
+        assert!((crate::unix_timestamp() - at).abs() < 10);
+        let stored: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'general'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(stored, "second");
+    }
+
+    #[tokio::test]
+    async fn test_freeze_schedule_owner() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&json!({ "username": "frank", "password": "correct horse" }))
+            .send()
+            .await
+            .unwrap();
+        let cookie = response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        // The room is created by the account
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("cookie", cookie.parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({
+            "username": "frank",
+            "channel": "owned_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+
+        let freeze_url = format!("http://{addr}/api/rooms/owned_room/freeze");
+        let schedule =
+            json!({ "windows": [{ "day": "friday", "start": "16:00", "end": "18:00" }] });
+
+        let response = client
+            .put(&freeze_url)
+            .json(&schedule)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .put(&freeze_url)
+            .header("cookie", &cookie)
+            .json(&schedule)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // The schedule is stored with the room
+        let stored: Option<String> =
+            sqlx::query_scalar("SELECT freeze_schedule FROM rooms WHERE room_id = 'owned_room'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&stored.unwrap()).unwrap(),
+            schedule
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_ownership_recovery() {
+        let (addr, _, db) = setup_test_server_with_db_and_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let mut cookies = Vec::new();
+        for username in ["grace", "heidi"] {
+            let response = client
+                .post(format!("http://{addr}/api/auth/register"))
+                .json(&json!({ "username": username, "password": "correct horse" }))
+                .send()
+                .await
+                .unwrap();
+            cookies.push(
+                response.headers()["set-cookie"]
+                    .to_str()
+                    .unwrap()
+                    .split(';')
+                    .next()
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+
+        // A room created before accounts existed
+        sqlx::query("INSERT INTO rooms (room_id, content) VALUES ('legacy_room', 'old')")
+            .execute(&db)
+            .await
+            .unwrap();
+        let claim_url = format!("http://{addr}/api/rooms/legacy_room/claim");
+
+        let response = client.post(&claim_url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(&claim_url)
+            .header("cookie", &cookies[0])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Claiming again is a no-op for the owner, refused for others
+        let response = client
+            .post(&claim_url)
+            .header("cookie", &cookies[0])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .post(&claim_url)
+            .header("cookie", &cookies[1])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+
+        let owner_of = |room: &'static str| {
+            let db = db.clone();
+            async move {
+                sqlx::query_scalar::<_, Option<String>>(
+                    r"
+                    SELECT users.username FROM rooms
+                    LEFT JOIN users ON users.id = rooms.owner_id
+                    WHERE rooms.room_id = ?
+                    ",
+                )
+                .bind(room)
+                .fetch_one(&db)
+                .await
+                .unwrap()
+            }
+        };
+        assert_eq!(owner_of("legacy_room").await.as_deref(), Some("grace"));
+
+        // The owner account is deleted, an administrator hands the room over
+        sqlx::query("DELETE FROM users WHERE username = 'grace'")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(owner_of("legacy_room").await, None);
+
+        let owner_url = format!("http://{addr}/api/admin/rooms/legacy_room/owner");
+        let response = client
+            .put(&owner_url)
+            .bearer_auth("s3cret")
+            .json(&json!({ "username": "nobody" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let response = client
+            .put(&owner_url)
+            .bearer_auth("s3cret")
+            .json(&json!({ "username": "heidi" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(owner_of("legacy_room").await.as_deref(), Some("heidi"));
+
+        // The content is left untouched
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'legacy_room'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(content, "old");
+    }
+
+    #[tokio::test]
+    async fn test_multiple_documents() {
+        async fn next_json<S>(ws: &mut S) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            serde_json::from_str(&msg).unwrap()
+        }
+
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join = |username: &str, capabilities: &[&str]| {
+            json!({
+                "username": username,
+                "channel": "docs_room",
+                "protocol_version": PROTOCOL_VERSION,
+                "capabilities": capabilities
+            })
+            .to_string()
+        };
+
+        let (mut alice, _) = connect_async(&ws_uri).await.unwrap();
+        alice
+            .send(Message::Text(join("alice", &["documents"])))
+            .await
+            .unwrap();
+        assert_eq!(next_json(&mut alice).await["type"], "hello");
+        assert_eq!(next_json(&mut alice).await["type"], "message");
+        let _ = next_json(&mut alice).await; // Join
+        let _ = next_json(&mut alice).await; // Presence
+
+        // Bob doesn't know about documents
+        let (mut bob, _) = connect_async(&ws_uri).await.unwrap();
+        bob.send(Message::Text(join("bob", &[]))).await.unwrap();
+        assert_eq!(next_json(&mut bob).await["type"], "hello");
+        assert_eq!(next_json(&mut bob).await["type"], "message");
+        let _ = next_json(&mut bob).await; // Join
+        let _ = next_json(&mut bob).await; // Presence
+        let _ = next_json(&mut alice).await; // Join of bob
+
+        let edit = |doc_id: &str, value: &str| {
+            Message::Text(json!({ "doc_id": doc_id, "value": value }).to_string())
+        };
+        alice.send(edit("notes", "todo")).await.unwrap();
+        let parsed = next_json(&mut alice).await;
+        assert_eq!(parsed["doc_id"], "notes");
+        assert_eq!(parsed["value"], "todo");
+
+        // Only edits of the main document reach bob
+        alice.send(edit("main", "hello")).await.unwrap();
+        let parsed = next_json(&mut alice).await;
+        assert!(parsed.get("doc_id").is_none());
+        let parsed = next_json(&mut bob).await;
+        assert!(parsed.get("doc_id").is_none());
+        assert_eq!(parsed["value"], "hello");
+
+        alice.send(edit("../notes", "nope")).await.unwrap();
+        assert_eq!(next_json(&mut alice).await["type"], "error");
+
+        let documents: serde_json::Value =
+            reqwest::get(format!("http://{addr}/api/rooms/docs_room/documents"))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(
+            documents["value"],
+            json!([{ "id": "main", "size": 5 }, { "id": "notes", "size": 4 }])
+        );
+
+        // Documents are saved, and sent to the clients joining later
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let stored: String = sqlx::query_scalar(
+            "SELECT content FROM documents WHERE room_id = 'docs_room' AND doc_id = 'notes'",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(stored, "todo");
+
+        let (mut carol, _) = connect_async(&ws_uri).await.unwrap();
+        carol
+            .send(Message::Text(join("carol", &["documents"])))
+            .await
+            .unwrap();
+        assert_eq!(next_json(&mut carol).await["type"], "hello");
+        assert_eq!(next_json(&mut carol).await["value"], "hello");
+        let parsed = next_json(&mut carol).await;
+        assert_eq!(parsed["doc_id"], "notes");
+        assert_eq!(parsed["value"], "todo");
+
+        let response = reqwest::Client::new()
+            .delete(format!("http://{addr}/api/rooms/docs_room/documents/notes"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let _ = next_json(&mut alice).await; // Join of carol
+        let _ = next_json(&mut alice).await; // Presence
+        let parsed = next_json(&mut alice).await;
+        assert_eq!(parsed["type"], "document-removed");
+        assert_eq!(parsed["doc_id"], "notes");
+
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM documents")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_file_attachments() {
+        let dir =
+            std::env::temp_dir().join(format!("partage-files-{}", crate::auth::generate_token()));
+        let (addr, _, _) = setup_test_server_with_db_and_config(Config {
+            attachments_dir: dir.clone(),
+            max_attachment_size: 16,
+            room_attachments_quota: 24,
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let upload = |filename: &str, content: &str| {
+            let body = format!(
+                "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: text/plain\r\n\r\n{content}\r\n--boundary--\r\n"
+            );
+            client
+                .post(format!("http://{addr}/api/rooms/general/files"))
+                .header("content-type", "multipart/form-data; boundary=boundary")
+                .body(body)
+                .send()
+        };
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        ws.send(Message::Text(
+            json!({ "username": "alice", "channel": "general" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let _ = ws.next().await; // Content
+        let _ = ws.next().await; // Join
+        let _ = ws.next().await; // Presence
+
+        let response = upload("../notes.txt", "hello world").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let id = body["value"]["id"].as_i64().unwrap();
+        assert_eq!(body["value"]["filename"], "notes.txt");
+
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "file-added");
+        let file: serde_json::Value =
+            serde_json::from_str(parsed["value"].as_str().unwrap()).unwrap();
+        assert_eq!(file["id"], id);
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms/general/files/{id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"notes.txt\""
+        );
+        assert_eq!(response.text().await.unwrap(), "hello world");
+
+        // Files are scoped to their room
+        let response = client
+            .get(format!("http://{addr}/api/rooms/other/files/{id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        // Size limits
+        let response = upload("big.txt", &"a".repeat(17)).await.unwrap();
+        assert_eq!(response.status(), 413);
+        let response = upload("quota.txt", &"a".repeat(16)).await.unwrap();
+        assert_eq!(response.status(), 413);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_merge_rooms() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join = |username: &str, channel: &str| {
+            Message::Text(
+                json!({
+                    "username": username,
+                    "channel": channel,
+                    "protocol_version": PROTOCOL_VERSION,
+                    "capabilities": ["documents"]
+                })
+                .to_string(),
+            )
+        };
+        let next_json = |msg: Message| -> serde_json::Value {
+            serde_json::from_str(&msg.into_text().unwrap()).unwrap()
+        };
+
+        let (mut alice, _) = connect_async(&ws_uri).await.unwrap();
+        alice.send(join("alice", "pad")).await.unwrap();
+        for _ in 0..3 {
+            let _ = alice.next().await; // Hello, content, join
+        }
+        alice
+            .send(Message::Text(
+                json!({ "doc_id": "main", "value": "first\n" }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let _ = alice.next().await;
+
+        let (mut bob, _) = connect_async(&ws_uri).await.unwrap();
+        bob.send(join("bob", "pad-copy")).await.unwrap();
+        for _ in 0..3 {
+            let _ = bob.next().await;
+        }
+        for (doc_id, value) in [("main", "second\n"), ("notes", "todo")] {
+            bob.send(Message::Text(
+                json!({ "doc_id": doc_id, "value": value }).to_string(),
+            ))
+            .await
+            .unwrap();
+            let _ = bob.next().await;
+        }
+
+        let client = reqwest::Client::new();
+        let merge = |source: &str| {
+            client
+                .post(format!("http://{addr}/api/rooms/pad/merge"))
+                .json(&json!({ "source": source }))
+                .send()
+        };
+        assert_eq!(merge("pad").await.unwrap().status(), 400);
+        assert_eq!(merge("missing").await.unwrap().status(), 404);
+
+        let response = merge("pad-copy").await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let parsed = next_json(alice.next().await.unwrap().unwrap());
+        assert_eq!(parsed["value"], "first\n\nsecond\n");
+        let parsed = next_json(alice.next().await.unwrap().unwrap());
+        assert_eq!(parsed["doc_id"], "notes");
+        assert_eq!(parsed["value"], "todo");
+
+        let parsed = next_json(bob.next().await.unwrap().unwrap());
+        assert_eq!(parsed["type"], "redirect");
+        assert_eq!(parsed["value"], "pad");
+
+        assert!(!state.rooms.lock().await.contains_key("pad-copy"));
+        let remaining: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM rooms WHERE room_id = 'pad-copy'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_paste() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/paste"))
+            .body("echo hello\n")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let url = response.text().await.unwrap();
+        let room_id = url
+            .trim_end()
+            .strip_prefix(&format!("http://{addr}/c/"))
+            .unwrap()
+            .to_string();
+
+        let response = reqwest::get(format!("http://{addr}/r/{room_id}/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.text().await.unwrap(), "echo hello\n");
+
+        let stored: String = sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = ?")
+            .bind(&room_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(stored, "echo hello\n");
+
+        let response = client
+            .post(format!("http://{addr}/api/paste"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = reqwest::get(format!("http://{addr}/r/missing/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_room_html() {
+        let (addr, state) = setup_test_server().await;
+        let _ = state.rooms.lock().await["general"]
+            .content_tx
+            .send("# Notes\n\n<script>alert(1)</script>\n\n*done*".to_string());
+
+        let response = reqwest::get(format!("http://{addr}/r/general/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        let html = response.text().await.unwrap();
+        assert!(html.contains("<title>general</title>"));
+        assert!(html.contains("<h1>Notes</h1>"));
+        assert!(html.contains("<em>done</em>"));
+        assert!(!html.contains("<script>"));
+
+        let response = reqwest::get(format!("http://{addr}/r/missing/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_room_language() {
+        let (addr, state, _) = setup_test_server_with_db().await;
+        let _ = state.rooms.lock().await["general"].content_tx.send(
+            "# Todo\n\n- write the notes for the meeting\n- send them to the team".to_string(),
+        );
+
+        let url = format!("http://{addr}/api/rooms/general/language");
+        let body: serde_json::Value = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        assert_eq!(body["value"]["kind"], "markdown");
+        assert_eq!(body["value"]["language"], "en");
+        assert_eq!(body["value"]["kind_overridden"], false);
+
+        let client = reqwest::Client::new();
+        let body: serde_json::Value = client
+            .patch(&url)
+            .json(&json!({ "kind": "text" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["value"]["kind"], "text");
+        assert_eq!(body["value"]["kind_overridden"], true);
+        assert_eq!(body["value"]["language"], "en");
+
+        let body: serde_json::Value = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        assert_eq!(body["value"]["kind"], "text");
+
+        let response = client
+            .patch(&url)
+            .json(&json!({ "language": "english" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_syntax_language() {
+        use partage_client::{Client, Event, JoinOptions};
+
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let url = format!("http://{addr}/api/rooms/general/syntax-language");
+        let client = reqwest::Client::new();
+        let response = client
+            .put(&url)
+            .json(&json!({ "language": "rust" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let stored: Option<String> =
+            sqlx::query_scalar("SELECT syntax_language FROM rooms WHERE room_id = 'general'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(stored.as_deref(), Some("rust"));
+
+        let rooms: serde_json::Value = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(rooms
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|room| room["id"] == "general" && room["language"] == "rust"));
+
+        let response = client
+            .put(&url)
+            .json(&json!({ "language": "<script>" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        // Joining members are told about it
+        let ws_uri = format!("ws://{addr}/ws");
+        let mut alice = Client::connect(&ws_uri, JoinOptions::new("alice", "general"))
+            .await
+            .unwrap();
+        loop {
+            match alice.next_event().await.unwrap() {
+                Some(Event::LanguageChanged { language }) => {
+                    assert_eq!(language.as_deref(), Some("rust"));
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("Connection closed before the language was sent"),
+            }
+        }
+
+        // And about changes made by other members
+        let mut bob = Client::connect(&ws_uri, JoinOptions::new("bob", "general"))
+            .await
+            .unwrap();
+        bob.set_language(Some("python")).await.unwrap();
+        loop {
+            match alice.next_event().await.unwrap() {
+                Some(Event::LanguageChanged { language }) => {
+                    assert_eq!(language.as_deref(), Some("python"));
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("Connection closed before the language was changed"),
+            }
+        }
+        let rooms = state.rooms.lock().await;
+        let language = rooms["general"].syntax_language.lock().await.clone();
+        drop(rooms);
+        assert_eq!(language.as_deref(), Some("python"));
+
+        bob.set_language(None).await.unwrap();
+        loop {
+            if let Some(Event::LanguageChanged { language }) = alice.next_event().await.unwrap() {
+                assert_eq!(language, None);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_import_room() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let _ = state.rooms.lock().await["general"]
+            .content_tx
+            .send("fn main() {}".to_string());
+        let client = reqwest::Client::new();
+        client
+            .put(format!("http://{addr}/api/rooms/general/syntax-language"))
+            .json(&json!({ "language": "rust" }))
+            .send()
+            .await
+            .unwrap();
+
+        let response = reqwest::get(format!("http://{addr}/api/rooms/general/export"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"general.json\""
+        );
+        let mut bundle: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(bundle["content"], "fn main() {}");
+        assert_eq!(bundle["syntax_language"], "rust");
+
+        bundle["room_id"] = json!("restored");
+        bundle["documents"] = json!({ "notes": "To do" });
+        let import_url = format!("http://{addr}/api/rooms/import");
+        let response = client.post(&import_url).json(&bundle).send().await.unwrap();
+        assert_eq!(response.status(), 201);
+
+        let body = reqwest::get(format!("http://{addr}/r/restored/raw"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "fn main() {}");
+        let (language, notes): (Option<String>, String) = sqlx::query_as(
+            r"
+            SELECT syntax_language, documents.content FROM rooms
+            JOIN documents USING (room_id)
+            WHERE room_id = 'restored' AND doc_id = 'notes'
+            ",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(language.as_deref(), Some("rust"));
+        assert_eq!(notes, "To do");
+
+        // Existing rooms are never overwritten
+        let response = client.post(&import_url).json(&bundle).send().await.unwrap();
+        assert_eq!(response.status(), 409);
+
+        bundle["room_id"] = json!("future");
+        bundle["version"] = json!(2);
+        let response = client.post(&import_url).json(&bundle).send().await.unwrap();
+        assert_eq!(response.status(), 400);
+
+        let response = reqwest::get(format!("http://{addr}/api/rooms/missing/export"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_download_room() {
+        let (addr, state) = setup_test_server().await;
+        let _ = state.rooms.lock().await["general"]
+            .content_tx
+            .send("# Notes".to_string());
+
+        let response = reqwest::get(format!(
+            "http://{addr}/api/rooms/general/download?format=md"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/markdown; charset=utf-8"
+        );
+        let disposition = response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(disposition.starts_with("attachment; filename=\"general-"));
+        assert!(disposition.ends_with(".md\""));
+        assert_eq!(response.text().await.unwrap(), "# Notes");
+
+        // Plain text unless asked otherwise
+        let response = reqwest::get(format!("http://{addr}/api/rooms/general/download"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+
+        let response = reqwest::get(format!(
+            "http://{addr}/api/rooms/general/download?format=pdf"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = reqwest::get(format!("http://{addr}/api/rooms/missing/download"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_room_trace() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let trace_url = format!("http://{addr}/api/admin/rooms/general/trace");
+
+        let response = client
+            .get(&trace_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let response = client
+            .put(&trace_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        ws.send(Message::Text("top secret".to_string()))
+            .await
+            .unwrap();
+        // Wait for the echo of the edit
+        loop {
+            let Message::Text(text) = ws.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            if text.contains("top secret") {
+                break;
+            }
+        }
+
+        let response = client
+            .get(&trace_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let trace = response.text().await.unwrap();
+        assert!(!trace.contains("top secret"));
+        let lines: Vec<serde_json::Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["room"], "general");
+        assert!(lines
+            .iter()
+            .any(|line| line["direction"] == "received" && line["value_bytes"] == 10));
+        assert!(lines.iter().any(|line| line["direction"] == "sent"
+            && line["message_type"] == "message"
+            && line["value_bytes"] == 10));
+
+        let response = client
+            .delete(&trace_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .get(&trace_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_admin_backup() {
+        let (addr, _, _) = setup_test_server_with_db_and_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let backup_url = format!("http://{addr}/api/admin/backup");
+
+        let response = client.get(&backup_url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .get(&backup_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/vnd.sqlite3"
+        );
+        let bytes = response.bytes().await.unwrap();
+        assert!(bytes.starts_with(b"SQLite format 3\0"));
+    }
+
+    #[tokio::test]
+    async fn test_subprotocols() {
+        let (addr, _) = setup_test_server().await;
+        let join_msg = json!({ "username": "alice", "channel": "v2_room" }).to_string();
+
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            "partage.v3, partage.v2".parse().unwrap(),
+        );
+        let (mut ws, response) = connect_async(request).await.unwrap();
+        assert_eq!(response.headers()["sec-websocket-protocol"], "partage.v2");
+        ws.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws.next().await; // Content
+        let _ = ws.next().await; // Join
+        let _ = ws.next().await; // Presence
+
+        // v2 clients tag their edits
+        ws.send(Message::Text("raw".to_string())).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "error");
+        ws.send(Message::Text(
+            json!({ "type": "edit", "value": "typed" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["type"], "message");
+        assert_eq!(parsed["value"], "typed");
+
+        // Clients asking for no subprotocol keep sending the raw content
+        let (mut legacy, response) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        assert!(response.headers().get("sec-websocket-protocol").is_none());
+        legacy
+            .send(Message::Text(
+                json!({ "username": "bob", "channel": "v2_room" }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let msg = legacy.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(parsed["value"], "typed");
+        let _ = legacy.next().await; // Join
+        legacy.send(Message::Text("raw".to_string())).await.unwrap();
+        loop {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            if parsed["type"] == "message" {
+                assert_eq!(parsed["value"], "raw");
+                assert_eq!(parsed["username"], "bob");
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_stale_edits() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, _) = setup_test_server().await;
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+            let (mut ws, _) = connect_async(request).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "merge_room" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let content = next_of_type(&mut ws, "message").await;
+            assert_eq!(content["revision"], 0);
+            sockets.push(ws);
+        }
+        let edit = |value: &str, revision: &serde_json::Value| {
+            Message::Text(
+                json!({ "type": "edit", "value": value, "revision": revision }).to_string(),
+            )
+        };
+
+        sockets[0]
+            .send(edit("the quick fox", &json!(0)))
+            .await
+            .unwrap();
+        let mut revision = json!(null);
+        for ws in &mut sockets {
+            let msg = next_of_type(ws, "message").await;
+            assert_eq!(msg["value"], "the quick fox");
+            revision = msg["revision"].clone();
+        }
+
+        // Both edit the same revision, bob's edit is merged with alice's
+        sockets[0]
+            .send(edit("the quick brown fox", &revision))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "the quick brown fox");
+        sockets[1]
+            .send(edit("a quick fox", &revision))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "a quick brown fox");
+        assert_eq!(msg["username"], "bob");
+        let revision = msg["revision"].clone();
+
+        // Changes of the same words keep the first one
+        sockets[0]
+            .send(edit("a quick brown dog", &revision))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "a quick brown dog");
+        sockets[1]
+            .send(edit("a quick brown cat", &revision))
+            .await
+            .unwrap();
+        // The broadcast and the notice may come in any order
+        let (mut merged, mut conflicts) = (None, None);
+        while merged.is_none() || conflicts.is_none() {
+            let msg = sockets[1]
+                .next()
+                .await
+                .unwrap()
+                .unwrap()
+                .into_text()
+                .unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            if msg["type"] == "message" {
+                merged = Some(msg["value"].clone());
+            } else if msg["type"] == "merge-conflict" {
+                conflicts = Some(msg["value"].clone());
+            }
+        }
+        assert_eq!(merged.unwrap(), "a quick brown dog");
+        assert_eq!(conflicts.unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn test_write_lock() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, _) = setup_test_server().await;
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+            let (mut ws, _) = connect_async(request).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "lock_room" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            next_of_type(&mut ws, "message").await;
+            sockets.push(ws);
+        }
+        let request_lock = || Message::Text(json!({ "type": "request-lock" }).to_string());
+        let edit =
+            |value: &str| Message::Text(json!({ "type": "edit", "value": value }).to_string());
+
+        // Alice takes the free lock, bob can't edit anymore
+        sockets[0].send(request_lock()).await.unwrap();
+        for ws in &mut sockets {
+            let msg = next_of_type(ws, "grant-lock").await;
+            assert_eq!(msg["value"], "alice");
+        }
+        sockets[1].send(edit("bob was here")).await.unwrap();
+        let msg = next_of_type(&mut sockets[1], "error").await;
+        assert_eq!(msg["code"], "room-locked");
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "");
+        sockets[0].send(edit("slides")).await.unwrap();
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "slides");
+
+        // Nor through the API
+        let client = reqwest::Client::new();
+        let response = client
+            .put(format!("http://{addr}/api/rooms/lock_room/content"))
+            .body("bob was here")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 423);
+        let response = client
+            .post(format!("http://{addr}/api/rooms/lock_room/format"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 423);
+
+        // Bob asks alice for the lock, only its holder can hand it over
+        sockets[1].send(request_lock()).await.unwrap();
+        let msg = next_of_type(&mut sockets[0], "request-lock").await;
+        assert_eq!(msg["username"], "bob");
+        sockets[1]
+            .send(Message::Text(json!({ "type": "grant-lock" }).to_string()))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "error").await;
+        assert_eq!(msg["code"], "forbidden");
+        sockets[0]
+            .send(Message::Text(
+                json!({ "type": "grant-lock", "value": "bob" }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "grant-lock").await;
+        assert_eq!(msg["value"], "bob");
+
+        // Late joiners are told who presents
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+        let (mut late_ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({ "username": "carol", "channel": "lock_room" }).to_string();
+        late_ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = next_of_type(&mut late_ws, "grant-lock").await;
+        assert_eq!(msg["value"], "bob");
+
+        // The lock is released once its holder leaves
+        drop(sockets.pop());
+        let msg = next_of_type(&mut sockets[0], "grant-lock").await;
+        assert_eq!(msg["value"], serde_json::Value::Null);
+        sockets[0].send(edit("back to everyone")).await.unwrap();
+        let msg = next_of_type(&mut late_ws, "message").await;
+        assert_eq!(msg["value"], "back to everyone");
+    }
+
+    #[tokio::test]
+    async fn test_line_claims() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, state) = setup_test_server().await;
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+            let (mut ws, _) = connect_async(request).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "claim_room" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            next_of_type(&mut ws, "message").await;
+            sockets.push(ws);
+        }
+        let edit =
+            |value: &str| Message::Text(json!({ "type": "edit", "value": value }).to_string());
+
+        sockets[0].send(edit("title\nalice\nbob")).await.unwrap();
+        for ws in &mut sockets {
+            next_of_type(ws, "message").await;
+        }
+        sockets[0]
+            .send(Message::Text(
+                json!({ "type": "claim-lines", "start": 1, "end": 2 }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "lines-claimed").await;
+        assert_eq!(msg["username"], "alice");
+        assert_eq!(msg["value"], json!({ "start": 1, "end": 2 }).to_string());
+
+        // Bob can't change the line of alice, nor claim it, but can change his own
+        sockets[1]
+            .send(edit("title\nnot alice\nbob"))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "error").await;
+        assert_eq!(msg["code"], "line-claimed");
+        let msg = next_of_type(&mut sockets[1], "message").await;
+        assert_eq!(msg["value"], "title\nalice\nbob");
+        sockets[1]
+            .send(Message::Text(
+                json!({ "type": "claim-lines", "start": 0, "end": 3 }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[1], "error").await;
+        assert_eq!(msg["code"], "line-claimed");
+        sockets[1]
+            .send(edit("title\nalice\nbob was here"))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[0], "message").await;
+        assert_eq!(msg["value"], "title\nalice\nbob was here");
+
+        // Nor through the API
+        let response = reqwest::Client::new()
+            .put(format!("http://{addr}/api/rooms/claim_room/content"))
+            .body("title\nnot alice\nbob was here")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+
+        // Claims expire once their holder went quiet
+        line_claims::expire_line_claims_once(&state, Duration::ZERO).await;
+        let msg = next_of_type(&mut sockets[1], "lines-released").await;
+        assert_eq!(msg["username"], "alice");
+        sockets[1]
+            .send(edit("title\nbob too\nbob was here"))
+            .await
+            .unwrap();
+        let msg = next_of_type(&mut sockets[0], "message").await;
+        assert_eq!(msg["value"], "title\nbob too\nbob was here");
+    }
+
+    #[tokio::test]
+    async fn test_history_diff() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        sqlx::query("INSERT INTO rooms (room_id, content) VALUES ('diff_room', 'a\nB\nc\n')")
+            .execute(&db)
+            .await
+            .unwrap();
+        for (content, created_at) in [("a\nb\n", 10), ("a\nb\nc\n", 1000)] {
+            crate::history::record(&db, "diff_room", content, created_at)
+                .await
+                .unwrap();
+        }
+
+        let history: serde_json::Value = client
+            .get(format!("http://{addr}/api/rooms/diff_room/history"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let versions = history["value"].as_array().unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0]["created_at"], 1000);
+        assert_eq!(versions[0]["size"], 6);
+        let (latest, first) = (&versions[0]["id"], &versions[1]["id"]);
+
+        let diff = |query: String| {
+            let client = client.clone();
+            async move {
+                client
+                    .get(format!("http://{addr}/api/rooms/diff_room/diff?{query}"))
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+        let response = diff(format!("from={first}&to={latest}")).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.text().await.unwrap(),
+            format!("--- diff_room@{first}\n+++ diff_room@{latest}\n@@ -1,2 +1,3 @@\n a\n b\n+c\n")
+        );
+        // Up to the current content
+        let response = diff(format!("from={latest}")).await;
+        assert_eq!(
+            response.text().await.unwrap(),
+            format!(
+                "--- diff_room@{latest}\n+++ diff_room@current\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+            )
+        );
+
+        assert_eq!(diff("from=999".to_string()).await.status(), 404);
+        assert_eq!(diff("to=1".to_string()).await.status(), 400);
+        let response = client
+            .get(format!("http://{addr}/api/rooms/unknown_room/diff?from=1"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let checkpoints_url = format!("http://{addr}/api/rooms/checkpoint_room/checkpoints");
+
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "checkpoint_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        next_of_type(&mut ws, "message").await;
+        let edit =
+            |value: &str| Message::Text(json!({ "type": "edit", "value": value }).to_string());
+
+        ws.send(edit("fn main() {}")).await.unwrap();
+        next_of_type(&mut ws, "message").await;
+        ws.send(Message::Text(
+            json!({ "type": "checkpoint", "value": "before refactor" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let msg = next_of_type(&mut ws, "checkpoint").await;
+        assert_eq!(msg["username"], "alice");
+        let checkpoint: serde_json::Value =
+            serde_json::from_str(msg["value"].as_str().unwrap()).unwrap();
+        assert_eq!(checkpoint["label"], "before refactor");
+        assert_eq!(checkpoint["created_by"], "alice");
+        assert_eq!(checkpoint["size"], 12);
+        let id = &checkpoint["id"];
+
+        ws.send(Message::Text(
+            json!({ "type": "checkpoint", "value": " " }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let msg = next_of_type(&mut ws, "error").await;
+        assert_eq!(msg["code"], "invalid-checkpoint");
+
+        ws.send(edit("fn main() { todo!() }")).await.unwrap();
+        next_of_type(&mut ws, "message").await;
+
+        let response = client
+            .post(&checkpoints_url)
+            .json(&json!({ "label": "after refactor" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(response["value"]["created_by"], serde_json::Value::Null);
+        let msg = next_of_type(&mut ws, "checkpoint").await;
+        assert_eq!(msg["username"], "");
+
+        let list: serde_json::Value = client
+            .get(&checkpoints_url)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let labels: Vec<_> = list["value"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|checkpoint| checkpoint["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["after refactor", "before refactor"]);
+
+        // Restoring puts the content back for the members
+        let response = client
+            .post(format!("{checkpoints_url}/{id}/restore"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let msg = next_of_type(&mut ws, "message").await;
+        assert_eq!(msg["value"], "fn main() {}");
+
+        let response = client
+            .delete(format!("{checkpoints_url}/{id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        for response in [
+            client.delete(format!("{checkpoints_url}/{id}")),
+            client.post(format!("{checkpoints_url}/{id}/restore")),
+        ] {
+            assert_eq!(response.send().await.unwrap().status(), 404);
+        }
+        let response = client
+            .post(&checkpoints_url)
+            .json(&json!({ "label": "" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_content_revisions() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let content_url = format!("http://{addr}/api/rooms/revision_room/content");
+
+        let response = client.get(&content_url).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "revision_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await; // Content
+
+        let response = client.get(&content_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["etag"], "\"0\"");
+        assert_eq!(response.text().await.unwrap(), "");
+
+        let put = |if_match: Option<&str>, content: &str| {
+            let mut request = client.put(&content_url).body(content.to_string());
+            if let Some(if_match) = if_match {
+                request = request.header("if-match", if_match);
+            }
+            request.send()
+        };
+        let response = put(Some("\"0\""), "hello").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["etag"], "\"1\"");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["value"], 1);
+
+        // Members get the content with its revision
+        loop {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            if msg["type"] == "message" {
+                assert_eq!(msg["value"], "hello");
+                assert_eq!(msg["revision"], 1);
+                break;
+            }
+        }
+
+        // Someone else wrote meanwhile
+        let response = put(Some("0"), "stale").await.unwrap();
+        assert_eq!(response.status(), 409);
+        let response = put(Some("latest"), "invalid").await.unwrap();
+        assert_eq!(response.status(), 400);
+        let response = put(None, "unconditional").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["value"], 2);
+
+        let response = client.get(&content_url).send().await.unwrap();
+        assert_eq!(response.headers()["etag"], "\"2\"");
+        assert_eq!(response.text().await.unwrap(), "unconditional");
+    }
+
+    #[tokio::test]
+    async fn test_room_pins() {
+        let (addr, _, _) = setup_test_server_with_db_and_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let mut sockets = Vec::new();
+        for room in ["pin_a", "pin_b", "pin_c"] {
+            let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+            let join_msg = json!({ "username": "alice", "channel": room }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let _ = ws.next().await; // Content
+            sockets.push(ws);
+        }
+        let room_ids = |client: reqwest::Client, cookie: Option<String>| async move {
+            let mut request = client.get(format!("http://{addr}/api/rooms"));
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", cookie);
+            }
+            let rooms: Vec<serde_json::Value> = request.send().await.unwrap().json().await.unwrap();
+            rooms
+                .iter()
+                .map(|room| room["id"].as_str().unwrap().to_string())
+                .filter(|id| id.starts_with("pin_"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            room_ids(client.clone(), None).await,
+            ["pin_a", "pin_b", "pin_c"]
+        );
+
+        // Pinned for everyone by an admin
+        let response = client
+            .put(format!("http://{addr}/api/admin/rooms/pin_c/pin"))
+            .json(&json!({ "position": 1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .put(format!("http://{addr}/api/admin/rooms/pin_c/pin"))
+            .bearer_auth("s3cret")
+            .json(&json!({ "position": 1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            room_ids(client.clone(), None).await,
+            ["pin_c", "pin_a", "pin_b"]
+        );
+
+        // Users pin rooms for themselves once logged in
+        let pin_url = format!("http://{addr}/api/rooms/pin_b/pin");
+        let response = client.put(&pin_url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&json!({ "username": "grace", "password": "correct horse" }))
+            .send()
+            .await
+            .unwrap();
+        let cookie = response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let response = client
+            .put(&pin_url)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .put(format!("http://{addr}/api/rooms/missing/pin"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        assert_eq!(
+            room_ids(client.clone(), Some(cookie.clone())).await,
+            ["pin_c", "pin_b", "pin_a"]
+        );
+        assert_eq!(
+            room_ids(client.clone(), None).await,
+            ["pin_c", "pin_a", "pin_b"]
+        );
+        let rooms: Vec<serde_json::Value> = client
+            .get(format!("http://{addr}/api/rooms"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let pin_b = rooms.iter().find(|room| room["id"] == "pin_b").unwrap();
+        assert_eq!(pin_b["pin"], json!({ "scope": "me", "position": 0 }));
+
+        // Unpinning
+        let response = client
+            .delete(&pin_url)
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .delete(format!("http://{addr}/api/admin/rooms/pin_c/pin"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            room_ids(client.clone(), Some(cookie)).await,
+            ["pin_a", "pin_b", "pin_c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_clear() {
+        let (addr, state) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let auto_clear_url = format!("http://{addr}/api/rooms/general/auto-clear");
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "general" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await; // Content
+        let _ = ws.next().await; // Join
+        let _ = ws.next().await; // Presence
+        ws.send(Message::Text("last visitor".to_string()))
+            .await
+            .unwrap();
+        let _ = ws.next().await; // Own edit
+
+        let response = client
+            .put(&auto_clear_url)
+            .json(&json!({ "minutes": 0 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = client
+            .put(&auto_clear_url)
+            .json(&json!({ "minutes": 5 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = client
+            .get(&auto_clear_url)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["value"]["minutes"], 5);
+
+        // Recent edits are kept
+        auto_clear::clear_stale_rooms_once(&state).await;
+        assert_eq!(
+            *state.rooms.lock().await["general"].content_rx.borrow(),
+            "last visitor"
+        );
+
+        let rooms = state.rooms.lock().await;
+        if let Some(long_ago) = Instant::now().checked_sub(Duration::from_secs(10 * 60)) {
+            *rooms["general"].last_edit.lock().await = long_ago;
+        }
+        drop(rooms);
+        auto_clear::clear_stale_rooms_once(&state).await;
+        assert_eq!(*state.rooms.lock().await["general"].content_rx.borrow(), "");
+
+        // The wipe is sent to the members
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["type"], "message");
+        assert_eq!(msg["value"], "");
+        assert_eq!(msg["username"], "Server");
+    }
+
+    #[tokio::test]
+    async fn test_burn_after_reading() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+
+        let url = client
+            .post(format!("http://{addr}/api/paste?burn=read"))
+            .body("hunter2")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let room_id = url.trim_end().rsplit('/').next().unwrap().to_string();
+        let raw_url = format!("http://{addr}/r/{room_id}/raw");
+
+        assert_eq!(
+            reqwest::get(&raw_url).await.unwrap().text().await.unwrap(),
+            "hunter2"
+        );
+        // Gone for the next readers, and from the database
+        assert_eq!(reqwest::get(&raw_url).await.unwrap().status(), 404);
+        let stored: String = sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = ?")
+            .bind(&room_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(stored, "");
+
+        // Every read path burns it
+        let url = client
+            .post(format!("http://{addr}/api/paste?burn=read"))
+            .body("hunter3")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let room_id = url.trim_end().rsplit('/').next().unwrap().to_string();
+        let content_url = format!("http://{addr}/api/rooms/{room_id}/content");
+        let response = reqwest::get(&content_url).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "hunter3");
+        assert_eq!(reqwest::get(&content_url).await.unwrap().status(), 404);
+        let response = reqwest::get(format!("http://{addr}/r/{room_id}/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_blobs() {
+        let (addr, _, db) = setup_test_server_with_db_and_config(Config {
+            max_blob_size: 16,
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/blobs?ttl=60"))
+            .body(vec![0u8, 159, 146, 150])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let created: serde_json::Value = response.json().await.unwrap();
+        let id = created["value"]["id"].as_str().unwrap().to_string();
+        let expires_at = created["value"]["expires_at"].as_i64().unwrap();
+        assert!((expires_at - crate::unix_timestamp() - 60).abs() <= 1);
+
+        let response = reqwest::get(format!("http://{addr}/api/blobs/{id}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), [0, 159, 146, 150]);
+
+        // Expired blobs are out of reach before being purged
+        sqlx::query("UPDATE encrypted_blobs SET expires_at = 0 WHERE id = ?")
+            .bind(&id)
+            .execute(&db)
+            .await
+            .unwrap();
+        let response = reqwest::get(format!("http://{addr}/api/blobs/{id}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let response = client
+            .post(format!("http://{addr}/api/blobs"))
+            .body(vec![0u8; 17])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 413);
+        let response = client
+            .post(format!("http://{addr}/api/blobs"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = client
+            .post(format!("http://{addr}/api/blobs?ttl=0"))
+            .body("x")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_burn_after_leave() {
+        let (addr, state) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let burn_url = format!("http://{addr}/api/rooms/general/burn");
+
+        let response = client
+            .put(&burn_url)
+            .json(&json!({ "after": "leave" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = client
+            .get(&burn_url)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["value"]["after"], "leave");
+
+        let ws_uri = format!("ws://{addr}/ws");
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "general" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let _ = ws.next().await; // Content
+            sockets.push(ws);
+        }
+        sockets[0]
+            .send(Message::Text("secret".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Kept while someone is still in the room
+        sockets.remove(0).close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            *state.rooms.lock().await["general"].content_rx.borrow(),
+            "secret"
+        );
+
+        sockets.remove(0).close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*state.rooms.lock().await["general"].content_rx.borrow(), "");
+    }
+
+    #[tokio::test]
+    async fn test_deferred_room_removal() {
+        let (addr, state) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let mut sockets = Vec::new();
+        for username in ["alice", "bob"] {
+            let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "closing_room" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let _ = ws.next().await; // Content
+            sockets.push(ws);
+        }
+
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/closing_room"))
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["type"], "scheduled");
+        assert_eq!(body["value"], 60);
+
+        // The countdown is announced to the users
+        loop {
+            let msg = sockets[0]
+                .next()
+                .await
+                .unwrap()
+                .unwrap()
+                .into_text()
+                .unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            if msg["type"] == "room-closing" {
+                assert_eq!(msg["value"], "60");
+                break;
+            }
+        }
+
+        // Nobody joins a closing room
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "carol", "channel": "closing_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["type"], "error");
+
+        // Deleted as soon as the room empties
+        for mut ws in sockets {
+            ws.close(None).await.unwrap();
+        }
+        let mut removed = false;
+        for _ in 0..50 {
+            if !state.rooms.lock().await.contains_key("closing_room") {
+                removed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(removed);
+    }
+
+    #[tokio::test]
+    async fn test_rooms_conditional_get() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let rooms_url = format!("http://{addr}/api/rooms");
+
+        let response = client.get(&rooms_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+        assert!(response.headers().contains_key("last-modified"));
+        let etag = response.headers()["etag"].clone();
+
+        let response = client
+            .get(&rooms_url)
+            .header("if-none-match", etag.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 304);
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        // A new room changes the list
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "cached_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await; // Content
+        let response = client
+            .get(&rooms_url)
+            .header("if-none-match", etag.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers()["etag"], etag);
+        let rooms: Vec<Room> = response.json().await.unwrap();
+        assert!(rooms.iter().any(|room| room.id == "cached_room"));
+    }
+
+    #[tokio::test]
+    async fn test_username_collision() {
+        let (addr, state) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join_msg = json!({ "username": "alice", "channel": "crowded_room" }).to_string();
+
+        let (mut ws1, _) = connect_async(&ws_uri).await.unwrap();
+        ws1.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws1.next().await; // Content
+
+        let (mut ws2, _) = connect_async(&ws_uri).await.unwrap();
+        ws2.send(Message::Text(join_msg.clone())).await.unwrap();
+        let _ = ws2.next().await; // Content
+        let msg = ws2.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["type"], "username-assigned");
+        assert_eq!(msg["value"], "alice-2");
+
+        let (mut ws3, _) = connect_async(&ws_uri).await.unwrap();
+        ws3.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws3.next().await; // Content
+        let msg = ws3.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["value"], "alice-3");
+
+        let mut users: Vec<String> = state.rooms.lock().await["crowded_room"]
+            .users
+            .lock()
+            .await
+            .names()
+            .cloned()
+            .collect();
+        users.sort();
+        assert_eq!(users, ["alice", "alice-2", "alice-3"]);
+
+        // The name is free again once its user left
+        ws1.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (mut ws4, _) = connect_async(&ws_uri).await.unwrap();
+        ws4.send(Message::Text(
+            json!({ "username": "alice", "channel": "crowded_room" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let _ = ws4.next().await; // Content
+        let msg = ws4.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["type"], "join");
+        assert_eq!(msg["username"], "alice");
+    }
+
+    #[tokio::test]
+    async fn test_presence() {
+        async fn next_json<S>(ws: &mut S) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            serde_json::from_str(&msg).unwrap()
+        }
+
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join = |username: &str| {
+            Message::Text(json!({ "username": username, "channel": "presence_room" }).to_string())
+        };
+        let users = |msg: &serde_json::Value| {
+            assert_eq!(msg["type"], "presence");
+            serde_json::from_str::<Vec<String>>(msg["value"].as_str().unwrap()).unwrap()
+        };
+
+        let (mut alice, _) = connect_async(&ws_uri).await.unwrap();
+        alice.send(join("alice")).await.unwrap();
+        let _ = alice.next().await; // Content
+        let _ = alice.next().await; // Join
+        assert_eq!(users(&next_json(&mut alice).await), ["alice"]);
+
+        let (mut bob, _) = connect_async(&ws_uri).await.unwrap();
+        bob.send(join("bob")).await.unwrap();
+        let _ = alice.next().await; // Join of bob
+        assert_eq!(users(&next_json(&mut alice).await), ["alice", "bob"]);
+
+        bob.close(None).await.unwrap();
+        assert_eq!(next_json(&mut alice).await["type"], "leave");
+        assert_eq!(users(&next_json(&mut alice).await), ["alice"]);
+
+        // Clients sending JSON can ask for the list at any time
+        let (mut carol, _) = connect_async(&ws_uri).await.unwrap();
+        carol
+            .send(Message::Text(
+                json!({
+                    "username": "carol",
+                    "channel": "presence_room",
+                    "protocol_version": PROTOCOL_VERSION,
+                    "capabilities": ["documents"]
+                })
+                .to_string(),
+            ))
+            .await
+            .unwrap();
+        loop {
+            if next_json(&mut carol).await["type"] == "presence" {
+                break;
+            }
+        }
+        carol
+            .send(Message::Text(json!({ "type": "get-presence" }).to_string()))
+            .await
+            .unwrap();
+        assert_eq!(users(&next_json(&mut carol).await), ["alice", "carol"]);
+    }
+
+    #[tokio::test]
+    async fn test_default_room_content() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            default_room_content: Some("This pad is public.".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "brand_new_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["value"], "This pad is public.");
+
+        // Existing rooms keep their content
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(Message::Text(
+            json!({ "username": "bob", "channel": "general" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["value"], "");
+    }
+
+    #[tokio::test]
+    async fn test_rename_room() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        let rename = |room_id: &str, id: &str| {
+            client
+                .patch(format!("http://{addr}/api/rooms/{room_id}"))
+                .json(&json!({ "id": id }))
+                .send()
+        };
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "draft" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await; // Content
+        ws.send(Message::Text("Meeting notes".to_string()))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(rename("draft", "").await.unwrap().status(), 400);
+        assert_eq!(rename("draft", "draft").await.unwrap().status(), 400);
+        assert_eq!(rename("draft", "general").await.unwrap().status(), 409);
+        assert_eq!(rename("general", "lobby").await.unwrap().status(), 400);
+        assert_eq!(rename("missing", "found").await.unwrap().status(), 404);
+
+        let response = rename("draft", "meeting").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["value"], "meeting");
+
+        // Members are told where the room went
+        loop {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            if msg["type"] == "room-renamed" {
+                assert_eq!(msg["value"], "meeting");
+                break;
+            }
+        }
+
+        let rooms = state.rooms.lock().await;
+        assert!(!rooms.contains_key("draft"));
+        assert_eq!(*rooms["meeting"].content_rx.borrow(), "Meeting notes");
+        drop(rooms);
+        let stored: Vec<String> = sqlx::query_scalar("SELECT room_id FROM rooms")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert!(stored.contains(&"meeting".to_string()));
+        assert!(!stored.contains(&"draft".to_string()));
+
+        // Edits made after the rename are saved under the new id
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "meeting" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_eq!(msg["value"], "Meeting notes");
+        ws.send(Message::Text("Minutes".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'meeting'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(content, "Minutes");
+    }
+
+    #[tokio::test]
+    async fn test_rooms_pagination() {
+        let (addr, state) = setup_test_server().await;
+        let mut rooms = state.rooms.lock().await;
+        for id in ["browse-a", "browse-b", "browse-c", "other"] {
+            rooms.insert(id.to_string(), RoomState::new(id, &state.write_behind));
+        }
+        let mut users = rooms["browse-b"].users.lock().await;
+        users.join("alice");
+        users.join("bob");
+        drop(users);
+        *rooms["browse-a"].last_edit.lock().await = Instant::now() - Duration::from_secs(3600);
+        drop(rooms);
+
+        let list = |query: &str| {
+            let url = format!("http://{addr}/api/rooms?{query}");
+            async move {
+                let response = reqwest::get(url).await.unwrap();
+                assert_eq!(response.status(), 200);
+                let total: usize = response.headers()["x-total-count"]
+                    .to_str()
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let rooms: Vec<Room> = response.json().await.unwrap();
+                (
+                    rooms.into_iter().map(|room| room.id).collect::<Vec<_>>(),
+                    total,
+                )
+            }
+        };
+
+        assert_eq!(
+            list("search=BROWSE").await,
+            (
+                vec!["browse-a".into(), "browse-b".into(), "browse-c".into()],
+                3
+            )
+        );
+        assert_eq!(
+            list("search=browse&per_page=2&page=2").await,
+            (vec!["browse-c".to_string()], 3)
+        );
+        assert_eq!(list("search=browse&sort=users").await.0[0], "browse-b");
+        assert_eq!(list("search=browse&sort=updated_at").await.0[2], "browse-a");
+        // Every room without pagination
+        assert_eq!(list("").await.1, 5);
+
+        for query in [
+            "per_page=0",
+            "per_page=1000",
+            "per_page=10&page=0",
+            "sort=size",
+        ] {
+            let response = reqwest::get(format!("http://{addr}/api/rooms?{query}"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 400, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_room_statistics() {
+        let (addr, state, db) = setup_test_server_with_db().await;
+        let room = |rooms: Vec<Room>| rooms.into_iter().find(|room| room.id == "stats_room");
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "stats_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await; // Content
+        ws.send(Message::Text("12345".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let loaded = room(rooms).unwrap();
+        assert_eq!(loaded.content_length, 5);
+        assert!(loaded.created_at.is_some());
+        assert!(loaded.updated_at >= loaded.created_at);
+
+        // Evicted rooms are described from the database
+        drop(ws);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        evict_idle_rooms_once(&state, Duration::ZERO).await;
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let evicted = room(rooms).unwrap();
+        assert_eq!(evicted.content_length, 5);
+        assert!(evicted.created_at.is_some());
+        assert!(evicted.updated_at.is_some());
+
+        // The length of compressed contents is read from their header
+        let large = "0123456789abcdef".repeat(4096);
+        let mut connection = db.acquire().await.unwrap();
+        update_room_content(&mut connection, "stats_room", &large)
+            .await
+            .unwrap();
+        drop(connection);
+        let rooms: Vec<Room> = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(room(rooms).unwrap().content_length, large.len());
+    }
+
+    #[tokio::test]
+    async fn test_resume_session() {
+        async fn next_json<S>(ws: &mut S) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            serde_json::from_str(&msg).unwrap()
+        }
+
+        let (addr, state) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join = |resume: Option<&str>| {
+            Message::Text(
+                json!({
+                    "username": "alice",
+                    "channel": "resume_room",
+                    "protocol_version": PROTOCOL_VERSION,
+                    "capabilities": ["resume"],
+                    "resume": resume,
+                })
+                .to_string(),
+            )
+        };
+        let resume_info = |msg: serde_json::Value| {
+            assert_eq!(msg["type"], "resume");
+            serde_json::from_str::<serde_json::Value>(msg["value"].as_str().unwrap()).unwrap()
+        };
+
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(join(None)).await.unwrap();
+        assert_eq!(next_json(&mut ws).await["type"], "hello");
+        let info = resume_info(next_json(&mut ws).await);
+        assert_eq!(info["resumed"], false);
+        assert_eq!(next_json(&mut ws).await["type"], "message");
+        let _ = next_json(&mut ws).await; // Join
+        let _ = next_json(&mut ws).await; // Presence
+        ws.send(Message::Text("draft".to_string())).await.unwrap();
+        assert_eq!(next_json(&mut ws).await["value"], "draft");
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Nothing changed meanwhile, the content is not sent again
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(join(info["token"].as_str())).await.unwrap();
+        assert_eq!(next_json(&mut ws).await["type"], "hello");
+        let info = resume_info(next_json(&mut ws).await);
+        assert_eq!(info["resumed"], true);
+        let msg = next_json(&mut ws).await;
+        assert_eq!(msg["type"], "join");
+        assert_eq!(msg["username"], "alice");
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Changes made while away are
+        let rooms = state.rooms.lock().await;
+        rooms["resume_room"]
+            .content_tx
+            .send_replace("edited by bob".to_string());
+        drop(rooms);
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(join(info["token"].as_str())).await.unwrap();
+        let _ = next_json(&mut ws).await; // Hello
+        assert_eq!(resume_info(next_json(&mut ws).await)["resumed"], true);
+        assert_eq!(next_json(&mut ws).await["value"], "edited by bob");
+
+        // Tokens are used only once
+        let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+        ws.send(join(info["token"].as_str())).await.unwrap();
+        let _ = next_json(&mut ws).await; // Hello
+        assert_eq!(resume_info(next_json(&mut ws).await)["resumed"], false);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reaps_dead_connections() {
+        let (addr, state) = setup_test_server_with_config(Config {
+            heartbeat_interval: Some(Duration::from_millis(100)),
+            ..Config::default()
+        })
+        .await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let join = |username: &str| {
+            Message::Text(json!({ "username": username, "channel": "heartbeat" }).to_string())
+        };
+
+        // Reading answers the pings
+        let (mut alive, _) = connect_async(&ws_uri).await.unwrap();
+        alive.send(join("alice")).await.unwrap();
+        // Never reading leaves them unanswered, like a connection that dropped
+        let (mut dead, _) = connect_async(&ws_uri).await.unwrap();
+        dead.send(join("bob")).await.unwrap();
+
+        let left = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let msg = alive.next().await.unwrap().unwrap();
+                if let Message::Text(text) = msg {
+                    let msg: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    if msg["type"] == "leave" {
+                        return msg["username"].clone();
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(left, "bob");
+
+        let rooms = state.rooms.lock().await;
+        let users = rooms["heartbeat"].users.lock().await.clone();
+        drop(rooms);
+        assert_eq!(users.len(), 1);
+        assert!(users.contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_lagging_client_resync() {
+        let (addr, state) = setup_test_server().await;
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "lagging"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap(); // Content
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Flood the room faster than the connection can forward
+        let rooms = state.rooms.lock().await;
+        let room = &rooms["lagging"];
+        for i in 0..DEFAULT_BROADCAST_CAPACITY * 2 {
+            room.tx
+                .send(
+                    json!({ "type": "message", "value": format!("spam {i}"), "username": "bob" })
+                        .to_string(),
+                )
+                .unwrap();
+        }
+        room.content_tx.send_replace("latest".to_string());
+        drop(rooms);
+
+        // The client is sent the whole content instead of being disconnected
+        let resynced = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["username"] == "Server" && msg["value"] == "latest" {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(resynced.is_ok());
+        assert!(state.rooms.lock().await["lagging"]
+            .users
+            .lock()
+            .await
+            .contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_acknowledged_resync() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, state) = setup_test_server().await;
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "acked_room" }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let seq = next_of_type(&mut ws, "join").await["seq"].clone();
+        assert!(seq.as_u64().unwrap() > 0);
+        let ack = |seq: &serde_json::Value| {
+            Message::Text(json!({ "type": "ack", "seq": seq }).to_string())
+        };
+        ws.send(ack(&seq)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Flood the room faster than the connection can forward
+        let rooms = state.rooms.lock().await;
+        let room = &rooms["acked_room"];
+        for i in 0..DEFAULT_BROADCAST_CAPACITY * 2 {
+            room.tx
+                .send(
+                    json!({ "type": "message", "value": format!("spam {i}"), "username": "bob" })
+                        .to_string(),
+                )
+                .unwrap();
+        }
+        room.content_tx.send_replace("latest".to_string());
+        drop(rooms);
+
+        // The messages after the gap come first, the resync waits for the client to catch up
+        let mut last = seq;
+        while let Ok(Some(Ok(msg))) =
+            tokio::time::timeout(Duration::from_millis(500), ws.next()).await
+        {
+            let msg: serde_json::Value = serde_json::from_str(&msg.into_text().unwrap()).unwrap();
+            assert_eq!(msg["username"], "bob");
+            assert!(msg["seq"].as_u64() > last.as_u64());
+            last = msg["seq"].clone();
+        }
+        ws.send(ack(&last)).await.unwrap();
+        let msg = next_of_type(&mut ws, "message").await;
+        assert_eq!(msg["username"], "Server");
+        assert_eq!(msg["value"], "latest");
+        assert!(state.rooms.lock().await["acked_room"]
+            .users
+            .lock()
+            .await
+            .contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_content_ranges() {
+        async fn next_of_type<S>(ws: &mut S, message_type: &str) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+                if msg["type"] == message_type {
+                    return msg;
+                }
+            }
+        }
+
+        let (addr, state) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let content_url = format!("http://{addr}/api/rooms/ranges_room/content");
+        let connect = || async {
+            let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", "partage.v2".parse().unwrap());
+            connect_async(request).await.unwrap().0
+        };
+        let join = |username: &str| {
+            Message::Text(
+                json!({
+                    "username": username,
+                    "channel": "ranges_room",
+                    "protocol_version": PROTOCOL_VERSION,
+                    "capabilities": ["ranges"],
+                })
+                .to_string(),
+            )
+        };
+
+        // Small contents are sent whole
+        let mut ws = connect().await;
+        ws.send(join("alice")).await.unwrap();
+        next_of_type(&mut ws, "message").await;
+        // "é" takes two bytes
+        let large = "é".repeat(200_000);
+        state.rooms.lock().await["ranges_room"]
+            .content_tx
+            .send_replace(large.clone());
+
+        let mut ws = connect().await;
+        ws.send(join("bob")).await.unwrap();
+        let msg = next_of_type(&mut ws, "content-size").await;
+        assert_eq!(msg["value"], "400000");
+        ws.send(Message::Text(
+            json!({ "type": "get-range", "offset": 1, "length": 10 }).to_string(),
+        ))
+        .await
+        .unwrap();
+        let msg = next_of_type(&mut ws, "range").await;
+        let range: serde_json::Value =
+            serde_json::from_str(msg["value"].as_str().unwrap()).unwrap();
+        assert_eq!(range["offset"], 0);
+        assert_eq!(range["end"], 12);
+        assert_eq!(range["total"], 400_000);
+        assert_eq!(range["content"], "éééééé");
+        ws.send(Message::Text(
+            json!({ "type": "get-range", "offset": 500_000, "length": 10 }).to_string(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            next_of_type(&mut ws, "error").await["code"],
+            "invalid-range"
+        );
+
+        let response = client
+            .get(format!("{content_url}?offset=399996&length=100"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(
+            response.headers()["content-range"],
+            "bytes 399996-399999/400000"
+        );
+        assert_eq!(response.text().await.unwrap(), "éé");
+        let response = client.get(&content_url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), large);
+        let response = client
+            .get(format!("{content_url}?offset=500000"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        sqlx::query(
+            r"
+            INSERT INTO rooms (room_id, content, visibility) VALUES
+                ('recipes', 'Crêpes need <flour>, milk and eggs', 'public'),
+                ('diary', 'Bought milk today', 'private')
+            ",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let search = |query: &'static str| {
+            let url = format!("http://{addr}/api/search?q={query}");
+            async move { reqwest::get(url).await.unwrap() }
+        };
+
+        let response = search("CREPES+milk").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body["value"],
+            json!([{
+                "room_id": "recipes",
+                "snippet": "<mark>Crêpes</mark> need &lt;flour&gt;, <mark>milk</mark> and eggs",
+            }])
+        );
+        // Private rooms are only searched by their members
+        let body: serde_json::Value = search("today").await.json().await.unwrap();
+        assert_eq!(body["value"], json!([]));
+        assert_eq!(search("+").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_account_tabs() {
+        async fn next_json<S>(ws: &mut S) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            serde_json::from_str(&msg).unwrap()
+        }
+
+        let (addr, state, _) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+        let credentials = json!({ "username": "erin", "password": "correct horse" });
+        client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        let response = client
+            .post(format!("http://{addr}/api/auth/login"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        let cookie = response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let join_msg = json!({ "username": "erin", "channel": "tabs_room" }).to_string();
+
+        let (mut watcher, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        watcher
+            .send(Message::Text(
+                json!({ "username": "watcher", "channel": "tabs_room" }).to_string(),
+            ))
+            .await
+            .unwrap();
+        let _ = next_json(&mut watcher).await; // Content
+        let _ = next_json(&mut watcher).await; // Join
+        let _ = next_json(&mut watcher).await; // Presence
+
+        let mut tabs = Vec::new();
+        for _ in 0..2 {
+            let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("cookie", cookie.parse().unwrap());
+            let (mut ws, _) = connect_async(request).await.unwrap();
+            ws.send(Message::Text(join_msg.clone())).await.unwrap();
+            let _ = next_json(&mut ws).await; // Content
+            tabs.push(ws);
+        }
+        // Only the first tab is announced
+        let msg = next_json(&mut watcher).await;
+        assert_eq!(msg["type"], "join");
+        assert_eq!(msg["username"], "erin");
+        let _ = next_json(&mut watcher).await; // Presence
+
+        // Closing one tab keeps the account in the room
+        tabs[0].close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(state.rooms.lock().await["tabs_room"]
+            .users
+            .lock()
+            .await
+            .contains("erin"));
+
+        tabs[1].close(None).await.unwrap();
+        let msg = next_json(&mut watcher).await;
+        assert_eq!(msg["type"], "leave");
+        assert_eq!(msg["username"], "erin");
+        let presence = next_json(&mut watcher).await;
+        assert_eq!(presence["value"], json!(["watcher"]).to_string());
+    }
+
+    #[tokio::test]
+    async fn test_admin_sessions() {
+        let (addr, _, db) = setup_test_server_with_db_and_config(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let list_sessions = || async {
+            client
+                .get(format!("http://{addr}/api/admin/sessions"))
+                .bearer_auth("s3cret")
+                .send()
+                .await
+                .unwrap()
+                .json::<Vec<serde_json::Value>>()
+                .await
+                .unwrap()
+        };
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "sessions_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sessions = list_sessions().await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["username"], "alice");
+        assert_eq!(sessions[0]["room_id"], "sessions_room");
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(stored, 1);
+
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(list_sessions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_webhooks() {
+        // Records the deliveries, with the path they were posted to
+        let (hook_tx, mut hook_rx) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = axum::Router::new().route(
+            "/*path",
+            axum::routing::post(
+                move |uri: axum::http::Uri, headers: axum::http::HeaderMap, body: String| {
+                    let _ = hook_tx.send((uri.path().to_string(), headers, body));
+                    async {}
+                },
+            ),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let (addr, _, db) = setup_test_server_with_db_and_config(Config {
+            webhook_urls: vec![format!("http://{hook_addr}/global")],
+            webhook_secret: Some("s3cret".to_string()),
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        async fn next_delivery(
+            hook_rx: &mut tokio::sync::mpsc::UnboundedReceiver<(
+                String,
+                axum::http::HeaderMap,
+                String,
+            )>,
+        ) -> (String, serde_json::Value) {
+            let (path, headers, body) =
+                tokio::time::timeout(Duration::from_secs(5), hook_rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+            let signature = headers["x-partage-signature"].to_str().unwrap();
+            assert_eq!(
+                signature,
+                format!("sha256={}", sign("s3cret", body.as_bytes()))
+            );
+            (path, serde_json::from_str(&body).unwrap())
+        }
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "hooked"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let (path, body) = next_delivery(&mut hook_rx).await;
+            assert_eq!(path, "/global");
+            assert_eq!(body["room"], "hooked");
+            events.push(body);
+        }
+        events.sort_by_key(|body| body["event"].as_str().unwrap().to_string());
+        assert_eq!(events[0]["event"], "room_created");
+        assert_eq!(events[1]["event"], "user_joined");
+        assert_eq!(events[1]["data"]["username"], "alice");
+
+        let client = reqwest::Client::new();
+        let webhooks_url = format!("http://{addr}/api/rooms/hooked/webhooks");
+        // Ownerless rooms don't let anyone register webhooks
+        let response = client
+            .post(&webhooks_url)
+            .json(&json!({ "url": "https://93.184.215.14/hook" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(&webhooks_url)
+            .bearer_auth("s3cret")
+            .json(&json!({ "url": "ftp://example.com" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        // Nor the server to call itself
+        let response = client
+            .post(&webhooks_url)
+            .bearer_auth("s3cret")
+            .json(&json!({ "url": format!("http://{hook_addr}/room") }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        // The receiver is on the loopback, stored as is for the deliveries to reach it
+        sqlx::query("INSERT INTO room_webhooks (room_id, url, created_at) VALUES ('hooked', ?, 0)")
+            .bind(format!("http://{hook_addr}/room"))
+            .execute(&db)
+            .await
+            .unwrap();
+        let webhooks: serde_json::Value = client
+            .get(&webhooks_url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(webhooks["value"].as_array().unwrap().len(), 1);
+
+        // Both the global and the room webhooks hear about the deletion
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/hooked"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let (path, body) = next_delivery(&mut hook_rx).await;
+            assert_eq!(body["event"], "room_deleted");
+            paths.push(path);
+        }
+        paths.sort();
+        assert_eq!(paths, ["/global", "/room"]);
+    }
+
+    #[tokio::test]
+    async fn test_room_events() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/api/rooms/unknown_room/events"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let mut response = client
+            .get(format!("http://{addr}/api/rooms/general/events"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        // Starts with the current content
+        let mut received = String::new();
+        while !received.contains("event: presence") {
+            let chunk = response.chunk().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(received.starts_with("event: message\ndata: {"));
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        ws.send(Message::Text("Live update".to_string()))
+            .await
+            .unwrap();
+
+        let streamed = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut received = String::new();
+            while !received.contains("Live update") {
+                let chunk = response.chunk().await.unwrap().unwrap();
+                received.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+            received
+        })
+        .await
+        .unwrap();
+        // Joins are not streamed, but the presence they change is
+        assert!(streamed.contains("event: presence"));
+        assert!(!streamed.contains("event: join"));
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let (addr, _) = setup_test_server().await;
+        let response = reqwest::get(format!("http://{addr}/api/openapi.json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let spec: serde_json::Value = response.json().await.unwrap();
+        assert!(spec["paths"]["/api/rooms"]["get"].is_object());
+
+        let response = reqwest::get(format!("http://{addr}/api/docs"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.text().await.unwrap().contains("/api/openapi.json"));
+    }
+
+    #[tokio::test]
+    async fn test_graphql() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::new();
+        let query = |query: &str| {
+            client
+                .post(format!("http://{addr}/api/graphql"))
+                .json(&json!({ "query": query }))
+                .send()
+        };
+
+        let response: serde_json::Value = query(r#"{ room(id: "graphql_room") { id } }"#)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["data"]["room"], serde_json::Value::Null);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "graphql_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let _ = ws.next().await.unwrap();
+        ws.send(Message::Text("hello".to_string())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response: serde_json::Value =
+            query(r#"{ rooms { id } room(id: "graphql_room") { id users content encrypted } }"#)
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(
+            response["data"]["room"],
+            json!({"id": "graphql_room", "users": ["alice"], "content": "hello", "encrypted": false})
+        );
+        assert!(response["data"]["rooms"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"id": "graphql_room"})));
+    }
+
+    #[tokio::test]
+    async fn test_operational_transform() {
+        async fn next_json<S>(ws: &mut S) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            serde_json::from_str(&msg).unwrap()
+        }
+
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let (mut alice, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "alice",
+            "channel": "ot_room",
+            "protocol_version": PROTOCOL_VERSION,
+            "capabilities": ["ot"],
+        })
+        .to_string();
+        alice.send(Message::Text(join_msg)).await.unwrap();
+        assert_eq!(next_json(&mut alice).await["type"], "hello");
+        assert_eq!(next_json(&mut alice).await["type"], "message");
+        assert_eq!(
+            next_json(&mut alice).await,
+            json!({"type": "ot-snapshot", "revision": 0, "value": ""})
+        );
+        let _ = next_json(&mut alice).await; // Join
+        let _ = next_json(&mut alice).await; // Presence
+
+        let (mut bob, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({
+            "username": "bob",
+            "channel": "ot_room"
+        })
+        .to_string();
+        bob.send(Message::Text(join_msg)).await.unwrap();
+        let _ = next_json(&mut bob).await; // Content
+        let _ = next_json(&mut bob).await; // Join
+        let _ = next_json(&mut bob).await; // Presence
+        let _ = next_json(&mut alice).await; // Join
+        let _ = next_json(&mut alice).await; // Presence
+
+        // Edits of the other clients reach alice as operations
+        bob.send(Message::Text("abc".to_string())).await.unwrap();
+        assert_eq!(next_json(&mut bob).await["value"], "abc");
+        let op = next_json(&mut alice).await;
+        assert_eq!(op["type"], "op");
+        assert_eq!(op["revision"], 1);
+        assert_eq!(op["ops"], json!(["abc"]));
+
+        // Made before alice saw the edit of bob
+        alice
+            .send(Message::Text(
+                json!({"type": "op", "revision": 0, "ops": ["x"], "op_id": "1"}).to_string(),
+            ))
+            .await
+            .unwrap();
+        let op = next_json(&mut alice).await;
+        assert_eq!(op["revision"], 2);
+        assert_eq!(op["ops"], json!(["x", 3]));
+        assert_eq!(op["username"], "alice");
+        assert_eq!(op["op_id"], "1");
+        let msg = next_json(&mut bob).await;
+        assert_eq!(msg["type"], "message");
+        assert_eq!(msg["value"], "xabc");
+
+        alice
+            .send(Message::Text(
+                json!({"type": "op", "revision": 5, "ops": [4, "!"]}).to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(next_json(&mut alice).await["type"], "error");
+        assert_eq!(
+            next_json(&mut alice).await,
+            json!({"type": "ot-snapshot", "revision": 2, "value": "xabc"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_members() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+
+        let mut cookies = Vec::new();
+        for username in ["ivan", "judy", "mallory"] {
+            let response = client
+                .post(format!("http://{addr}/api/auth/register"))
+                .json(&json!({ "username": username, "password": "correct horse" }))
+                .send()
+                .await
+                .unwrap();
+            cookies.push(
+                response.headers()["set-cookie"]
+                    .to_str()
+                    .unwrap()
+                    .split(';')
+                    .next()
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        let (ivan, judy, mallory) = (&cookies[0], &cookies[1], &cookies[2]);
+        sqlx::query(
+            "INSERT INTO rooms (room_id, content, owner_id) SELECT 'team_room', 'plans', id FROM users WHERE username = 'ivan'",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let member_url = format!("http://{addr}/api/rooms/team_room/members/judy");
+        let response = client
+            .put(&member_url)
+            .header("cookie", mallory)
+            .json(&json!({ "role": "viewer" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client
+            .put(&member_url)
+            .header("cookie", ivan)
+            .json(&json!({ "role": "viewer" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let members: serde_json::Value = client
+            .get(format!("http://{addr}/api/rooms/team_room/members"))
+            .header("cookie", ivan)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            members["value"],
+            json!([{ "username": "judy", "role": "viewer" }])
+        );
+        let response = client
+            .put(format!("http://{addr}/api/rooms/team_room/visibility"))
+            .header("cookie", ivan)
+            .json(&json!({ "visibility": "private" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Hidden from the others
+        let listed = |cookie: Option<&String>| {
+            let mut request = client.get(format!("http://{addr}/api/rooms"));
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", cookie);
+            }
+            async move {
+                let rooms: Vec<serde_json::Value> =
+                    request.send().await.unwrap().json().await.unwrap();
+                rooms.iter().any(|room| room["id"] == "team_room")
+            }
+        };
+        assert!(!listed(None).await);
+        assert!(!listed(Some(mallory)).await);
+        assert!(listed(Some(judy)).await);
+        assert!(listed(Some(ivan)).await);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "eve",
+            "channel": "team_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["type"], "error");
+        assert_eq!(msg["value"], "This room is private.");
+
+        // Viewers read without writing
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("cookie", judy.parse().unwrap());
+        let (mut ws, _) = connect_async(request).await.unwrap();
+        let join_msg = json!({
+            "username": "judy",
+            "channel": "team_room"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["value"], "plans");
+        let _ = ws.next().await.unwrap(); // Join
+        let _ = ws.next().await.unwrap(); // Presence
+        ws.send(Message::Text("vandalized".to_string()))
+            .await
+            .unwrap();
+        let msg: serde_json::Value =
+            serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+        assert_eq!(msg["type"], "error");
+        assert_eq!(msg["value"], "Viewers can't edit this room.");
+        ws.close(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Only owners remove the room
+        let room_url = format!("http://{addr}/api/rooms/team_room");
+        let response = client
+            .delete(&room_url)
+            .header("cookie", judy)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client
+            .delete(&room_url)
+            .header("cookie", ivan)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        // Kept in the trash with the room, until it is purged
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM room_members")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn test_room_visibility() {
+        let (addr, _, db) = setup_test_server_with_db().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/api/auth/register"))
+            .json(&json!({ "username": "oscar", "password": "correct horse" }))
+            .send()
+            .await
+            .unwrap();
+        let oscar = response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        sqlx::query(
+            "INSERT INTO rooms (room_id, content, owner_id) SELECT 'quiet_room', 'hush', id FROM users WHERE username = 'oscar'",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let visibility_url = format!("http://{addr}/api/rooms/quiet_room/visibility");
+        let response = client
+            .put(&visibility_url)
+            .json(&json!({ "visibility": "unlisted" }))
+            .send()
+            .await
+            .unwrap();
+        assert!(matches!(response.status().as_u16(), 401 | 403));
+        let response = client
+            .put(&visibility_url)
+            .header("cookie", &oscar)
+            .json(&json!({ "visibility": "unlisted", "password": "sesame" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = client
+            .put(&visibility_url)
+            .header("cookie", &oscar)
+            .json(&json!({ "visibility": "unlisted" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Unlisted rooms are only listed for their owners, but anyone can join them
+        let listed = |cookie: Option<&String>| {
+            let mut request = client.get(format!("http://{addr}/api/rooms"));
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", cookie);
+            }
+            async move {
+                let rooms: Vec<serde_json::Value> =
+                    request.send().await.unwrap().json().await.unwrap();
+                rooms.iter().any(|room| room["id"] == "quiet_room")
+            }
+        };
+        assert!(!listed(None).await);
+        assert!(listed(Some(&oscar)).await);
+
+        let join = |password: Option<&str>| {
+            let mut join_msg = json!({
+                "username": "peggy",
+                "channel": "quiet_room"
+            });
+            if let Some(password) = password {
+                join_msg["password"] = json!(password);
+            }
+            async move {
+                let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+                ws.send(Message::Text(join_msg.to_string())).await.unwrap();
+                let msg: serde_json::Value =
+                    serde_json::from_str(&ws.next().await.unwrap().unwrap().into_text().unwrap())
+                        .unwrap();
+                ws.close(None).await.unwrap();
+                msg
+            }
+        };
+        assert_eq!(join(None).await["value"], "hush");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Private rooms let non-members in with their password
+        let response = client
+            .put(&visibility_url)
+            .header("cookie", &oscar)
+            .json(&json!({ "visibility": "private", "password": "sesame" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let visibility: serde_json::Value = client
+            .get(&visibility_url)
+            .header("cookie", &oscar)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            visibility["value"],
+            json!({ "visibility": "private", "password": true })
+        );
+        for password in [None, Some("open sesame")] {
+            let msg = join(password).await;
+            assert_eq!(msg["type"], "error");
+            assert_eq!(msg["value"], "This room is private.");
+        }
+        assert_eq!(join(Some("sesame")).await["value"], "hush");
+        assert!(!listed(None).await);
+
+        // Nor read through the other APIs
+        for url in [
+            "r/quiet_room/raw",
+            "r/quiet_room/html",
+            "api/rooms/quiet_room/download",
+            "api/rooms/quiet_room/events",
+            "api/rooms/quiet_room/documents",
+            "api/rooms/quiet_room/language",
+            "api/rooms/quiet_room/freeze",
+            "api/rooms/quiet_room/auto-clear",
+            "api/rooms/quiet_room/burn",
+            "api/rooms/quiet_room/files/1",
+        ] {
+            let response = client
+                .get(format!("http://{addr}/{url}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 403, "{url}");
+        }
+        // Nor format it
+        let response = client
+            .post(format!("http://{addr}/api/rooms/quiet_room/format"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = client
+            .get(format!("http://{addr}/r/quiet_room/raw"))
+            .header("cookie", &oscar)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "hush");
+        let graphql = |cookie: Option<&String>| {
+            let mut request = client.post(format!("http://{addr}/api/graphql")).json(
+                &json!({ "query": r#"{ rooms { id } room(id: "quiet_room") { content } }"# }),
+            );
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", cookie);
+            }
+            async move {
+                request
+                    .send()
+                    .await
+                    .unwrap()
+                    .json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let response = graphql(None).await;
+        assert_eq!(response["errors"][0]["message"], "This room is private.");
+        let response = graphql(Some(&oscar)).await;
+        assert_eq!(response["data"]["room"]["content"], "hush");
+        assert!(response["data"]["rooms"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "id": "quiet_room" })));
+    }
+
+    #[tokio::test]
+    async fn test_client_sdk() {
+        use partage_client::{Client, Event, JoinOptions};
+
+        let (addr, _) = setup_test_server().await;
+        let ws_uri = format!("ws://{addr}/ws");
+
+        let mut alice = Client::connect(&ws_uri, JoinOptions::new("alice", "sdk_room"))
+            .await
+            .unwrap();
+        assert_eq!(alice.hello().protocol_version, PROTOCOL_VERSION);
+        assert!(alice.hello().has("documents"));
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Content {
+                doc_id: "main".to_string(),
+                username: "Server".to_string(),
+                value: String::new(),
+            })
+        );
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Join {
+                username: "alice".to_string()
+            })
+        );
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Presence {
+                users: vec!["alice".to_string()]
+            })
+        );
+
+        let bob = Client::connect(&ws_uri, JoinOptions::new("bob", "sdk_room"))
+            .await
+            .unwrap();
+        let (mut bob_sender, mut bob_updates) = bob.split();
+        assert!(matches!(
+            bob_updates.next().await,
+            Some(Ok(Event::Content { .. }))
+        ));
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Join {
+                username: "bob".to_string()
+            })
+        );
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Presence {
+                users: vec!["alice".to_string(), "bob".to_string()]
+            })
+        );
+
+        bob_sender.edit_document("notes", "todo").await.unwrap();
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Content {
+                doc_id: "notes".to_string(),
+                username: "bob".to_string(),
+                value: "todo".to_string(),
+            })
+        );
+
+        bob_sender.close().await.unwrap();
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Leave {
+                username: "bob".to_string()
+            })
+        );
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Presence {
+                users: vec!["alice".to_string()]
+            })
+        );
+        alice.request_presence().await.unwrap();
+        assert_eq!(
+            alice.next_event().await.unwrap(),
+            Some(Event::Presence {
+                users: vec!["alice".to_string()]
+            })
+        );
+    }
+
+    /// Identity provider answering discovery and token requests,
+    /// issuing identity tokens with the nonce found in `nonce`
+    async fn spawn_fake_oidc_provider(nonce: Arc<std::sync::Mutex<String>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = {
+            let issuer = issuer.clone();
+            move || {
+                let issuer = issuer.clone();
+                async move {
+                    axum::Json(json!({
+                        "issuer": issuer,
+                        "authorization_endpoint": format!("{issuer}/authorize"),
+                        "token_endpoint": format!("{issuer}/token"),
+                    }))
+                }
+            }
+        };
+        let token = {
+            let issuer = issuer.clone();
+            move || {
+                let claims = json!({
+                    "iss": issuer,
+                    "aud": "partage",
+                    "exp": crate::unix_timestamp() + 60,
+                    "nonce": *nonce.lock().unwrap(),
+                    "sub": "erin-subject",
+                    "preferred_username": "erin",
+                });
+                async move {
+                    let id_token = format!(
+                        "{}.{}.",
+                        URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+                        URL_SAFE_NO_PAD.encode(claims.to_string())
+                    );
+                    axum::Json(json!({ "id_token": id_token, "token_type": "Bearer" }))
+                }
+            }
+        };
+
+        let provider = axum::Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                axum::routing::get(discovery),
+            )
+            .route("/token", axum::routing::post(token));
+        tokio::spawn(async move {
+            axum::serve(listener, provider).await.unwrap();
+        });
+
+        issuer
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login() {
+        let nonce = Arc::new(std::sync::Mutex::new(String::new()));
+        let issuer = spawn_fake_oidc_provider(nonce.clone()).await;
+        let (addr, _, _) = setup_test_server_with_db_and_config(Config {
+            oidc: Some(OidcConfig {
+                issuer_url: issuer.clone(),
+                client_id: "partage".to_string(),
+                client_secret: None,
+                redirect_url: "http://localhost/api/auth/oidc/callback".to_string(),
+                username_claim: "preferred_username".to_string(),
+            }),
+            ..Config::default()
+        })
+        .await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        // Redirected to the provider, with PKCE
+        let response = client
+            .get(format!("http://{addr}/api/auth/oidc/login"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 303);
+        let location =
+            reqwest::Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        assert_eq!(
+            location.as_str().split('?').next(),
+            Some(&*format!("{issuer}/authorize"))
+        );
+        let params: HashMap<String, String> = location.query_pairs().into_owned().collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+        *nonce.lock().unwrap() = params["nonce"].clone();
+
+        // Back from the provider
+        let callback_url = format!(
+            "http://{addr}/api/auth/oidc/callback?code=abc&state={}",
+            params["state"]
+        );
+        let response = client.get(&callback_url).send().await.unwrap();
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.headers()["location"], "/");
+        let cookie = response.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        let account: serde_json::Value = client
+            .get(format!("http://{addr}/api/auth/me"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(account["username"], "erin");
+
+        // A login can't be completed twice
+        let response = client.get(&callback_url).send().await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_require_auth() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            require_auth: true,
+            ..Config::default()
+        })
+        .await;
+
+        let response = reqwest::get(format!("http://{addr}/api/rooms"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({
+            "username": "anonymous",
+            "channel": "general"
+        })
+        .to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["type"], "error");
+        assert_eq!(parsed["code"], "unauthorized");
+        assert_eq!(parsed["value"], "Authentication required.");
+        // The connection is closed with the same reason
+        match ws.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(u16::from(frame.code), 1008);
+                assert_eq!(frame.reason, "Authentication required.");
+            }
+            msg => panic!("Expected a close frame, got {msg:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            max_connections_per_ip: 2,
+            max_room_users: 1,
+            ..Config::default()
+        })
+        .await;
+        let ws_uri = format!("ws://{addr}/ws");
+        let first_error = |received: Message| {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&received.into_text().unwrap()).unwrap();
+            assert_eq!(parsed["type"], "error");
+            parsed["code"].as_str().unwrap().to_string()
+        };
+
+        let (mut alice, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "small_room" }).to_string();
+        alice.send(Message::Text(join_msg)).await.unwrap();
+        let _ = alice.next().await.unwrap().unwrap();
+
+        let (mut bob, _) = connect_async(&ws_uri).await.unwrap();
+        let join_msg = json!({ "username": "bob", "channel": "small_room" }).to_string();
+        bob.send(Message::Text(join_msg)).await.unwrap();
+        assert_eq!(first_error(bob.next().await.unwrap().unwrap()), "room-full");
+        match bob.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.reason, "This room is full."),
+            msg => panic!("Expected a close frame, got {msg:?}"),
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Alice and an idle connection are as many as an IP gets
+        let (_idle, _) = connect_async(&ws_uri).await.unwrap();
+        let (mut carol, _) = connect_async(&ws_uri).await.unwrap();
+        assert_eq!(
+            first_error(carol.next().await.unwrap().unwrap()),
+            "too-many-connections"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_creation_limits() {
+        let (addr, _) = setup_test_server_with_config(Config {
+            room_creations_per_minute: 1,
+            room_creation_burst: 1,
+            reserved_room_names: Some(regex::Regex::new("^(?:admin|secret.*)$").unwrap()),
+            ..Config::default()
+        })
+        .await;
+        let join = |channel: &str| {
+            let ws_uri = format!("ws://{addr}/ws");
+            let join_msg = json!({ "username": "alice", "channel": channel }).to_string();
+            async move {
+                let (mut ws, _) = connect_async(&ws_uri).await.unwrap();
+                ws.send(Message::Text(join_msg)).await.unwrap();
+                let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                serde_json::from_str::<serde_json::Value>(&received).unwrap()
+            }
+        };
+
+        let refused = join("secret_plans").await;
+        assert_eq!(refused["type"], "error");
+        assert_eq!(refused["value"], "This room name is reserved.");
+        assert_eq!(refused["code"], "reserved-room-id");
+        assert_ne!(join("new_room").await["type"], "error");
+        let refused = join("another_room").await;
+        assert_eq!(refused["value"], "Too many rooms created, try again later.");
+        // Existing rooms can still be joined
+        assert_ne!(join("new_room").await["type"], "error");
+        assert_ne!(join("general").await["type"], "error");
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}/api/paste"))
+            .body("spam")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+    }
+
+    #[tokio::test]
+    async fn test_room_id_normalization() {
+        let (addr, _) = setup_test_server().await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        // The decomposed form of é is sent to the precomposed one
+        let response = client
+            .get(format!("http://{addr}/api/rooms/Cafe%CC%81/content?x=1"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 308);
+        assert_eq!(
+            response.headers()["location"],
+            "/api/rooms/Caf%C3%A9/content?x=1"
+        );
+
+        let response = client
+            .delete(format!("http://{addr}/api/rooms/a%20b"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let join_msg = json!({ "username": "alice", "channel": "..".repeat(3) }).to_string();
+        ws.send(Message::Text(join_msg)).await.unwrap();
+        let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(parsed["type"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_username_validation() {
+        let (addr, _) = setup_test_server().await;
+        let join = |username: &'static str| async move {
+            let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+            let join_msg = json!({ "username": username, "channel": "general" }).to_string();
+            ws.send(Message::Text(join_msg)).await.unwrap();
+            let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+            let close = ws.next().await.map(|msg| msg.unwrap());
+            (parsed, close)
+        };
+
+        for username in ["SERVER", "Ser\u{200B}ver", "   "] {
+            let (parsed, close) = join(username).await;
+            assert_eq!(parsed["type"], "error");
+            assert_eq!(parsed["code"], "invalid-username");
+            assert!(matches!(close, Some(Message::Close(Some(_)))));
+        }
+        let (parsed, _) = join("x\u{7}").await;
+        assert_ne!(parsed["type"], "error");
+    }
+}
@@ -0,0 +1,376 @@
+use crate::rooms::RoomState;
+use crate::{AppState, SocketMessage, SocketMessageType};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use tokio::time::{self, Duration, Instant};
+use ts_rs::TS;
+
+/// Lines of a document, counted from 0 with `end` excluded
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct LineRange {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Claim {
+    doc_id: Option<String>,
+    lines: Range<usize>,
+    /// Last claim or edit of its holder
+    last_active: Instant,
+}
+
+/// Lines claimed by the members of a room, which only they can change: lighter than
+/// merging edits, the members keep to their own lines
+#[derive(Debug, Default)]
+pub(crate) struct LineClaims {
+    /// By username, a member claiming other lines gives up the previous ones
+    claims: HashMap<String, Claim>,
+}
+
+impl LineClaims {
+    /// Claim `lines` of a document for `username`, unless another member claimed some of them
+    pub(crate) fn claim(
+        &mut self,
+        username: &str,
+        doc_id: Option<String>,
+        lines: Range<usize>,
+        now: Instant,
+    ) -> Result<(), String> {
+        if lines.is_empty() {
+            return Err("A claim covers at least one line.".to_string());
+        }
+        if let Some((holder, _)) = self.claims.iter().find(|(holder, claim)| {
+            *holder != username
+                && claim.doc_id == doc_id
+                && claim.lines.start < lines.end
+                && lines.start < claim.lines.end
+        }) {
+            return Err(format!("{holder} claimed some of these lines."));
+        }
+        self.claims.insert(
+            username.to_string(),
+            Claim {
+                doc_id,
+                lines,
+                last_active: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Give up the lines claimed by `username`.
+    /// Returns the document they were in, if they claimed some.
+    pub(crate) fn release(&mut self, username: &str) -> Option<Option<String>> {
+        self.claims.remove(username).map(|claim| claim.doc_id)
+    }
+
+    /// Remove the claims whose holders neither claimed nor edited lines for `timeout`.
+    /// Returns their holders and documents.
+    pub(crate) fn expire(
+        &mut self,
+        now: Instant,
+        timeout: Duration,
+    ) -> Vec<(String, Option<String>)> {
+        let mut expired = Vec::new();
+        self.claims.retain(|holder, claim| {
+            let active = now.duration_since(claim.last_active) < timeout;
+            if !active {
+                expired.push((holder.clone(), claim.doc_id.clone()));
+            }
+            active
+        });
+        expired
+    }
+
+    /// Check that `username` changing a document from `old` to `new` leaves the lines of the
+    /// others alone, then move the claims below the change along with their lines
+    pub(crate) fn edit(
+        &mut self,
+        username: &str,
+        doc_id: Option<&str>,
+        old: &str,
+        new: &str,
+        now: Instant,
+    ) -> Result<(), String> {
+        let (changed, inserted) = changed_lines(old, new);
+        if changed.is_empty() && inserted == 0 {
+            return Ok(());
+        }
+        if let Some((holder, _)) = self.claims.iter().find(|(holder, claim)| {
+            *holder != username
+                && claim.doc_id.as_deref() == doc_id
+                && touches(&claim.lines, &changed)
+        }) {
+            return Err(format!("{holder} claimed the lines you changed."));
+        }
+
+        for (holder, claim) in &mut self.claims {
+            if claim.doc_id.as_deref() != doc_id {
+                continue;
+            }
+            let start = shift_start(claim.lines.start, &changed, inserted);
+            let end = shift_end(claim.lines.end, &changed, inserted);
+            claim.lines = start..end.max(start + 1);
+            if holder == username {
+                claim.last_active = now;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The lines of `old` that `new` replaces, and how many lines replace them
+fn changed_lines(old: &str, new: &str) -> (Range<usize>, usize) {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(old.len().min(new.len()) - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    (prefix..old.len() - suffix, new.len() - prefix - suffix)
+}
+
+/// Whether changing the lines `changed` changes some of the claimed `lines`, lines inserted
+/// between two claims belonging to neither
+fn touches(lines: &Range<usize>, changed: &Range<usize>) -> bool {
+    if changed.is_empty() {
+        lines.start < changed.start && changed.start < lines.end
+    } else {
+        lines.start < changed.end && changed.start < lines.end
+    }
+}
+
+/// Where the first line of a claim is once `changed` is replaced by `inserted` lines
+const fn shift_start(start: usize, changed: &Range<usize>, inserted: usize) -> usize {
+    if start >= changed.end {
+        start + inserted - (changed.end - changed.start)
+    } else if start <= changed.start {
+        start
+    } else {
+        changed.start
+    }
+}
+
+/// Where the end of a claim is once `changed` is replaced by `inserted` lines
+const fn shift_end(end: usize, changed: &Range<usize>, inserted: usize) -> usize {
+    if end <= changed.start {
+        end
+    } else if end >= changed.end {
+        end + inserted - (changed.end - changed.start)
+    } else {
+        changed.start + inserted
+    }
+}
+
+/// Tells the members that `username` claimed `lines`
+fn claimed_message(username: &str, doc_id: Option<String>, lines: &Range<usize>) -> String {
+    let range = LineRange {
+        start: lines.start,
+        end: lines.end,
+    };
+    json!(SocketMessage! {
+        doc_id: doc_id,
+        message_type: SocketMessageType::LinesClaimed,
+        value: Some(json!(range).to_string()),
+        username: username.to_string(),
+    })
+    .to_string()
+}
+
+/// Tells the members that the lines `username` claimed are free again
+fn released_message(username: &str, doc_id: Option<String>) -> String {
+    json!(SocketMessage! {
+        doc_id: doc_id,
+        message_type: SocketMessageType::LinesReleased,
+        username: username.to_string(),
+    })
+    .to_string()
+}
+
+impl RoomState {
+    /// Claim `lines` of a document for `username` and tell the members
+    pub(crate) async fn claim_lines(
+        &self,
+        username: &str,
+        doc_id: Option<String>,
+        lines: LineRange,
+    ) -> Result<(), String> {
+        // Ciphertext has no lines the server could tell apart
+        if self.encryption.is_some() {
+            return Err("Lines of encrypted rooms can't be claimed.".to_string());
+        }
+        if self.content_of(doc_id.as_deref()).await.is_none() {
+            return Err("This document doesn't exist.".to_string());
+        }
+        let lines = lines.start..lines.end;
+        self.line_claims.lock().await.claim(
+            username,
+            doc_id.clone(),
+            lines.clone(),
+            Instant::now(),
+        )?;
+        let _ = self.tx.send(claimed_message(username, doc_id, &lines));
+        Ok(())
+    }
+
+    /// Give up the lines claimed by `username`, if any, and tell the members
+    pub(crate) async fn release_lines(&self, username: &str) {
+        let released = self.line_claims.lock().await.release(username);
+        if let Some(doc_id) = released {
+            let _ = self.tx.send(released_message(username, doc_id));
+        }
+    }
+
+    /// Check that `username` editing a document to `new` leaves the lines of the others
+    /// alone, and move the claims along with the lines it adds or removes
+    pub(crate) async fn edit_lines(
+        &self,
+        username: &str,
+        doc_id: Option<&str>,
+        new: &str,
+    ) -> Result<(), String> {
+        if self.encryption.is_some() {
+            return Ok(());
+        }
+        let mut claims = self.line_claims.lock().await;
+        if claims.claims.is_empty() {
+            return Ok(());
+        }
+        let old = self.content_of(doc_id).await.unwrap_or_default();
+        claims.edit(username, doc_id, &old, new, Instant::now())
+    }
+
+    /// Messages telling a joining client the lines claimed in the room
+    pub(crate) async fn line_claim_messages(&self) -> Vec<String> {
+        self.line_claims
+            .lock()
+            .await
+            .claims
+            .iter()
+            .map(|(holder, claim)| claimed_message(holder, claim.doc_id.clone(), &claim.lines))
+            .collect()
+    }
+
+    async fn expire_line_claims(&self, timeout: Duration) {
+        let expired = self
+            .line_claims
+            .lock()
+            .await
+            .expire(Instant::now(), timeout);
+        for (holder, doc_id) in expired {
+            let _ = self.tx.send(released_message(&holder, doc_id));
+        }
+    }
+}
+
+/// Release the lines whose holders went quiet for `LINE_CLAIM_TIMEOUT_SECONDS`
+pub(crate) async fn expire_line_claims(state: Arc<AppState>) {
+    let timeout = state.config.line_claim_timeout;
+    let mut interval = time::interval(timeout.min(Duration::from_secs(10)));
+    loop {
+        interval.tick().await;
+        expire_line_claims_once(&state, timeout).await;
+    }
+}
+
+pub(crate) async fn expire_line_claims_once(state: &AppState, timeout: Duration) {
+    let rooms = state.rooms.lock().await;
+    for room in rooms.values() {
+        room.expire_line_claims(timeout).await;
+    }
+    drop(rooms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_lines, LineClaims};
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn test_changed_lines() {
+        assert_eq!(changed_lines("a\nb\nc", "a\nB\nc"), (1..2, 1));
+        assert_eq!(changed_lines("a\nb\nc", "a\nb\nb\nc"), (2..2, 1));
+        assert_eq!(changed_lines("a\nb\nc", "a\nc"), (1..2, 0));
+        assert_eq!(changed_lines("a", "a\n"), (1..1, 1));
+        assert_eq!(changed_lines("a\nb", "a\nb"), (2..2, 0));
+    }
+
+    #[test]
+    fn test_claims_dont_overlap() {
+        let now = Instant::now();
+        let mut claims = LineClaims::default();
+        claims.claim("alice", None, 2..5, now).unwrap();
+        assert!(claims.claim("bob", None, 4..6, now).is_err());
+        assert!(claims.claim("bob", None, 0..0, now).is_err());
+        claims.claim("bob", None, 5..6, now).unwrap();
+        claims
+            .claim("bob", Some("notes".to_string()), 2..5, now)
+            .unwrap();
+
+        // Claiming again replaces the previous claim
+        claims.claim("alice", None, 0..1, now).unwrap();
+        claims.claim("bob", None, 3..4, now).unwrap();
+        assert_eq!(claims.release("alice"), Some(None));
+        assert_eq!(claims.release("alice"), None);
+    }
+
+    #[test]
+    fn test_edits_keep_off_claimed_lines() {
+        let now = Instant::now();
+        let old = "0\n1\n2\n3\n4";
+        let mut claims = LineClaims::default();
+        claims.claim("alice", None, 1..3, now).unwrap();
+
+        assert!(claims
+            .edit("bob", None, old, "0\n1\n2!\n3\n4", now)
+            .is_err());
+        assert!(claims.edit("bob", None, old, "0\n2\n3\n4", now).is_err());
+        assert!(claims
+            .edit("bob", None, old, "0\n1\nnew\n2\n3\n4", now)
+            .is_err());
+        assert!(claims.edit("bob", Some("notes"), old, "", now).is_ok());
+        claims
+            .edit("alice", None, old, "0\n1!\n2\n3\n4", now)
+            .unwrap();
+
+        // Lines inserted above move the claim down, the ones added by its holder extend it
+        claims
+            .edit("bob", None, old, "new\n0\n1\n2\n3\n4", now)
+            .unwrap();
+        assert_eq!(claims.claims["alice"].lines, 2..4);
+        let old = "new\n0\n1\n2\n3\n4";
+        claims
+            .edit("alice", None, old, "new\n0\n1\n1b\n2\n3\n4", now)
+            .unwrap();
+        assert_eq!(claims.claims["alice"].lines, 2..5);
+        // Lines inserted right below are free
+        let old = "new\n0\n1\n1b\n2\n3\n4";
+        claims
+            .edit("bob", None, old, "new\n0\n1\n1b\n2\nmore\n3\n4", now)
+            .unwrap();
+        assert_eq!(claims.claims["alice"].lines, 2..5);
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Instant::now();
+        let mut claims = LineClaims::default();
+        claims.claim("alice", None, 0..1, now).unwrap();
+        claims.claim("bob", None, 1..2, now).unwrap();
+        let later = now + Duration::from_secs(30);
+        claims.edit("bob", None, "a\nb", "a\nb!", later).unwrap();
+
+        let expired = claims.expire(now + Duration::from_secs(60), Duration::from_secs(60));
+        assert_eq!(expired, vec![("alice".to_string(), None)]);
+        assert!(claims.claims.contains_key("bob"));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::paste::readable_content;
+use crate::{AppState, CustomError};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use pulldown_cmark::{html, Options, Parser};
+use std::sync::Arc;
+
+/// Scripts are stripped by `ammonia` already, the policy keeps them out should one slip
+/// through
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src https: data:";
+
+/// Markdown as HTML, without the scripts, event handlers and unsafe links it may hold
+fn render(markdown: &str) -> String {
+    // Not task lists nor footnotes, whose checkboxes and anchors `ammonia` takes out
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    ammonia::clean(&unsafe_html)
+}
+
+/// Page of the rendered content of a room. Room ids are letters, digits and `-_.~`, they
+/// need no escaping.
+fn page(room_id: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{room_id}</title>
+<style>body {{ max-width: 48rem; margin: 2rem auto; padding: 0 1rem; font-family: system-ui, sans-serif; line-height: 1.5; }} pre {{ overflow-x: auto; }} img {{ max-width: 100%; }}</style>
+</head>
+<body>
+{body}</body>
+</html>
+"#
+    )
+}
+
+/// Content of a room rendered as Markdown, for it to double as a published page
+pub(crate) async fn get_html(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    let content = readable_content(&state, &headers, &room_id).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY),
+        ],
+        page(&room_id, &render(&content)),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render("# Notes"), "<h1>Notes</h1>\n");
+        assert!(render("| a |\n|---|\n| b |").contains("<td>b</td>"));
+        assert!(render("~~old~~").contains("<del>old</del>"));
+    }
+
+    #[test]
+    fn test_render_is_sanitized() {
+        let html = render("<script>alert(1)</script>\n\n<img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+        let html = render("[link](javascript:alert(1))");
+        assert!(!html.contains("javascript:"));
+    }
+}
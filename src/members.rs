@@ -0,0 +1,431 @@
+use crate::visibility::{self, Visibility};
+use crate::{auth, check_room_owner, ensure_room_loaded, AppState, CustomError};
+use anyhow::{bail, Result};
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Role of a member of a room
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum RoomRole {
+    /// Manages the room like the account owning it
+    Owner,
+    /// Edits the room
+    Editor,
+    /// Reads the room without editing it
+    Viewer,
+}
+
+impl RoomRole {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Editor => "editor",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    fn parse(role: &str) -> Result<Self> {
+        match role {
+            "owner" => Ok(Self::Owner),
+            "editor" => Ok(Self::Editor),
+            "viewer" => Ok(Self::Viewer),
+            _ => bail!("Unknown room role {role}"),
+        }
+    }
+}
+
+/// Member of a room, as listed by `GET /api/rooms/:room_id/members`
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct RoomMember {
+    pub(crate) username: String,
+    pub(crate) role: RoomRole,
+}
+
+/// Body of `PUT /api/rooms/:room_id/members/:username`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct MemberRequest {
+    role: RoomRole,
+}
+
+/// What a user may do in a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// Not a member, but the room is not private or its password was given
+    Open,
+    Member(RoomRole),
+    Denied,
+}
+
+impl Access {
+    pub(crate) const fn can_edit(self) -> bool {
+        !matches!(self, Self::Member(RoomRole::Viewer) | Self::Denied)
+    }
+}
+
+async fn load_access(
+    db: &SqlitePool,
+    room_id: &str,
+    user_id: Option<i64>,
+    password: Option<&str>,
+) -> Result<Access> {
+    if let Some(user_id) = user_id {
+        let owner_id =
+            sqlx::query_scalar::<_, Option<i64>>("SELECT owner_id FROM rooms WHERE room_id = ?")
+                .bind(room_id)
+                .fetch_optional(db)
+                .await?
+                .flatten();
+        if owner_id == Some(user_id) {
+            return Ok(Access::Member(RoomRole::Owner));
+        }
+        let role = sqlx::query_scalar::<_, String>(
+            "SELECT role FROM room_members WHERE room_id = ? AND user_id = ?",
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+        if let Some(role) = role {
+            return Ok(Access::Member(RoomRole::parse(&role)?));
+        }
+    }
+
+    let (visibility, password_hash) = visibility::load(db, room_id).await?;
+    if visibility != Visibility::Private {
+        return Ok(Access::Open);
+    }
+    if let (Some(password), Some(password_hash)) = (password, password_hash) {
+        if auth::verify_password(password.to_string(), password_hash).await {
+            return Ok(Access::Open);
+        }
+    }
+    Ok(Access::Denied)
+}
+
+/// Access of a user to a room, `None` for anonymous users, given the password they sent.
+/// Denied when it can't be read, so that private rooms never leak.
+pub(crate) async fn access(
+    db: &SqlitePool,
+    room_id: &str,
+    user_id: Option<i64>,
+    password: Option<&str>,
+) -> Access {
+    load_access(db, room_id, user_id, password)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to read members of room {room_id}: {e:#}");
+            Access::Denied
+        })
+}
+
+/// Accounts owning a room: the one that created or claimed it, and the members with the owner role
+pub(crate) async fn owners(db: &SqlitePool, room_id: &str) -> Vec<i64> {
+    sqlx::query_scalar::<_, i64>(
+        r"
+        SELECT owner_id FROM rooms WHERE room_id = ? AND owner_id IS NOT NULL
+        UNION
+        SELECT user_id FROM room_members WHERE room_id = ? AND role = 'owner'
+        ",
+    )
+    .bind(room_id)
+    .bind(room_id)
+    .fetch_all(db)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to read owners of room {room_id}: {e}");
+        Vec::new()
+    })
+}
+
+/// Unlisted and private rooms a user, `None` if anonymous, neither owns nor is a member of
+pub(crate) async fn hidden_rooms(db: &SqlitePool, user_id: Option<i64>) -> Result<HashSet<String>> {
+    Ok(sqlx::query_scalar::<_, String>(
+        r"
+        SELECT room_id FROM rooms
+        WHERE visibility != 'public'
+        AND room_id NOT IN (SELECT room_id FROM room_members WHERE user_id = ?)
+        AND (owner_id IS NULL OR owner_id IS NOT ?)
+        ",
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect())
+}
+
+/// Members of a room, by username
+async fn list(db: &SqlitePool, room_id: &str) -> Result<Vec<RoomMember>> {
+    sqlx::query_as::<_, (String, String)>(
+        r"
+        SELECT users.username, room_members.role FROM room_members
+        JOIN users ON users.id = room_members.user_id
+        WHERE room_members.room_id = ?
+        ORDER BY users.username
+        ",
+    )
+    .bind(room_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|(username, role)| {
+        Ok(RoomMember {
+            username,
+            role: RoomRole::parse(&role)?,
+        })
+    })
+    .collect()
+}
+
+/// Add a member to a room or change their role, `None` removes them.
+/// Returns whether the account exists.
+async fn store(
+    db: &SqlitePool,
+    room_id: &str,
+    username: &str,
+    role: Option<RoomRole>,
+) -> Result<bool> {
+    let Some(user_id) = sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(db)
+        .await?
+    else {
+        return Ok(false);
+    };
+    match role {
+        Some(role) => {
+            sqlx::query(
+                r"
+                INSERT INTO room_members (room_id, user_id, role) VALUES (?, ?, ?)
+                ON CONFLICT (room_id, user_id) DO UPDATE SET role = excluded.role
+                ",
+            )
+            .bind(room_id)
+            .bind(user_id)
+            .bind(role.as_str())
+            .execute(db)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM room_members WHERE room_id = ? AND user_id = ?")
+                .bind(room_id)
+                .bind(user_id)
+                .execute(db)
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+/// Forget the members of a deleted room
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM room_members WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Load the room and make sure the request comes from one of its owners
+async fn check_room(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<(), CustomError> {
+    let mut rooms = state.rooms.lock().await;
+    let loaded = ensure_room_loaded(state, &mut rooms, room_id).await;
+    drop(rooms);
+    if !loaded {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(state, headers, room_id).await
+}
+
+/// List the members of a room, besides the account owning it
+pub(crate) async fn list_members(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+    let members = list(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to list room members: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": members
+    })))
+}
+
+/// Give an account a role in a room
+pub(crate) async fn set_member(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, username)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(body): Json<MemberRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+    // Anyone could lock the others out of an ownerless room
+    if owners(&state.db, &room_id).await.is_empty() {
+        return Err(CustomError::bad_request(
+            "Claim the room before adding members.",
+        ));
+    }
+
+    let found = store(db, &room_id, &username, Some(body.role))
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to store room member: {e:#}");
+            auth::internal_error()
+        })?;
+    if !found {
+        return Err(CustomError::not_found("Account not found."));
+    }
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": RoomMember {
+            username,
+            role: body.role,
+        }
+    })))
+}
+
+/// Remove a member of a room
+pub(crate) async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, username)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+
+    let found = store(db, &room_id, &username, None).await.map_err(|e| {
+        eprintln!("Failed to remove room member: {e:#}");
+        auth::internal_error()
+    })?;
+    if !found {
+        return Err(CustomError::not_found("Account not found."));
+    }
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": username
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{access, hidden_rooms, list, owners, store, Access, RoomMember, RoomRole};
+    use crate::database;
+
+    #[tokio::test]
+    async fn test_members() {
+        let db = database::memory().await.unwrap();
+        let mut ids = Vec::new();
+        for username in ["alice", "bob", "carol"] {
+            ids.push(
+                sqlx::query_scalar::<_, i64>(
+                    "INSERT INTO users (username, password_hash, created_at) VALUES (?, '', 0) RETURNING id",
+                )
+                .bind(username)
+                .fetch_one(&db)
+                .await
+                .unwrap(),
+            );
+        }
+        let (alice, bob, carol) = (ids[0], ids[1], ids[2]);
+        sqlx::query("INSERT INTO rooms (room_id, content, owner_id) VALUES ('team', '', ?)")
+            .bind(alice)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert!(store(&db, "team", "bob", Some(RoomRole::Viewer))
+            .await
+            .unwrap());
+        assert!(!store(&db, "team", "mallory", Some(RoomRole::Editor))
+            .await
+            .unwrap());
+        assert_eq!(
+            access(&db, "team", Some(alice), None).await,
+            Access::Member(RoomRole::Owner)
+        );
+        assert_eq!(
+            access(&db, "team", Some(bob), None).await,
+            Access::Member(RoomRole::Viewer)
+        );
+        assert!(!access(&db, "team", Some(bob), None).await.can_edit());
+        // Public until told otherwise
+        assert_eq!(access(&db, "team", Some(carol), None).await, Access::Open);
+        assert!(hidden_rooms(&db, None).await.unwrap().is_empty());
+
+        let password_hash = crate::auth::hash_password("letmein".to_string())
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE rooms SET visibility = 'private', password_hash = ? WHERE room_id = 'team'",
+        )
+        .bind(password_hash)
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(access(&db, "team", Some(carol), None).await, Access::Denied);
+        assert_eq!(
+            access(&db, "team", None, Some("guess")).await,
+            Access::Denied
+        );
+        assert_eq!(
+            access(&db, "team", None, Some("letmein")).await,
+            Access::Open
+        );
+        assert_eq!(access(&db, "elsewhere", None, None).await, Access::Open);
+
+        assert!(hidden_rooms(&db, None).await.unwrap().contains("team"));
+        assert!(hidden_rooms(&db, Some(carol))
+            .await
+            .unwrap()
+            .contains("team"));
+        assert!(hidden_rooms(&db, Some(alice)).await.unwrap().is_empty());
+        assert!(hidden_rooms(&db, Some(bob)).await.unwrap().is_empty());
+
+        store(&db, "team", "carol", Some(RoomRole::Owner))
+            .await
+            .unwrap();
+        let mut room_owners = owners(&db, "team").await;
+        room_owners.sort_unstable();
+        assert_eq!(room_owners, vec![alice, carol]);
+        assert_eq!(
+            list(&db, "team").await.unwrap(),
+            vec![
+                RoomMember {
+                    username: "bob".to_string(),
+                    role: RoomRole::Viewer
+                },
+                RoomMember {
+                    username: "carol".to_string(),
+                    role: RoomRole::Owner
+                },
+            ]
+        );
+
+        store(&db, "team", "carol", None).await.unwrap();
+        assert_eq!(access(&db, "team", Some(carol), None).await, Access::Denied);
+    }
+}
@@ -0,0 +1,185 @@
+/// Cells of the table comparing the changed parts of two versions, past that they are
+/// considered entirely different
+const MAX_COMPARED: usize = 4_000_000;
+
+/// Content of two concurrent edits of a document merged together
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Merged {
+    pub(crate) content: String,
+    /// Places changed differently by both edits, where `ours` was kept
+    pub(crate) conflicts: usize,
+}
+
+/// Split a text into words, runs of whitespace and single other characters
+fn tokenize(text: &str) -> Vec<&str> {
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            1
+        } else if c.is_whitespace() {
+            2
+        } else {
+            0
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        let current = class(c);
+        if i > start && (current == 0 || previous != Some(current)) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        previous = Some(current);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+/// For each token of `base`, the index of the same token in `other` if it is part of their
+/// longest common subsequence
+fn matches(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let prefix = base.iter().zip(other).take_while(|(a, b)| a == b).count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(other[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut matched: Vec<Option<usize>> =
+        (0..base.len()).map(|i| (i < prefix).then_some(i)).collect();
+    for i in 1..=suffix {
+        matched[base.len() - i] = Some(other.len() - i);
+    }
+
+    let base_middle = &base[prefix..base.len() - suffix];
+    let other_middle = &other[prefix..other.len() - suffix];
+    let (n, m) = (base_middle.len(), other_middle.len());
+    if n == 0 || m == 0 || n.saturating_mul(m) > MAX_COMPARED {
+        return matched;
+    }
+    // Length of the longest common subsequence of `base_middle[i..]` and `other_middle[j..]`
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    let mut lengths = vec![0_u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[at(i, j)] = if base_middle[i] == other_middle[j] {
+                lengths[at(i + 1, j + 1)] + 1
+            } else {
+                lengths[at(i + 1, j)].max(lengths[at(i, j + 1)])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if base_middle[i] == other_middle[j] {
+            matched[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[at(i + 1, j)] >= lengths[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matched
+}
+
+/// Merge two versions made from the same `base` word by word, like `diff3`.
+/// Changes made by only one of them are kept, `ours` wins where both changed the same words.
+pub(crate) fn merge(base: &str, ours: &str, theirs: &str) -> Merged {
+    if ours == base || ours == theirs {
+        return Merged {
+            content: theirs.to_string(),
+            conflicts: 0,
+        };
+    }
+    if theirs == base {
+        return Merged {
+            content: ours.to_string(),
+            conflicts: 0,
+        };
+    }
+
+    let (base, ours, theirs) = (tokenize(base), tokenize(ours), tokenize(theirs));
+    let (ours_matches, theirs_matches) = (matches(&base, &ours), matches(&base, &theirs));
+    let mut merged = Merged {
+        content: String::new(),
+        conflicts: 0,
+    };
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Left as is by both
+        let stable = (o..base.len())
+            .take_while(|&i| {
+                ours_matches[i] == Some(a + i - o) && theirs_matches[i] == Some(b + i - o)
+            })
+            .count();
+        if stable > 0 {
+            merged.content.extend(base[o..o + stable].iter().copied());
+            (o, a, b) = (o + stable, a + stable, b + stable);
+            continue;
+        }
+
+        // Changed by either, up to the next word both kept
+        let next = (o..base.len()).find_map(|i| Some((i, ours_matches[i]?, theirs_matches[i]?)));
+        let (o_end, a_end, b_end) = next.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let base_chunk = &base[o..o_end];
+        let ours_chunk = &ours[a..a_end];
+        let theirs_chunk = &theirs[b..b_end];
+        let chunk = if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            theirs_chunk
+        } else if theirs_chunk == base_chunk {
+            ours_chunk
+        } else {
+            merged.conflicts += 1;
+            ours_chunk
+        };
+        merged.content.extend(chunk.iter().copied());
+        if next.is_none() {
+            return merged;
+        }
+        (o, a, b) = (o_end, a_end, b_end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, tokenize, Merged};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("let x_1 = été;\n\n"),
+            ["let", " ", "x_1", " ", "=", " ", "été", ";", "\n\n"]
+        );
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = |content: &str, conflicts| Merged {
+            content: content.to_string(),
+            conflicts,
+        };
+        assert_eq!(
+            merge("the quick fox", "the quick brown fox", "a quick fox"),
+            merged("a quick brown fox", 0)
+        );
+        assert_eq!(
+            merge("one two three", "one 2 three", "one two three four"),
+            merged("one 2 three four", 0)
+        );
+        // Made by both
+        assert_eq!(merge("a b", "a c", "a c"), merged("a c", 0));
+        assert_eq!(
+            merge("hello world", "hello there", "hello everyone"),
+            merged("hello there", 1)
+        );
+        assert_eq!(merge("", "mine", "yours"), merged("mine", 1));
+        assert_eq!(merge("a b c", "b c", "a b c d"), merged("b c d", 0));
+    }
+}
@@ -0,0 +1,337 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Counters of the static asset pipeline
+#[derive(Debug, Default)]
+pub(crate) struct AssetMetrics {
+    hits: Mutex<HashMap<String, u64>>,
+    not_found: AtomicU64,
+    bytes_served: AtomicU64,
+}
+
+/// Snapshot of [`AssetMetrics`]
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AssetMetricsSnapshot {
+    pub(crate) hits: HashMap<String, u64>,
+    pub(crate) not_found: u64,
+    pub(crate) bytes_served: u64,
+}
+
+impl AssetMetrics {
+    /// Record a served asset, `path` must be an embedded asset to keep the map bounded
+    pub(crate) fn record_hit(&self, path: &str, bytes: usize) {
+        *self
+            .hits
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default() += 1;
+        self.bytes_served
+            .fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_not_found(&self, path: &str) {
+        println!("Asset not found: {path}");
+        self.not_found.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> AssetMetricsSnapshot {
+        AssetMetricsSnapshot {
+            hits: self.hits.lock().unwrap().clone(),
+            not_found: self.not_found.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters feeding the saturation gauges
+#[derive(Debug, Default)]
+pub(crate) struct SaturationMetrics {
+    lagged_receivers: AtomicU64,
+    skipped_messages: AtomicU64,
+    slow_consumers: AtomicU64,
+}
+
+impl SaturationMetrics {
+    /// Record a client that fell behind its room broadcast channel and missed messages
+    pub(crate) fn record_lagged(&self, skipped: u64) {
+        self.lagged_receivers.fetch_add(1, Ordering::Relaxed);
+        self.skipped_messages.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Record a client disconnected for falling behind again right after being resynced
+    pub(crate) fn record_slow_consumer(&self) {
+        self.slow_consumers.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Point in time view of how close the server is to dropping messages
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct SaturationSnapshot {
+    pub(crate) rooms: usize,
+    pub(crate) connections: usize,
+    /// Capacity of each room broadcast channel
+    pub(crate) broadcast_capacity: usize,
+    /// Highest room broadcast channel fill ratio, between 0 and 1
+    pub(crate) broadcast_fill_ratio_max: f64,
+    /// Messages queued for the slowest client of each room, summed over rooms
+    pub(crate) outbound_queue_depth_sum: usize,
+    /// Messages queued for the slowest client of the server
+    pub(crate) outbound_queue_depth_max: usize,
+    /// Rooms with changes not written to the database yet
+    pub(crate) db_write_queue_length: usize,
+}
+
+impl SaturationSnapshot {
+    /// Render in the Prometheus text exposition format
+    pub(crate) fn to_prometheus(&self, counters: &SaturationMetrics) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP partage_{name} {help}");
+            let _ = writeln!(out, "# TYPE partage_{name} {kind}");
+            let _ = writeln!(out, "partage_{name} {value}");
+        };
+
+        metric(
+            "rooms",
+            "gauge",
+            "Rooms loaded in memory.",
+            self.rooms.to_string(),
+        );
+        metric(
+            "connections",
+            "gauge",
+            "WebSocket clients connected to a room.",
+            self.connections.to_string(),
+        );
+        metric(
+            "broadcast_capacity",
+            "gauge",
+            "Capacity of each room broadcast channel.",
+            self.broadcast_capacity.to_string(),
+        );
+        metric(
+            "broadcast_fill_ratio_max",
+            "gauge",
+            "Highest room broadcast channel fill ratio, messages are dropped at 1.",
+            self.broadcast_fill_ratio_max.to_string(),
+        );
+        metric(
+            "outbound_queue_depth_sum",
+            "gauge",
+            "Messages queued for the slowest client of each room, summed over rooms.",
+            self.outbound_queue_depth_sum.to_string(),
+        );
+        metric(
+            "outbound_queue_depth_max",
+            "gauge",
+            "Messages queued for the slowest client.",
+            self.outbound_queue_depth_max.to_string(),
+        );
+        metric(
+            "db_write_queue_length",
+            "gauge",
+            "Rooms with changes waiting to be written to the database.",
+            self.db_write_queue_length.to_string(),
+        );
+        metric(
+            "lagged_receivers_total",
+            "counter",
+            "Clients that fell behind their room broadcast channel and were resynced.",
+            counters
+                .lagged_receivers
+                .load(Ordering::Relaxed)
+                .to_string(),
+        );
+        metric(
+            "skipped_messages_total",
+            "counter",
+            "Messages missed by clients falling behind their room broadcast channel.",
+            counters
+                .skipped_messages
+                .load(Ordering::Relaxed)
+                .to_string(),
+        );
+        metric(
+            "slow_consumers_total",
+            "counter",
+            "Clients disconnected for falling behind their room broadcast channel repeatedly.",
+            counters.slow_consumers.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "task_panics_total",
+            "counter",
+            "Panics caught in room and connection tasks, room tasks are restarted.",
+            crate::supervisor::task_panics().to_string(),
+        );
+
+        out
+    }
+}
+
+/// Messages received by a room, see `GET /api/admin/stats`
+#[derive(Debug)]
+pub(crate) struct RoomActivity {
+    messages: AtomicU64,
+    minutes: Mutex<MinuteCounts>,
+}
+
+/// Messages of the current minute and of the previous one, which the rate of the last
+/// 60 seconds is estimated from
+#[derive(Debug)]
+struct MinuteCounts {
+    started: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl MinuteCounts {
+    /// Move on to the minute `now` is in
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed >= MINUTE * 2 {
+            self.previous = 0;
+            self.current = 0;
+            self.started = now;
+        } else if elapsed >= MINUTE {
+            self.previous = self.current;
+            self.current = 0;
+            self.started += MINUTE;
+        }
+    }
+}
+
+impl Default for RoomActivity {
+    fn default() -> Self {
+        Self {
+            messages: AtomicU64::new(0),
+            minutes: Mutex::new(MinuteCounts {
+                started: Instant::now(),
+                current: 0,
+                previous: 0,
+            }),
+        }
+    }
+}
+
+impl RoomActivity {
+    /// Record a message received from a member
+    pub(crate) fn record_message(&self) {
+        self.record_message_at(Instant::now());
+    }
+
+    fn record_message_at(&self, now: Instant) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        let mut minutes = self.minutes.lock().unwrap();
+        minutes.advance(now);
+        minutes.current += 1;
+    }
+
+    /// Messages received since the room was loaded
+    pub(crate) fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    /// Messages received over the last minute
+    pub(crate) fn messages_per_minute(&self) -> f64 {
+        self.messages_per_minute_at(Instant::now())
+    }
+
+    /// The previous minute counts for the part of it still in the last 60 seconds
+    #[allow(clippy::cast_precision_loss)]
+    fn messages_per_minute_at(&self, now: Instant) -> f64 {
+        let mut minutes = self.minutes.lock().unwrap();
+        minutes.advance(now);
+        let elapsed =
+            now.saturating_duration_since(minutes.started).as_secs_f64() / MINUTE.as_secs_f64();
+        (minutes.previous as f64).mul_add(1.0 - elapsed, minutes.current as f64)
+    }
+}
+
+/// Lowercase hexadecimal representation of bytes
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        to_hex, AssetMetrics, RoomActivity, SaturationMetrics, SaturationSnapshot, MINUTE,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_asset_metrics() {
+        let metrics = AssetMetrics::default();
+        metrics.record_hit("index.html", 100);
+        metrics.record_hit("index.html", 100);
+        metrics.record_hit("favicon.ico", 10);
+        metrics.record_not_found("missing.js");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.hits["index.html"], 2);
+        assert_eq!(snapshot.hits["favicon.ico"], 1);
+        assert_eq!(snapshot.not_found, 1);
+        assert_eq!(snapshot.bytes_served, 210);
+    }
+
+    #[test]
+    fn test_saturation_prometheus() {
+        let counters = SaturationMetrics::default();
+        counters.record_lagged(12);
+        counters.record_slow_consumer();
+        let snapshot = SaturationSnapshot {
+            rooms: 2,
+            connections: 5,
+            broadcast_capacity: 100,
+            broadcast_fill_ratio_max: 0.25,
+            outbound_queue_depth_sum: 30,
+            outbound_queue_depth_max: 25,
+            db_write_queue_length: 1,
+        };
+
+        let text = snapshot.to_prometheus(&counters);
+        assert!(text.contains("# TYPE partage_broadcast_fill_ratio_max gauge\n"));
+        assert!(text.contains("\npartage_broadcast_fill_ratio_max 0.25\n"));
+        assert!(text.contains("\npartage_db_write_queue_length 1\n"));
+        assert!(text.contains("\npartage_lagged_receivers_total 1\n"));
+        assert!(text.contains("\npartage_skipped_messages_total 12\n"));
+        assert!(text.contains("\npartage_slow_consumers_total 1\n"));
+    }
+
+    #[test]
+    fn test_room_activity() {
+        let activity = RoomActivity::default();
+        let start = Instant::now();
+        for _ in 0..30 {
+            activity.record_message_at(start);
+        }
+        assert!((activity.messages_per_minute_at(start) - 30.0).abs() < 0.01);
+
+        // Half of the previous minute is still in the last 60 seconds
+        let later = start + MINUTE + Duration::from_secs(30);
+        activity.record_message_at(later);
+        assert!((activity.messages_per_minute_at(later) - 16.0).abs() < 0.01);
+
+        // Quiet for long enough, nothing is left
+        assert!(activity.messages_per_minute_at(later + MINUTE * 3).abs() < 0.01);
+        assert_eq!(activity.messages(), 31);
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+        assert_eq!(to_hex(&[]), "");
+    }
+}
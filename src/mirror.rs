@@ -0,0 +1,274 @@
+use crate::storage::{self, Storage};
+use crate::write_behind::Write;
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Uploads waiting for their turn at most, past that they are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Prefix of the room snapshots in the bucket
+const ROOMS: &str = "rooms";
+
+/// Prefix of the attachment blobs in the bucket
+const BLOBS: &str = "blobs";
+
+/// S3-compatible bucket the rooms and attachments are mirrored to, enabled by `S3_BUCKET`
+#[derive(Debug, Clone)]
+pub(crate) struct MirrorConfig {
+    pub(crate) bucket: String,
+    /// URL of a MinIO or other S3-compatible server, AWS if unset
+    pub(crate) endpoint: Option<String>,
+    pub(crate) region: String,
+    /// The usual `AWS_*` variables and instance credentials are used if unset
+    pub(crate) access_key_id: Option<String>,
+    pub(crate) secret_access_key: Option<String>,
+}
+
+/// Change to make to the bucket
+enum Upload {
+    Put(Path, Vec<u8>),
+    /// Every object under the path
+    Delete(Path),
+}
+
+impl Upload {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Put(path, _) | Self::Delete(path) => path,
+        }
+    }
+}
+
+/// Copy of the room snapshots and attachment blobs kept in object storage, so that a server
+/// whose disk is gone gets them back.
+/// Uploads are queued and made by a task of their own, nothing waits on the bucket.
+#[derive(Debug, Clone)]
+pub(crate) struct Mirror {
+    store: Arc<dyn ObjectStore>,
+    tx: mpsc::Sender<Upload>,
+}
+
+impl Mirror {
+    /// Mirror to the bucket of `config`
+    pub(crate) fn new(config: &MirrorConfig) -> Result<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&config.bucket)
+            .with_region(&config.region);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(access_key_id) = &config.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        let store = builder
+            .build()
+            .with_context(|| format!("Invalid S3 bucket {}", config.bucket))?;
+        Ok(Self::spawn(Arc::new(store)))
+    }
+
+    /// Start uploading to `store`
+    pub(crate) fn spawn(store: Arc<dyn ObjectStore>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(upload(store.clone(), rx));
+        Self { store, tx }
+    }
+
+    fn queue(&self, upload: Upload) {
+        match self.tx.try_send(upload) {
+            Ok(()) => {}
+            // The next write of the room uploads it again
+            Err(TrySendError::Full(upload)) => {
+                eprintln!(
+                    "Mirror queue is full, dropped the upload of {}",
+                    upload.path()
+                );
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Upload the content of a document just written to its storage
+    pub(crate) fn snapshot(&self, write: &Write) {
+        let room = Path::from_iter([ROOMS, write.room_id.as_str()]);
+        let path = match &write.doc_id {
+            None => room.child("content"),
+            Some(doc_id) => room.child("documents").child(doc_id.as_str()),
+        };
+        match serde_json::to_vec(write) {
+            Ok(bytes) => self.queue(Upload::Put(path, bytes)),
+            Err(e) => eprintln!(
+                "Failed to serialize snapshot of room {}: {e}",
+                write.room_id
+            ),
+        }
+    }
+
+    /// Upload a room stored without going through the write-behind, such as an imported one
+    pub(crate) fn snapshot_room<'a>(
+        &self,
+        room_id: &str,
+        content: &str,
+        documents: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) {
+        for write in Write::room(room_id, content, documents) {
+            self.snapshot(&write);
+        }
+    }
+
+    /// Forget the snapshots of a room
+    pub(crate) fn remove_room(&self, room_id: &str) {
+        self.queue(Upload::Delete(Path::from_iter([ROOMS, room_id])));
+    }
+
+    /// Forget the snapshot of a document of a room
+    pub(crate) fn remove_document(&self, room_id: &str, doc_id: &str) {
+        self.queue(Upload::Delete(Path::from_iter([
+            ROOMS,
+            room_id,
+            "documents",
+            doc_id,
+        ])));
+    }
+
+    /// Upload an attachment blob, stored under its hash
+    pub(crate) fn blob(&self, hash: &str, bytes: &[u8]) {
+        self.queue(Upload::Put(Path::from_iter([BLOBS, hash]), bytes.to_vec()));
+    }
+
+    /// Forget an attachment blob collected as garbage
+    pub(crate) fn remove_blob(&self, hash: &str) {
+        self.queue(Upload::Delete(Path::from_iter([BLOBS, hash])));
+    }
+
+    /// Download an attachment blob, `None` if it was never mirrored
+    pub(crate) async fn get_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        match self.store.get(&Path::from_iter([BLOBS, hash])).await {
+            Ok(object) => Ok(Some(object.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the mirrored rooms to an empty database and `storage`, returning how many were
+    /// restored
+    pub(crate) async fn restore(&self, db: &SqlitePool, storage: &dyn Storage) -> Result<usize> {
+        let objects: Vec<_> = self
+            .store
+            .list(Some(&Path::from(ROOMS)))
+            .try_collect()
+            .await?;
+        let mut writes = Vec::new();
+        for object in objects {
+            let bytes = self.store.get(&object.location).await?.bytes().await?;
+            let write: Write = serde_json::from_slice(&bytes)
+                .with_context(|| format!("Invalid snapshot {}", object.location))?;
+            writes.push(write);
+        }
+        // Rooms before their documents
+        writes.sort_by(|a, b| a.doc_id.cmp(&b.doc_id));
+        storage::store(db, storage, &writes).await?;
+        Ok(writes.iter().filter(|write| write.doc_id.is_none()).count())
+    }
+}
+
+/// Make the queued uploads one after the other, until every mirror is dropped
+async fn upload(store: Arc<dyn ObjectStore>, mut rx: mpsc::Receiver<Upload>) {
+    while let Some(upload) = rx.recv().await {
+        let result = match &upload {
+            Upload::Put(path, bytes) => store
+                .put(path, PutPayload::from(bytes.clone()))
+                .await
+                .map(|_| ()),
+            Upload::Delete(prefix) => delete(store.as_ref(), prefix).await,
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to mirror {}: {e}", upload.path());
+        }
+    }
+}
+
+async fn delete(store: &dyn ObjectStore, prefix: &Path) -> object_store::Result<()> {
+    let objects: Vec<_> = store.list(Some(prefix)).try_collect().await?;
+    for object in objects {
+        store.delete(&object.location).await?;
+    }
+    // A blob is an object of its own rather than a prefix
+    match store.delete(prefix).await {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mirror;
+    use crate::database;
+    use crate::storage::{SqliteStorage, Storage};
+    use crate::write_behind::Write;
+    use object_store::memory::InMemory;
+    use sqlx::SqlitePool;
+    use std::sync::Arc;
+    use tokio::time::{self, Duration};
+
+    async fn test_db() -> SqlitePool {
+        database::memory().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rooms_are_restored_from_the_mirror() {
+        let mirror = Mirror::spawn(Arc::new(InMemory::new()));
+        for (room_id, doc_id, content) in [
+            ("notes", None, "Hello"),
+            ("notes", Some("todo"), "Milk"),
+            ("draft/2", None, "Gone"),
+        ] {
+            mirror.snapshot(&Write {
+                room_id: room_id.to_string(),
+                doc_id: doc_id.map(str::to_string),
+                content: content.to_string(),
+            });
+        }
+        mirror.remove_room("draft/2");
+        time::sleep(Duration::from_millis(100)).await;
+
+        let db = test_db().await;
+        let storage = SqliteStorage::new(db.clone());
+        assert_eq!(mirror.restore(&db, &storage).await.unwrap(), 1);
+        assert_eq!(
+            storage.content("notes").await.unwrap().as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(storage.content("draft/2").await.unwrap(), None);
+        assert_eq!(
+            storage.documents("notes").await.unwrap(),
+            [("todo".to_string(), "Milk".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blobs_are_mirrored() {
+        let mirror = Mirror::spawn(Arc::new(InMemory::new()));
+        assert_eq!(mirror.get_blob("abcd").await.unwrap(), None);
+        mirror.blob("abcd", b"file");
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            mirror.get_blob("abcd").await.unwrap().as_deref(),
+            Some(&b"file"[..])
+        );
+
+        mirror.remove_blob("abcd");
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mirror.get_blob("abcd").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::auth::{
+    create_session, generate_token, internal_error, session_cookie, SESSION_TTL_SECS,
+};
+use crate::username;
+use crate::{unix_timestamp, AppState, CustomError};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tokio::time::{Duration, Instant};
+
+/// Logins not completed within this delay are forgotten
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// OpenID Connect provider settings
+#[derive(Debug, Clone)]
+pub(crate) struct OidcConfig {
+    pub(crate) issuer_url: String,
+    pub(crate) client_id: String,
+    pub(crate) client_secret: Option<String>,
+    /// Public URL of `/api/auth/oidc/callback`, as registered with the provider
+    pub(crate) redirect_url: String,
+    /// Claim of the identity token used as username
+    pub(crate) username_claim: String,
+}
+
+/// Subset of the provider discovery document
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+impl ProviderMetadata {
+    /// Make sure the document is the one of the configured issuer, and that its endpoints
+    /// are reached over TLS, which `verify_id_token` relies on
+    fn check(&self, issuer_url: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.issuer == issuer_url,
+            "issuer {} is not OIDC_ISSUER_URL",
+            self.issuer
+        );
+        check_https(&self.authorization_endpoint)?;
+        check_https(&self.token_endpoint)
+    }
+}
+
+/// Make sure a URL of the provider is `https`, or `http` on the loopback for local providers
+pub(crate) fn check_https(url: &str) -> anyhow::Result<()> {
+    let url = reqwest::Url::parse(url)?;
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    anyhow::ensure!(
+        url.scheme() == "https" || (url.scheme() == "http" && loopback),
+        "{url} is not https"
+    );
+    Ok(())
+}
+
+#[derive(Debug)]
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    created: Instant,
+}
+
+/// OpenID Connect client, for the authorization code flow with PKCE
+#[derive(Debug)]
+pub(crate) struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    /// Logins in progress, keyed by their `state` parameter
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    pub(crate) fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetch the discovery document once
+    async fn metadata(&self) -> Result<&ProviderMetadata, CustomError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer_url.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                metadata.check(&self.config.issuer_url)?;
+                anyhow::Ok(metadata)
+            })
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to discover OIDC provider: {e:#}");
+                CustomError::new(StatusCode::BAD_GATEWAY, "Identity provider unavailable.")
+            })
+    }
+}
+
+/// Routes nested under `/api/auth/oidc`
+pub(crate) fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+}
+
+fn client(state: &AppState) -> Result<&OidcClient, CustomError> {
+    state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| CustomError::not_found("OIDC login is not configured."))
+}
+
+/// Redirect to the identity provider
+async fn login(State(state): State<Arc<AppState>>) -> Result<Redirect, CustomError> {
+    let client = client(&state)?;
+    let metadata = client.metadata().await?;
+
+    let csrf_state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", client.config.client_id.as_str()),
+            ("redirect_uri", client.config.redirect_url.as_str()),
+            ("scope", "openid profile email"),
+            ("state", csrf_state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| {
+        eprintln!("Invalid OIDC authorization endpoint: {e}");
+        internal_error()
+    })?;
+
+    let mut pending = client.pending.lock().unwrap();
+    pending.retain(|_, login| login.created.elapsed() < PENDING_LOGIN_TTL);
+    pending.insert(
+        csrf_state,
+        PendingLogin {
+            nonce,
+            code_verifier,
+            created: Instant::now(),
+        },
+    );
+    drop(pending);
+
+    Ok(Redirect::to(url.as_str()))
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Complete the login: exchange the code, check the identity token and open a session
+async fn callback(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, CustomError> {
+    let client = client(&state)?;
+    let db = &state.db;
+
+    let pending = client.pending.lock().unwrap().remove(&query.state);
+    let Some(pending) = pending.filter(|login| login.created.elapsed() < PENDING_LOGIN_TTL) else {
+        return Err(CustomError::bad_request(
+            "Unknown or expired login, try again.",
+        ));
+    };
+    if let Some(error) = query.error {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            format!("Login refused by the identity provider: {error}"),
+        ));
+    }
+    let Some(code) = query.code else {
+        return Err(CustomError::bad_request("Missing authorization code."));
+    };
+
+    let metadata = client.metadata().await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", client.config.redirect_url.as_str()),
+        ("client_id", client.config.client_id.as_str()),
+        ("code_verifier", pending.code_verifier.as_str()),
+    ];
+    if let Some(secret) = &client.config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let tokens: TokenResponse = client
+        .http
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| {
+            eprintln!("OIDC token exchange failed: {e}");
+            CustomError::new(StatusCode::BAD_GATEWAY, "Identity provider unavailable.")
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            eprintln!("Invalid OIDC token response: {e}");
+            CustomError::new(
+                StatusCode::BAD_GATEWAY,
+                "Invalid identity provider response.",
+            )
+        })?;
+
+    let claims = verify_id_token(
+        &tokens.id_token,
+        &client.config.issuer_url,
+        &client.config.client_id,
+        &pending.nonce,
+    )
+    .map_err(|reason| {
+        eprintln!("Rejected OIDC identity token: {reason}");
+        CustomError::new(StatusCode::UNAUTHORIZED, "Invalid identity token.")
+    })?;
+
+    let Some(subject) = claims["sub"].as_str() else {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid identity token.",
+        ));
+    };
+    let username = claims[client.config.username_claim.as_str()]
+        .as_str()
+        .unwrap_or(subject)
+        .trim()
+        .to_string();
+
+    let user_id = find_or_create_user(db, subject, &username).await?;
+    let token = create_session(db, user_id).await?;
+
+    println!("OIDC login: {username}");
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            session_cookie(&state.config, &token, SESSION_TTL_SECS),
+        )],
+        Redirect::to(&format!("{}/", state.config.base_path)),
+    )
+        .into_response())
+}
+
+/// Decode the claims of an identity token and check them.
+/// The token comes straight from the token endpoint over TLS, so per OIDC Core 3.1.3.7
+/// the TLS validation of the provider stands in for the signature check: see
+/// [`ProviderMetadata::check`].
+fn verify_id_token(
+    id_token: &str,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<Value, String> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| "malformed token".to_string())?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|e| format!("invalid encoding: {e}"))?;
+    let claims: Value =
+        serde_json::from_slice(&payload).map_err(|e| format!("invalid claims: {e}"))?;
+
+    if claims["iss"].as_str() != Some(issuer) {
+        return Err(format!("unexpected issuer {}", claims["iss"]));
+    }
+    let audience_ok = match &claims["aud"] {
+        Value::String(audience) => audience == client_id,
+        Value::Array(audiences) => audiences.iter().any(|a| a.as_str() == Some(client_id)),
+        _ => false,
+    };
+    if !audience_ok {
+        return Err(format!("unexpected audience {}", claims["aud"]));
+    }
+    if claims["exp"].as_i64().unwrap_or(0) <= unix_timestamp() {
+        return Err("token expired".to_string());
+    }
+    if claims["nonce"].as_str() != Some(nonce) {
+        return Err("nonce mismatch".to_string());
+    }
+
+    Ok(claims)
+}
+
+/// Get the local account linked to an identity, creating it on first login
+async fn find_or_create_user(
+    db: &SqlitePool,
+    subject: &str,
+    username: &str,
+) -> Result<i64, CustomError> {
+    let existing = sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE oidc_subject = ?")
+        .bind(subject)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read user from database: {e}");
+            internal_error()
+        })?;
+    if let Some(user_id) = existing {
+        return Ok(user_id);
+    }
+
+    let username = username::sanitize(username).map_err(CustomError::bad_request)?;
+
+    // No password: these accounts can only log in through the provider
+    match sqlx::query(
+        "INSERT INTO users (username, password_hash, created_at, oidc_subject) VALUES (?, '', ?, ?)",
+    )
+    .bind(username)
+    .bind(unix_timestamp())
+    .bind(subject)
+    .execute(db)
+    .await
+    {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(CustomError::new(
+            StatusCode::CONFLICT,
+            "Username already taken by another account.",
+        )),
+        Err(e) => {
+            eprintln!("Failed to create user: {e}");
+            Err(internal_error())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_https, verify_id_token, ProviderMetadata};
+    use crate::unix_timestamp;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use serde_json::json;
+
+    fn unsigned_token(claims: &serde_json::Value) -> String {
+        format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn test_verify_id_token() {
+        let valid = json!({
+            "iss": "https://idp.example.com",
+            "aud": ["partage", "other"],
+            "exp": unix_timestamp() + 60,
+            "nonce": "n0nce",
+            "sub": "42",
+        });
+        let check = |claims: &serde_json::Value| {
+            verify_id_token(
+                &unsigned_token(claims),
+                "https://idp.example.com",
+                "partage",
+                "n0nce",
+            )
+        };
+
+        assert_eq!(check(&valid).unwrap()["sub"], "42");
+
+        let mut wrong_issuer = valid.clone();
+        wrong_issuer["iss"] = json!("https://evil.example.com");
+        assert!(check(&wrong_issuer).is_err());
+
+        let mut wrong_audience = valid.clone();
+        wrong_audience["aud"] = json!("other");
+        assert!(check(&wrong_audience).is_err());
+
+        let mut expired = valid.clone();
+        expired["exp"] = json!(unix_timestamp() - 1);
+        assert!(check(&expired).is_err());
+
+        let mut replayed = valid;
+        replayed["nonce"] = json!("other");
+        assert!(check(&replayed).is_err());
+
+        assert!(verify_id_token("garbage", "", "", "").is_err());
+    }
+
+    #[test]
+    fn test_provider_is_reached_over_tls() {
+        assert!(check_https("https://idp.example.com/token").is_ok());
+        assert!(check_https("http://localhost:8080/token").is_ok());
+        assert!(check_https("http://[::1]/token").is_ok());
+        assert!(check_https("http://idp.example.com/token").is_err());
+        assert!(check_https("http://localhost.evil.example.com/token").is_err());
+
+        let metadata = ProviderMetadata {
+            issuer: "https://idp.example.com".to_string(),
+            authorization_endpoint: "https://idp.example.com/authorize".to_string(),
+            token_endpoint: "https://idp.example.com/token".to_string(),
+        };
+        assert!(metadata.check("https://idp.example.com").is_ok());
+        assert!(metadata.check("https://other.example.com").is_err());
+        let downgraded = ProviderMetadata {
+            token_endpoint: "http://idp.example.com/token".to_string(),
+            ..metadata
+        };
+        assert!(downgraded.check("https://idp.example.com").is_err());
+    }
+}
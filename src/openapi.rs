@@ -0,0 +1,472 @@
+use crate::AppState;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use serde_json::{json, Map, Value};
+use std::sync::{Arc, OnceLock};
+
+/// Operation of the REST API, documented in the OpenAPI specification
+struct Operation {
+    method: &'static str,
+    /// OpenAPI path, with `{name}` parameters
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    /// Schema of the JSON request body
+    body: Option<Value>,
+    /// Status of the successful response
+    status: u16,
+    /// Schema of the successful response
+    response: Value,
+    /// Query parameters, by name, with their schema
+    query: Vec<(&'static str, Value)>,
+}
+
+impl Operation {
+    fn new(
+        method: &'static str,
+        path: &'static str,
+        tag: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        Self {
+            method,
+            path,
+            tag,
+            summary,
+            body: None,
+            status: 200,
+            response: success(json!({})),
+            query: Vec::new(),
+        }
+    }
+
+    fn body(mut self, schema: Value) -> Self {
+        self.body = Some(schema);
+        self
+    }
+
+    fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    fn response(mut self, schema: Value) -> Self {
+        self.response = schema;
+        self
+    }
+
+    fn query(mut self, name: &'static str, schema: Value) -> Self {
+        self.query.push((name, schema));
+        self
+    }
+
+    /// Path parameters, in order of appearance
+    fn path_parameters(&self) -> Vec<&'static str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .collect()
+    }
+
+    fn to_json(&self) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path_parameters()
+            .into_iter()
+            .map(|name| {
+                json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } })
+            })
+            .collect();
+        parameters.extend(self.query.iter().map(|(name, schema)| {
+            json!({ "name": name, "in": "query", "required": false, "schema": schema })
+        }));
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "parameters": parameters,
+            "responses": {
+                self.status.to_string(): {
+                    "description": "Success",
+                    "content": { "application/json": { "schema": self.response } },
+                },
+                "default": {
+                    "description": "Error",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+                },
+            },
+        });
+        if let Some(body) = &self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body } },
+            });
+        }
+        if self.tag == "admin" {
+            operation["security"] = json!([{ "adminToken": [] }]);
+        }
+        operation
+    }
+}
+
+/// `{"type": "success", "value": ...}` envelope of most responses
+fn success(value: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": ["type", "value"],
+        "properties": {
+            "type": { "type": "string", "enum": ["success"] },
+            "value": value,
+        },
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// Object schema with required string properties
+fn strings(names: &[&str]) -> Value {
+    let properties: Map<String, Value> = names
+        .iter()
+        .map(|name| ((*name).to_string(), json!({ "type": "string" })))
+        .collect();
+    json!({ "type": "object", "required": names, "properties": properties })
+}
+
+#[allow(clippy::too_many_lines)]
+fn operations() -> Vec<Operation> {
+    let string = json!({ "type": "string" });
+    let integer = json!({ "type": "integer" });
+    let visibility = json!({ "type": "string", "enum": ["public", "unlisted", "private"] });
+    vec![
+        Operation::new("get", "/api/rooms", "rooms", "List the rooms")
+            .query("search", string.clone())
+            .query("sort", json!({ "type": "string", "enum": ["id", "users", "updated_at"] }))
+            .query("page", integer.clone())
+            .query("per_page", integer.clone())
+            .query("include", json!({ "type": "string", "enum": ["archived"] }))
+            .response(json!({ "type": "array", "items": schema_ref("Room") })),
+        Operation::new("delete", "/api/rooms/{room_id}", "rooms", "Move a room to the trash")
+            .query("dry_run", json!({ "type": "boolean" })),
+        Operation::new("get", "/api/rooms/trash", "rooms", "List the removed rooms that can be restored")
+            .response(success(json!({ "type": "array", "items": { "type": "object" } }))),
+        Operation::new("post", "/api/rooms/{room_id}/restore", "rooms", "Take a room out of the trash")
+            .response(success(string.clone())),
+        Operation::new("post", "/api/rooms/{room_id}/archive", "rooms", "Move an unused room out of memory, keeping its content")
+            .response(success(string.clone())),
+        Operation::new("post", "/api/rooms/{room_id}/unarchive", "rooms", "Take a room out of the archive")
+            .response(success(string.clone())),
+        Operation::new("patch", "/api/rooms/{room_id}", "rooms", "Rename a room")
+            .body(strings(&["id"]))
+            .response(success(string.clone())),
+        Operation::new("post", "/api/rooms/import", "rooms", "Import a room exported by GET /api/rooms/{room_id}/export")
+            .body(json!({ "type": "object" }))
+            .status(201)
+            .response(success(string.clone())),
+        Operation::new("get", "/api/rooms/{room_id}/export", "rooms", "Export a room with its documents and settings")
+            .response(json!({ "type": "object" })),
+        Operation::new("get", "/api/rooms/{room_id}/download", "rooms", "Download the content of a room as a file")
+            .query("format", json!({ "type": "string", "enum": ["txt", "md"] }))
+            .response(json!({ "type": "string" })),
+        Operation::new("post", "/api/rooms/{room_id}/merge", "rooms", "Append another room to this one and remove it")
+            .body(strings(&["source"])),
+        Operation::new("post", "/api/rooms/{room_id}/format", "rooms", "Format the content of a room")
+            .body(json!({ "type": "object", "properties": { "formatter": string } }))
+            .response(success(string.clone())),
+        Operation::new("get", "/api/rooms/{room_id}/content", "rooms", "Get the content of a room as plain text, its revision as ETag, or the chunk of length bytes from offset")
+            .query("offset", integer.clone())
+            .query("length", integer.clone())
+            .response(json!({ "type": "string" })),
+        Operation::new("put", "/api/rooms/{room_id}/content", "rooms", "Replace the content of a room with the request body, if still at the revision given with If-Match")
+            .response(success(integer.clone())),
+        Operation::new("get", "/api/rooms/{room_id}/history", "rooms", "List the saved versions of the content of a room, the latest first")
+            .response(success(json!({ "type": "array", "items": schema_ref("HistoryVersion") }))),
+        Operation::new("get", "/api/rooms/{room_id}/diff", "rooms", "Get a unified diff between two saved versions of a room, or from one to the current content")
+            .query("from", integer.clone())
+            .query("to", integer.clone())
+            .response(json!({ "type": "string", "description": "text/x-diff" })),
+        Operation::new("get", "/api/rooms/{room_id}/checkpoints", "rooms", "List the checkpoints of a room, the latest first")
+            .response(success(json!({ "type": "array", "items": schema_ref("Checkpoint") }))),
+        Operation::new("post", "/api/rooms/{room_id}/checkpoints", "rooms", "Save the current content of a room under a label")
+            .body(strings(&["label"]))
+            .response(success(schema_ref("Checkpoint"))),
+        Operation::new("post", "/api/rooms/{room_id}/checkpoints/{checkpoint_id}/restore", "rooms", "Put the content of a checkpoint back")
+            .response(success(integer.clone())),
+        Operation::new("delete", "/api/rooms/{room_id}/checkpoints/{checkpoint_id}", "rooms", "Remove a checkpoint"),
+        Operation::new("post", "/api/rooms/{room_id}/claim", "rooms", "Become the owner of a room without one"),
+        Operation::new("get", "/api/rooms/{room_id}/freeze", "rooms", "Get the freeze schedule of a room"),
+        Operation::new("put", "/api/rooms/{room_id}/freeze", "rooms", "Set the freeze schedule of a room")
+            .body(json!({ "type": "object" })),
+        Operation::new("get", "/api/rooms/{room_id}/auto-clear", "rooms", "Get the auto-clear delay of a room"),
+        Operation::new("put", "/api/rooms/{room_id}/auto-clear", "rooms", "Set the auto-clear delay of a room")
+            .body(json!({ "type": "object", "properties": { "minutes": { "type": ["integer", "null"] } } })),
+        Operation::new("get", "/api/rooms/{room_id}/burn", "rooms", "Get when the content of a room is wiped"),
+        Operation::new("put", "/api/rooms/{room_id}/burn", "rooms", "Wipe the content of a room once read, or once everyone left")
+            .body(json!({ "type": "object", "properties": { "after": { "type": ["string", "null"], "enum": ["read", "leave", null] } } })),
+        Operation::new("put", "/api/rooms/{room_id}/pin", "rooms", "Pin a room for the current user")
+            .body(json!({ "type": "object", "properties": { "position": integer } })),
+        Operation::new("delete", "/api/rooms/{room_id}/pin", "rooms", "Unpin a room for the current user"),
+        Operation::new("put", "/api/rooms/{room_id}/syntax-language", "rooms", "Set the highlighting language of a room")
+            .body(json!({ "type": "object", "properties": { "language": { "type": ["string", "null"] } } })),
+        Operation::new("get", "/api/rooms/{room_id}/language", "rooms", "Get the detected type and language of a room"),
+        Operation::new("patch", "/api/rooms/{room_id}/language", "rooms", "Override the detected type or language of a room")
+            .body(json!({ "type": "object" })),
+        Operation::new("get", "/api/rooms/{room_id}/events", "rooms", "Stream the contents and presence of a room as Server-Sent Events")
+            .response(json!({ "type": "string", "description": "text/event-stream" })),
+        Operation::new("get", "/api/rooms/{room_id}/documents", "documents", "List the documents of a room"),
+        Operation::new("delete", "/api/rooms/{room_id}/documents/{doc_id}", "documents", "Remove a document"),
+        Operation::new("post", "/api/rooms/{room_id}/files", "files", "Attach a file to a room, as multipart form data")
+            .status(201),
+        Operation::new("get", "/api/rooms/{room_id}/files/{file_id}", "files", "Download an attached file")
+            .response(json!({ "type": "string", "format": "binary" })),
+        Operation::new("get", "/api/rooms/{room_id}/webhooks", "webhooks", "List the webhooks of a room")
+            .response(success(json!({ "type": "array", "items": schema_ref("RoomWebhook") }))),
+        Operation::new("post", "/api/rooms/{room_id}/webhooks", "webhooks", "Register a webhook called for the events of a room")
+            .body(strings(&["url"]))
+            .status(201)
+            .response(success(schema_ref("RoomWebhook"))),
+        Operation::new("delete", "/api/rooms/{room_id}/webhooks/{webhook_id}", "webhooks", "Remove a webhook of a room")
+            .response(success(integer.clone())),
+        Operation::new("get", "/api/rooms/{room_id}/visibility", "members", "Get who can find and join a room")
+            .response(success(schema_ref("RoomVisibility"))),
+        Operation::new("put", "/api/rooms/{room_id}/visibility", "members", "Make a room public, unlisted or private, with an optional password")
+            .body(json!({
+                "type": "object",
+                "required": ["visibility"],
+                "properties": { "visibility": visibility.clone(), "password": string.clone() },
+            }))
+            .response(success(schema_ref("RoomVisibility"))),
+        Operation::new("get", "/api/rooms/{room_id}/members", "members", "List the members of a room")
+            .response(success(json!({ "type": "array", "items": schema_ref("RoomMember") }))),
+        Operation::new("put", "/api/rooms/{room_id}/members/{username}", "members", "Give an account a role in a room")
+            .body(strings(&["role"]))
+            .response(success(schema_ref("RoomMember"))),
+        Operation::new("delete", "/api/rooms/{room_id}/members/{username}", "members", "Remove a member of a room")
+            .response(success(string.clone())),
+        Operation::new("post", "/api/paste", "rooms", "Create a room from the request body, answering its URL")
+            .query("burn", json!({ "type": "string", "enum": ["read", "leave"] }))
+            .status(201)
+            .response(json!({ "type": "string" })),
+        Operation::new("post", "/api/blobs", "blobs", "Store an encrypted payload until it expires")
+            .query("ttl", integer.clone())
+            .status(201)
+            .response(success(json!({ "type": "object", "properties": { "id": string, "expires_at": integer } }))),
+        Operation::new("get", "/api/blobs/{blob_id}", "blobs", "Get an encrypted payload")
+            .response(json!({ "type": "string", "format": "binary" })),
+        Operation::new("get", "/api/search", "rooms", "Search the contents of the rooms, with snippets of the matches")
+            .query("q", string.clone())
+            .response(success(json!({ "type": "array", "items": schema_ref("SearchResult") }))),
+        Operation::new("get", "/r/{room_id}/raw", "rooms", "Get the content of a room as plain text")
+            .response(json!({ "type": "string" })),
+        Operation::new("get", "/r/{room_id}/html", "rooms", "Get the content of a room rendered as Markdown")
+            .response(json!({ "type": "string" })),
+        Operation::new("post", "/api/auth/register", "auth", "Create an account")
+            .body(strings(&["username", "password"]))
+            .status(201),
+        Operation::new("post", "/api/auth/login", "auth", "Log in, setting the session cookie")
+            .body(strings(&["username", "password"])),
+        Operation::new("post", "/api/auth/logout", "auth", "Log out"),
+        Operation::new("get", "/api/auth/me", "auth", "Get the current account")
+            .response(json!({ "type": "object", "properties": { "id": integer, "username": string } })),
+        Operation::new("get", "/api/admin/connections", "admin", "List the connected clients")
+            .response(json!({ "type": "array", "items": { "type": "object" } })),
+        Operation::new("get", "/api/admin/sessions", "admin", "List the connections recorded in the database")
+            .response(json!({ "type": "array", "items": { "type": "object" } })),
+        Operation::new("get", "/api/admin/metrics", "admin", "Get the metrics in the Prometheus text format")
+            .response(json!({ "type": "string" })),
+        Operation::new("get", "/api/admin/assets", "admin", "Get the static asset statistics"),
+        Operation::new("delete", "/api/admin/rooms/{room_id}", "admin", "Remove a room whatever its number of users"),
+        Operation::new("post", "/api/admin/rooms/{room_id}/kick", "admin", "Disconnect a user from a room")
+            .body(strings(&["username"])),
+        Operation::new("post", "/api/admin/rooms/{room_id}/mute", "admin", "Drop what a user writes to a room for a while")
+            .body(json!({ "type": "object", "required": ["username"], "properties": { "username": string, "minutes": integer } }))
+            .response(success(integer.clone())),
+        Operation::new("delete", "/api/admin/rooms/{room_id}/mute/{username}", "admin", "Let a muted user write again"),
+        Operation::new("put", "/api/admin/rooms/{room_id}/owner", "admin", "Reassign a room")
+            .body(json!({ "type": "object", "properties": { "username": { "type": ["string", "null"] } } })),
+        Operation::new("put", "/api/admin/rooms/{room_id}/pin", "admin", "Pin a room for everyone")
+            .body(json!({ "type": "object", "properties": { "position": integer } })),
+        Operation::new("delete", "/api/admin/rooms/{room_id}/pin", "admin", "Unpin a room for everyone"),
+        Operation::new("post", "/api/admin/announce", "admin", "Send an announcement to every room")
+            .body(json!({
+                "type": "object",
+                "required": ["message"],
+                "properties": {
+                    "message": string,
+                    "severity": { "type": "string", "enum": ["info", "warning", "critical"] },
+                    "expires_in": integer,
+                },
+            })),
+        Operation::new("post", "/api/admin/attachments/gc", "admin", "Remove the unreferenced attachment blobs"),
+        Operation::new("get", "/api/admin/backup", "admin", "Download a backup of the database")
+            .response(json!({ "type": "string", "format": "binary" })),
+        Operation::new("delete", "/api/admin/rooms/{room_id}/history", "admin", "Forget the saved versions of a room")
+            .query("dry_run", json!({ "type": "boolean" })),
+        Operation::new("get", "/api/admin/bans", "admin", "List the banned IP addresses")
+            .response(json!({ "type": "array", "items": { "type": "object" } })),
+        Operation::new("post", "/api/admin/bans", "admin", "Ban an IP address, disconnecting its clients")
+            .body(json!({
+                "type": "object",
+                "required": ["ip"],
+                "properties": { "ip": string.clone(), "reason": string.clone() },
+            })),
+        Operation::new("delete", "/api/admin/bans/{ip}", "admin", "Lift the ban of an IP address"),
+    ]
+}
+
+/// OpenAPI 3.1 specification of the REST API
+fn specification() -> Value {
+    let mut paths = Map::new();
+    for operation in operations() {
+        let path = paths.entry(operation.path).or_insert_with(|| json!({}));
+        path[operation.method] = operation.to_json();
+    }
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Partage",
+            "description": "Rooms sharing a text content edited in real time over the `/ws` WebSocket.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": strings(&["error"]),
+                "Room": {
+                    "type": "object",
+                    "required": ["id", "users", "persistence_degraded", "encrypted", "content_length"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "users": { "type": "array", "items": { "type": "string" } },
+                        "persistence_degraded": { "type": "boolean" },
+                        "encrypted": { "type": "boolean" },
+                        "language": { "type": ["string", "null"] },
+                        "pin": {
+                            "type": ["object", "null"],
+                            "properties": {
+                                "scope": { "type": "string", "enum": ["everyone", "me"] },
+                                "position": { "type": "integer" },
+                            },
+                        },
+                        "created_at": { "type": ["integer", "null"] },
+                        "updated_at": { "type": ["integer", "null"] },
+                        "content_length": { "type": "integer" },
+                    },
+                },
+                "HistoryVersion": {
+                    "type": "object",
+                    "required": ["id", "created_at", "size"],
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "created_at": { "type": "integer" },
+                        "size": { "type": "integer" },
+                    },
+                },
+                "Checkpoint": {
+                    "type": "object",
+                    "required": ["id", "label", "created_by", "created_at", "size"],
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "label": { "type": "string" },
+                        "created_by": { "type": ["string", "null"] },
+                        "created_at": { "type": "integer" },
+                        "size": { "type": "integer" },
+                    },
+                },
+                "SearchResult": {
+                    "type": "object",
+                    "required": ["room_id", "snippet"],
+                    "properties": {
+                        "room_id": { "type": "string" },
+                        "snippet": { "type": "string" },
+                    },
+                },
+                "RoomVisibility": {
+                    "type": "object",
+                    "required": ["visibility", "password"],
+                    "properties": {
+                        "visibility": { "type": "string", "enum": ["public", "unlisted", "private"] },
+                        "password": { "type": "boolean" },
+                    },
+                },
+                "RoomMember": {
+                    "type": "object",
+                    "required": ["username", "role"],
+                    "properties": {
+                        "username": { "type": "string" },
+                        "role": { "type": "string", "enum": ["owner", "editor", "viewer"] },
+                    },
+                },
+                "RoomWebhook": {
+                    "type": "object",
+                    "required": ["id", "url", "created_at"],
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "url": { "type": "string" },
+                        "created_at": { "type": "integer" },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" },
+                "session": { "type": "apiKey", "in": "cookie", "name": crate::auth::SESSION_COOKIE },
+            },
+        },
+    })
+}
+
+/// `GET /api/openapi.json`
+pub(crate) async fn openapi_json() -> Response {
+    static SPECIFICATION: OnceLock<String> = OnceLock::new();
+    let body = SPECIFICATION.get_or_init(|| specification().to_string());
+    ([(header::CONTENT_TYPE, "application/json")], body.as_str()).into_response()
+}
+
+/// `GET /api/docs`, Swagger UI browsing the specification
+pub(crate) async fn swagger_ui(State(state): State<Arc<AppState>>) -> Html<String> {
+    let base_path = &state.config.base_path;
+    Html(format!(
+        r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Partage API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({{ url: "{base_path}/api/openapi.json", dom_id: "#swagger-ui" }})</script>
+</body>
+</html>
+"##
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{operations, specification};
+
+    #[test]
+    fn test_specification() {
+        let spec = specification();
+        assert_eq!(spec["openapi"], "3.1.0");
+        let rooms = &spec["paths"]["/api/rooms/{room_id}"];
+        assert!(rooms["delete"].is_object());
+        assert_eq!(rooms["patch"]["parameters"][0]["name"], "room_id");
+        assert!(rooms["patch"]["requestBody"].is_object());
+        assert_eq!(
+            spec["paths"]["/api/admin/backup"]["get"]["security"][0]["adminToken"],
+            serde_json::json!([])
+        );
+
+        // Every operation is documented once
+        let mut seen = std::collections::HashSet::new();
+        for operation in operations() {
+            assert!(seen.insert((operation.method, operation.path)));
+            assert!(operation.path.starts_with('/'));
+        }
+    }
+}
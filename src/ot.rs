@@ -0,0 +1,738 @@
+use crate::documents::MAIN_DOCUMENT;
+use crate::merge::{self, Merged};
+use crate::{AppState, RoomState};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use zeroize::Zeroize;
+
+/// Operations kept to transform those made on older revisions.
+/// Clients further behind get a snapshot of the document instead.
+const MAX_HISTORY: usize = 1000;
+
+/// Contents of the previous revisions kept to merge the whole-document edits made on them
+const MAX_BASES: usize = 16;
+
+/// Component of an operation, lengths count Unicode code points.
+/// On the wire, retains are positive integers, deletes negative ones and inserts strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "WireComponent", into = "WireComponent")]
+pub(crate) enum Component {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum WireComponent {
+    Skip(i64),
+    Insert(String),
+}
+
+impl TryFrom<WireComponent> for Component {
+    type Error = String;
+
+    fn try_from(component: WireComponent) -> Result<Self, Self::Error> {
+        let length = |n: i64| usize::try_from(n.unsigned_abs()).map_err(|e| e.to_string());
+        match component {
+            WireComponent::Skip(0) => Err("Empty operation component.".to_string()),
+            WireComponent::Skip(n) if n > 0 => Ok(Self::Retain(length(n)?)),
+            WireComponent::Skip(n) => Ok(Self::Delete(length(n)?)),
+            WireComponent::Insert(text) => Ok(Self::Insert(text)),
+        }
+    }
+}
+
+impl From<Component> for WireComponent {
+    fn from(component: Component) -> Self {
+        let skip = |n: usize| i64::try_from(n).unwrap_or(i64::MAX);
+        match component {
+            Component::Retain(n) => Self::Skip(skip(n)),
+            Component::Delete(n) => Self::Skip(-skip(n)),
+            Component::Insert(text) => Self::Insert(text),
+        }
+    }
+}
+
+/// Edit of a whole document: it retains, deletes or inserts text from start to end
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<Component>", into = "Vec<Component>")]
+pub(crate) struct Operation {
+    components: Vec<Component>,
+}
+
+impl From<Vec<Component>> for Operation {
+    fn from(components: Vec<Component>) -> Self {
+        let mut operation = Self::default();
+        for component in components {
+            operation.push(component);
+        }
+        operation
+    }
+}
+
+impl From<Operation> for Vec<Component> {
+    fn from(operation: Operation) -> Self {
+        operation.components
+    }
+}
+
+impl Operation {
+    /// Replace `old` by `new`, keeping their common prefix and suffix
+    pub(crate) fn replace(old: &str, new: &str) -> Self {
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut operation = Self::default();
+        operation.push(Component::Retain(prefix));
+        operation.push(Component::Delete(old.len() - prefix - suffix));
+        operation.push(Component::Insert(
+            new[prefix..new.len() - suffix].iter().collect(),
+        ));
+        operation.push(Component::Retain(suffix));
+        operation
+    }
+
+    /// Append a component, merging it with the previous ones.
+    /// Inserts go before deletes, so that equivalent operations are equal.
+    fn push(&mut self, component: Component) {
+        match component {
+            Component::Retain(0) | Component::Delete(0) => {}
+            Component::Insert(text) if text.is_empty() => {}
+            Component::Retain(n) => match self.components.last_mut() {
+                Some(Component::Retain(last)) => *last += n,
+                _ => self.components.push(Component::Retain(n)),
+            },
+            Component::Delete(n) => match self.components.last_mut() {
+                Some(Component::Delete(last)) => *last += n,
+                _ => self.components.push(Component::Delete(n)),
+            },
+            Component::Insert(text) => {
+                let mut at = self.components.len();
+                if matches!(self.components.last(), Some(Component::Delete(_))) {
+                    at -= 1;
+                }
+                match at.checked_sub(1).and_then(|i| self.components.get_mut(i)) {
+                    Some(Component::Insert(previous)) => previous.push_str(&text),
+                    _ => self.components.insert(at, Component::Insert(text)),
+                }
+            }
+        }
+    }
+
+    /// Length of the documents the operation applies to
+    fn base_len(&self) -> usize {
+        self.components
+            .iter()
+            .map(|component| match component {
+                Component::Retain(n) | Component::Delete(n) => *n,
+                Component::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    pub(crate) fn apply(&self, text: &str) -> Result<String, String> {
+        let mut chars = text.chars();
+        let mut result = String::with_capacity(text.len());
+        for component in &self.components {
+            match component {
+                Component::Retain(n) => {
+                    let before = result.len();
+                    result.extend(chars.by_ref().take(*n));
+                    if result[before..].chars().count() < *n {
+                        return Err("The operation is longer than the document.".to_string());
+                    }
+                }
+                Component::Insert(inserted) => result.push_str(inserted),
+                Component::Delete(n) => {
+                    if chars.by_ref().take(*n).count() < *n {
+                        return Err("The operation is longer than the document.".to_string());
+                    }
+                }
+            }
+        }
+        if chars.next().is_some() {
+            return Err("The operation is shorter than the document.".to_string());
+        }
+        Ok(result)
+    }
+
+    /// Transform two operations made on the same document into `(a', b')`,
+    /// such that applying `a` then `b'` gives the same document as `b` then `a'`.
+    /// Text inserted by both at the same place is ordered with that of `a` first.
+    pub(crate) fn transform(a: &Self, b: &Self) -> Result<(Self, Self), String> {
+        if a.base_len() != b.base_len() {
+            return Err("The operations apply to different documents.".to_string());
+        }
+
+        let (mut a_prime, mut b_prime) = (Self::default(), Self::default());
+        let mut a_components = a.components.iter().cloned();
+        let mut b_components = b.components.iter().cloned();
+        let (mut a_next, mut b_next) = (a_components.next(), b_components.next());
+        loop {
+            match (a_next.take(), b_next.take()) {
+                (None, None) => break,
+                (Some(Component::Insert(text)), b_component) => {
+                    b_prime.push(Component::Retain(text.chars().count()));
+                    a_prime.push(Component::Insert(text));
+                    a_next = a_components.next();
+                    b_next = b_component;
+                }
+                (a_component, Some(Component::Insert(text))) => {
+                    a_prime.push(Component::Retain(text.chars().count()));
+                    b_prime.push(Component::Insert(text));
+                    a_next = a_component;
+                    b_next = b_components.next();
+                }
+                (Some(a_component), Some(b_component)) => {
+                    let (a_len, b_len) = (component_len(&a_component), component_len(&b_component));
+                    let len = a_len.min(b_len);
+                    match (&a_component, &b_component) {
+                        (Component::Retain(_), Component::Retain(_)) => {
+                            a_prime.push(Component::Retain(len));
+                            b_prime.push(Component::Retain(len));
+                        }
+                        (Component::Delete(_), Component::Retain(_)) => {
+                            a_prime.push(Component::Delete(len));
+                        }
+                        (Component::Retain(_), Component::Delete(_)) => {
+                            b_prime.push(Component::Delete(len));
+                        }
+                        // Deleted by both
+                        _ => {}
+                    }
+                    a_next = shorten(a_component, len, &mut a_components);
+                    b_next = shorten(b_component, len, &mut b_components);
+                }
+                (None, Some(_)) | (Some(_), None) => {
+                    return Err("The operations apply to different documents.".to_string());
+                }
+            }
+        }
+        Ok((a_prime, b_prime))
+    }
+}
+
+fn component_len(component: &Component) -> usize {
+    match component {
+        Component::Retain(n) | Component::Delete(n) => *n,
+        Component::Insert(text) => text.chars().count(),
+    }
+}
+
+/// What remains of a retain or delete once `len` of it was transformed, the next one if nothing
+fn shorten(
+    component: Component,
+    len: usize,
+    rest: &mut impl Iterator<Item = Component>,
+) -> Option<Component> {
+    match component {
+        Component::Retain(n) if n > len => Some(Component::Retain(n - len)),
+        Component::Delete(n) if n > len => Some(Component::Delete(n - len)),
+        _ => rest.next(),
+    }
+}
+
+/// Orders the operations made on a document, transforming each one against those the client
+/// hadn't seen when making it
+#[derive(Debug)]
+pub(crate) struct Sequencer {
+    revision: u64,
+    /// Operations leading to `revision`, the oldest first
+    history: VecDeque<Operation>,
+    /// Contents of the revisions before `revision`, the oldest first
+    bases: VecDeque<String>,
+    /// Content at `revision`
+    content: String,
+}
+
+impl Sequencer {
+    fn new(content: String) -> Self {
+        Self {
+            revision: 0,
+            history: VecDeque::new(),
+            bases: VecDeque::new(),
+            content,
+        }
+    }
+
+    /// Content of a recent revision
+    fn content_at(&self, revision: u64) -> Result<&str, String> {
+        if revision > self.revision {
+            return Err("Unknown revision.".to_string());
+        }
+        let behind = usize::try_from(self.revision - revision).unwrap_or(usize::MAX);
+        match behind.checked_sub(1) {
+            None => Ok(&self.content),
+            Some(i) if i < self.bases.len() => Ok(&self.bases[self.bases.len() - 1 - i]),
+            Some(_) => Err(
+                "The revision is too old to merge the edit, resync from the content.".to_string(),
+            ),
+        }
+    }
+
+    /// Record the document changed by other means than operations, as an operation replacing it
+    fn catch_up(&mut self, content: &str) -> Option<Operation> {
+        if self.content == content {
+            return None;
+        }
+        let operation = Operation::replace(&self.content, content);
+        self.commit(operation.clone(), content.to_string());
+        Some(operation)
+    }
+
+    /// Transform an operation made at `revision` against those sequenced since,
+    /// returning it with the content it leads to
+    fn rebase(
+        &self,
+        revision: u64,
+        mut operation: Operation,
+    ) -> Result<(Operation, String), String> {
+        if revision > self.revision {
+            return Err("Unknown revision.".to_string());
+        }
+        let oldest = self
+            .revision
+            .saturating_sub(u64::try_from(self.history.len()).unwrap_or(u64::MAX));
+        if revision < oldest {
+            return Err("The revision is too old, resync from the snapshot.".to_string());
+        }
+
+        let concurrent = usize::try_from(self.revision - revision).unwrap_or(usize::MAX);
+        for sequenced in self.history.iter().skip(self.history.len() - concurrent) {
+            operation = Operation::transform(&operation, sequenced)?.0;
+        }
+        let content = operation.apply(&self.content)?;
+        Ok((operation, content))
+    }
+
+    fn commit(&mut self, operation: Operation, content: String) {
+        self.history.push_back(operation);
+        if self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.bases
+            .push_back(std::mem::replace(&mut self.content, content));
+        if self.bases.len() > MAX_BASES {
+            self.bases.pop_front();
+        }
+        self.revision += 1;
+    }
+}
+
+/// Sequencers of the documents of a room by document id, shared with the write-behind actor
+pub(crate) type Sequencers = Arc<Mutex<HashMap<String, Sequencer>>>;
+
+/// Revision of a document if `content` is its latest one, `current` telling whether it is
+/// still its content. Documents start at revision 0 from their current content until sequenced.
+pub(crate) fn revision_of(
+    sequencers: &HashMap<String, Sequencer>,
+    doc_id: Option<&str>,
+    content: &str,
+    current: bool,
+) -> Option<u64> {
+    match sequencers.get(doc_id.unwrap_or(MAIN_DOCUMENT)) {
+        Some(sequencer) => (sequencer.content == content).then_some(sequencer.revision),
+        None => current.then_some(0),
+    }
+}
+
+/// Operation of a client, made on the given revision of a document
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct SubmittedOperation {
+    /// Document edited, the main one if `None`
+    #[serde(default)]
+    pub(crate) doc_id: Option<String>,
+    pub(crate) revision: u64,
+    pub(crate) ops: Operation,
+    /// Echoed in the broadcast operation, for the client to recognize it
+    #[serde(default)]
+    pub(crate) op_id: Option<String>,
+}
+
+/// Refused operation, the client should start over from the snapshot
+#[derive(Debug)]
+pub(crate) struct OperationRejected {
+    pub(crate) error: String,
+    pub(crate) snapshot: String,
+}
+
+/// Operation sequenced by the server, broadcast to the clients with the `ot` capability
+fn operation_message(
+    doc_id: Option<&str>,
+    revision: u64,
+    operation: &Operation,
+    username: &str,
+    op_id: Option<&str>,
+) -> String {
+    // `doc_id` goes first, see `documents::is_document_message`
+    let mut message = serde_json::Map::new();
+    if let Some(doc_id) = doc_id {
+        message.insert("doc_id".to_string(), json!(doc_id));
+    }
+    message.insert("type".to_string(), json!("op"));
+    message.insert("revision".to_string(), json!(revision));
+    message.insert("ops".to_string(), json!(operation));
+    message.insert("username".to_string(), json!(username));
+    if let Some(op_id) = op_id {
+        message.insert("op_id".to_string(), json!(op_id));
+    }
+    serde_json::Value::Object(message).to_string()
+}
+
+/// Content of a document at a revision, for a client to start applying operations from it
+fn snapshot_message(doc_id: Option<&str>, revision: u64, content: &str) -> String {
+    let mut message = serde_json::Map::new();
+    if let Some(doc_id) = doc_id {
+        message.insert("doc_id".to_string(), json!(doc_id));
+    }
+    message.insert("type".to_string(), json!("ot-snapshot"));
+    message.insert("revision".to_string(), json!(revision));
+    message.insert("value".to_string(), json!(content));
+    serde_json::Value::Object(message).to_string()
+}
+
+/// Whether a broadcast message is an operation, which only clients with the `ot` capability get
+pub(crate) fn is_operation_message(msg: &str) -> bool {
+    msg.contains(r#""type":"op","#)
+}
+
+/// Document of a broadcast content message, `None` if the message carries no content.
+/// Clients with the `ot` capability get operations instead.
+pub(crate) fn content_message_document(msg: &str) -> Option<String> {
+    if !msg.contains(r#""type":"message""#) {
+        return None;
+    }
+    let message: serde_json::Value = serde_json::from_str(msg).ok()?;
+    (message["type"] == "message").then(|| {
+        message["doc_id"]
+            .as_str()
+            .unwrap_or(MAIN_DOCUMENT)
+            .to_string()
+    })
+}
+
+impl RoomState {
+    /// Broadcast the changes made to a document besides operations as an operation,
+    /// if clients follow its operations
+    pub(crate) async fn catch_up_operations(&self, doc_id: Option<&str>) {
+        let content = self.content_of(doc_id).await.unwrap_or_default();
+        let mut sequencers = self.sequencers.lock().await;
+        if let Some(sequencer) = sequencers.get_mut(doc_id.unwrap_or(MAIN_DOCUMENT)) {
+            if let Some(operation) = sequencer.catch_up(&content) {
+                let _ = self.tx.send(operation_message(
+                    doc_id,
+                    sequencer.revision,
+                    &operation,
+                    "Server",
+                    None,
+                ));
+            }
+        }
+        drop(sequencers);
+    }
+
+    /// Drop the operations and past contents kept to merge concurrent edits, which can't be
+    /// made on the revisions before anymore
+    pub(crate) async fn forget_operations(&self) {
+        let mut sequencers = self.sequencers.lock().await;
+        for sequencer in sequencers.values_mut() {
+            sequencer.history.clear();
+            for base in &mut sequencer.bases {
+                base.zeroize();
+            }
+            sequencer.bases.clear();
+        }
+        drop(sequencers);
+    }
+
+    /// Latest revision of a document, counting the changes made besides operations
+    pub(crate) async fn revision(&self, doc_id: Option<&str>) -> u64 {
+        self.catch_up_operations(doc_id).await;
+        let content = self.content_of(doc_id).await.unwrap_or_default();
+        let mut sequencers = self.sequencers.lock().await;
+        let revision = sequencers
+            .entry(doc_id.unwrap_or(MAIN_DOCUMENT).to_string())
+            .or_insert_with(|| Sequencer::new(content))
+            .revision;
+        drop(sequencers);
+        revision
+    }
+
+    /// Merge the whole content of a document sent by a client with the changes made since the
+    /// revision it edited. `base` is the content the client edited if it isn't that of the
+    /// revision, when its previous edit wasn't sequenced yet as it made this one.
+    pub(crate) async fn merge_edit(
+        &self,
+        doc_id: Option<&str>,
+        revision: u64,
+        base: Option<&str>,
+        content: &str,
+    ) -> Result<Merged, String> {
+        self.catch_up_operations(doc_id).await;
+        let sequencers = self.sequencers.lock().await;
+        let merged = match sequencers.get(doc_id.unwrap_or(MAIN_DOCUMENT)) {
+            Some(sequencer) => {
+                let base = base.map_or_else(|| sequencer.content_at(revision), Ok)?;
+                merge::merge(base, &sequencer.content, content)
+            }
+            // Nobody got a revision of the document yet
+            None => Merged {
+                content: content.to_string(),
+                conflicts: 0,
+            },
+        };
+        drop(sequencers);
+        Ok(merged)
+    }
+
+    /// Snapshot of a document at its latest revision
+    pub(crate) async fn snapshot(&self, doc_id: Option<&str>) -> String {
+        self.catch_up_operations(doc_id).await;
+        let content = self.content_of(doc_id).await.unwrap_or_default();
+        let mut sequencers = self.sequencers.lock().await;
+        let sequencer = sequencers
+            .entry(doc_id.unwrap_or(MAIN_DOCUMENT).to_string())
+            .or_insert_with(|| Sequencer::new(content));
+        let snapshot = snapshot_message(doc_id, sequencer.revision, &sequencer.content);
+        drop(sequencers);
+        snapshot
+    }
+
+    /// Sequence the operation of a client and apply it to the document.
+    /// The operation is broadcast to the clients following the operations, the new content is
+    /// returned for the caller to broadcast it to the others.
+    /// The caller must hold the lock of the rooms, so that nothing edits the document meanwhile.
+    pub(crate) async fn submit_operation(
+        &self,
+        state: &AppState,
+        room_id: &str,
+        submitted: SubmittedOperation,
+        username: &str,
+    ) -> Result<String, OperationRejected> {
+        let doc_id = submitted.doc_id.as_deref();
+        let result = self.sequence(state, room_id, &submitted, username).await;
+        match result {
+            Ok(content) => Ok(content),
+            Err(error) => Err(OperationRejected {
+                error,
+                snapshot: self.snapshot(doc_id).await,
+            }),
+        }
+    }
+
+    async fn sequence(
+        &self,
+        state: &AppState,
+        room_id: &str,
+        submitted: &SubmittedOperation,
+        username: &str,
+    ) -> Result<String, String> {
+        // Ciphertext can't be edited in place
+        if self.encryption.is_some() {
+            return Err("Encrypted rooms can't be edited with operations.".to_string());
+        }
+        if let Some((_, notice)) = self.edit_refusal(username).await {
+            return Err(notice);
+        }
+
+        let doc_id = submitted.doc_id.as_deref();
+        self.catch_up_operations(doc_id).await;
+        let current = self.content_of(doc_id).await.unwrap_or_default();
+        let mut sequencers = self.sequencers.lock().await;
+        let sequencer = sequencers
+            .entry(doc_id.unwrap_or(MAIN_DOCUMENT).to_string())
+            .or_insert_with(|| Sequencer::new(current));
+        let (operation, content) = sequencer.rebase(submitted.revision, submitted.ops.clone())?;
+        self.edit_lines(username, doc_id, &content).await?;
+        self.update_content(state, room_id, doc_id, &content)
+            .await?;
+        sequencer.commit(operation.clone(), content.clone());
+        let _ = self.tx.send(operation_message(
+            doc_id,
+            sequencer.revision,
+            &operation,
+            username,
+            submitted.op_id.as_deref(),
+        ));
+        drop(sequencers);
+        Ok(content)
+    }
+}
+
+/// Snapshots of the documents of a room, the main one first
+pub(crate) async fn snapshot_messages(
+    state: &AppState,
+    room_id: &str,
+    documents: bool,
+) -> Vec<String> {
+    let rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get(room_id) else {
+        return Vec::new();
+    };
+    let mut snapshots = vec![room.snapshot(None).await];
+    if documents {
+        let mut doc_ids: Vec<String> = room.documents.lock().await.keys().cloned().collect();
+        doc_ids.sort();
+        for doc_id in doc_ids {
+            snapshots.push(room.snapshot(Some(&doc_id)).await);
+        }
+    }
+    drop(rooms);
+    snapshots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        content_message_document, is_operation_message, revision_of, Operation, Sequencer,
+    };
+    use std::collections::HashMap;
+
+    fn operation(json: &str) -> Operation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            operation(r#"[5, " world", -1]"#).apply("hello!"),
+            Ok("hello world".to_string())
+        );
+        assert_eq!(operation(r#"["é", 2]"#).apply("tè"), Ok("étè".to_string()));
+        assert!(operation("[3]").apply("hello").is_err());
+        assert!(operation("[6]").apply("hello").is_err());
+        assert!(serde_json::from_str::<Operation>("[0]").is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            operation(r#"[1, 1, -1, "a"]"#),
+            operation(r#"[2, "a", -1]"#)
+        );
+        assert_eq!(
+            serde_json::to_string(&operation(r#"[1, 1, -1, "a"]"#)).unwrap(),
+            r#"[2,"a",-1]"#
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        let replace = Operation::replace("hello world", "hello there world");
+        assert_eq!(replace, operation(r#"[6, "there ", 5]"#));
+        assert_eq!(
+            replace.apply("hello world"),
+            Ok("hello there world".to_string())
+        );
+        assert_eq!(
+            Operation::replace("aaa", "aa").apply("aaa"),
+            Ok("aa".to_string())
+        );
+    }
+
+    #[test]
+    fn test_transform() {
+        let base = "hello world";
+        let cases = [
+            (r#"[5, ",", 6]"#, r#"[11, "!"]"#),
+            (r#"[-6, 5]"#, r#"[6, -5, "there"]"#),
+            (r#"[2, -3, 6]"#, r#"[3, -4, 4]"#),
+            (r#"[5, "a", 6]"#, r#"[5, "b", 6]"#),
+        ];
+        for (a, b) in cases {
+            let (a, b) = (operation(a), operation(b));
+            let (a_prime, b_prime) = Operation::transform(&a, &b).unwrap();
+            let ab = b_prime.apply(&a.apply(base).unwrap()).unwrap();
+            let ba = a_prime.apply(&b.apply(base).unwrap()).unwrap();
+            assert_eq!(ab, ba);
+        }
+
+        // Concurrent inserts at the same place keep the first operation first
+        let (a_prime, _) =
+            Operation::transform(&operation(r#"[5, "a", 6]"#), &operation(r#"[5, "b", 6]"#))
+                .unwrap();
+        assert_eq!(
+            a_prime.apply("hellob world"),
+            Ok("helloab world".to_string())
+        );
+
+        assert!(Operation::transform(&operation("[3]"), &operation("[4]")).is_err());
+    }
+
+    #[test]
+    fn test_sequencer() {
+        let mut sequencer = Sequencer::new("abc".to_string());
+        let (first, content) = sequencer.rebase(0, operation(r#"[3, "d"]"#)).unwrap();
+        sequencer.commit(first, content);
+        assert_eq!(sequencer.revision, 1);
+
+        // Made before the first operation reached its client
+        let (second, content) = sequencer.rebase(0, operation(r#"["x", 3]"#)).unwrap();
+        assert_eq!(second, operation(r#"["x", 4]"#));
+        assert_eq!(content, "xabcd");
+        sequencer.commit(second, content);
+
+        assert!(sequencer.rebase(3, operation("[5]")).is_err());
+        assert_eq!(
+            sequencer.catch_up("replaced"),
+            Some(Operation::replace("xabcd", "replaced"))
+        );
+        assert_eq!(sequencer.revision, 3);
+        assert_eq!(sequencer.catch_up("replaced"), None);
+
+        assert_eq!(sequencer.content_at(3), Ok("replaced"));
+        assert_eq!(sequencer.content_at(1), Ok("abcd"));
+        assert_eq!(sequencer.content_at(0), Ok("abc"));
+        assert!(sequencer.content_at(4).is_err());
+    }
+
+    #[test]
+    fn test_revision_of() {
+        let mut sequencers = HashMap::new();
+        assert_eq!(revision_of(&sequencers, None, "abc", true), Some(0));
+        assert_eq!(revision_of(&sequencers, None, "abc", false), None);
+
+        let mut sequencer = Sequencer::new("abc".to_string());
+        sequencer.catch_up("abcd");
+        sequencers.insert("main".to_string(), sequencer);
+        assert_eq!(revision_of(&sequencers, None, "abcd", false), Some(1));
+        assert_eq!(revision_of(&sequencers, None, "abc", true), None);
+        assert_eq!(
+            revision_of(&sequencers, Some("notes"), "abc", true),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let op = super::operation_message(Some("notes"), 2, &operation("[1]"), "alice", None);
+        assert!(is_operation_message(&op));
+        assert!(crate::documents::is_document_message(&op));
+        assert_eq!(content_message_document(&op), None);
+
+        assert_eq!(
+            content_message_document(r#"{"type":"message","value":"hi","username":"alice"}"#)
+                .as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            content_message_document(r#"{"doc_id":"notes","type":"message","value":"hi"}"#)
+                .as_deref(),
+            Some("notes")
+        );
+        assert!(!is_operation_message(
+            r#"{"type":"message","value":"\"type\":\"op\",","username":"alice"}"#
+        ));
+    }
+}
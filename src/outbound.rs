@@ -0,0 +1,181 @@
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use futures::{Sink, SinkExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Frames waiting to be written to a connection at most, past that the client is disconnected
+const OUTBOUND_CAPACITY: usize = 256;
+
+/// Time given to a client to take a frame, past that its connection is considered gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close frame sent to clients that can't keep up with their queue, they get the whole
+/// state of the room back by reconnecting
+fn too_slow_close_frame() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::AGAIN,
+        reason: "Too slow to keep up with the room.".into(),
+    }))
+}
+
+/// Called once a frame was written to the socket
+type OnWritten = Box<dyn FnOnce() + Send>;
+
+/// Frames sent to a client, written to its socket in order by a task of its own.
+/// Nothing waits on a slow client: the pongs, pings and broadcasts are only queued.
+#[derive(Clone)]
+pub(crate) struct Outbound {
+    tx: mpsc::Sender<(Message, Option<OnWritten>)>,
+    /// Cancelled when the queue filled up, the client is disconnected
+    overflowed: CancellationToken,
+    /// Cancelled when the writer task ended
+    finished: CancellationToken,
+}
+
+impl Outbound {
+    /// Start writing to `sink`, the sending half of a socket, the frames sent through the
+    /// returned queue, until a close frame was written, the socket failed or the queue is dropped
+    pub(crate) fn spawn<S>(sink: S) -> Self
+    where
+        S: Sink<Message> + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(OUTBOUND_CAPACITY);
+        let outbound = Self {
+            tx,
+            overflowed: CancellationToken::new(),
+            finished: CancellationToken::new(),
+        };
+        tokio::spawn(write(
+            sink,
+            rx,
+            outbound.overflowed.clone(),
+            outbound.finished.clone(),
+        ));
+        outbound
+    }
+
+    /// Queue a frame, `false` if the connection is closed or the client fell too far behind
+    pub(crate) fn send(&self, message: Message) -> bool {
+        self.queue(message, None)
+    }
+
+    /// Queue a frame like [`Outbound::send`], calling `on_written` once the client got it
+    pub(crate) fn send_then(
+        &self,
+        message: Message,
+        on_written: impl FnOnce() + Send + 'static,
+    ) -> bool {
+        self.queue(message, Some(Box::new(on_written)))
+    }
+
+    fn queue(&self, message: Message, on_written: Option<OnWritten>) -> bool {
+        match self.tx.try_send((message, on_written)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.cancel();
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Wait for the queue to fill up, the writer then closes the connection
+    pub(crate) async fn overflowed(&self) {
+        self.overflowed.cancelled().await;
+    }
+
+    /// Send a close frame after the queued frames, and wait for the client to get it
+    pub(crate) async fn close(&self, frame: Message) {
+        if self.send(frame) {
+            let _ = time::timeout(WRITE_TIMEOUT, self.finished.cancelled()).await;
+        }
+    }
+}
+
+async fn write<S: Sink<Message> + Unpin>(
+    mut sink: S,
+    mut rx: mpsc::Receiver<(Message, Option<OnWritten>)>,
+    overflowed: CancellationToken,
+    finished: CancellationToken,
+) {
+    let _finished = finished.drop_guard();
+    loop {
+        // The frames still queued are dropped for the client that can't keep up
+        let (message, on_written) = tokio::select! {
+            biased;
+            () = overflowed.cancelled() => (too_slow_close_frame(), None),
+            frame = rx.recv() => match frame {
+                Some(frame) => frame,
+                None => return,
+            },
+        };
+        let closing = matches!(message, Message::Close(_));
+        if !matches!(
+            time::timeout(WRITE_TIMEOUT, sink.send(message)).await,
+            Ok(Ok(()))
+        ) {
+            return;
+        }
+        if let Some(on_written) = on_written {
+            on_written();
+        }
+        if closing {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outbound, OUTBOUND_CAPACITY};
+    use axum::extract::ws::{close_code, Message};
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_slow_client_is_disconnected() {
+        // A client reading nothing
+        let (sink, mut written) = mpsc::channel(0);
+        let outbound = Outbound::spawn(sink);
+        let mut queued = 0;
+        while outbound.send(Message::Text(queued.to_string())) {
+            queued += 1;
+        }
+        assert!(queued >= OUTBOUND_CAPACITY);
+        outbound.overflowed().await;
+        assert!(!outbound.send(Message::Text("late".to_string())));
+
+        // It gets the close frame instead of the rest of the queue
+        let mut frames = Vec::new();
+        while let Some(frame) = written.next().await {
+            frames.push(frame);
+        }
+        assert!(frames.len() < queued);
+        let Some(Message::Close(Some(frame))) = frames.last() else {
+            panic!("{frames:?}");
+        };
+        assert_eq!(frame.code, close_code::AGAIN);
+    }
+
+    #[tokio::test]
+    async fn test_frames_are_written_in_order() {
+        let (sink, written) = mpsc::channel(OUTBOUND_CAPACITY);
+        let outbound = Outbound::spawn(sink);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        assert!(outbound.send(Message::Text("a".to_string())));
+        assert!(outbound.send_then(Message::Text("b".to_string()), move || {
+            let _ = tx.send(());
+        }));
+        rx.await.unwrap();
+        drop(outbound);
+        let frames: Vec<Message> = written.collect().await;
+        assert_eq!(
+            frames,
+            [
+                Message::Text("a".to_string()),
+                Message::Text("b".to_string())
+            ]
+        );
+    }
+}
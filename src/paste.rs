@@ -0,0 +1,199 @@
+use crate::burn::{Burn, BurnAfter};
+use crate::client_ip::ClientIp;
+use crate::storage;
+use crate::webhooks::WebhookEvent;
+use crate::write_behind::Write;
+use crate::{
+    auth, ensure_room_loaded, get_stored_content, revisions, AppState, CustomError, RoomState,
+    SocketMessage, SocketMessageType,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Length of the room ids generated for pastes
+const PASTE_ID_LENGTH: usize = 8;
+
+fn random_room_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PASTE_ID_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Absolute URL of a path of this server, as reached by the client
+fn public_url(headers: &HeaderMap, path: &str) -> String {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let scheme = header(header::HeaderName::from_static("x-forwarded-proto")).unwrap_or("http");
+    let host = header(header::HOST).unwrap_or("localhost");
+    format!("{scheme}://{host}{path}")
+}
+
+/// Query parameters of `POST /api/paste`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PasteQuery {
+    /// Wipe the paste after this event, `?burn=read` for a secret read once
+    #[serde(default)]
+    burn: Option<BurnAfter>,
+}
+
+/// Create a room holding the request body, answering with its URL as plain text:
+/// `curl --data-binary @notes.txt https://partage.example/api/paste`
+pub(crate) async fn create_paste(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<PasteQuery>,
+    headers: HeaderMap,
+    content: String,
+) -> Result<Response, CustomError> {
+    if content.is_empty() {
+        return Err(CustomError::bad_request("Empty paste."));
+    }
+    // Pastes created by an account belong to it
+    let owner_id = auth::current_user(&state, &headers)
+        .await
+        .map(|account| account.id);
+
+    let mut rooms = state.rooms.lock().await;
+    let room_id = loop {
+        let room_id = random_room_id();
+        if !rooms.contains_key(&room_id)
+            && !state.room_creation.is_reserved(&room_id)
+            && get_stored_content(&state, &room_id).await.is_none()
+        {
+            break room_id;
+        }
+    };
+    state.room_creation.check(&state.db, ip, &room_id).await?;
+
+    let burn = Burn { after: query.burn };
+    if let Err(e) = sqlx::query(
+        "INSERT INTO rooms (room_id, content, owner_id, burn_after) VALUES (?, '', ?, ?)",
+    )
+    .bind(&room_id)
+    .bind(owner_id)
+    .bind(burn.after.map(BurnAfter::as_str))
+    .execute(&state.db)
+    .await
+    {
+        eprintln!("Failed to store paste in database: {e}");
+        return Err(auth::internal_error());
+    }
+    let writes = Write::room(&room_id, &content, []);
+    if let Err(e) = storage::store(&state.db, state.storage.as_ref(), &writes).await {
+        eprintln!("Failed to store paste content: {e:#}");
+        return Err(auth::internal_error());
+    }
+    if let Some(mirror) = &state.mirror {
+        mirror.snapshot_room(&room_id, &content, []);
+    }
+    let bytes = content.len();
+    let room_state = RoomState::new(&room_id, &state.write_behind).with_burn(burn);
+    let _ = room_state.content_tx.send(content);
+    rooms.insert(room_id.clone(), room_state);
+
+    for room_state in rooms.values() {
+        let _ = room_state.tx.send(
+            json!(SocketMessage {
+                doc_id: None,
+                message_type: SocketMessageType::UpdateRoomsList,
+                value: None,
+                code: None,
+                revision: None,
+                username: String::new(),
+            })
+            .to_string(),
+        );
+    }
+    drop(rooms);
+
+    println!("Created paste {room_id} ({bytes} bytes)");
+    state
+        .webhooks
+        .emit(WebhookEvent::RoomCreated, &room_id, json!({}))
+        .await;
+
+    let url = public_url(&headers, &format!("{}/c/{room_id}", state.config.base_path));
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, url.clone()),
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+        ],
+        format!("{url}\n"),
+    )
+        .into_response())
+}
+
+/// Content of a room the user of a request can read, unless it is encrypted, which is wiped
+/// if it burns after reading
+pub(crate) async fn readable_content(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<String, CustomError> {
+    revisions::request_access(state, headers, room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(state, &mut rooms, room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[room_id];
+    if room.encryption.is_some() {
+        return Err(CustomError::bad_request(
+            "Encrypted rooms can't be read by the server.",
+        ));
+    }
+    let content = room.read_content(state, room_id).await;
+    drop(rooms);
+    content
+}
+
+/// Content of a room as plain text
+pub(crate) async fn get_raw(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    let content = readable_content(&state, &headers, &room_id).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{public_url, random_room_id, PASTE_ID_LENGTH};
+    use axum::http::HeaderMap;
+
+    #[test]
+    fn test_random_room_id() {
+        let id = random_room_id();
+        assert_eq!(id.len(), PASTE_ID_LENGTH);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(id, random_room_id());
+    }
+
+    #[test]
+    fn test_public_url() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "partage.example".parse().unwrap());
+        assert_eq!(public_url(&headers, "/c/a"), "http://partage.example/c/a");
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        assert_eq!(public_url(&headers, "/c/a"), "https://partage.example/c/a");
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use ts_rs::TS;
+
+/// Who a room is pinned for
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum PinScope {
+    /// Pinned by an administrator, at the top of every sidebar
+    Everyone,
+    /// Pinned by the current user, after the rooms pinned for everyone
+    Me,
+}
+
+/// Room pinned at the top of the rooms list
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct RoomPin {
+    pub(crate) scope: PinScope,
+    /// Rooms with the lowest positions come first
+    #[ts(type = "number")]
+    pub(crate) position: i64,
+}
+
+/// Body of the requests pinning a room
+#[derive(TS, Debug, Default, Deserialize)]
+#[ts(export)]
+pub(crate) struct PinRequest {
+    #[serde(default)]
+    #[ts(type = "number | undefined")]
+    pub(crate) position: i64,
+}
+
+/// Pins applying to the rooms list of a user, by room id
+#[derive(Debug, Default)]
+pub(crate) struct Pins {
+    everyone: HashMap<String, i64>,
+    user: HashMap<String, i64>,
+}
+
+impl Pins {
+    /// Pins for everyone and those of the user, if logged in
+    pub(crate) async fn load(db: &SqlitePool, user_id: Option<i64>) -> Result<Self> {
+        let everyone = sqlx::query_as::<_, (String, i64)>(
+            "SELECT room_id, pin_position FROM rooms WHERE pin_position IS NOT NULL",
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .collect();
+        let user = match user_id {
+            Some(user_id) => sqlx::query_as::<_, (String, i64)>(
+                "SELECT room_id, position FROM user_pins WHERE user_id = ?",
+            )
+            .bind(user_id)
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .collect(),
+            None => HashMap::new(),
+        };
+        Ok(Self { everyone, user })
+    }
+
+    /// Pin of a room, the one for everyone winning over the one of the user
+    pub(crate) fn get(&self, room_id: &str) -> Option<RoomPin> {
+        self.everyone
+            .get(room_id)
+            .map(|&position| RoomPin {
+                scope: PinScope::Everyone,
+                position,
+            })
+            .or_else(|| {
+                self.user.get(room_id).map(|&position| RoomPin {
+                    scope: PinScope::Me,
+                    position,
+                })
+            })
+    }
+}
+
+/// Sort key of a room in the rooms list: pinned for everyone, then pinned by the user,
+/// then the others, by position then id
+pub(crate) fn sort_key(pin: Option<RoomPin>) -> (u8, i64) {
+    match pin {
+        Some(RoomPin {
+            scope: PinScope::Everyone,
+            position,
+        }) => (0, position),
+        Some(RoomPin {
+            scope: PinScope::Me,
+            position,
+        }) => (1, position),
+        None => (2, 0),
+    }
+}
+
+/// Pin a room for everyone, `None` unpins it
+pub(crate) async fn store_global(
+    db: &SqlitePool,
+    room_id: &str,
+    content: &str,
+    position: Option<i64>,
+) -> Result<()> {
+    sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, pin_position) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET pin_position = excluded.pin_position
+        ",
+    )
+    .bind(room_id)
+    .bind(content)
+    .bind(position)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Pin a room for a user, `None` unpins it
+pub(crate) async fn store_user(
+    db: &SqlitePool,
+    user_id: i64,
+    room_id: &str,
+    position: Option<i64>,
+) -> Result<()> {
+    match position {
+        Some(position) => {
+            sqlx::query(
+                r"
+                INSERT INTO user_pins (user_id, room_id, position) VALUES (?, ?, ?)
+                ON CONFLICT (user_id, room_id) DO UPDATE SET position = excluded.position
+                ",
+            )
+            .bind(user_id)
+            .bind(room_id)
+            .bind(position)
+            .execute(db)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM user_pins WHERE user_id = ? AND room_id = ?")
+                .bind(user_id)
+                .bind(room_id)
+                .execute(db)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Forget the pins of users on a removed room
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM user_pins WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sort_key, store_global, store_user, PinScope, Pins, RoomPin};
+    use crate::database;
+
+    #[tokio::test]
+    async fn test_pins() {
+        let db = database::memory().await.unwrap();
+        let user_id: i64 = sqlx::query_scalar(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', '', 0) RETURNING id",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        store_global(&db, "announcements", "", Some(1))
+            .await
+            .unwrap();
+        store_user(&db, user_id, "notes", Some(0)).await.unwrap();
+        store_user(&db, user_id, "announcements", Some(5))
+            .await
+            .unwrap();
+
+        let pins = Pins::load(&db, Some(user_id)).await.unwrap();
+        assert_eq!(
+            pins.get("announcements"),
+            Some(RoomPin {
+                scope: PinScope::Everyone,
+                position: 1
+            })
+        );
+        assert_eq!(
+            pins.get("notes"),
+            Some(RoomPin {
+                scope: PinScope::Me,
+                position: 0
+            })
+        );
+        assert_eq!(pins.get("random"), None);
+        assert!(sort_key(pins.get("announcements")) < sort_key(pins.get("notes")));
+        assert!(sort_key(pins.get("notes")) < sort_key(pins.get("random")));
+
+        // Pins of a user are theirs only
+        let pins = Pins::load(&db, None).await.unwrap();
+        assert_eq!(pins.get("notes"), None);
+
+        store_global(&db, "announcements", "", None).await.unwrap();
+        store_user(&db, user_id, "notes", None).await.unwrap();
+        let pins = Pins::load(&db, Some(user_id)).await.unwrap();
+        assert_eq!(pins.get("notes"), None);
+        assert_eq!(
+            pins.get("announcements").map(|pin| pin.scope),
+            Some(PinScope::Me)
+        );
+    }
+}
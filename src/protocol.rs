@@ -0,0 +1,341 @@
+use axum::extract::ws::Message;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use ts_rs::TS;
+
+/// Version of the WebSocket protocol spoken by the server
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version the server still accepts
+pub(crate) const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Smaller `value` fields are sent as is even when compression was negotiated
+pub(crate) const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// Optional protocol feature, used only when both ends support it
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum Capability {
+    /// Edits are sent as diffs rather than the whole content
+    DiffSync,
+    /// Join and leave messages, each followed by a `presence` message listing the users
+    Presence,
+    /// `value` fields above [`COMPRESSION_THRESHOLD`] bytes are sent as base64 zlib streams,
+    /// with a `"compression": "deflate"` field
+    Compression,
+    /// Messages after `hello` are sent as MessagePack binary frames
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// Rooms hold several named documents, edits are sent as `{"doc_id", "value"}` objects.
+    /// Clients can also send commands such as `{"type": "set-language", "value": "rust"}`
+    Documents,
+    /// A `resume` message carries a token to rejoin the room after a disconnection, under the
+    /// same name and without receiving the documents again if they didn't change
+    Resume,
+    /// Edits are sent as `{"type": "op", "revision", "ops"}` messages, made on a revision of a
+    /// document. The server transforms them against the operations sequenced since and
+    /// broadcasts them in place of the content. Clients get an `ot-snapshot` message with the
+    /// revision of each document after joining, and whenever one of their operations is refused
+    #[serde(rename = "ot")]
+    OperationalTransform,
+    /// Documents from [`ranges::LAZY_CONTENT_SIZE`](crate::ranges::LAZY_CONTENT_SIZE) bytes
+    /// are not sent on join, a `content-size` message gives their size instead. Clients fetch
+    /// their chunks with `{"type": "get-range", "offset", "length"}` messages, answered by
+    /// `range` messages.
+    Ranges,
+}
+
+/// Capabilities implemented by the server
+const SERVER_CAPABILITIES: &[Capability] = &[
+    Capability::Presence,
+    Capability::Compression,
+    Capability::MessagePack,
+    Capability::Documents,
+    Capability::Resume,
+    Capability::OperationalTransform,
+    Capability::Ranges,
+];
+
+impl Capability {
+    /// Parse a capability name, `None` for capabilities this server doesn't know about
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "diff-sync" => Some(Self::DiffSync),
+            "presence" => Some(Self::Presence),
+            "compression" => Some(Self::Compression),
+            "msgpack" => Some(Self::MessagePack),
+            "documents" => Some(Self::Documents),
+            "resume" => Some(Self::Resume),
+            "ot" => Some(Self::OperationalTransform),
+            "ranges" => Some(Self::Ranges),
+            _ => None,
+        }
+    }
+}
+
+/// WebSocket subprotocol, telling how a client encodes its messages.
+/// Server messages were typed JSON from the start, every client gets the same ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Subprotocol {
+    /// Edits are the raw content, or `{"doc_id", "value"}` objects with the `documents`
+    /// capability. Spoken by the clients asking for no subprotocol, such as cached frontends.
+    #[default]
+    V1,
+    /// Every message is a JSON object tagged by its `type`, see [`crate::compat::ClientMessage`]
+    V2,
+}
+
+impl Subprotocol {
+    pub(crate) const fn name(self) -> &'static str {
+        match self {
+            Self::V1 => "partage.v1",
+            Self::V2 => "partage.v2",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::V1, Self::V2]
+            .into_iter()
+            .find(|subprotocol| subprotocol.name() == name)
+    }
+
+    /// First subprotocol of the `Sec-WebSocket-Protocol` header of a client that the server speaks
+    pub(crate) fn select(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .find_map(|name| Self::from_name(name.trim()))
+    }
+}
+
+/// Reply of the server to a versioned handshake, before the room content
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct Hello {
+    /// Version used for the rest of the connection
+    pub(crate) protocol_version: u32,
+    pub(crate) min_protocol_version: u32,
+    pub(crate) max_protocol_version: u32,
+    /// Capabilities requested by the client that the server supports
+    pub(crate) capabilities: Vec<Capability>,
+}
+
+impl Hello {
+    pub(crate) fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Serialization of the messages sent to a client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum WireFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+/// Encoding of the messages sent to a client, agreed on during the handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Wire {
+    pub(crate) format: WireFormat,
+    /// Compress large `value` fields
+    pub(crate) compression: bool,
+}
+
+impl Wire {
+    /// Clients predating the handshake get uncompressed JSON
+    pub(crate) fn negotiated(hello: Option<&Hello>) -> Self {
+        let has = |capability| hello.is_some_and(|hello| hello.has(capability));
+        Self {
+            format: if has(Capability::MessagePack) {
+                WireFormat::MessagePack
+            } else {
+                WireFormat::Json
+            },
+            compression: has(Capability::Compression),
+        }
+    }
+
+    /// Frame carrying a message, given as the JSON broadcast to rooms.
+    /// Falls back to the JSON text frame if it can't be re-encoded.
+    pub(crate) fn frame(self, json: String) -> Message {
+        // Nothing to compress in a message smaller than the threshold
+        let compress = self.compression && json.len() > COMPRESSION_THRESHOLD;
+        if self.format == WireFormat::Json && !compress {
+            return Message::Text(json);
+        }
+
+        let mut message = match serde_json::from_str::<serde_json::Value>(&json) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("Failed to re-encode message: {e}");
+                return Message::Text(json);
+            }
+        };
+        if compress {
+            compress_value(&mut message);
+        }
+
+        match self.format {
+            WireFormat::Json => Message::Text(message.to_string()),
+            WireFormat::MessagePack => match rmp_serde::to_vec_named(&message) {
+                Ok(bytes) => Message::Binary(bytes),
+                Err(e) => {
+                    eprintln!("Failed to encode message as MessagePack: {e}");
+                    Message::Text(json)
+                }
+            },
+        }
+    }
+}
+
+/// Replace a large `value` field of a message by its deflated content, if that makes it smaller
+fn compress_value(message: &mut serde_json::Value) {
+    let Some(serde_json::Value::String(value)) = message.get("value") else {
+        return;
+    };
+    if value.len() <= COMPRESSION_THRESHOLD {
+        return;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    let compressed = match encoder
+        .write_all(value.as_bytes())
+        .and_then(|()| encoder.finish())
+    {
+        Ok(compressed) => STANDARD.encode(compressed),
+        Err(e) => {
+            eprintln!("Failed to compress message: {e}");
+            return;
+        }
+    };
+    if compressed.len() < value.len() {
+        message["value"] = compressed.into();
+        message["compression"] = "deflate".into();
+    }
+}
+
+/// Agree on a protocol version and capabilities with a client.
+/// Unknown capabilities are ignored, so newer clients can still connect.
+pub(crate) fn negotiate(version: u32, requested: &[String]) -> Result<Hello, String> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(format!(
+            "Unsupported protocol version {version}, this server supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}."
+        ));
+    }
+
+    let mut capabilities = Vec::new();
+    for capability in requested
+        .iter()
+        .filter_map(|name| Capability::from_name(name))
+    {
+        if SERVER_CAPABILITIES.contains(&capability) && !capabilities.contains(&capability) {
+            capabilities.push(capability);
+        }
+    }
+
+    Ok(Hello {
+        protocol_version: version,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        max_protocol_version: PROTOCOL_VERSION,
+        capabilities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        negotiate, Capability, Subprotocol, Wire, WireFormat, COMPRESSION_THRESHOLD,
+        PROTOCOL_VERSION,
+    };
+    use axum::extract::ws::Message;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        let requested = ["presence", "diff-sync", "presence", "telepathy"].map(String::from);
+        let hello = negotiate(PROTOCOL_VERSION, &requested).unwrap();
+        assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(hello.capabilities, vec![Capability::Presence]);
+        assert_eq!(Wire::negotiated(Some(&hello)), Wire::default());
+
+        assert!(negotiate(PROTOCOL_VERSION, &[])
+            .unwrap()
+            .capabilities
+            .is_empty());
+        assert!(negotiate(0, &[]).is_err());
+        assert!(negotiate(PROTOCOL_VERSION + 1, &[]).is_err());
+    }
+
+    #[test]
+    fn test_subprotocol() {
+        assert_eq!(Subprotocol::select("partage.v2"), Some(Subprotocol::V2));
+        assert_eq!(
+            Subprotocol::select("chat, partage.v1 , partage.v2"),
+            Some(Subprotocol::V1)
+        );
+        assert_eq!(Subprotocol::select("chat"), None);
+        assert_eq!(Subprotocol::select(""), None);
+    }
+
+    #[test]
+    fn test_message_pack_frame() {
+        let hello = negotiate(PROTOCOL_VERSION, &["msgpack".to_string()]).unwrap();
+        let wire = Wire::negotiated(Some(&hello));
+        assert_eq!(wire.format, WireFormat::MessagePack);
+
+        let json = r#"{"type":"message","value":"hello","username":"alice"}"#;
+        let Message::Binary(bytes) = wire.frame(json.to_string()) else {
+            panic!("Expected a binary frame");
+        };
+        assert!(bytes.len() < json.len());
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+
+        assert_eq!(
+            Wire::default().frame(json.to_string()),
+            Message::Text(json.to_string())
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let hello = negotiate(PROTOCOL_VERSION, &["compression".to_string()]).unwrap();
+        let wire = Wire::negotiated(Some(&hello));
+        assert!(wire.compression);
+
+        // Small messages are left alone
+        let small = r#"{"type":"message","value":"hello"}"#;
+        assert_eq!(
+            wire.frame(small.to_string()),
+            Message::Text(small.to_string())
+        );
+
+        let content = "All work and no play makes Jack a dull boy. ".repeat(1000);
+        assert!(content.len() > COMPRESSION_THRESHOLD);
+        let json = serde_json::json!({ "type": "message", "value": content }).to_string();
+        let Message::Text(text) = wire.frame(json.clone()) else {
+            panic!("Expected a text frame");
+        };
+        assert!(text.len() < json.len() / 10);
+
+        let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(message["compression"], "deflate");
+        let compressed = STANDARD.decode(message["value"].as_str().unwrap()).unwrap();
+        let mut decompressed = String::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, content);
+    }
+}
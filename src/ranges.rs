@@ -0,0 +1,123 @@
+use crate::{SocketMessage, SocketMessageType};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ts_rs::TS;
+
+/// Contents from this many bytes are not sent to the clients with the `ranges` capability
+/// when they join, only their size, for them to fetch the chunks they need
+pub(crate) const LAZY_CONTENT_SIZE: usize = 256 * 1024;
+
+/// Longest chunk sent at once, in bytes
+pub(crate) const MAX_RANGE_LENGTH: usize = 1024 * 1024;
+
+/// `GET /api/rooms/:room_id/content` query, the whole content if both are unset
+#[derive(Deserialize, Debug)]
+pub(crate) struct RangeQuery {
+    pub(crate) offset: Option<usize>,
+    pub(crate) length: Option<usize>,
+}
+
+impl RangeQuery {
+    pub(crate) const fn is_partial(&self) -> bool {
+        self.offset.is_some() || self.length.is_some()
+    }
+}
+
+/// A chunk of a document, answering a `get-range` message
+#[derive(TS, Serialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct ContentRange {
+    /// Byte offset of the chunk in the document
+    pub(crate) offset: usize,
+    /// Byte offset right after the chunk, where the next one starts
+    pub(crate) end: usize,
+    /// Bytes of the whole document
+    pub(crate) total: usize,
+    pub(crate) content: String,
+}
+
+/// The chunk of `content` of about `length` bytes from `offset`, its bounds moved to the
+/// nearest character boundaries. Chunks are at most [`MAX_RANGE_LENGTH`] bytes long.
+pub(crate) fn slice(content: &str, offset: usize, length: usize) -> Result<ContentRange, String> {
+    if length == 0 {
+        return Err("The length of a range must be positive.".to_string());
+    }
+    let total = content.len();
+    if offset > total {
+        return Err(format!(
+            "The offset is past the end of the content, {total} bytes long."
+        ));
+    }
+    let mut start = offset;
+    while !content.is_char_boundary(start) {
+        start -= 1;
+    }
+    // Rounded up for the chunk never to be empty before the end
+    let mut end = offset
+        .saturating_add(length.min(MAX_RANGE_LENGTH))
+        .min(total);
+    while !content.is_char_boundary(end) {
+        end += 1;
+    }
+    Ok(ContentRange {
+        offset: start,
+        end,
+        total,
+        content: content[start..end].to_string(),
+    })
+}
+
+/// Whether a client with the `ranges` capability only gets the size of `content` on join
+pub(crate) const fn is_lazy(content: &str) -> bool {
+    content.len() >= LAZY_CONTENT_SIZE
+}
+
+/// Size of a document too large to be sent on join, as `value`
+pub(crate) fn size_message(doc_id: Option<String>, revision: Option<u64>, size: usize) -> String {
+    json!(SocketMessage {
+        doc_id,
+        message_type: SocketMessageType::ContentSize,
+        value: Some(size.to_string()),
+        code: None,
+        revision,
+        username: "Server".to_string(),
+    })
+    .to_string()
+}
+
+/// A chunk of a document, as a JSON [`ContentRange`] in `value`
+pub(crate) fn range_message(doc_id: Option<String>, revision: u64, range: &ContentRange) -> String {
+    json!(SocketMessage {
+        doc_id,
+        message_type: SocketMessageType::Range,
+        value: Some(json!(range).to_string()),
+        code: None,
+        revision: Some(revision),
+        username: "Server".to_string(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{slice, MAX_RANGE_LENGTH};
+
+    #[test]
+    fn test_slice() {
+        let range = slice("hello world", 6, 100).unwrap();
+        assert_eq!(range.content, "world");
+        assert_eq!((range.offset, range.end, range.total), (6, 11, 11));
+        assert_eq!(slice("hello", 5, 10).unwrap().content, "");
+        assert!(slice("hello", 6, 10).is_err());
+        assert!(slice("hello", 0, 0).is_err());
+
+        // "é" takes bytes 1 and 2
+        let range = slice("héllo", 2, 1).unwrap();
+        assert_eq!(range.content, "é");
+        assert_eq!((range.offset, range.end), (1, 3));
+        assert_eq!(slice("héllo", 0, 2).unwrap().content, "hé");
+
+        let large = "a".repeat(MAX_RANGE_LENGTH + 10);
+        assert_eq!(slice(&large, 0, usize::MAX).unwrap().end, MAX_RANGE_LENGTH);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limit of a token bucket, a `per_second` of 0 disables limiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: u32,
+    pub(crate) burst: u32,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter keyed by client IP
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Tokens given back per second, 0 disables limiting
+    refill: f64,
+    burst: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            refill: f64::from(limit.per_second),
+            burst: limit.burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limiter of rarer actions, a `per_minute` of 0 disables limiting
+    pub(crate) fn per_minute(per_minute: u32, burst: u32) -> Self {
+        Self {
+            refill: f64::from(per_minute) / 60.0,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for `ip`, returns `false` if the client is throttled
+    pub(crate) fn check(&self, ip: IpAddr) -> bool {
+        if self.refill <= 0.0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let burst = f64::from(self.burst.max(1));
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = elapsed.mul_add(self.refill, bucket.tokens).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forget clients that have not been seen for `max_idle`
+    pub(crate) fn cleanup(&self, max_idle: Duration) {
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| bucket.updated.elapsed() < max_idle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    const IP_1: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const IP_2: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn test_burst_then_throttle() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 1,
+            burst: 3,
+        });
+
+        assert!(limiter.check(IP_1));
+        assert!(limiter.check(IP_1));
+        assert!(limiter.check(IP_1));
+        assert!(!limiter.check(IP_1));
+
+        // Buckets are independent per IP
+        assert!(limiter.check(IP_2));
+    }
+
+    #[test]
+    fn test_refill() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 20,
+            burst: 1,
+        });
+
+        assert!(limiter.check(IP_1));
+        assert!(!limiter.check(IP_1));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(limiter.check(IP_1));
+    }
+
+    #[test]
+    fn test_per_minute() {
+        let limiter = RateLimiter::per_minute(1, 2);
+        assert!(limiter.check(IP_1));
+        assert!(limiter.check(IP_1));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!limiter.check(IP_1));
+        assert!(RateLimiter::per_minute(0, 0).check(IP_1));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 0,
+            burst: 0,
+        });
+
+        for _ in 0..1000 {
+            assert!(limiter.check(IP_1));
+        }
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 1,
+            burst: 1,
+        });
+
+        assert!(limiter.check(IP_1));
+        assert!(!limiter.check(IP_1));
+        limiter.cleanup(Duration::ZERO);
+        assert!(limiter.check(IP_1));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::room_creation::Refusal;
+use crate::room_id;
+use crate::storage;
+use crate::write_behind::{Write, WriteBehind};
+use crate::{
+    auth, check_room_owner, ensure_room_loaded, get_stored_content, trash, AppState, CustomError,
+    RoomState, SocketMessage, SocketMessageType, DEFAULT_ROOM,
+};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Body of `PATCH /api/rooms/:room_id`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct RenameRequest {
+    /// New id of the room
+    id: String,
+}
+
+/// Move the rows of a room to its new id, then its contents, writing those the write-behind
+/// may not have written yet
+async fn store_rename(
+    state: &AppState,
+    from: &str,
+    to: &str,
+    content: &str,
+    documents: &[(String, String)],
+) -> Result<()> {
+    let db = &state.db;
+    let mut transaction = db.begin().await?;
+    let renamed = sqlx::query("UPDATE rooms SET room_id = ? WHERE room_id = ?")
+        .bind(to)
+        .bind(from)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    for table in [
+        "user_pins",
+        "room_webhooks",
+        "room_members",
+        "room_history",
+        "room_checkpoints",
+    ] {
+        sqlx::query(&format!("UPDATE {table} SET room_id = ? WHERE room_id = ?"))
+            .bind(to)
+            .bind(from)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+
+    state.storage.delete_room(from).await?;
+    let mut writes = Write::room(
+        to,
+        content,
+        documents.iter().map(|(doc_id, content)| (doc_id, content)),
+    );
+    // Rooms nobody wrote to yet have no row
+    writes.retain(|write| renamed > 0 || write.doc_id.is_some() || !write.content.is_empty());
+    storage::store(db, state.storage.as_ref(), &writes).await
+}
+
+/// Give a room a new id, with its content, documents, settings and attachments.
+/// Its members are told to rejoin it under the new id.
+pub(crate) async fn rename_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<RenameRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let new_id = room_id::normalize(&body.id, state.config.slugify_room_ids)
+        .map_err(CustomError::bad_request)?;
+    if new_id == room_id {
+        return Err(CustomError::bad_request("The room already has this id."));
+    }
+    if room_id == DEFAULT_ROOM {
+        return Err(CustomError::bad_request("Cannot rename the default room."));
+    }
+    if state.room_creation.is_reserved(&new_id) {
+        return Err(Refusal::Reserved.into());
+    }
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    if rooms.contains_key(&new_id)
+        || get_stored_content(&state, &new_id).await.is_some()
+        || trash::is_trashed(&state.db, &new_id).await
+    {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            format!("Room {new_id} already exists."),
+        ));
+    }
+    if rooms[&room_id].closing_at.lock().await.is_some() {
+        return Err(CustomError::new(
+            StatusCode::CONFLICT,
+            "This room is being deleted.",
+        ));
+    }
+
+    let Some(room) = rooms.remove(&room_id) else {
+        return Err(CustomError::not_found("Room not found."));
+    };
+    // Nothing may be written under the old id past this point,
+    // dropping the documents stops writing them
+    room.shutdown();
+    let content = room.content_rx.borrow().clone();
+    let documents: Vec<(String, String)> = room
+        .documents
+        .lock()
+        .await
+        .drain()
+        .map(|(doc_id, document)| {
+            let content = document.content_rx.borrow().clone();
+            (doc_id, content)
+        })
+        .collect();
+
+    if let Err(e) = store_rename(&state, &room_id, &new_id, &content, &documents).await {
+        eprintln!("Failed to rename room in database: {e:#}");
+        // Writes resume under the old id, members rejoin the restarted room
+        rooms.insert(
+            room_id.clone(),
+            room.restart(&state.write_behind, &room_id, documents).await,
+        );
+        drop(rooms);
+        state.connections.disconnect(&room_id, None);
+        return Err(auth::internal_error());
+    }
+    if let Some(mirror) = &state.mirror {
+        mirror.remove_room(&room_id);
+        mirror.snapshot_room(
+            &new_id,
+            &content,
+            documents.iter().map(|(doc_id, content)| (doc_id, content)),
+        );
+    }
+    if let Err(e) = state.attachments.move_room(&room_id, &new_id).await {
+        eprintln!("Failed to move room attachments: {e:#}");
+    }
+    state.traces.stop(&room_id);
+
+    let _ = room.tx.send(
+        json!(SocketMessage {
+            doc_id: None,
+            message_type: SocketMessageType::RoomRenamed,
+            value: Some(new_id.clone()),
+            code: None,
+            revision: None,
+            username: String::new(),
+        })
+        .to_string(),
+    );
+    let renamed = room.restart(&state.write_behind, &new_id, documents).await;
+    rooms.insert(new_id.clone(), renamed);
+
+    for room_state in rooms.values() {
+        let _ = room_state.tx.send(
+            json!(SocketMessage {
+                doc_id: None,
+                message_type: SocketMessageType::UpdateRoomsList,
+                value: None,
+                code: None,
+                revision: None,
+                username: String::new(),
+            })
+            .to_string(),
+        );
+    }
+    drop(rooms);
+
+    println!("Renamed room {room_id} to {new_id}");
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": new_id
+    })))
+}
+
+impl RoomState {
+    /// Same room with its content, `documents` and settings, persisted under `room_id`.
+    /// Members must join it again.
+    async fn restart(
+        self,
+        write_behind: &WriteBehind,
+        room_id: &str,
+        documents: Vec<(String, String)>,
+    ) -> Self {
+        let content = self.content_rx.borrow().clone();
+        let mut room_state = Self::new(room_id, write_behind)
+            .with_freeze_schedule(self.freeze_schedule.into_inner())
+            .with_encryption(self.encryption)
+            .with_syntax_language(self.syntax_language.into_inner())
+            .with_auto_clear(self.auto_clear.into_inner())
+            .with_burn(self.burn.into_inner())
+            .with_documents(write_behind, room_id, documents);
+        room_state.created_at = self.created_at;
+        // Revisions go on under the new id, the actor writing the room following them
+        std::mem::swap(
+            &mut *room_state.sequencers.lock().await,
+            &mut *self.sequencers.lock().await,
+        );
+        let _ = room_state.content_tx.send(content);
+        room_state
+    }
+}
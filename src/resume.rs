@@ -0,0 +1,180 @@
+use crate::auth;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// How long a client can resume its session after its connection ended
+const RESUME_WINDOW: Duration = Duration::from_secs(120);
+
+type ContentHash = [u8; 32];
+
+fn content_hash(content: &str) -> ContentHash {
+    Sha256::digest(content.as_bytes()).into()
+}
+
+/// Value of the `resume` message sent to the clients with the `resume` capability,
+/// before the room content
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct ResumeInfo {
+    /// Sent in the `resume` field of the next join message to pick up from this connection
+    pub(crate) token: String,
+    /// The previous session was resumed, documents the client already has are not sent again
+    pub(crate) resumed: bool,
+}
+
+/// Session of a connection that may be resumed
+#[derive(Debug)]
+struct Session {
+    username: String,
+    room: String,
+    /// Account of the client, resuming requires the same one
+    account_id: Option<i64>,
+    /// Content last delivered to the client, by document, `None` for the main one
+    delivered: HashMap<Option<String>, ContentHash>,
+    /// When the connection ended, `None` while it is open
+    ended_at: Option<Instant>,
+}
+
+/// Session resumed by a reconnecting client
+#[derive(Debug)]
+pub(crate) struct Resumed {
+    pub(crate) username: String,
+    delivered: HashMap<Option<String>, ContentHash>,
+}
+
+impl Resumed {
+    /// Whether the client already has this content of a document, the main one if `doc_id` is `None`
+    pub(crate) fn has(&self, doc_id: Option<&str>, content: &str) -> bool {
+        self.delivered.get(&doc_id.map(ToString::to_string)) == Some(&content_hash(content))
+    }
+}
+
+/// Sessions of the clients with the `resume` capability, by token
+#[derive(Debug, Default)]
+pub(crate) struct ResumeSessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Message sent by the server, as far as resuming is concerned
+#[derive(Deserialize)]
+struct SentMessage {
+    #[serde(default)]
+    doc_id: Option<String>,
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+impl ResumeSessions {
+    /// Start the session of a client that joined a room, returning its token
+    pub(crate) fn issue(&self, username: &str, room: &str, account_id: Option<i64>) -> String {
+        let token = auth::generate_token();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| {
+            session
+                .ended_at
+                .is_none_or(|ended_at| ended_at.elapsed() < RESUME_WINDOW)
+        });
+        sessions.insert(
+            token.clone(),
+            Session {
+                username: username.to_string(),
+                room: room.to_string(),
+                account_id,
+                delivered: HashMap::new(),
+                ended_at: None,
+            },
+        );
+        drop(sessions);
+        token
+    }
+
+    /// Remember that the client got the content of a document
+    pub(crate) fn delivered(&self, token: &str, doc_id: Option<String>, content: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(token) {
+            session.delivered.insert(doc_id, content_hash(content));
+        }
+    }
+
+    /// Remember the content carried by a message the client got, if any
+    pub(crate) fn sent(&self, token: &str, message: &str) {
+        let Ok(message) = serde_json::from_str::<SentMessage>(message) else {
+            return;
+        };
+        if let ("message" | "encrypted", Some(value)) =
+            (message.message_type.as_str(), &message.value)
+        {
+            self.delivered(token, message.doc_id, value);
+        }
+    }
+
+    /// Start the resume window of a session, once its connection ended
+    pub(crate) fn end(&self, token: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(token) {
+            session.ended_at = Some(Instant::now());
+        }
+    }
+
+    /// Take over the session of a token, if it is still valid for this room and account.
+    /// Tokens are used only once, the resumed connection gets a new one.
+    pub(crate) fn take(&self, token: &str, room: &str, account_id: Option<i64>) -> Option<Resumed> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.remove(token)?;
+        drop(sessions);
+        let expired = session
+            .ended_at
+            .is_some_and(|ended_at| ended_at.elapsed() >= RESUME_WINDOW);
+        (!expired && session.room == room && session.account_id == account_id).then_some(Resumed {
+            username: session.username,
+            delivered: session.delivered,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResumeSessions;
+
+    #[test]
+    fn test_resume() {
+        let sessions = ResumeSessions::default();
+        let token = sessions.issue("alice", "room", None);
+        sessions.delivered(&token, None, "hello");
+        sessions.sent(
+            &token,
+            r#"{"doc_id":"notes","type":"message","username":"bob","value":"todo"}"#,
+        );
+        sessions.sent(&token, r#"{"type":"join","username":"bob"}"#);
+        sessions.end(&token);
+
+        assert!(sessions.take(&token, "other", None).is_none());
+        // Tokens are single use
+        assert!(sessions.take(&token, "room", None).is_none());
+
+        let token = sessions.issue("alice", "room", None);
+        sessions.delivered(&token, None, "hello");
+        sessions.sent(
+            &token,
+            r#"{"doc_id":"notes","type":"message","username":"bob","value":"todo"}"#,
+        );
+        assert!(sessions.take(&token, "room", Some(1)).is_none());
+
+        let token = sessions.issue("alice", "room", None);
+        sessions.delivered(&token, None, "hello");
+        sessions.sent(
+            &token,
+            r#"{"doc_id":"notes","type":"message","username":"bob","value":"todo"}"#,
+        );
+        let resumed = sessions.take(&token, "room", None).unwrap();
+        assert_eq!(resumed.username, "alice");
+        assert!(resumed.has(None, "hello"));
+        assert!(!resumed.has(None, "hello world"));
+        assert!(resumed.has(Some("notes"), "todo"));
+        assert!(!resumed.has(Some("other"), ""));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::auth::{self, AuthUser};
+use crate::members::{self, Access};
+use crate::ranges::{self, RangeQuery, MAX_RANGE_LENGTH};
+use crate::{
+    encryption, ensure_room_loaded, AppState, CustomError, RoomState, SocketMessage,
+    SocketMessageType,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Whether the `If-Match` header of a request lets it write over `revision`.
+/// Entity tags are revision numbers, quoted or not, `*` matches any.
+fn if_match_passes(if_match: &str, revision: u64) -> Result<bool, String> {
+    let mut passes = false;
+    for tag in if_match.split(',').map(str::trim) {
+        if tag == "*" {
+            passes = true;
+            continue;
+        }
+        let tag = tag.strip_prefix("W/").unwrap_or(tag);
+        let tag = tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .unwrap_or(tag);
+        let tag: u64 = tag
+            .parse()
+            .map_err(|_| "If-Match takes revision numbers.".to_string())?;
+        passes |= tag == revision;
+    }
+    Ok(passes)
+}
+
+fn etag(revision: u64) -> (header::HeaderName, String) {
+    (header::ETAG, format!("\"{revision}\""))
+}
+
+/// Access of the user of a request to a room, private rooms are only read by their members
+pub(crate) async fn request_access(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<Access, CustomError> {
+    let user = auth::current_user(state, headers).await;
+    user_access(state, user.as_ref(), room_id).await
+}
+
+async fn user_access(
+    state: &AppState,
+    user: Option<&AuthUser>,
+    room_id: &str,
+) -> Result<Access, CustomError> {
+    let access = members::access(&state.db, room_id, user.map(|user| user.id), None).await;
+    if access == Access::Denied {
+        return Err(CustomError::new(
+            StatusCode::FORBIDDEN,
+            "This room is private.",
+        ));
+    }
+    Ok(access)
+}
+
+/// Make sure the user of a request may edit a room.
+/// Returns their username, empty if anonymous, for [`check_edit`].
+pub(crate) async fn check_editor(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<String, CustomError> {
+    let user = auth::current_user(state, headers).await;
+    if !user_access(state, user.as_ref(), room_id).await?.can_edit() {
+        return Err(CustomError::new(
+            StatusCode::FORBIDDEN,
+            "Viewers can't edit this room.",
+        ));
+    }
+    Ok(user.map(|user| user.username).unwrap_or_default())
+}
+
+/// Make sure `username` can write a room now, `423 Locked` if it is frozen or someone else
+/// is presenting
+pub(crate) async fn check_writable(room: &RoomState, username: &str) -> Result<(), CustomError> {
+    match room.edit_refusal(username).await {
+        Some((_, notice)) => Err(CustomError::new(StatusCode::LOCKED, notice)),
+        None => Ok(()),
+    }
+}
+
+/// Make sure `username` can write `new` over a document of a room now: `423 Locked` as with
+/// [`check_writable`], `409 Conflict` if it changes lines someone else claimed
+pub(crate) async fn check_edit(
+    room: &RoomState,
+    username: &str,
+    doc_id: Option<&str>,
+    new: &str,
+) -> Result<(), CustomError> {
+    check_writable(room, username).await?;
+    room.edit_lines(username, doc_id, new)
+        .await
+        .map_err(|e| CustomError::new(StatusCode::CONFLICT, e))
+}
+
+/// `GET /api/rooms/:room_id/content`, the main content of a room, its revision as `ETag`.
+/// With `offset` or `length`, a chunk of it as `206 Partial Content` with a `Content-Range`.
+pub(crate) async fn get_content(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    Query(query): Query<RangeQuery>,
+    headers: HeaderMap,
+) -> Result<Response, CustomError> {
+    request_access(&state, &headers, &room_id).await?;
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[&room_id];
+    let revision = room.revision(None).await;
+    let content = room.read_content(&state, &room_id).await?;
+    drop(rooms);
+
+    let content_type = (
+        header::CONTENT_TYPE,
+        "text/plain; charset=utf-8".to_string(),
+    );
+    if !query.is_partial() {
+        return Ok(([content_type, etag(revision)], content).into_response());
+    }
+    let range = ranges::slice(
+        &content,
+        query.offset.unwrap_or(0),
+        query.length.unwrap_or(MAX_RANGE_LENGTH),
+    )
+    .map_err(CustomError::bad_request)?;
+    let content_range = if range.end > range.offset {
+        format!("bytes {}-{}/{}", range.offset, range.end - 1, range.total)
+    } else {
+        format!("bytes */{}", range.total)
+    };
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        [
+            content_type,
+            etag(revision),
+            (header::CONTENT_RANGE, content_range),
+        ],
+        range.content,
+    )
+        .into_response())
+}
+
+/// `PUT /api/rooms/:room_id/content`, replace the main content of a room with the body.
+/// With `If-Match`, only if the room is still at that revision, `409 Conflict` otherwise.
+pub(crate) async fn set_content(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    content: String,
+) -> Result<Response, CustomError> {
+    let username = check_editor(&state, &headers, &room_id).await?;
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| CustomError::bad_request("Invalid If-Match header."))
+        })
+        .transpose()?;
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    let room = &rooms[&room_id];
+    let encrypted = room.encryption.is_some();
+    // The server must never receive the plaintext of an encrypted room
+    if encrypted && !encryption::is_ciphertext(&content) {
+        return Err(CustomError::bad_request(
+            "Encrypted rooms only accept ciphertext.",
+        ));
+    }
+    check_writable(room, &username).await?;
+
+    let current = room.revision(None).await;
+    if let Some(if_match) = if_match {
+        if !if_match_passes(if_match, current).map_err(CustomError::bad_request)? {
+            return Err(CustomError::new(
+                StatusCode::CONFLICT,
+                format!("The room was edited since, it is at revision {current}."),
+            ));
+        }
+    }
+    check_edit(room, &username, None, &content).await?;
+
+    room.update_content(&state, &room_id, None, &content)
+        .await
+        .map_err(CustomError::bad_request)?;
+    let revision = room.revision(None).await;
+    let _ = room.tx.send(
+        json!(SocketMessage {
+            doc_id: None,
+            message_type: SocketMessageType::content(encrypted),
+            value: Some(content),
+            code: None,
+            revision: Some(revision),
+            username: "Server".to_string(),
+        })
+        .to_string(),
+    );
+    drop(rooms);
+
+    Ok((
+        [etag(revision)],
+        Json(json!({
+            "type": "success",
+            "value": revision
+        })),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::if_match_passes;
+
+    #[test]
+    fn test_if_match_passes() {
+        assert_eq!(if_match_passes("3", 3), Ok(true));
+        assert_eq!(if_match_passes("\"3\"", 3), Ok(true));
+        assert_eq!(if_match_passes("W/\"2\", \"3\"", 3), Ok(true));
+        assert_eq!(if_match_passes("*", 3), Ok(true));
+        assert_eq!(if_match_passes("\"2\"", 3), Ok(false));
+        assert!(if_match_passes("\"abc\"", 3).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::Config;
+use crate::error_code::ErrorCode;
+use crate::rate_limit::RateLimiter;
+use crate::CustomError;
+use axum::http::StatusCode;
+use regex::Regex;
+use sqlx::SqlitePool;
+use std::net::IpAddr;
+
+/// Why a room can't be created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Refusal {
+    /// Its id matches `RESERVED_ROOM_NAMES`
+    Reserved,
+    /// The client created too many rooms lately
+    Throttled,
+    /// The server holds `MAX_ROOMS` rooms already
+    TooManyRooms,
+}
+
+impl Refusal {
+    pub(crate) const fn message(self) -> &'static str {
+        match self {
+            Self::Reserved => "This room name is reserved.",
+            Self::Throttled => "Too many rooms created, try again later.",
+            Self::TooManyRooms => "This server can't hold more rooms.",
+        }
+    }
+
+    pub(crate) const fn code(self) -> ErrorCode {
+        match self {
+            Self::Reserved => ErrorCode::ReservedRoomId,
+            Self::Throttled => ErrorCode::RateLimited,
+            Self::TooManyRooms => ErrorCode::TooManyRooms,
+        }
+    }
+
+    const fn status(self) -> StatusCode {
+        match self {
+            Self::Reserved => StatusCode::FORBIDDEN,
+            Self::Throttled => StatusCode::TOO_MANY_REQUESTS,
+            Self::TooManyRooms => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl From<Refusal> for CustomError {
+    fn from(refusal: Refusal) -> Self {
+        Self::new(refusal.status(), refusal.message())
+    }
+}
+
+/// Who may create which rooms, so that join messages can't be spammed into endless rooms.
+/// Only new rooms are checked, existing ones can always be joined.
+#[derive(Debug)]
+pub(crate) struct RoomCreation {
+    limiter: RateLimiter,
+    max_rooms: usize,
+    reserved: Option<Regex>,
+}
+
+impl RoomCreation {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            limiter: RateLimiter::per_minute(
+                config.room_creations_per_minute,
+                config.room_creation_burst,
+            ),
+            max_rooms: config.max_rooms,
+            reserved: config.reserved_room_names.clone(),
+        }
+    }
+
+    /// Whether no room may be created under `room_id`
+    pub(crate) fn is_reserved(&self, room_id: &str) -> bool {
+        self.reserved
+            .as_ref()
+            .is_some_and(|reserved| reserved.is_match(room_id))
+    }
+
+    /// Check that `ip` may create `room_id`, counting it against its rate limit if so
+    pub(crate) async fn check(
+        &self,
+        db: &SqlitePool,
+        ip: IpAddr,
+        room_id: &str,
+    ) -> Result<(), Refusal> {
+        if self.is_reserved(room_id) {
+            return Err(Refusal::Reserved);
+        }
+        if self.max_rooms > 0 && count_rooms(db).await >= self.max_rooms {
+            return Err(Refusal::TooManyRooms);
+        }
+        if !self.limiter.check(ip) {
+            return Err(Refusal::Throttled);
+        }
+        Ok(())
+    }
+
+    /// Forget clients that have not created rooms lately
+    pub(crate) fn cleanup(&self) {
+        self.limiter
+            .cleanup(std::time::Duration::from_secs(60 * 60));
+    }
+}
+
+/// Rooms stored, trashed ones included as they still take room
+async fn count_rooms(db: &SqlitePool) -> usize {
+    match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM rooms")
+        .fetch_one(db)
+        .await
+    {
+        Ok(count) => usize::try_from(count).unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Failed to count rooms: {e}");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Refusal, RoomCreation};
+    use crate::config::Config;
+    use crate::database;
+    use regex::Regex;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[tokio::test]
+    async fn test_room_creation() {
+        let db = database::memory().await.unwrap();
+        let creation = RoomCreation::new(&Config {
+            room_creations_per_minute: 1,
+            room_creation_burst: 2,
+            max_rooms: 2,
+            reserved_room_names: Some(Regex::new("^(?:admin|api)$").unwrap()),
+            ..Config::default()
+        });
+
+        assert_eq!(
+            creation.check(&db, IP, "admin").await,
+            Err(Refusal::Reserved)
+        );
+        assert!(!creation.is_reserved("administration"));
+        assert_eq!(creation.check(&db, IP, "a").await, Ok(()));
+        assert_eq!(creation.check(&db, IP, "b").await, Ok(()));
+        assert_eq!(creation.check(&db, IP, "c").await, Err(Refusal::Throttled));
+
+        for room_id in ["a", "b"] {
+            sqlx::query("INSERT INTO rooms (room_id, content) VALUES (?, '')")
+                .bind(room_id)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            creation.check(&db, IP, "c").await,
+            Err(Refusal::TooManyRooms)
+        );
+    }
+}
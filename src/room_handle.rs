@@ -0,0 +1,63 @@
+use tokio_util::sync::CancellationToken;
+
+/// Lifetime of a room, its documents are written to the database until it stops.
+/// It stops with [`RoomHandle::shutdown`] when the room is removed or evicted, and when the
+/// handle is dropped in any other way, so that nothing is written for a room that is gone.
+#[derive(Debug, Default)]
+pub(crate) struct RoomHandle {
+    cancel: CancellationToken,
+}
+
+impl RoomHandle {
+    /// Handle of a part of the room, like a document, stopped with the room or on its own
+    pub(crate) fn child(&self) -> Self {
+        Self {
+            cancel: self.cancel.child_token(),
+        }
+    }
+
+    /// Cancelled once the room, or this part of it, stopped
+    pub(crate) fn stopped(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Stop the room, for good
+    pub(crate) fn shutdown(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for RoomHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoomHandle;
+
+    #[test]
+    fn test_shutdown_stops_parts() {
+        let handle = RoomHandle::default();
+        let document = handle.child();
+        let other = handle.child();
+        let stopped = document.stopped();
+
+        // A document stops on its own
+        drop(document);
+        assert!(stopped.is_cancelled());
+        assert!(!handle.stopped().is_cancelled());
+
+        handle.shutdown();
+        assert!(other.stopped().is_cancelled());
+    }
+
+    #[test]
+    fn test_drop_stops_room() {
+        let handle = RoomHandle::default();
+        let stopped = handle.stopped();
+        drop(handle);
+        assert!(stopped.is_cancelled());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{AppState, CustomError};
+use axum::extract::{MatchedPath, OriginalUri, RawPathParams, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest room id, in bytes
+const MAX_LENGTH: usize = 128;
+
+/// Characters of room ids besides letters and digits, none of them special in URLs
+const PUNCTUATION: [char; 4] = ['-', '_', '.', '~'];
+
+fn is_allowed(c: char) -> bool {
+    c.is_alphanumeric() || PUNCTUATION.contains(&c)
+}
+
+/// Check a room id given by a client, which ends up in URLs, file names and the database
+pub(crate) fn validate(room_id: &str) -> Result<(), String> {
+    if room_id.is_empty() {
+        return Err("The room id is empty.".to_string());
+    }
+    if room_id.len() > MAX_LENGTH {
+        return Err(format!("Room ids are {MAX_LENGTH} bytes long at most."));
+    }
+    if !room_id.chars().all(is_allowed) {
+        return Err("Room ids only hold letters, digits, '-', '_', '.' and '~'.".to_string());
+    }
+    // `.` and `..` would be taken for path segments
+    if room_id.chars().all(|c| c == '.') {
+        return Err("Invalid room id.".to_string());
+    }
+    Ok(())
+}
+
+/// The room a client means by `raw`, so that `Café` typed on different keyboards is a
+/// single room: the id in Unicode normalization form C, slugified with `slugify`
+pub(crate) fn normalize(raw: &str, slugify: bool) -> Result<String, String> {
+    let room_id: String = raw.trim().nfc().collect();
+    let room_id = if slugify { to_slug(&room_id) } else { room_id };
+    validate(&room_id)?;
+    Ok(room_id)
+}
+
+/// Lowercase, with every run of other characters than letters and digits turned into a `-`,
+/// accents dropped: `Café Notes!` is `cafe-notes`
+fn to_slug(room_id: &str) -> String {
+    let mut slug = String::with_capacity(room_id.len());
+    // Letters and their combining accents apart
+    for c in room_id.nfd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    slug.nfc().collect()
+}
+
+/// `room_id` as a path segment
+fn encode_segment(room_id: &str) -> String {
+    let mut segment = String::with_capacity(room_id.len());
+    for byte in room_id.bytes() {
+        if byte.is_ascii_alphanumeric() || PUNCTUATION.contains(&char::from(byte)) {
+            segment.push(char::from(byte));
+        } else {
+            let _ = write!(segment, "%{byte:02X}");
+        }
+    }
+    segment
+}
+
+/// Refuse the requests naming an invalid room, and redirect those naming a room in another
+/// form than its normalized id to the same URL with the normalized id
+pub(crate) async fn normalize_path(
+    State(state): State<Arc<AppState>>,
+    params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some((_, raw)) = params.iter().find(|(key, _)| *key == "room_id") else {
+        return next.run(request).await;
+    };
+    let room_id = match normalize(raw, state.config.slugify_room_ids) {
+        Ok(room_id) => room_id,
+        Err(e) => return CustomError::bad_request(e).into_response(),
+    };
+    if room_id == raw {
+        return next.run(request).await;
+    }
+
+    // The room id is the segment at the same place from the end in the route and the URL
+    let Some(matched) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let Some(from_end) = matched
+        .as_str()
+        .rsplit('/')
+        .position(|segment| segment == ":room_id")
+    else {
+        return next.run(request).await;
+    };
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or_else(|| request.uri().clone(), |original| original.0.clone());
+    let mut segments: Vec<&str> = uri.path().split('/').collect();
+    let Some(index) = segments.len().checked_sub(from_end + 1) else {
+        return next.run(request).await;
+    };
+    let encoded = encode_segment(&room_id);
+    segments[index] = &encoded;
+    let mut location = segments.join("/");
+    if let Some(query) = uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    (
+        StatusCode::PERMANENT_REDIRECT,
+        [(header::LOCATION, location)],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_segment, normalize, validate};
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("general").is_ok());
+        assert!(validate("Notes_2024-01.v2~draft").is_ok());
+        assert!(validate("café").is_ok());
+        for invalid in [
+            "",
+            "..",
+            ".",
+            "../etc",
+            "a/b",
+            "a b",
+            "🎉",
+            "tab\t",
+            "a".repeat(129).as_str(),
+        ] {
+            assert!(validate(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        // Decomposed and precomposed forms are the same room
+        assert_eq!(normalize("Cafe\u{301}", false).unwrap(), "Caf\u{e9}");
+        assert_eq!(normalize(" notes ", false).unwrap(), "notes");
+        assert!(normalize("Café Notes!", false).is_err());
+        assert_eq!(normalize("Café Notes!", true).unwrap(), "cafe-notes");
+        assert_eq!(normalize("--Hello, World--", true).unwrap(), "hello-world");
+        assert_eq!(normalize("my_room", true).unwrap(), "my_room");
+        assert!(normalize("🎉", true).is_err());
+    }
+
+    #[test]
+    fn test_encode_segment() {
+        assert_eq!(encode_segment("notes-1"), "notes-1");
+        assert_eq!(encode_segment("café"), "caf%C3%A9");
+    }
+}
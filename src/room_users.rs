@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+/// Users of a room, by name. Accounts may join from several tabs under the same name,
+/// they stay in the room until their last connection closes.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RoomUsers {
+    /// Open connections of each user
+    connections: HashMap<String, usize>,
+}
+
+impl RoomUsers {
+    /// Add a connection of `username`, returning whether it is the first one
+    pub(crate) fn join(&mut self, username: &str) -> bool {
+        let connections = self.connections.entry(username.to_string()).or_default();
+        *connections += 1;
+        *connections == 1
+    }
+
+    /// Remove a connection of `username`, returning whether it was the last one
+    pub(crate) fn leave(&mut self, username: &str) -> bool {
+        let Some(connections) = self.connections.get_mut(username) else {
+            return false;
+        };
+        *connections -= 1;
+        if *connections > 0 {
+            return false;
+        }
+        self.connections.remove(username);
+        true
+    }
+
+    pub(crate) fn contains(&self, username: &str) -> bool {
+        self.connections.contains_key(username)
+    }
+
+    /// Number of users, whatever their number of connections
+    pub(crate) fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &String> {
+        self.connections.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoomUsers;
+
+    #[test]
+    fn test_room_users() {
+        let mut users = RoomUsers::default();
+        assert!(users.join("alice"));
+        assert!(!users.join("alice"));
+        assert!(users.join("bob"));
+        assert_eq!(users.len(), 2);
+
+        // One tab of alice closes, she is still there
+        assert!(!users.leave("alice"));
+        assert!(users.contains("alice"));
+        assert!(users.leave("alice"));
+        assert!(!users.contains("alice"));
+        assert!(!users.leave("alice"));
+
+        assert!(users.leave("bob"));
+        assert!(users.is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+use crate::auto_clear::{self, AutoClear};
+use crate::burn::{self, Burn};
+use crate::documents::{self, Document, MAX_DOCUMENTS};
+use crate::encryption::EncryptionParams;
+use crate::freeze::FreezeSchedule;
+use crate::line_claims::LineClaims;
+use crate::metrics::RoomActivity;
+use crate::ot::Sequencers;
+use crate::room_handle::RoomHandle;
+use crate::room_users::RoomUsers;
+use crate::sequence::RoomSender;
+use crate::storage::{
+    get_stored_content, get_stored_encryption, get_stored_freeze_schedule,
+    get_stored_syntax_language,
+};
+use crate::write_behind::{Listeners, Write, WriteBehind};
+use crate::write_lock::WriteLock;
+use crate::ws::{freeze_message, syntax_language_message};
+use crate::{
+    admission, attachments, content_log, unix_timestamp, AppState, SocketMessage, SocketMessageType,
+};
+use anyhow::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{watch, Mutex};
+use tokio::time::{self, Duration, Instant};
+
+pub(crate) static DEFAULT_ROOM: &str = "general";
+
+/// Consecutive failed writes after which the members of a room are told it is memory-only
+const PERSISTENCE_FAILURES_BEFORE_DEGRADED: u32 = 3;
+
+pub(crate) const DEFAULT_PERSIST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Delay between writes of changed contents, set once at startup from [`Config`]
+pub(crate) static PERSIST_INTERVAL: OnceLock<Duration> = OnceLock::new();
+
+/// Messages a room keeps for its slowest member, it misses the older ones past that
+pub(crate) const DEFAULT_BROADCAST_CAPACITY: usize = 100;
+
+/// Capacity of the room broadcast channels, set once at startup from [`Config`]
+pub(crate) static BROADCAST_CAPACITY: OnceLock<usize> = OnceLock::new();
+
+pub(crate) fn broadcast_capacity() -> usize {
+    BROADCAST_CAPACITY
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_BROADCAST_CAPACITY)
+}
+
+const MAX_SYNTAX_LANGUAGE_LENGTH: usize = 32;
+
+/// State of a room
+#[derive(Debug)]
+pub(crate) struct RoomState {
+    pub(crate) users: Mutex<RoomUsers>,
+    pub(crate) tx: RoomSender,
+    pub(crate) content_tx: watch::Sender<String>,
+    pub(crate) content_rx: watch::Receiver<String>,
+    /// Stops writing the room and its documents to the database
+    pub(crate) handle: RoomHandle,
+    pub(crate) last_activity: Mutex<Instant>,
+    pub(crate) freeze_schedule: Mutex<FreezeSchedule>,
+    /// Whether the freeze schedule made the room read-only, as last announced to its members
+    pub(crate) frozen: AtomicBool,
+    /// Set while the content can't be written to the database
+    pub(crate) persistence_degraded: Arc<AtomicBool>,
+    /// Set while the content has changes not written to the database yet
+    pub(crate) unsaved: Arc<AtomicBool>,
+    /// End-to-end encrypted rooms only hold ciphertext, set when the room is created
+    pub(crate) encryption: Option<EncryptionParams>,
+    /// Documents besides the main one, by id
+    pub(crate) documents: Mutex<HashMap<String, Document>>,
+    /// Language used by the clients to highlight the content, like `rust`
+    pub(crate) syntax_language: Mutex<Option<String>>,
+    /// Content cleared once nobody edited it for a while
+    pub(crate) auto_clear: Mutex<AutoClear>,
+    /// Wiping of the content once read
+    pub(crate) burn: Mutex<Burn>,
+    /// Last edit of any document of the room
+    pub(crate) last_edit: Mutex<Instant>,
+    /// When the room is deleted, set while it counts down to its deletion
+    pub(crate) closing_at: Mutex<Option<Instant>>,
+    /// Unix timestamp of the creation of the room, or of its loading if it was stored
+    pub(crate) created_at: i64,
+    /// Operations of the documents followed by clients with the `ot` capability, by document id
+    pub(crate) sequencers: Sequencers,
+    /// Messages received from the members, shared with their connections
+    pub(crate) activity: Arc<RoomActivity>,
+    /// Member allowed to edit the room while the others only read it, if any
+    pub(crate) write_lock: Mutex<WriteLock>,
+    /// Lines of the documents claimed by the members, which only they can change
+    pub(crate) line_claims: Mutex<LineClaims>,
+}
+
+/// Tracks consecutive write failures of a room, to report degraded persistence only once
+#[derive(Debug, Default)]
+pub(crate) struct PersistenceHealth {
+    pub(crate) failures: u32,
+}
+
+impl PersistenceHealth {
+    /// Record the outcome of a write.
+    /// Returns the new degraded state when it changes, `None` otherwise.
+    pub(crate) fn record(&mut self, success: bool) -> Option<bool> {
+        let was_degraded = self.failures >= PERSISTENCE_FAILURES_BEFORE_DEGRADED;
+        self.failures = if success {
+            0
+        } else {
+            self.failures.saturating_add(1)
+        };
+        let degraded = self.failures >= PERSISTENCE_FAILURES_BEFORE_DEGRADED;
+        (degraded != was_degraded).then_some(degraded)
+    }
+}
+
+impl RoomState {
+    pub(crate) fn new(room_id: &str, write_behind: &WriteBehind) -> Self {
+        let (content_tx, content_rx) = watch::channel(String::new());
+        let tx = RoomSender::new(broadcast_capacity());
+        let persistence_degraded = Arc::new(AtomicBool::new(false));
+        let sequencers = Sequencers::default();
+
+        let handle = RoomHandle::default();
+        let unsaved = write_behind.track(
+            room_id,
+            None,
+            &content_rx,
+            &Listeners {
+                tx: tx.clone(),
+                persistence_degraded: persistence_degraded.clone(),
+                sequencers: sequencers.clone(),
+            },
+            &handle,
+        );
+
+        Self {
+            users: Mutex::new(RoomUsers::default()),
+            tx,
+            content_tx,
+            content_rx,
+            handle,
+            last_activity: Mutex::new(Instant::now()),
+            freeze_schedule: Mutex::new(FreezeSchedule::default()),
+            frozen: AtomicBool::new(false),
+            persistence_degraded,
+            unsaved,
+            encryption: None,
+            documents: Mutex::new(HashMap::new()),
+            syntax_language: Mutex::new(None),
+            auto_clear: Mutex::new(AutoClear { minutes: None }),
+            burn: Mutex::new(Burn::default()),
+            last_edit: Mutex::new(Instant::now()),
+            closing_at: Mutex::new(None),
+            created_at: unix_timestamp(),
+            sequencers,
+            activity: Arc::default(),
+            write_lock: Mutex::new(WriteLock::default()),
+            line_claims: Mutex::new(LineClaims::default()),
+        }
+    }
+
+    /// Members of the room, for the actor writing its documents
+    pub(crate) fn listeners(&self) -> Listeners {
+        Listeners {
+            tx: self.tx.clone(),
+            persistence_degraded: self.persistence_degraded.clone(),
+            sequencers: self.sequencers.clone(),
+        }
+    }
+
+    pub(crate) fn with_syntax_language(mut self, language: Option<String>) -> Self {
+        self.syntax_language = Mutex::new(language);
+        self
+    }
+
+    /// Change the highlighting language of the room and announce it to its members
+    pub(crate) async fn set_syntax_language(
+        &self,
+        state: &AppState,
+        room_id: &str,
+        language: Option<String>,
+    ) -> Result<(), String> {
+        if let Some(language) = &language {
+            validate_syntax_language(language)?;
+        }
+        let content = self.content_rx.borrow().clone();
+        if let Err(e) = sqlx::query(
+            r"
+            INSERT INTO rooms (room_id, content, syntax_language) VALUES (?, ?, ?)
+            ON CONFLICT (room_id) DO UPDATE SET syntax_language = excluded.syntax_language
+            ",
+        )
+        .bind(room_id)
+        .bind(content)
+        .bind(language.as_deref())
+        .execute(&state.db)
+        .await
+        {
+            eprintln!("Failed to store room language in database: {e}");
+            return Err("Failed to store the language.".to_string());
+        }
+
+        self.syntax_language.lock().await.clone_from(&language);
+        let _ = self.tx.send(syntax_language_message(language));
+        Ok(())
+    }
+
+    pub(crate) fn with_encryption(mut self, encryption: Option<EncryptionParams>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Restore the documents of a room that has no members yet
+    pub(crate) fn with_documents(
+        mut self,
+        write_behind: &WriteBehind,
+        room_id: &str,
+        documents: Vec<(String, String)>,
+    ) -> Self {
+        let documents = documents
+            .into_iter()
+            .map(|(doc_id, content)| {
+                let document = Document::new(
+                    write_behind,
+                    room_id,
+                    &doc_id,
+                    content,
+                    &self.listeners(),
+                    &self.handle,
+                );
+                (doc_id, document)
+            })
+            .collect();
+        self.documents = Mutex::new(documents);
+        self
+    }
+
+    /// Content of a document, the main one if `doc_id` is `None`
+    pub(crate) async fn content_of(&self, doc_id: Option<&str>) -> Option<String> {
+        match doc_id {
+            None => Some(self.content_rx.borrow().clone()),
+            Some(doc_id) => self
+                .documents
+                .lock()
+                .await
+                .get(doc_id)
+                .map(|document| document.content_rx.borrow().clone()),
+        }
+    }
+
+    /// Update a document, the main one if `doc_id` is `None`.
+    /// Other documents are created by their first edit.
+    pub(crate) async fn update_content(
+        &self,
+        state: &AppState,
+        room_id: &str,
+        doc_id: Option<&str>,
+        content: &str,
+    ) -> Result<(), String> {
+        *self.last_edit.lock().await = Instant::now();
+        state.webhooks.content_updated(room_id);
+        let Some(doc_id) = doc_id else {
+            let previous_bytes = self.content_rx.borrow().len();
+            content_log::edit(state.config.content_log, room_id, previous_bytes, content);
+
+            // ignore errors but log them
+            self.content_tx
+                .send(content.to_string())
+                .unwrap_or_else(|err| eprintln!("Failed to send message to room: {err}"));
+            return Ok(());
+        };
+
+        let mut documents = self.documents.lock().await;
+        if !documents.contains_key(doc_id) {
+            documents::validate_doc_id(doc_id)?;
+            if documents.len() >= MAX_DOCUMENTS {
+                return Err(format!(
+                    "A room has at most {MAX_DOCUMENTS} documents besides the main one."
+                ));
+            }
+            let document = Document::new(
+                &state.write_behind,
+                room_id,
+                doc_id,
+                String::new(),
+                &self.listeners(),
+                &self.handle,
+            );
+            documents.insert(doc_id.to_string(), document);
+        }
+
+        let document = &documents[doc_id];
+        let previous_bytes = document.content_rx.borrow().len();
+        content_log::edit(state.config.content_log, room_id, previous_bytes, content);
+        document.content_tx.send_replace(content.to_string());
+        drop(documents);
+        Ok(())
+    }
+
+    /// Set the freeze schedule of a room that has no members yet
+    pub(crate) fn with_freeze_schedule(mut self, schedule: FreezeSchedule) -> Self {
+        self.frozen = AtomicBool::new(schedule.frozen_until(unix_timestamp()).is_some());
+        self.freeze_schedule = Mutex::new(schedule);
+        self
+    }
+
+    /// Announce a freeze or unfreeze to the members if the schedule changed the state of the room
+    pub(crate) async fn update_frozen(&self, now: i64) {
+        let until = self.freeze_schedule.lock().await.frozen_until(now);
+        if self.frozen.swap(until.is_some(), Ordering::Relaxed) != until.is_some() {
+            let _ = self.tx.send(freeze_message(until).to_string());
+        }
+    }
+
+    /// Stop writing the room and its documents to the database, they are flushed on their own
+    pub(crate) fn shutdown(&self) {
+        self.handle.shutdown();
+    }
+}
+
+/// Restore a room that is only stored (e.g. after an eviction)
+pub(crate) async fn restore_room(state: &AppState, room_id: &str) -> Option<RoomState> {
+    let content = get_stored_content(state, room_id).await?;
+    let documents = state.storage.documents(room_id).await.unwrap_or_else(|e| {
+        eprintln!("Failed to read room documents from storage: {e:#}");
+        Vec::new()
+    });
+    println!("Restoring room: {room_id}");
+    let room_state = RoomState::new(room_id, &state.write_behind)
+        .with_freeze_schedule(get_stored_freeze_schedule(&state.db, room_id).await)
+        .with_encryption(get_stored_encryption(&state.db, room_id).await)
+        .with_syntax_language(get_stored_syntax_language(&state.db, room_id).await)
+        .with_auto_clear(auto_clear::load(&state.db, room_id).await)
+        .with_burn(burn::load(&state.db, room_id).await)
+        .with_documents(&state.write_behind, room_id, documents);
+    let _ = room_state.content_tx.send(content);
+    Some(room_state)
+}
+
+/// Make sure a room is in memory, restoring it from the database if needed.
+/// Returns `false` if the room doesn't exist at all.
+pub(crate) async fn ensure_room_loaded(
+    state: &AppState,
+    rooms: &mut HashMap<String, RoomState>,
+    room_id: &str,
+) -> bool {
+    if rooms.contains_key(room_id) {
+        return true;
+    }
+    match restore_room(state, room_id).await {
+        Some(room_state) => {
+            rooms.insert(room_id.to_string(), room_state);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Tear down rooms that have been empty for longer than `timeout`, flushing their content first
+pub(crate) async fn evict_idle_rooms_once(state: &AppState, timeout: Duration) {
+    let mut rooms = state.rooms.lock().await;
+
+    let mut idle_rooms = Vec::new();
+    for (room_id, room) in rooms.iter() {
+        // The default room always stays loaded
+        if room_id == DEFAULT_ROOM {
+            continue;
+        }
+        // Rooms waiting to be cleared stay loaded, their content must not outlive them
+        let auto_clear = *room.auto_clear.lock().await;
+        let awaiting_clear = auto_clear.minutes.is_some() && !room.is_empty().await;
+        if room.users.lock().await.is_empty()
+            && room.last_activity.lock().await.elapsed() >= timeout
+            && !awaiting_clear
+        {
+            idle_rooms.push(room_id.clone());
+        }
+    }
+
+    for room_id in idle_rooms {
+        let Some(room) = rooms.remove(&room_id) else {
+            continue;
+        };
+        room.shutdown();
+        flush_room(state, &room_id, &room).await;
+
+        println!("Evicted idle room: {room_id}");
+    }
+
+    drop(rooms);
+}
+
+/// Write the content of a room and its documents, which the write-behind may not have
+/// written yet, all of them or none
+pub(crate) async fn flush_room(state: &AppState, room_id: &str, room: &RoomState) {
+    let mut writes = Vec::new();
+    let content = room.content_rx.borrow().clone();
+    let stored = get_stored_content(state, room_id).await;
+    if stored.as_ref() != Some(&content) && !(stored.is_none() && content.is_empty()) {
+        writes.push(Write {
+            room_id: room_id.to_string(),
+            doc_id: None,
+            content,
+        });
+    }
+    for (doc_id, document) in room.documents.lock().await.iter() {
+        writes.push(Write {
+            room_id: room_id.to_string(),
+            doc_id: Some(doc_id.clone()),
+            content: document.content_rx.borrow().clone(),
+        });
+    }
+    if let Err(e) = state.write_behind.write(&writes).await {
+        eprintln!("Failed to flush room to storage: {e:#}");
+    }
+}
+
+/// Let the clients leave on their own before the server stops: announce the shutdown with a
+/// reconnection delay, refuse new connections, and close the sockets still open after
+/// `SHUTDOWN_GRACE_SECONDS`
+pub(crate) async fn drain(state: &AppState) {
+    state.draining.cancel();
+
+    let retry_after = admission::reconnect_delay(state.config.reconnect_jitter);
+    let grace = state.config.shutdown_grace;
+    let message = json!(SocketMessage! {
+        message_type: SocketMessageType::ServerShutdown,
+        value: Some(
+            json!({
+                "retry_after_ms": retry_after.as_millis(),
+                "grace_ms": grace.as_millis(),
+            })
+            .to_string()
+        ),
+    })
+    .to_string();
+    let rooms = state.rooms.lock().await;
+    for room in rooms.values() {
+        let _ = room.tx.send(message.clone());
+    }
+    drop(rooms);
+
+    let connections = state.connections.list().len();
+    if connections > 0 {
+        println!("Draining {connections} connections for up to {grace:?}");
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline && !state.connections.list().is_empty() {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    // Close the remaining sockets with a reconnection hint
+    state.shutdown.cancel();
+}
+
+/// Flush every loaded room once the server stopped
+pub(crate) async fn flush_rooms(state: &AppState) {
+    let rooms = state.rooms.lock().await;
+    for (room_id, room) in rooms.iter() {
+        room.shutdown();
+        flush_room(state, room_id, room).await;
+    }
+    drop(rooms);
+}
+
+/// Periodically evict idle rooms
+pub(crate) async fn evict_idle_rooms(state: Arc<AppState>, timeout: Duration) {
+    let mut interval = time::interval(timeout.min(Duration::from_secs(60)));
+    loop {
+        interval.tick().await;
+        evict_idle_rooms_once(&state, timeout).await;
+    }
+}
+
+/// Announce freeze schedule transitions of loaded rooms, on every minute
+pub(crate) async fn apply_freeze_schedules(state: Arc<AppState>) {
+    loop {
+        let now = unix_timestamp();
+        time::sleep(Duration::from_secs(60 - now.rem_euclid(60).unsigned_abs())).await;
+
+        let now = unix_timestamp();
+        let rooms = state.rooms.lock().await;
+        for room in rooms.values() {
+            room.update_frozen(now).await;
+        }
+        drop(rooms);
+    }
+}
+
+/// Run the attachment garbage collector once
+pub(crate) async fn collect_attachment_garbage(state: &AppState) -> Option<attachments::GcReport> {
+    match state
+        .attachments
+        .collect_garbage(attachments::GC_GRACE_PERIOD)
+        .await
+    {
+        Ok(report) => {
+            if report.blobs_removed > 0 {
+                println!(
+                    "Removed {} unreferenced attachment blobs, {} bytes freed",
+                    report.blobs_removed, report.bytes_freed
+                );
+            }
+            Some(report)
+        }
+        Err(e) => {
+            eprintln!("Failed to collect attachment garbage: {e:#}");
+            None
+        }
+    }
+}
+
+/// Highlighting languages are names like `rust`, `c++` or `objective-c`
+pub(crate) fn validate_syntax_language(language: &str) -> Result<(), String> {
+    if language.is_empty()
+        || language.len() > MAX_SYNTAX_LANGUAGE_LENGTH
+        || !language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+#-_.".contains(c))
+    {
+        return Err(format!(
+            "Languages are 1 to {MAX_SYNTAX_LANGUAGE_LENGTH} letters, digits or +#-_. characters."
+        ));
+    }
+    Ok(())
+}
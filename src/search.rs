@@ -0,0 +1,272 @@
+use crate::compression::StoredContent;
+use crate::{auth, AppState, CustomError};
+use anyhow::Result;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Rooms returned by a search at most, the best matches first
+const MAX_RESULTS: i64 = 50;
+
+/// Words of the snippets around the matches
+const SNIPPET_TOKENS: i64 = 16;
+
+/// Marks around the matches in the snippets SQLite returns, before they are HTML-escaped
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// `GET /api/search` query
+#[derive(Deserialize, Debug)]
+pub(crate) struct SearchQuery {
+    q: String,
+}
+
+/// A room whose content matches a search
+#[derive(TS, sqlx::FromRow, Serialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct SearchResult {
+    pub(crate) room_id: String,
+    /// Excerpt of the content around the matches, HTML-escaped, the matches in `<mark>` tags
+    pub(crate) snippet: String,
+}
+
+/// FTS5 query matching the contents holding every word of `query`, `None` if it has none.
+/// Words are quoted for the FTS5 syntax never to fail on them.
+fn match_expression(query: &str) -> Option<String> {
+    let words: Vec<_> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// `snippet` HTML-escaped, with its matches in `<mark>` tags
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Index a content stored compressed, which the triggers of the `rooms` table can't read.
+/// Does nothing if the content of the room is stored as is, or encrypted.
+pub(crate) async fn index_compressed(
+    db: &mut SqliteConnection,
+    room_id: &str,
+    content: &str,
+) -> Result<()> {
+    sqlx::query(
+        r"
+        INSERT INTO room_search (room_id, content)
+        SELECT room_id, ? FROM rooms WHERE room_id = ? AND compressed AND NOT encrypted
+        ",
+    )
+    .bind(content)
+    .bind(room_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Index the compressed contents stored before the search index existed
+pub(crate) async fn index_missing(db: &SqlitePool) -> Result<()> {
+    let missing = sqlx::query_as::<_, (String, Option<Vec<u8>>)>(
+        r"
+        SELECT room_id, content_zstd FROM rooms
+        WHERE compressed AND NOT encrypted
+        AND room_id NOT IN (SELECT room_id FROM room_search)
+        ",
+    )
+    .fetch_all(db)
+    .await?;
+    let mut connection = db.acquire().await?;
+    for (room_id, content_zstd) in missing {
+        let stored = StoredContent {
+            content: String::new(),
+            content_zstd,
+            compressed: true,
+        };
+        match stored.decode() {
+            Ok(content) => index_compressed(&mut connection, &room_id, &content).await?,
+            Err(e) => eprintln!("Failed to index room {room_id}: {e:#}"),
+        }
+    }
+    Ok(())
+}
+
+/// Rooms matching `query` that a user, `None` if anonymous, can find: the public ones and
+/// those they own or are a member of. Rooms in the trash, archived or burning are left out.
+async fn search(db: &SqlitePool, query: &str, user_id: Option<i64>) -> Result<Vec<SearchResult>> {
+    let Some(expression) = match_expression(query) else {
+        return Ok(Vec::new());
+    };
+    let results = sqlx::query_as::<_, SearchResult>(
+        r"
+        SELECT room_search.room_id,
+            snippet(room_search, 1, char(2), char(3), '…', ?) AS snippet
+        FROM room_search JOIN rooms ON rooms.room_id = room_search.room_id
+        WHERE room_search MATCH ?
+        AND rooms.deleted_at IS NULL AND NOT rooms.archived AND rooms.burn_after IS NULL
+        AND (
+            rooms.visibility = 'public' OR rooms.owner_id = ?
+            OR rooms.room_id IN (SELECT room_id FROM room_members WHERE user_id = ?)
+        )
+        ORDER BY rank
+        LIMIT ?
+        ",
+    )
+    .bind(SNIPPET_TOKENS)
+    .bind(expression)
+    .bind(user_id)
+    .bind(user_id)
+    .bind(MAX_RESULTS)
+    .fetch_all(db)
+    .await?;
+    Ok(results
+        .into_iter()
+        .map(|result| SearchResult {
+            snippet: highlight(&result.snippet),
+            ..result
+        })
+        .collect())
+}
+
+/// `GET /api/search?q=`, the rooms whose content holds every word of `q`
+pub(crate) async fn search_rooms(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    if match_expression(&query.q).is_none() {
+        return Err(CustomError::bad_request("The search is empty."));
+    }
+    let user_id = auth::current_user(&state, &headers)
+        .await
+        .map(|account| account.id);
+    let results = search(&state.db, &query.q, user_id).await.map_err(|e| {
+        eprintln!("Failed to search rooms: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": results,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, index_missing, match_expression, search};
+    use crate::database;
+    use crate::storage::update_room_content;
+
+    #[test]
+    fn test_match_expression() {
+        assert_eq!(
+            match_expression(" rust  async "),
+            Some(r#""rust" "async""#.to_string())
+        );
+        assert_eq!(
+            match_expression(r#"say "hi"#),
+            Some(r#""say" """hi""#.to_string())
+        );
+        assert_eq!(match_expression("  "), None);
+        assert_eq!(
+            highlight("a <b> & \u{2}match\u{3}"),
+            "a &lt;b&gt; &amp; <mark>match</mark>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let db = database::memory().await.unwrap();
+        let mut connection = db.acquire().await.unwrap();
+        update_room_content(&mut connection, "notes", "Buy milk and eggs")
+            .await
+            .unwrap();
+        // Stored compressed
+        let large = format!("{} needle", "haystack ".repeat(5000));
+        update_room_content(&mut connection, "large", &large)
+            .await
+            .unwrap();
+        drop(connection);
+        sqlx::query(
+            r"
+            INSERT INTO rooms (room_id, content, visibility, encrypted)
+            VALUES ('secret', 'milk', 'private', FALSE), ('sealed', 'milk', 'public', TRUE)
+            ",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO rooms (room_id, content, deleted_at) VALUES ('trashed', 'milk', 1)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let found = |query: &'static str| {
+            let db = db.clone();
+            async move {
+                search(&db, query, None)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|result| result.room_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(found("MILK").await, ["notes"]);
+        assert_eq!(found("milk cheese").await, Vec::<String>::new());
+        assert_eq!(found("needle").await, ["large"]);
+        let snippet = &search(&db, "eggs", None).await.unwrap()[0].snippet;
+        assert_eq!(snippet, "Buy milk and <mark>eggs</mark>");
+
+        // Edits replace the indexed content
+        let mut connection = db.acquire().await.unwrap();
+        update_room_content(&mut connection, "notes", "Buy bread")
+            .await
+            .unwrap();
+        update_room_content(&mut connection, "large", "small now")
+            .await
+            .unwrap();
+        drop(connection);
+        assert!(found("milk").await.is_empty());
+        assert!(found("needle").await.is_empty());
+
+        sqlx::query("DELETE FROM rooms WHERE room_id = 'notes'")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert!(found("bread").await.is_empty());
+
+        // Compressed contents stored before the index
+        let mut connection = db.acquire().await.unwrap();
+        update_room_content(&mut connection, "large", &large)
+            .await
+            .unwrap();
+        drop(connection);
+        sqlx::query("DELETE FROM room_search")
+            .execute(&db)
+            .await
+            .unwrap();
+        index_missing(&db).await.unwrap();
+        assert_eq!(found("needle").await, ["large"]);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::freeze::FreezeSchedule;
+use crate::storage::{self, Storage};
+use crate::write_behind::Write;
+use crate::{auth, documents, unix_timestamp};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Declarative set of accounts and rooms, read from YAML.
+/// Only missing ones are created, so a seed can be applied at every startup.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Seed {
+    #[serde(default)]
+    users: Vec<SeedUser>,
+    #[serde(default)]
+    rooms: Vec<SeedRoom>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeedUser {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeedRoom {
+    id: String,
+    #[serde(default)]
+    content: String,
+    /// Named documents besides the main one
+    #[serde(default)]
+    documents: BTreeMap<String, String>,
+    /// Username of the owner, an account of the seed or an existing one
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    freeze_schedule: Option<FreezeSchedule>,
+}
+
+/// Outcome of applying a seed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SeedReport {
+    pub(crate) users_created: usize,
+    pub(crate) rooms_created: usize,
+    /// Already existing, left untouched
+    pub(crate) rooms_skipped: usize,
+}
+
+/// Parse the command line: `seed --file <path>` applies a seed and exits,
+/// no arguments start the server
+pub(crate) fn seed_file_from_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<Option<PathBuf>> {
+    match args.next().as_deref() {
+        None => Ok(None),
+        Some("seed") => match (args.next().as_deref(), args.next(), args.next()) {
+            (Some("--file"), Some(path), None) => Ok(Some(PathBuf::from(path))),
+            _ => bail!("Usage: partage seed --file <seed.yaml>"),
+        },
+        Some(command) => {
+            bail!("Unknown command {command}, expected: partage [seed --file <seed.yaml>]")
+        }
+    }
+}
+
+impl Seed {
+    pub(crate) fn parse(yaml: &str) -> Result<Self> {
+        let seed: Self = serde_yaml::from_str(yaml)?;
+        seed.validate()?;
+        Ok(seed)
+    }
+
+    /// Catch mistakes before anything is written
+    fn validate(&self) -> Result<()> {
+        for user in &self.users {
+            auth::validate_credentials(&user.username, &user.password)
+                .map_err(|e| anyhow::anyhow!("User {}: {}", user.username, e.message))?;
+        }
+        for room in &self.rooms {
+            if room.id.is_empty() {
+                bail!("Rooms need an id");
+            }
+            if room.documents.len() > documents::MAX_DOCUMENTS {
+                bail!(
+                    "Room {}: at most {} documents besides the main one",
+                    room.id,
+                    documents::MAX_DOCUMENTS
+                );
+            }
+            for doc_id in room.documents.keys() {
+                documents::validate_doc_id(doc_id)
+                    .map_err(|e| anyhow::anyhow!("Room {}: {e}", room.id))?;
+            }
+            if let Some(schedule) = &room.freeze_schedule {
+                schedule
+                    .validate()
+                    .map_err(|e| anyhow::anyhow!("Room {}: {e}", room.id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the accounts and rooms of the seed that don't exist yet, the contents of the
+    /// rooms going to `storage`
+    pub(crate) async fn apply(&self, db: &SqlitePool, storage: &dyn Storage) -> Result<SeedReport> {
+        let mut report = SeedReport::default();
+
+        for user in &self.users {
+            // Hashing is slow, don't do it at every startup for nothing
+            let exists = sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+                .bind(&user.username)
+                .fetch_optional(db)
+                .await?
+                .is_some();
+            if exists {
+                continue;
+            }
+            let password_hash = auth::hash_password(user.password.clone())
+                .await
+                .map_err(|e| anyhow::anyhow!(e.message))?;
+            let created = sqlx::query(
+                r"
+                INSERT INTO users (username, password_hash, created_at) VALUES (?, ?, ?)
+                ON CONFLICT (username) DO NOTHING
+                ",
+            )
+            .bind(&user.username)
+            .bind(password_hash)
+            .bind(unix_timestamp())
+            .execute(db)
+            .await?
+            .rows_affected();
+            report.users_created += usize::try_from(created).unwrap_or(0);
+        }
+
+        for room in &self.rooms {
+            let owner_id = match &room.owner {
+                Some(username) => Some(
+                    sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+                        .bind(username)
+                        .fetch_optional(db)
+                        .await?
+                        .with_context(|| {
+                            format!("Room {}: owner {username} doesn't exist", room.id)
+                        })?,
+                ),
+                None => None,
+            };
+            let freeze_schedule = room
+                .freeze_schedule
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
+
+            let created = sqlx::query(
+                r"
+                INSERT INTO rooms (room_id, content, owner_id, freeze_schedule) VALUES (?, '', ?, ?)
+                ON CONFLICT (room_id) DO NOTHING
+                ",
+            )
+            .bind(&room.id)
+            .bind(owner_id)
+            .bind(freeze_schedule)
+            .execute(db)
+            .await?
+            .rows_affected()
+                > 0;
+            if !created {
+                report.rooms_skipped += 1;
+                continue;
+            }
+
+            let writes = Write::room(&room.id, &room.content, &room.documents);
+            storage::store(db, storage, &writes).await?;
+            report.rooms_created += 1;
+        }
+
+        Ok(report)
+    }
+}
+
+/// Apply a seed file to the database and `storage`
+pub(crate) async fn run(db: &SqlitePool, storage: &dyn Storage, path: &Path) -> Result<SeedReport> {
+    let yaml = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let seed = Seed::parse(&yaml).with_context(|| format!("Invalid seed {}", path.display()))?;
+    let report = seed.apply(db, storage).await?;
+    println!(
+        "Seeded {}: {} users and {} rooms created, {} existing rooms skipped",
+        path.display(),
+        report.users_created,
+        report.rooms_created,
+        report.rooms_skipped
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seed_file_from_args, Seed, SeedReport};
+    use crate::database;
+    use crate::storage::SqliteStorage;
+    use std::path::PathBuf;
+
+    const SEED: &str = r"
+users:
+  - username: demo
+    password: demo-password
+rooms:
+  - id: welcome
+    content: Hello!
+    owner: demo
+    documents:
+      notes: Some notes
+  - id: standup
+    freeze_schedule:
+      windows: []
+";
+
+    #[test]
+    fn test_args() {
+        let args = |args: &[&str]| seed_file_from_args(args.iter().map(ToString::to_string));
+        assert_eq!(args(&[]).unwrap(), None);
+        assert_eq!(
+            args(&["seed", "--file", "seed.yaml"]).unwrap(),
+            Some(PathBuf::from("seed.yaml"))
+        );
+        assert!(args(&["seed"]).is_err());
+        assert!(args(&["seed", "--file", "a.yaml", "b.yaml"]).is_err());
+        assert!(args(&["serve"]).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Seed::parse(SEED).is_ok());
+        // Sections this server doesn't know about are refused rather than ignored
+        assert!(Seed::parse("tags: [a]").is_err());
+        assert!(Seed::parse("rooms:\n  - id: a\n    documents:\n      ../x: y").is_err());
+        assert!(Seed::parse("users:\n  - username: a\n    password: short").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let db = database::memory().await.unwrap();
+
+        let storage = SqliteStorage::new(db.clone());
+        let seed = Seed::parse(SEED).unwrap();
+        assert_eq!(
+            seed.apply(&db, &storage).await.unwrap(),
+            SeedReport {
+                users_created: 1,
+                rooms_created: 2,
+                rooms_skipped: 0,
+            }
+        );
+
+        let owner: Option<i64> =
+            sqlx::query_scalar("SELECT owner_id FROM rooms WHERE room_id = 'welcome'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(owner.is_some());
+        let notes: String = sqlx::query_scalar(
+            "SELECT content FROM documents WHERE room_id = 'welcome' AND doc_id = 'notes'",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(notes, "Some notes");
+
+        // Existing rooms are left alone
+        sqlx::query("UPDATE rooms SET content = 'edited' WHERE room_id = 'welcome'")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            seed.apply(&db, &storage).await.unwrap(),
+            SeedReport {
+                users_created: 0,
+                rooms_created: 0,
+                rooms_skipped: 2,
+            }
+        );
+        let content: String =
+            sqlx::query_scalar("SELECT content FROM rooms WHERE room_id = 'welcome'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(content, "edited");
+    }
+}
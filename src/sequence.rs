@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::SendError};
+use tokio::time::{Duration, Instant};
+
+/// A client falling behind again this soon after being resynced is disconnected,
+/// it can't keep up with its room
+const SLOW_CONSUMER_WINDOW: Duration = Duration::from_secs(10);
+
+/// Broadcast channel of a room, numbering the messages sent to its members with a `seq`
+/// increasing by one with each of them, for them to acknowledge the last they handled
+#[derive(Debug, Clone)]
+pub(crate) struct RoomSender {
+    tx: broadcast::Sender<String>,
+    /// Number of the last message sent, held while sending for the members to get them in order
+    seq: Arc<Mutex<u64>>,
+}
+
+impl RoomSender {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+            seq: Arc::default(),
+        }
+    }
+
+    /// Send `msg`, a JSON object, to the members with the next sequence number
+    pub(crate) fn send(&self, msg: String) -> Result<usize, SendError<String>> {
+        let mut seq = self.seq.lock().unwrap();
+        *seq += 1;
+        let sent = self.tx.send(numbered(msg, *seq));
+        drop(seq);
+        sent
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    pub(crate) fn receiver_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// Messages not received yet by the slowest member
+    pub(crate) fn len(&self) -> usize {
+        self.tx.len()
+    }
+}
+
+/// `msg` with `seq` as its last field, if it is a JSON object
+fn numbered(msg: String, seq: u64) -> String {
+    match msg.strip_suffix('}') {
+        Some(fields) if fields.starts_with('{') => {
+            let separator = if fields.len() > 1 { "," } else { "" };
+            format!(r#"{fields}{separator}"seq":{seq}}}"#)
+        }
+        _ => msg,
+    }
+}
+
+/// Sequence number of a message sent by a [`RoomSender`]
+pub(crate) fn seq_of(msg: &str) -> Option<u64> {
+    msg.strip_suffix('}')?
+        .rsplit_once(r#""seq":"#)?
+        .1
+        .parse()
+        .ok()
+}
+
+/// What to do with a connection that missed messages of its room
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Missed {
+    /// Send it the whole state of the room now
+    Resync,
+    /// Wait for its client to acknowledge the last message before those missed, see
+    /// [`Delivery::ack`]
+    AwaitAck,
+    /// It fell behind again right after being resynced, it can't keep up with the room
+    TooSlow,
+}
+
+/// Messages of a room received by a connection, and acknowledged by its client
+#[derive(Debug, Default)]
+pub(crate) struct Delivery {
+    /// Last message received
+    received: u64,
+    /// Last message acknowledged, `None` for the clients not acknowledging them, which are
+    /// resynced as soon as they miss messages
+    acked: Option<u64>,
+    /// Last message received before those missed, until the client is resynced
+    missed_after: Option<u64>,
+    resynced_at: Option<Instant>,
+}
+
+impl Delivery {
+    /// Record a message received from the room
+    pub(crate) fn received(&mut self, msg: &str) {
+        if let Some(seq) = seq_of(msg) {
+            self.received = seq;
+        }
+    }
+
+    /// Record that the connection missed messages, `resync_now` for clients that can't apply
+    /// the messages after those until resynced
+    pub(crate) fn missed(&mut self, resync_now: bool) -> Missed {
+        if self
+            .resynced_at
+            .is_some_and(|at| at.elapsed() < SLOW_CONSUMER_WINDOW)
+        {
+            return Missed::TooSlow;
+        }
+        if resync_now || self.acked.is_none() {
+            self.resynced_at = Some(Instant::now());
+            return Missed::Resync;
+        }
+        // Already waiting if the client didn't catch up with the previous gap yet
+        if self.missed_after.is_none() {
+            self.missed_after = Some(self.received);
+        }
+        Missed::AwaitAck
+    }
+
+    /// Record that the client handled the messages up to `seq`.
+    /// Returns whether to resync it, having handled everything before the messages it missed.
+    pub(crate) fn ack(&mut self, seq: u64) -> bool {
+        // Messages not received yet can't be acknowledged
+        if seq > self.received {
+            return false;
+        }
+        self.acked = Some(self.acked.map_or(seq, |acked| acked.max(seq)));
+        if !self.missed_after.is_some_and(|after| seq >= after) {
+            return false;
+        }
+        self.missed_after = None;
+        self.resynced_at = Some(Instant::now());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{numbered, seq_of, Delivery, Missed, RoomSender};
+
+    #[test]
+    fn test_numbered() {
+        assert_eq!(
+            numbered(r#"{"type":"join","username":"alice"}"#.to_string(), 3),
+            r#"{"type":"join","username":"alice","seq":3}"#
+        );
+        assert_eq!(numbered("{}".to_string(), 1), r#"{"seq":1}"#);
+        assert_eq!(numbered("plain".to_string(), 1), "plain");
+        assert_eq!(seq_of(r#"{"value":"\"seq\":1","seq":12}"#), Some(12));
+        assert_eq!(seq_of(r#"{"type":"join"}"#), None);
+    }
+
+    #[test]
+    fn test_room_sender() {
+        let tx = RoomSender::new(4);
+        let mut rx = tx.subscribe();
+        tx.send(r#"{"type":"join"}"#.to_string()).unwrap();
+        tx.send(r#"{"type":"leave"}"#.to_string()).unwrap();
+        assert_eq!(seq_of(&rx.try_recv().unwrap()), Some(1));
+        assert_eq!(seq_of(&rx.try_recv().unwrap()), Some(2));
+    }
+
+    #[test]
+    fn test_delivery() {
+        // Clients not acknowledging messages are resynced right away
+        let mut delivery = Delivery::default();
+        assert_eq!(delivery.missed(false), Missed::Resync);
+        assert_eq!(delivery.missed(false), Missed::TooSlow);
+
+        let mut delivery = Delivery::default();
+        delivery.received(r#"{"seq":5}"#);
+        assert!(!delivery.ack(6));
+        assert!(!delivery.ack(4));
+        assert_eq!(delivery.missed(false), Missed::AwaitAck);
+        delivery.received(r#"{"seq":20}"#);
+        // Gaps before the client caught up are resynced once
+        assert_eq!(delivery.missed(false), Missed::AwaitAck);
+        assert!(!delivery.ack(4));
+        assert!(delivery.ack(5));
+        assert!(!delivery.ack(20));
+        assert_eq!(delivery.missed(false), Missed::TooSlow);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::authenticator::{Anonymous, Authenticator};
+use crate::config::Config;
+use crate::storage::{self, MemoryStorage, SqliteStorage, Storage};
+use crate::{listen, router, start};
+use anyhow::Result;
+use axum::Router;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+/// A partage server to mount in another axum app or to run on its own
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let server = partage::PartageServer::builder()
+///     .database_url("sqlite://partage.db")
+///     .port(8080)
+///     .build();
+/// server.serve().await
+/// # }
+/// ```
+pub struct PartageServer {
+    config: Config,
+    pool: Option<SqlitePool>,
+    storage: Option<Arc<dyn Storage>>,
+    authenticator: Arc<dyn Authenticator>,
+}
+
+impl PartageServer {
+    /// A server with the default configuration, in memory
+    #[must_use]
+    pub fn builder() -> PartageServerBuilder {
+        PartageServerBuilder::default()
+    }
+
+    /// The database of the server, and the storage of the contents of its rooms
+    async fn open(&self) -> Result<(SqlitePool, Arc<dyn Storage>)> {
+        let db = match &self.pool {
+            Some(pool) => {
+                storage::migrate(pool).await?;
+                pool.clone()
+            }
+            None => storage::open_database(&self.config).await?,
+        };
+        // Unless set, the contents are kept with the rest of the rooms, or in memory like them
+        let contents: Arc<dyn Storage> = match &self.storage {
+            Some(storage) => storage.clone(),
+            None if self.pool.is_none() && self.config.database_url.is_none() => {
+                Arc::new(MemoryStorage::default())
+            }
+            None => Arc::new(SqliteStorage::new(db.clone())),
+        };
+        Ok((db, contents))
+    }
+
+    /// Router of the server, its rooms being written to the database in the background
+    /// until the runtime stops
+    ///
+    /// # Errors
+    ///
+    /// When the database can't be opened, or its rooms can't be loaded
+    pub async fn router(self) -> Result<Router> {
+        let (db, contents) = self.open().await?;
+        Ok(router(
+            start(self.config, db, contents, self.authenticator).await?,
+        ))
+    }
+
+    /// Serve until a shutdown signal, then write the rooms to the database
+    ///
+    /// # Errors
+    ///
+    /// When the database can't be opened, its rooms can't be loaded or the listener can't be
+    /// bound
+    pub async fn serve(self) -> Result<()> {
+        let (db, contents) = self.open().await?;
+        listen(start(self.config, db, contents, self.authenticator).await?).await
+    }
+}
+
+/// Settings of a `PartageServer`, the ones not set being those of `Config::default()`
+#[derive(Default)]
+pub struct PartageServerBuilder {
+    config: Config,
+    database_url: Option<String>,
+    pool: Option<SqlitePool>,
+    storage: Option<Arc<dyn Storage>>,
+    port: Option<u16>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl PartageServerBuilder {
+    /// Start from `config`, such as the one of `Config::load()`
+    #[must_use]
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Store the rooms in the SQLite database at `url`, created if needed, rather than at the
+    /// `DATABASE_URL` of the configuration
+    #[must_use]
+    pub fn database_url(mut self, url: impl Into<String>) -> Self {
+        self.database_url = Some(url.into());
+        self
+    }
+
+    /// Store the rooms in `pool`, a pool of the application whose database gets the tables of
+    /// partage, rather than at the `DATABASE_URL` of the configuration
+    #[must_use]
+    pub fn database(mut self, pool: SqlitePool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Keep the contents of the rooms in `storage`, such as a [`MemoryStorage`], rather than
+    /// in the database with the rest of the rooms
+    #[must_use]
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Listen on `port` rather than the `PORT` of the configuration
+    #[must_use]
+    pub const fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Tell who the clients without a session are with `authenticator`, rather than letting
+    /// them in anonymously
+    #[must_use]
+    pub fn authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// The server, the database and port set overriding those of the configuration
+    #[must_use]
+    pub fn build(self) -> PartageServer {
+        let mut config = self.config;
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(url) = self.database_url {
+            config.database_url = Some(url);
+        }
+        PartageServer {
+            config,
+            pool: self.pool,
+            storage: self.storage,
+            authenticator: self.authenticator.unwrap_or_else(|| Arc::new(Anonymous)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartageServer;
+    use crate::config::Config;
+    use crate::database;
+    use crate::storage::MemoryStorage;
+    use crate::write_behind::Write;
+    use sqlx::sqlite::SqlitePool;
+
+    #[test]
+    fn test_builder_overrides_config() {
+        let server = PartageServer::builder()
+            .database_url("sqlite://other.db")
+            .config(Config {
+                port: 4000,
+                database_url: Some("sqlite://partage.db".to_string()),
+                ..Config::default()
+            })
+            .port(8080)
+            .build();
+        assert_eq!(server.config.port, 8080);
+        assert_eq!(
+            server.config.database_url.as_deref(),
+            Some("sqlite://other.db")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pool_is_migrated() {
+        // Not through `database::memory`, which migrates it already
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let (db, _) = PartageServer::builder()
+            .database(db)
+            .build()
+            .open()
+            .await
+            .unwrap();
+        let rooms: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rooms")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(rooms, 0);
+    }
+
+    #[tokio::test]
+    async fn test_contents_storage() {
+        let writes = Write::room("notes", "Hello", []);
+        let stored = |db: SqlitePool| async move {
+            sqlx::query_scalar::<_, String>("SELECT content FROM rooms WHERE room_id = 'notes'")
+                .fetch_optional(&db)
+                .await
+                .unwrap()
+        };
+
+        // With the rest of the rooms in the database
+        let (db, storage) = PartageServer::builder()
+            .database(database::memory().await.unwrap())
+            .build()
+            .open()
+            .await
+            .unwrap();
+        storage.write(&writes).await.unwrap();
+        assert_eq!(stored(db).await.as_deref(), Some("Hello"));
+
+        // In the storage set, or in memory without a database
+        for builder in [
+            PartageServer::builder()
+                .database(database::memory().await.unwrap())
+                .storage(MemoryStorage::default()),
+            PartageServer::builder(),
+        ] {
+            let (db, storage) = builder.build().open().await.unwrap();
+            storage.write(&writes).await.unwrap();
+            assert_eq!(
+                storage.content("notes").await.unwrap().as_deref(),
+                Some("Hello")
+            );
+            assert_eq!(stored(db).await, None);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use ts_rs::TS;
+
+/// Connection to a room recorded in the database, as listed by `GET /api/admin/sessions`
+#[derive(TS, sqlx::FromRow, Debug, Clone, PartialEq, Eq, Serialize)]
+#[ts(export)]
+pub(crate) struct StoredSession {
+    #[ts(type = "number")]
+    pub(crate) connection_id: i64,
+    pub(crate) room_id: String,
+    pub(crate) username: String,
+    #[ts(type = "number")]
+    pub(crate) connected_at: i64,
+}
+
+/// Record a connection that joined a room
+pub(crate) async fn store(db: &SqlitePool, session: &StoredSession) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO sessions (connection_id, room_id, username, connected_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(session.connection_id)
+    .bind(&session.room_id)
+    .bind(&session.username)
+    .bind(session.connected_at)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Forget a connection that left its room
+pub(crate) async fn remove(db: &SqlitePool, connection_id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE connection_id = ?")
+        .bind(connection_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Recorded connections, oldest first
+pub(crate) async fn list(db: &SqlitePool) -> Result<Vec<StoredSession>> {
+    Ok(sqlx::query_as::<_, StoredSession>(
+        "SELECT connection_id, room_id, username, connected_at FROM sessions ORDER BY connected_at, connection_id",
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+/// Remove the connections left over by the previous run, which stopped before they could leave.
+/// Returns them, for diagnostics.
+pub(crate) async fn clear_stale(db: &SqlitePool) -> Result<Vec<StoredSession>> {
+    let stale = list(db).await?;
+    sqlx::query("DELETE FROM sessions").execute(db).await?;
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_stale, list, remove, store, StoredSession};
+    use crate::database;
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let db = database::memory().await.unwrap();
+        let session = |connection_id, username: &str| StoredSession {
+            connection_id,
+            room_id: "general".to_string(),
+            username: username.to_string(),
+            connected_at: 100 + connection_id,
+        };
+
+        store(&db, &session(0, "alice")).await.unwrap();
+        store(&db, &session(1, "bob")).await.unwrap();
+        remove(&db, 0).await.unwrap();
+        assert_eq!(list(&db).await.unwrap(), vec![session(1, "bob")]);
+
+        assert_eq!(clear_stale(&db).await.unwrap(), vec![session(1, "bob")]);
+        assert!(list(&db).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::metrics::{self, AssetMetricsSnapshot};
+use crate::{base_path, AppState};
+use axum::extract::State;
+use axum::http::{header, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use rust_embed::Embed;
+use serde::Serialize;
+use std::sync::Arc;
+
+pub(crate) static INDEX_HTML: &str = "index.html";
+
+#[derive(Embed)]
+#[folder = "client/dist/"]
+pub(crate) struct Assets;
+
+#[cfg(not(debug_assertions))]
+const CACHE_EXTENTIONS: [&str; 9] = [
+    ".css", ".js", ".wasm", ".png", ".jpg", ".jpeg", ".gif", ".webp", ".svg",
+];
+
+/// Static file handler with conditional caching
+pub(crate) async fn static_handler(
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+) -> impl IntoResponse {
+    let path = uri.path().trim_start_matches('/');
+
+    if path.is_empty() || path == INDEX_HTML {
+        return index_html(&state);
+    }
+
+    if let Some(content) = Assets::get(path) {
+        state.asset_metrics.record_hit(path, content.data.len());
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+        #[cfg(debug_assertions)]
+        {
+            // Debug build: no caching, original behavior
+            ([(header::CONTENT_TYPE, mime.as_ref())], content.data).into_response()
+        }
+
+        #[cfg(not(debug_assertions))]
+        {
+            // Release build: add cache-control header for static assets
+            let cache_header_value = if CACHE_EXTENTIONS.iter().any(|ext| path.ends_with(ext)) {
+                // Cache assets for 1 year
+                "public, max-age=31536000"
+            } else {
+                // No caching for non-static assets or HTML
+                "no-cache, no-store, must-revalidate"
+            };
+
+            (
+                [
+                    (header::CONTENT_TYPE, mime.as_ref()),
+                    (header::CACHE_CONTROL, cache_header_value),
+                ],
+                content.data,
+            )
+                .into_response()
+        }
+    } else {
+        if path.contains('.') {
+            state.asset_metrics.record_not_found(path);
+            return not_found();
+        }
+
+        index_html(&state)
+    }
+}
+
+/// Index HTML handler, its URLs pointing under `BASE_PATH`
+fn index_html(state: &AppState) -> Response {
+    match Assets::get(INDEX_HTML) {
+        Some(content) => {
+            state
+                .asset_metrics
+                .record_hit(INDEX_HTML, content.data.len());
+            Html(base_path::rewrite_index_html(
+                &String::from_utf8_lossy(&content.data),
+                &state.config.base_path,
+            ))
+            .into_response()
+        }
+        None => {
+            state.asset_metrics.record_not_found(INDEX_HTML);
+            not_found()
+        }
+    }
+}
+
+/// Embedded asset, as listed by `GET /api/admin/assets`
+#[derive(Debug, Serialize)]
+struct AssetInfo {
+    path: String,
+    sha256: String,
+    size: usize,
+    hits: u64,
+}
+
+/// Embedded assets with their hashes and serving metrics
+#[derive(Debug, Serialize)]
+pub(crate) struct AssetsReport {
+    assets: Vec<AssetInfo>,
+    #[serde(flatten)]
+    metrics: AssetMetricsSnapshot,
+}
+
+/// List the embedded assets, to check which frontend build is running
+pub(crate) async fn get_assets(State(state): State<Arc<AppState>>) -> Json<AssetsReport> {
+    let snapshot = state.asset_metrics.snapshot();
+
+    let mut assets: Vec<AssetInfo> = Assets::iter()
+        .filter_map(|path| {
+            let file = Assets::get(&path)?;
+            Some(AssetInfo {
+                sha256: metrics::to_hex(&file.metadata.sha256_hash()),
+                size: file.data.len(),
+                hits: snapshot.hits.get(&*path).copied().unwrap_or_default(),
+                path: path.into_owned(),
+            })
+        })
+        .collect();
+    assets.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Json(AssetsReport {
+        assets,
+        metrics: snapshot,
+    })
+}
+
+/// 404 handler
+pub(crate) fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, "404").into_response()
+}
+
+/// Identify the frontend build embedded in this binary
+pub(crate) fn log_embedded() {
+    match Assets::get(INDEX_HTML) {
+        Some(index) => println!(
+            "Embedded frontend: {} assets, {INDEX_HTML} sha256 {}",
+            Assets::iter().count(),
+            metrics::to_hex(&index.metadata.sha256_hash())
+        ),
+        None => eprintln!("No embedded frontend found, did you build the client?"),
+    }
+}
@@ -0,0 +1,154 @@
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Rooms listed by `GET /api/admin/stats` unless `limit` says otherwise
+const DEFAULT_LIMIT: usize = 20;
+
+/// What the busiest rooms are ranked by
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StatsSort {
+    /// Most messages over the last minute first
+    #[default]
+    Messages,
+    /// Most connections first
+    Connections,
+    /// Largest content first
+    Size,
+    /// Most database writes first
+    Writes,
+}
+
+/// Query parameters of `GET /api/admin/stats`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct StatsQuery {
+    #[serde(default)]
+    sort: StatsSort,
+    /// Rooms listed at most
+    limit: Option<usize>,
+}
+
+/// Activity of a loaded room
+#[derive(TS, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[ts(export)]
+pub(crate) struct RoomStats {
+    pub(crate) room_id: String,
+    pub(crate) connections: usize,
+    /// Size of the main content and of the documents, in bytes
+    pub(crate) content_length: usize,
+    /// Messages received since the room was loaded
+    #[ts(type = "number")]
+    pub(crate) messages: u64,
+    /// Messages received over the last minute
+    pub(crate) messages_per_minute: f64,
+    /// Writes of the documents of the room to the database since it was loaded
+    #[ts(type = "number")]
+    pub(crate) db_writes: u64,
+}
+
+/// Totals of the loaded rooms, with the busiest ones
+#[derive(TS, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[ts(export)]
+pub(crate) struct ServerStats {
+    pub(crate) rooms: usize,
+    pub(crate) connections: usize,
+    pub(crate) content_length: usize,
+    pub(crate) messages_per_minute: f64,
+    /// Documents written to the database since the server started
+    #[ts(type = "number")]
+    pub(crate) db_writes: u64,
+    /// Ranked by the `sort` query parameter
+    pub(crate) top_rooms: Vec<RoomStats>,
+}
+
+/// Find the rooms that are abused or running away
+pub(crate) async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Json<ServerStats> {
+    let mut connections = HashMap::<String, usize>::new();
+    for connection in state.connections.list() {
+        *connections.entry(connection.room).or_default() += 1;
+    }
+    let db_writes = state.write_behind.room_writes();
+
+    let mut rooms = Vec::new();
+    let loaded = state.rooms.lock().await;
+    for (room_id, room) in &*loaded {
+        let documents = room.documents.lock().await;
+        let content_length = room.content_rx.borrow().len()
+            + documents
+                .values()
+                .map(|document| document.content_rx.borrow().len())
+                .sum::<usize>();
+        drop(documents);
+        rooms.push(RoomStats {
+            room_id: room_id.clone(),
+            connections: connections.get(room_id).copied().unwrap_or_default(),
+            content_length,
+            messages: room.activity.messages(),
+            messages_per_minute: room.activity.messages_per_minute(),
+            db_writes: db_writes.get(room_id).copied().unwrap_or_default(),
+        });
+    }
+    drop(loaded);
+
+    let stats = ServerStats {
+        rooms: rooms.len(),
+        connections: rooms.iter().map(|room| room.connections).sum(),
+        content_length: rooms.iter().map(|room| room.content_length).sum(),
+        messages_per_minute: rooms.iter().map(|room| room.messages_per_minute).sum(),
+        db_writes: state.write_behind.written(),
+        top_rooms: rank(rooms, query.sort, query.limit.unwrap_or(DEFAULT_LIMIT)),
+    };
+    Json(stats)
+}
+
+/// The `limit` first rooms by `sort`, ties broken by id
+fn rank(mut rooms: Vec<RoomStats>, sort: StatsSort, limit: usize) -> Vec<RoomStats> {
+    rooms.sort_by(|a, b| {
+        let order = match sort {
+            StatsSort::Messages => b.messages_per_minute.total_cmp(&a.messages_per_minute),
+            StatsSort::Connections => b.connections.cmp(&a.connections),
+            StatsSort::Size => b.content_length.cmp(&a.content_length),
+            StatsSort::Writes => b.db_writes.cmp(&a.db_writes),
+        };
+        order.then_with(|| a.room_id.cmp(&b.room_id))
+    });
+    rooms.truncate(limit);
+    rooms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rank, RoomStats, StatsSort};
+
+    fn room(room_id: &str, connections: usize, messages_per_minute: f64) -> RoomStats {
+        RoomStats {
+            room_id: room_id.to_string(),
+            connections,
+            content_length: 0,
+            messages: 0,
+            messages_per_minute,
+            db_writes: 0,
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let rooms = vec![room("a", 1, 2.0), room("b", 5, 120.0), room("c", 5, 0.5)];
+        let ids = |rooms: Vec<RoomStats>| -> Vec<String> {
+            rooms.into_iter().map(|room| room.room_id).collect()
+        };
+        assert_eq!(
+            ids(rank(rooms.clone(), StatsSort::Messages, 10)),
+            ["b", "a", "c"]
+        );
+        assert_eq!(ids(rank(rooms, StatsSort::Connections, 2)), ["b", "c"]);
+    }
+}
@@ -0,0 +1,678 @@
+use crate::auto_clear::AutoClear;
+use crate::burn::{self, Burn};
+use crate::compression::StoredContent;
+use crate::config::Config;
+use crate::encryption::EncryptionParams;
+use crate::freeze::FreezeSchedule;
+use crate::rooms::{ensure_room_loaded, RoomState, DEFAULT_ROOM};
+use crate::write_behind::{Write, WriteBehind};
+use crate::{
+    auth, checkpoints, database, documents, history, members, pins, search, unix_timestamp,
+    webhooks, AppState, CustomError,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::http::{HeaderMap, StatusCode};
+use sqlx::migrate::MigrateDatabase;
+use sqlx::sqlite::{Sqlite, SqliteConnection, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tracing::Instrument;
+use zeroize::Zeroize;
+
+/// Where the contents of the rooms are kept: their main content and their other documents.
+/// Their settings, members and saved versions stay in the database of the server.
+/// Given to a server with [`PartageServerBuilder::storage`](crate::PartageServerBuilder::storage).
+///
+/// ```
+/// use partage::{Storage, Write};
+/// use std::collections::HashMap;
+/// use std::sync::Mutex;
+///
+/// #[derive(Default)]
+/// struct MainOnly(Mutex<HashMap<String, String>>);
+///
+/// #[async_trait::async_trait]
+/// impl Storage for MainOnly {
+///     async fn content(&self, room_id: &str) -> anyhow::Result<Option<String>> {
+///         Ok(self.0.lock().unwrap().get(room_id).cloned())
+///     }
+///     async fn documents(&self, _room_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+///         Ok(Vec::new())
+///     }
+///     async fn write(&self, writes: &[Write]) -> anyhow::Result<()> {
+///         anyhow::ensure!(writes.iter().all(|write| write.doc_id.is_none()), "Main documents only");
+///         let mut contents = self.0.lock().unwrap();
+///         for write in writes {
+///             contents.insert(write.room_id.clone(), write.content.clone());
+///         }
+///         Ok(())
+///     }
+///     async fn delete_document(&self, _room_id: &str, _doc_id: &str) -> anyhow::Result<()> {
+///         Ok(())
+///     }
+///     async fn delete_room(&self, room_id: &str) -> anyhow::Result<()> {
+///         self.0.lock().unwrap().remove(room_id);
+///         Ok(())
+///     }
+///     async fn wipe_room(&self, room_id: &str) -> anyhow::Result<()> {
+///         self.delete_room(room_id).await
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The main content of a room, `None` if it was never written
+    async fn content(&self, room_id: &str) -> Result<Option<String>>;
+
+    /// The other documents of a room as `(doc_id, content)`, ordered by id
+    async fn documents(&self, room_id: &str) -> Result<Vec<(String, String)>>;
+
+    /// Write `writes`, all of them or none
+    async fn write(&self, writes: &[Write]) -> Result<()>;
+
+    /// Remove a document of a room
+    async fn delete_document(&self, room_id: &str, doc_id: &str) -> Result<()>;
+
+    /// Remove the contents of a room
+    async fn delete_room(&self, room_id: &str) -> Result<()>;
+
+    /// Empty the contents of a room, leaving nothing of them to recover.
+    /// Called when a room burns, see `BURN_AFTER`.
+    async fn wipe_room(&self, room_id: &str) -> Result<()>;
+}
+
+/// Contents kept in the database of the server, with the rest of the rooms.
+/// Only these are found by the search.
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    db: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Contents kept in `db`, which must have the tables of partage
+    #[must_use]
+    pub const fn new(db: SqlitePool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn content(&self, room_id: &str) -> Result<Option<String>> {
+        sqlx::query_as!(
+            StoredContent,
+            r#"
+            SELECT content, content_zstd, compressed AS "compressed: bool" FROM rooms
+            WHERE room_id = ?
+            "#,
+            room_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .map(StoredContent::decode)
+        .transpose()
+    }
+
+    async fn documents(&self, room_id: &str) -> Result<Vec<(String, String)>> {
+        documents::load(&self.db, room_id).await
+    }
+
+    async fn write(&self, writes: &[Write]) -> Result<()> {
+        let mut transaction = self.db.begin().await?;
+        for write in writes {
+            let span = tracing::info_span!(
+                "db_write",
+                room_id = %write.room_id,
+                doc_id = write.doc_id.as_deref(),
+                bytes = write.content.len(),
+            );
+            match &write.doc_id {
+                None => {
+                    store_content(&mut transaction, &write.room_id, &write.content)
+                        .instrument(span)
+                        .await?;
+                }
+                Some(doc_id) => {
+                    documents::store(&mut *transaction, &write.room_id, doc_id, &write.content)
+                        .instrument(span)
+                        .await?;
+                }
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_document(&self, room_id: &str, doc_id: &str) -> Result<()> {
+        documents::delete(&self.db, room_id, doc_id).await
+    }
+
+    async fn delete_room(&self, room_id: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE rooms SET content = '', content_zstd = NULL, compressed = FALSE WHERE room_id = ?",
+        )
+        .bind(room_id)
+        .execute(&self.db)
+        .await?;
+        documents::delete_room(&self.db, room_id).await
+    }
+
+    async fn wipe_room(&self, room_id: &str) -> Result<()> {
+        burn::wipe(
+            &self.db,
+            room_id,
+            &[
+                "UPDATE rooms SET content = '', content_zstd = NULL, compressed = FALSE WHERE room_id = ?",
+                "UPDATE documents SET content = '' WHERE room_id = ?",
+            ],
+        )
+        .await
+    }
+}
+
+/// Contents kept in memory, gone once the server stops
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// Documents by room, the main one under `None`
+    rooms: Mutex<HashMap<String, BTreeMap<Option<String>, String>>>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn content(&self, room_id: &str) -> Result<Option<String>> {
+        let rooms = self.rooms.lock().unwrap();
+        Ok(rooms
+            .get(room_id)
+            .and_then(|documents| documents.get(&None).cloned()))
+    }
+
+    async fn documents(&self, room_id: &str) -> Result<Vec<(String, String)>> {
+        let rooms = self.rooms.lock().unwrap();
+        Ok(rooms.get(room_id).map_or_else(Vec::new, |documents| {
+            documents
+                .iter()
+                .filter_map(|(doc_id, content)| Some((doc_id.clone()?, content.clone())))
+                .collect()
+        }))
+    }
+
+    async fn write(&self, writes: &[Write]) -> Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        for write in writes {
+            rooms
+                .entry(write.room_id.clone())
+                .or_default()
+                .insert(write.doc_id.clone(), write.content.clone());
+        }
+        drop(rooms);
+        Ok(())
+    }
+
+    async fn delete_document(&self, room_id: &str, doc_id: &str) -> Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(documents) = rooms.get_mut(room_id) {
+            documents.remove(&Some(doc_id.to_string()));
+        }
+        drop(rooms);
+        Ok(())
+    }
+
+    async fn delete_room(&self, room_id: &str) -> Result<()> {
+        self.rooms.lock().unwrap().remove(room_id);
+        Ok(())
+    }
+
+    async fn wipe_room(&self, room_id: &str) -> Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(documents) = rooms.get_mut(room_id) {
+            for content in documents.values_mut() {
+                content.zeroize();
+            }
+        }
+        drop(rooms);
+        Ok(())
+    }
+}
+
+/// Write `writes` to `storage`, all of them or none, then note the new main contents in
+/// `db`: their rooms get a row for their settings if they had none, and a saved version.
+/// Both steps don't commit together: when noting fails, the write-behind retries the whole,
+/// writing the same contents again and saving their version once.
+#[tracing::instrument(name = "db_flush", skip_all, fields(documents = writes.len()))]
+pub(crate) async fn store(db: &SqlitePool, storage: &dyn Storage, writes: &[Write]) -> Result<()> {
+    if writes.is_empty() {
+        return Ok(());
+    }
+    storage.write(writes).await?;
+    let mut transaction = db.begin().await?;
+    for write in writes.iter().filter(|write| write.doc_id.is_none()) {
+        record_content(&mut transaction, &write.room_id, &write.content).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Get the content of a room from its storage, if it exists and is neither in the trash nor
+/// archived
+pub(crate) async fn get_stored_content(state: &AppState, room_id: &str) -> Option<String> {
+    // Rooms in the trash are only restored through `POST /api/rooms/:room_id/restore`, and
+    // archived ones once unarchived
+    let hidden = sqlx::query_scalar::<_, bool>(
+        "SELECT deleted_at IS NOT NULL OR archived FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(&state.db)
+    .await;
+    let stored = match hidden {
+        Ok(Some(true)) => return None,
+        Ok(hidden) => hidden.is_some(),
+        Err(e) => {
+            eprintln!("Failed to read room from database: {e}");
+            return None;
+        }
+    };
+    match state.storage.content(room_id).await {
+        // Rooms whose settings were stored before their content
+        Ok(content) => content.or_else(|| stored.then(String::new)),
+        Err(e) => {
+            eprintln!("Failed to read room content from storage: {e:#}");
+            None
+        }
+    }
+}
+
+/// Get the freeze schedule of a room stored in the database, empty if none
+pub(crate) async fn get_stored_freeze_schedule(db: &SqlitePool, room_id: &str) -> FreezeSchedule {
+    match sqlx::query_scalar::<_, Option<String>>(
+        "SELECT freeze_schedule FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    {
+        Ok(schedule) => FreezeSchedule::from_stored(schedule.flatten().as_deref()),
+        Err(e) => {
+            eprintln!("Failed to read freeze schedule from database: {e}");
+            FreezeSchedule::default()
+        }
+    }
+}
+
+/// Get the highlighting language of a room stored in the database
+pub(crate) async fn get_stored_syntax_language(db: &SqlitePool, room_id: &str) -> Option<String> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT syntax_language FROM rooms WHERE room_id = ?")
+        .bind(room_id)
+        .fetch_optional(db)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to read room language from database: {e}");
+            None
+        })
+        .flatten()
+}
+
+/// Get the account owning a room, rooms created anonymously have none
+pub(crate) async fn get_room_owner(db: &SqlitePool, room_id: &str) -> Option<i64> {
+    sqlx::query_scalar::<_, Option<i64>>("SELECT owner_id FROM rooms WHERE room_id = ?")
+        .bind(room_id)
+        .fetch_optional(db)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to read room owner from database: {e}");
+            None
+        })
+        .flatten()
+}
+
+/// Get the encryption parameters of a room stored in the database, `None` if not encrypted
+pub(crate) async fn get_stored_encryption(
+    db: &SqlitePool,
+    room_id: &str,
+) -> Option<EncryptionParams> {
+    match sqlx::query_as::<_, (bool, Option<String>)>(
+        "SELECT encrypted, encryption_salt FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await
+    {
+        Ok(row) => row.and_then(|(encrypted, salt)| stored_encryption(encrypted, salt)),
+        Err(e) => {
+            eprintln!("Failed to read room encryption from database: {e}");
+            None
+        }
+    }
+}
+
+pub(crate) fn stored_encryption(encrypted: bool, salt: Option<String>) -> Option<EncryptionParams> {
+    encrypted.then(|| EncryptionParams {
+        salt: salt.unwrap_or_default(),
+    })
+}
+
+/// Write the content of a room to its columns, indexing it for search
+async fn store_content(db: &mut SqliteConnection, room_id: &str, content: &str) -> Result<()> {
+    let stored = StoredContent::encode(content)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO rooms (room_id, content, content_zstd, compressed)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET
+            content = excluded.content, content_zstd = excluded.content_zstd,
+            compressed = excluded.compressed
+        "#,
+        room_id,
+        stored.content,
+        stored.content_zstd,
+        stored.compressed
+    )
+    .execute(&mut *db)
+    .await?;
+    search::index_compressed(db, room_id, content).await?;
+    Ok(())
+}
+
+/// Note a new content of a room, written to its storage.
+/// Rooms burning get no saved version, their content must not outlive the burn.
+async fn record_content(db: &mut SqliteConnection, room_id: &str, content: &str) -> Result<()> {
+    let updated_at = unix_timestamp();
+    let burns = sqlx::query_scalar::<_, bool>(
+        r"
+        INSERT INTO rooms (room_id, content, updated_at) VALUES (?, '', ?)
+        ON CONFLICT (room_id) DO UPDATE SET updated_at = excluded.updated_at
+        RETURNING burn_after IS NOT NULL
+        ",
+    )
+    .bind(room_id)
+    .bind(updated_at)
+    .fetch_one(&mut *db)
+    .await?;
+    if !burns {
+        history::record(db, room_id, content, updated_at).await?;
+    }
+    Ok(())
+}
+
+/// Update the room content, as stored in the database
+#[cfg(test)]
+pub(crate) async fn update_room_content(
+    db: &mut SqliteConnection,
+    room_id: &str,
+    new_content: &str,
+) -> Result<()> {
+    store_content(db, room_id, new_content).await?;
+    record_content(db, room_id, new_content).await
+}
+
+/// Remove the rows and attachments of a room, without telling anyone
+pub(crate) async fn delete_stored_room(state: &AppState, room_id: &str) -> Result<(), CustomError> {
+    let db = &state.db;
+    if let Err(e) = state.storage.delete_room(room_id).await {
+        eprintln!("Failed to remove room contents from storage: {e:#}");
+        return Err(CustomError::bad_request(
+            "Failed to remove room from database.",
+        ));
+    }
+    if let Err(e) = sqlx::query!("DELETE FROM rooms WHERE room_id = $1", room_id)
+        .execute(db)
+        .await
+    {
+        eprintln!("Failed to remove room from database: {e:?}");
+        return Err(CustomError::bad_request(
+            "Failed to remove room from database.",
+        ));
+    }
+    if let Err(e) = pins::delete_room(db, room_id).await {
+        eprintln!("Failed to remove room pins from database: {e:#}");
+    }
+    if let Err(e) = webhooks::delete_room(db, room_id).await {
+        eprintln!("Failed to remove room webhooks from database: {e:#}");
+    }
+    if let Err(e) = members::delete_room(db, room_id).await {
+        eprintln!("Failed to remove room members from database: {e:#}");
+    }
+    if let Err(e) = history::delete_room(db, room_id).await {
+        eprintln!("Failed to remove room history from database: {e:#}");
+    }
+    if let Err(e) = checkpoints::delete_room(db, room_id).await {
+        eprintln!("Failed to remove room checkpoints from database: {e:#}");
+    }
+    if let Err(e) = state.attachments.remove_room(room_id).await {
+        eprintln!("Failed to remove room attachments: {e:#}");
+    }
+    if let Some(mirror) = &state.mirror {
+        mirror.remove_room(room_id);
+    }
+    Ok(())
+}
+
+/// Store the settings of a room that was just created, before it has any content
+pub(crate) async fn store_new_room(
+    db: &SqlitePool,
+    room_id: &str,
+    owner_id: Option<i64>,
+    encryption: Option<&EncryptionParams>,
+) {
+    if owner_id.is_none() && encryption.is_none() {
+        return;
+    }
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, owner_id, encrypted, encryption_salt)
+        VALUES (?, '', ?, ?, ?)
+        ON CONFLICT (room_id) DO NOTHING
+        ",
+    )
+    .bind(room_id)
+    .bind(owner_id)
+    .bind(encryption.is_some())
+    .bind(encryption.map(|params| params.salt.as_str()))
+    .execute(db)
+    .await
+    {
+        eprintln!("Failed to store new room in database: {e}");
+    }
+}
+
+/// Store the owner of a loaded room, `None` leaving it ownerless.
+/// Returns whether the owner changed, never when `only_if_unowned` and the room has one.
+pub(crate) async fn store_room_owner(
+    state: &AppState,
+    room: &RoomState,
+    room_id: &str,
+    owner_id: Option<i64>,
+    only_if_unowned: bool,
+) -> Result<bool, CustomError> {
+    let db = &state.db;
+    let content = room.content_rx.borrow().clone();
+    let query = if only_if_unowned {
+        r"
+        INSERT INTO rooms (room_id, content, owner_id) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET owner_id = excluded.owner_id
+        WHERE rooms.owner_id IS NULL
+        "
+    } else {
+        r"
+        INSERT INTO rooms (room_id, content, owner_id) VALUES (?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE SET owner_id = excluded.owner_id
+        "
+    };
+
+    match sqlx::query(query)
+        .bind(room_id)
+        .bind(content)
+        .bind(owner_id)
+        .execute(db)
+        .await
+    {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(e) => {
+            eprintln!("Failed to store room owner in database: {e}");
+            Err(auth::internal_error())
+        }
+    }
+}
+
+pub(crate) async fn store_user_pin(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+    position: Option<i64>,
+) -> Result<(), CustomError> {
+    let db = &state.db;
+    let Some(user) = auth::current_user(state, headers).await else {
+        return Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Log in to pin rooms.",
+        ));
+    };
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(state, &mut rooms, room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    drop(rooms);
+
+    pins::store_user(db, user.id, room_id, position)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to store room pin in database: {e:#}");
+            auth::internal_error()
+        })
+}
+
+/// The database at `DATABASE_URL`, created and migrated if needed, or an in-memory one
+pub(crate) async fn open_database(config: &Config) -> Result<SqlitePool> {
+    let Some(db_url) = &config.database_url else {
+        println!("No DATABASE_URL configured, rooms are kept in memory until the server stops");
+        return database::memory().await;
+    };
+    println!("Database URL: {db_url}");
+
+    let db_url = db_url.as_str();
+
+    if Sqlite::database_exists(db_url).await.unwrap_or(false) {
+        println!("Database already exists");
+    } else {
+        println!("Creating database {db_url}");
+        Sqlite::create_database(db_url)
+            .await
+            .with_context(|| format!("Failed to create database {db_url}"))?;
+        println!("Create db success");
+    }
+
+    let db = database::connect(db_url, config.sqlite.clone()).await?;
+    migrate(&db).await?;
+    Ok(db)
+}
+
+/// Bring the tables of `db` up to date
+pub(crate) async fn migrate(db: &SqlitePool) -> Result<()> {
+    sqlx::migrate!()
+        .run(db)
+        .await
+        .context("Failed to migrate the database")?;
+    println!("Migration success");
+    if let Err(e) = search::index_missing(db).await {
+        eprintln!("Failed to index the rooms for search: {e:#}");
+    }
+    Ok(())
+}
+
+/// The rooms stored in `db` that are neither in the trash nor archived, with their contents
+/// from `storage` and the default room even if it was never stored
+pub(crate) async fn load_rooms(
+    db: &SqlitePool,
+    storage: &dyn Storage,
+    write_behind: &WriteBehind,
+) -> Result<HashMap<String, RoomState>> {
+    let mut rooms = HashMap::new();
+    for room in sqlx::query!("SELECT * FROM rooms WHERE deleted_at IS NULL AND NOT archived")
+        .fetch_all(db)
+        .await?
+    {
+        let content = storage
+            .content(&room.room_id)
+            .await
+            .with_context(|| format!("Failed to restore room {}", room.room_id))?
+            .unwrap_or_default();
+        println!("Restoring room: {} ({} bytes)", room.room_id, content.len());
+        let room_state = RoomState::new(&room.room_id, write_behind)
+            .with_freeze_schedule(FreezeSchedule::from_stored(room.freeze_schedule.as_deref()))
+            .with_encryption(stored_encryption(room.encrypted, room.encryption_salt))
+            .with_syntax_language(room.syntax_language)
+            .with_auto_clear(AutoClear {
+                minutes: room
+                    .auto_clear_minutes
+                    .and_then(|minutes| u32::try_from(minutes).ok()),
+            })
+            .with_burn(Burn::from_stored(room.burn_after.as_deref()))
+            .with_documents(
+                write_behind,
+                &room.room_id,
+                storage
+                    .documents(&room.room_id)
+                    .await
+                    .with_context(|| format!("Failed to restore room {}", room.room_id))?,
+            );
+        room_state.content_tx.send(content)?;
+        rooms.insert(room.room_id, room_state);
+    }
+
+    // If no "general" room is found, create one
+    if !rooms.contains_key(DEFAULT_ROOM) {
+        rooms.insert(
+            DEFAULT_ROOM.to_string(),
+            RoomState::new(DEFAULT_ROOM, write_behind),
+        );
+    }
+    Ok(rooms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStorage, Storage};
+    use crate::write_behind::Write;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage = MemoryStorage::default();
+        assert_eq!(storage.content("notes").await.unwrap(), None);
+
+        let documents = BTreeMap::from([
+            ("todo".to_string(), "Milk".to_string()),
+            ("done".to_string(), "Eggs".to_string()),
+        ]);
+        storage
+            .write(&Write::room("notes", "Hello", &documents))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.content("notes").await.unwrap().as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(
+            storage.documents("notes").await.unwrap(),
+            [
+                ("done".to_string(), "Eggs".to_string()),
+                ("todo".to_string(), "Milk".to_string()),
+            ]
+        );
+
+        storage.delete_document("notes", "done").await.unwrap();
+        assert_eq!(storage.documents("notes").await.unwrap().len(), 1);
+
+        storage.wipe_room("notes").await.unwrap();
+        assert_eq!(storage.content("notes").await.unwrap().as_deref(), Some(""));
+        assert_eq!(
+            storage.documents("notes").await.unwrap(),
+            [("todo".to_string(), String::new())]
+        );
+
+        storage.delete_room("notes").await.unwrap();
+        assert_eq!(storage.content("notes").await.unwrap(), None);
+        assert!(storage.documents("notes").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use futures::FutureExt;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{self, Duration};
+
+/// Delay before restarting a task that panicked, so a task panicking right away doesn't spin
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Panics caught in background and connection tasks since startup
+static TASK_PANICS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn task_panics() -> u64 {
+    TASK_PANICS.load(Ordering::Relaxed)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+fn record_panic(task: &str, payload: &(dyn Any + Send)) {
+    TASK_PANICS.fetch_add(1, Ordering::Relaxed);
+    eprintln!("Task {task} panicked: {}", panic_message(payload));
+}
+
+/// Spawn a task made by `make`, making a new one whenever it panics.
+/// `make` is given the number of restarts so far, to resume from a clean state.
+/// Aborting the returned handle stops the task for good.
+pub(crate) fn spawn_supervised<F, Fut>(task: String, make: F) -> JoinHandle<()>
+where
+    F: Fn(u32) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(supervised(task, make))
+}
+
+/// Run the task made by `make` like [`spawn_supervised`], in the current task.
+/// Dropping the future stops the task for good.
+pub(crate) async fn supervised<F, Fut>(task: String, make: F)
+where
+    F: Fn(u32) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    for restarts in 0_u32.. {
+        // Polled in this future, so dropping it also stops the current attempt
+        match AssertUnwindSafe(make(restarts)).catch_unwind().await {
+            Ok(()) => return,
+            Err(payload) => {
+                record_panic(&task, payload.as_ref());
+                time::sleep(RESTART_DELAY).await;
+                println!("Restarting task {task}");
+            }
+        }
+    }
+}
+
+/// Log and count the panic of a task that is not restarted, like the tasks of a connection
+pub(crate) fn check_join(task: &str, result: Result<(), JoinError>) {
+    if let Err(e) = result {
+        if e.is_panic() {
+            record_panic(task, e.into_panic().as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_supervised, task_panics};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::time::{self, Duration};
+
+    #[tokio::test]
+    async fn test_restart_after_panic() {
+        let panics_before = task_panics();
+        let runs = Arc::new(AtomicU32::new(0));
+        let handle = {
+            let runs = runs.clone();
+            spawn_supervised("test".to_string(), move |restarts| {
+                let runs = runs.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::Relaxed);
+                    assert!(restarts >= 2, "failing on purpose");
+                }
+            })
+        };
+        for _ in 0..50 {
+            if handle.is_finished() {
+                break;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(handle.is_finished());
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        assert!(task_panics() >= panics_before + 2);
+    }
+}
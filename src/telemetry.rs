@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Collector the spans are exported to, enabled by `OTEL_EXPORTER_OTLP_ENDPOINT`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OtlpConfig {
+    /// gRPC endpoint of the collector, like `http://localhost:4317`
+    pub(crate) endpoint: String,
+    /// Name the spans are reported under
+    pub(crate) service_name: String,
+}
+
+/// Exports the spans until it is dropped, which sends the ones still buffered
+#[must_use]
+pub(crate) struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to export the last spans: {e}");
+            }
+        }
+    }
+}
+
+/// Log to stdout according to `RUST_LOG`, and export the spans over OTLP with `otlp` set
+pub(crate) fn init(otlp: Option<&OtlpConfig>) -> Result<Telemetry> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    let Some(otlp) = otlp else {
+        registry.init();
+        return Ok(Telemetry { provider: None });
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&otlp.endpoint)
+        .build()
+        .with_context(|| format!("Invalid OTLP endpoint {}", otlp.endpoint))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            otlp.service_name.clone(),
+        )]))
+        .build();
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("partage")))
+        .init();
+    println!("Exporting traces to {}", otlp.endpoint);
+    Ok(Telemetry {
+        provider: Some(provider),
+    })
+}
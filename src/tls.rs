@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::PathBuf;
+
+/// Certificate chain and private key served over HTTPS, both PEM encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TlsConfig {
+    pub(crate) cert_path: PathBuf,
+    pub(crate) key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub(crate) async fn load(&self) -> Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert_path, &self.key_path)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to load TLS certificate {} and key {}",
+                    self.cert_path.display(),
+                    self.key_path.display()
+                )
+            })
+    }
+}
+
+/// Reload the certificate and key on SIGHUP, so renewed certificates are served without a restart.
+/// New connections use the reloaded certificate, established ones keep theirs.
+#[cfg(unix)]
+pub(crate) async fn reload_on_sighup(tls: TlsConfig, rustls: RustlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            eprintln!("Failed to install SIGHUP handler, TLS certificates won't be reloaded: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match rustls
+            .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+            .await
+        {
+            Ok(()) => println!("Reloaded TLS certificate {}", tls.cert_path.display()),
+            // The previous certificate is still served
+            Err(e) => eprintln!("Failed to reload TLS certificate: {e}"),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Messages a trace keeps, the oldest ones are dropped past that
+const MAX_TRACE_ENTRIES: usize = 10_000;
+
+/// Way a message went through a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Direction {
+    /// From the client
+    Received,
+    /// To the client
+    Sent,
+}
+
+/// Protocol message seen on a connection of a traced room
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TraceEntry {
+    /// Unix timestamp in milliseconds
+    at: i64,
+    /// Id of the connection, as listed by `GET /api/admin/connections`
+    connection: u64,
+    direction: Direction,
+    /// `type` field of the message, `None` for edits which have none
+    message_type: Option<String>,
+    doc_id: Option<String>,
+    /// Size of the whole message in bytes
+    bytes: usize,
+    /// Size of the content carried by the message in bytes
+    value_bytes: Option<usize>,
+    /// The message itself, only kept when the trace was started with payloads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+}
+
+impl TraceEntry {
+    fn new(connection: u64, direction: Direction, message: &str, payload: bool) -> Self {
+        // Clients not speaking JSON send the content as is
+        let (message_type, doc_id, value_bytes) =
+            match serde_json::from_str::<serde_json::Value>(message) {
+                Ok(serde_json::Value::Object(fields)) => {
+                    let field = |name| fields.get(name).and_then(|value| value.as_str());
+                    (
+                        field("type").map(ToString::to_string),
+                        field("doc_id").map(ToString::to_string),
+                        field("value").map(str::len),
+                    )
+                }
+                _ => (None, None, Some(message.len())),
+            };
+        Self {
+            at: unix_timestamp_millis(),
+            connection,
+            direction,
+            message_type,
+            doc_id,
+            bytes: message.len(),
+            value_bytes,
+            payload: payload.then(|| message.to_string()),
+        }
+    }
+}
+
+/// First line of a trace file
+#[derive(Debug, Serialize)]
+struct TraceHeader<'a> {
+    room: &'a str,
+    started_at: i64,
+    payloads: bool,
+    /// Messages dropped because the trace was full, the oldest ones
+    dropped: usize,
+}
+
+#[derive(Debug)]
+struct Trace {
+    started_at: i64,
+    payloads: bool,
+    entries: VecDeque<TraceEntry>,
+    dropped: usize,
+}
+
+/// Rooms whose protocol messages are recorded, to debug sync issues reported by users
+#[derive(Debug, Default)]
+pub(crate) struct Traces {
+    rooms: Mutex<HashMap<String, Trace>>,
+}
+
+impl Traces {
+    /// Start tracing a room, dropping its previous trace if any.
+    /// Messages are only recorded with their sizes unless `payloads` is set.
+    pub(crate) fn start(&self, room: &str, payloads: bool) {
+        self.rooms.lock().unwrap().insert(
+            room.to_string(),
+            Trace {
+                started_at: unix_timestamp_millis() / 1000,
+                payloads,
+                entries: VecDeque::new(),
+                dropped: 0,
+            },
+        );
+    }
+
+    /// Stop tracing a room and drop its trace. Returns whether it was traced.
+    pub(crate) fn stop(&self, room: &str) -> bool {
+        self.rooms.lock().unwrap().remove(room).is_some()
+    }
+
+    /// Record a message of a connection to a room, if the room is traced
+    pub(crate) fn record(&self, room: &str, connection: u64, direction: Direction, message: &str) {
+        let payloads = self
+            .rooms
+            .lock()
+            .unwrap()
+            .get(room)
+            .map(|trace| trace.payloads);
+        let Some(payloads) = payloads else {
+            return;
+        };
+        // Parsed without holding the lock, every connection records its messages
+        let entry = TraceEntry::new(connection, direction, message, payloads);
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(trace) = rooms.get_mut(room) else {
+            return;
+        };
+        if trace.entries.len() == MAX_TRACE_ENTRIES {
+            trace.entries.pop_front();
+            trace.dropped += 1;
+        }
+        trace.entries.push_back(entry);
+        drop(rooms);
+    }
+
+    /// Trace of a room as JSON lines, a header followed by one line per message
+    pub(crate) fn export(&self, room: &str) -> Option<String> {
+        let rooms = self.rooms.lock().unwrap();
+        let trace = rooms.get(room)?;
+        let header = TraceHeader {
+            room,
+            started_at: trace.started_at,
+            payloads: trace.payloads,
+            dropped: trace.dropped,
+        };
+        let mut lines = serde_json::to_string(&header).ok()?;
+        lines.push('\n');
+        for entry in &trace.entries {
+            let _ = writeln!(lines, "{}", serde_json::to_string(entry).ok()?);
+        }
+        drop(rooms);
+        Some(lines)
+    }
+}
+
+fn unix_timestamp_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| {
+            i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Traces, MAX_TRACE_ENTRIES};
+
+    fn lines(traces: &Traces, room: &str) -> Vec<serde_json::Value> {
+        traces
+            .export(room)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_sizes_only() {
+        let traces = Traces::default();
+        traces.record("room", 1, Direction::Received, "ignored");
+        assert_eq!(traces.export("room"), None);
+
+        traces.start("room", false);
+        traces.record("room", 1, Direction::Received, "secret");
+        traces.record(
+            "room",
+            2,
+            Direction::Sent,
+            r#"{"doc_id":"notes","type":"message","value":"secret","username":"bob"}"#,
+        );
+        traces.record("other", 3, Direction::Received, "ignored");
+
+        let lines = lines(&traces, "room");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["room"], "room");
+        assert_eq!(lines[0]["payloads"], false);
+        assert_eq!(lines[1]["direction"], "received");
+        assert_eq!(lines[1]["message_type"], serde_json::Value::Null);
+        assert_eq!(lines[1]["value_bytes"], 6);
+        assert_eq!(lines[2]["connection"], 2);
+        assert_eq!(lines[2]["message_type"], "message");
+        assert_eq!(lines[2]["doc_id"], "notes");
+        assert_eq!(lines[2]["value_bytes"], 6);
+        assert!(!traces.export("room").unwrap().contains("secret"));
+
+        assert!(traces.stop("room"));
+        assert!(!traces.stop("room"));
+        assert_eq!(traces.export("room"), None);
+    }
+
+    #[test]
+    fn test_payloads() {
+        let traces = Traces::default();
+        traces.start("room", true);
+        traces.record("room", 1, Direction::Received, "hello");
+        assert_eq!(lines(&traces, "room")[1]["payload"], "hello");
+    }
+
+    #[test]
+    fn test_full_trace() {
+        let traces = Traces::default();
+        traces.start("room", false);
+        for connection in 0..=u64::try_from(MAX_TRACE_ENTRIES).unwrap() {
+            traces.record("room", connection, Direction::Received, "x");
+        }
+        let lines = lines(&traces, "room");
+        assert_eq!(lines.len(), MAX_TRACE_ENTRIES + 1);
+        assert_eq!(lines[0]["dropped"], 1);
+        assert_eq!(lines[1]["connection"], 1);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    auth, check_room_owner, delete_room, delete_stored_room, flush_room, members, unix_timestamp,
+    visibility, webhooks::WebhookEvent, AppState, CustomError, RoomState, SocketMessage,
+    SocketMessageType,
+};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use ts_rs::TS;
+
+/// Delay between two purges of the trash
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A removed room waiting in the trash
+#[derive(TS, Debug, Serialize, sqlx::FromRow)]
+#[ts(export)]
+pub(crate) struct TrashedRoom {
+    id: String,
+    /// When the room was removed, in seconds since the epoch
+    #[ts(type = "number")]
+    deleted_at: i64,
+    /// When the room will be deleted for good, `None` if the trash was disabled since
+    #[ts(type = "number | null")]
+    purge_at: Option<i64>,
+}
+
+/// Seconds a room stays in the trash, `None` if rooms are deleted right away
+fn retention_secs(state: &AppState) -> Option<i64> {
+    state
+        .config
+        .trash_retention
+        .map(|retention| i64::try_from(retention.as_secs()).unwrap_or(i64::MAX))
+}
+
+/// Whether a room is in the trash
+pub(crate) async fn is_trashed(db: &SqlitePool, room_id: &str) -> bool {
+    match sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM rooms WHERE room_id = ? AND deleted_at IS NOT NULL",
+    )
+    .bind(room_id)
+    .fetch_one(db)
+    .await
+    {
+        Ok(count) => count > 0,
+        Err(e) => {
+            eprintln!("Failed to read room trash state from database: {e}");
+            false
+        }
+    }
+}
+
+/// Move a room to the trash, or delete it right away if the trash is disabled.
+/// The room leaves memory and the rooms list like a deleted one, but its rows are kept.
+pub(crate) async fn discard_room(
+    state: &AppState,
+    rooms: &mut HashMap<String, RoomState>,
+    room_id: &str,
+) -> Result<(), CustomError> {
+    if state.config.trash_retention.is_none() {
+        return delete_room(state, rooms, room_id).await;
+    }
+    let db = &state.db;
+
+    if let Some(room_state) = rooms.remove(room_id) {
+        room_state.shutdown();
+        flush_room(state, room_id, &room_state).await;
+    }
+    // Rooms never written to are stored too, so that they can be restored like the others
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, deleted_at) VALUES (?, '', ?)
+        ON CONFLICT (room_id) DO UPDATE SET deleted_at = excluded.deleted_at
+        ",
+    )
+    .bind(room_id)
+    .bind(unix_timestamp())
+    .execute(db)
+    .await
+    {
+        eprintln!("Failed to move room to the trash: {e}");
+        return Err(CustomError::bad_request(
+            "Failed to remove room from database.",
+        ));
+    }
+    state.traces.stop(room_id);
+    state
+        .webhooks
+        .emit(WebhookEvent::RoomDeleted, room_id, json!({}))
+        .await;
+
+    if visibility::is_listed(&state.db, room_id).await {
+        notify_rooms_list(rooms);
+    }
+    Ok(())
+}
+
+pub(crate) fn notify_rooms_list(rooms: &HashMap<String, RoomState>) {
+    for room_state in rooms.values() {
+        let _ = room_state.tx.send(
+            json!(SocketMessage {
+                doc_id: None,
+                message_type: SocketMessageType::UpdateRoomsList,
+                value: None,
+                code: None,
+                revision: None,
+                username: String::new(),
+            })
+            .to_string(),
+        );
+    }
+}
+
+/// `GET /api/rooms/trash`, the removed rooms that can still be restored
+pub(crate) async fn list_trash(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    let user_id = auth::current_user(&state, &headers)
+        .await
+        .map(|account| account.id);
+    // Private rooms are only listed for their members, in the trash too
+    let hidden = members::hidden_rooms(db, user_id).await.map_err(|e| {
+        eprintln!("Failed to read room members from database: {e:#}");
+        auth::internal_error()
+    })?;
+
+    let trashed = sqlx::query_as::<_, TrashedRoom>(
+        r"
+        SELECT room_id AS id, deleted_at, deleted_at + ? AS purge_at
+        FROM rooms WHERE deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC
+        ",
+    )
+    .bind(retention_secs(&state))
+    .fetch_all(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to list trashed rooms from database: {e}");
+        auth::internal_error()
+    })?;
+    let trashed: Vec<_> = trashed
+        .into_iter()
+        .filter(|room| !hidden.contains(&room.id))
+        .collect();
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": trashed
+    })))
+}
+
+/// `POST /api/rooms/:room_id/restore`, take a room out of the trash
+pub(crate) async fn restore_room(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room_owner(&state, &headers, &room_id).await?;
+
+    let rooms = state.rooms.lock().await;
+    let restored = sqlx::query(
+        "UPDATE rooms SET deleted_at = NULL WHERE room_id = ? AND deleted_at IS NOT NULL",
+    )
+    .bind(&room_id)
+    .execute(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to restore room from the trash: {e}");
+        auth::internal_error()
+    })?
+    .rows_affected();
+    if restored == 0 {
+        return Err(CustomError::not_found("Room not in the trash."));
+    }
+    println!("Room {room_id} restored from the trash");
+
+    // The room is loaded again by the next user joining it
+    if visibility::is_listed(&state.db, &room_id).await {
+        notify_rooms_list(&rooms);
+    }
+    drop(rooms);
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": "Room restored."
+    })))
+}
+
+/// Ids of the rooms in the trash for longer than `retention`
+async fn expired(db: &SqlitePool, retention: i64, now: i64) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar(
+        "SELECT room_id FROM rooms WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+    )
+    .bind(now.saturating_sub(retention))
+    .fetch_all(db)
+    .await?)
+}
+
+/// Delete the rooms kept in the trash for longer than `TRASH_RETENTION_DAYS`, every hour
+pub(crate) async fn run_purge(state: Arc<AppState>) {
+    let Some(retention) = retention_secs(&state) else {
+        return;
+    };
+    let db = &state.db;
+    let mut interval = time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let room_ids = match expired(db, retention, unix_timestamp()).await {
+            Ok(room_ids) => room_ids,
+            Err(e) => {
+                eprintln!("Failed to list expired rooms of the trash: {e:#}");
+                continue;
+            }
+        };
+        for room_id in room_ids {
+            // Their webhooks were called when they were moved to the trash
+            match delete_stored_room(&state, &room_id).await {
+                Ok(()) => println!("Purged room {room_id} from the trash"),
+                Err(_) => eprintln!("Failed to purge room {room_id} from the trash"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expired;
+    use crate::database;
+
+    #[tokio::test]
+    async fn test_expired() {
+        let db = database::memory().await.unwrap();
+
+        for (room_id, deleted_at) in [("old", Some(100)), ("recent", Some(950)), ("kept", None)] {
+            sqlx::query("INSERT INTO rooms (room_id, content, deleted_at) VALUES (?, '', ?)")
+                .bind(room_id)
+                .bind(deleted_at)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(expired(&db, 500, 1000).await.unwrap(), vec!["old"]);
+        assert!(expired(&db, 2000, 1000).await.unwrap().is_empty());
+    }
+}
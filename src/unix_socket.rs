@@ -0,0 +1,137 @@
+use anyhow::{bail, Context, Result};
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use std::fs::{DirBuilder, Permissions};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::net::UnixListener;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Address given to the clients of the socket, which are all local.
+/// The reverse proxy in front of it is the one that knows their address, its forwarding
+/// headers are trusted unless `trusted_proxies` is set.
+const PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Bind the socket at `path` with the `mode` permissions.
+/// It is bound in a directory of its own that only the server can enter, given its mode
+/// there and then moved into place, so that no other user can connect to it in between.
+/// The socket left behind by a previous run is replaced, any other file is an error.
+pub(crate) fn bind(path: &Path, mode: u32) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(_) => {}
+    }
+
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    // Short, socket paths are limited to about a hundred bytes
+    let private = parent.join(format!(".partage-{}", &crate::auth::generate_token()[..16]));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed to create {}", private.display()))?;
+    let listener = bind_in(&private, path, mode);
+    if let Err(e) = std::fs::remove_dir_all(&private) {
+        eprintln!("Failed to remove {}: {e}", private.display());
+    }
+    listener.with_context(|| format!("Failed to bind Unix socket {}", path.display()))
+}
+
+/// Bind a socket in the `private` directory, then give it its mode and move it to `path`
+fn bind_in(private: &Path, path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    let bound = private.join("partage.sock");
+    let listener = std::os::unix::net::UnixListener::bind(&bound)?;
+    std::fs::set_permissions(&bound, Permissions::from_mode(mode))?;
+    std::fs::rename(&bound, path)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+/// Serve `app` on a Unix socket until `shutdown` is cancelled, then wait for the pending
+/// requests and remove the socket file
+pub(crate) async fn serve(
+    listener: UnixListener,
+    path: &Path,
+    app: Router,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let app = app.layer(Extension(ConnectInfo(PEER_ADDR)));
+    let tracker = TaskTracker::new();
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept Unix socket connection: {e}");
+                    continue;
+                }
+            },
+            () = shutdown.cancelled() => break,
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let shutdown = shutdown.clone();
+        tracker.spawn(async move {
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                () = shutdown.cancelled() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("Unix socket connection error: {e}");
+            }
+        });
+    }
+
+    drop(listener);
+    tracker.close();
+    tracker.wait().await;
+
+    std::fs::remove_file(path)
+        .with_context(|| format!("Failed to remove socket {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bind;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_bind() {
+        let dir = std::env::temp_dir().join(format!(
+            "partage-socket-{}",
+            &crate::auth::generate_token()[..16]
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("partage.sock");
+
+        let listener = bind(&path, 0o660).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        // Reachable where it was moved, with nothing left of the directory it was bound in
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The socket of a server that didn't clean up is replaced
+        drop(listener);
+        assert!(bind(&path, 0o600).is_ok());
+
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(bind(&file, 0o660).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
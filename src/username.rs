@@ -0,0 +1,100 @@
+/// Name the system messages are sent under, which no one can take
+const SERVER_USERNAME: &str = "Server";
+
+/// Longest username, in characters
+pub(crate) const MAX_LENGTH: usize = 32;
+
+/// Most `name-N` a user whose name is taken in a room is given to choose from
+const MAX_ALTERNATIVES: usize = 20;
+
+/// Characters that show nothing, letting `Server` be impersonated by a look-alike
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{AD}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{FEFF}'
+        )
+}
+
+/// Whether `username` is the one of the system messages, whatever its case
+pub(crate) fn is_reserved(username: &str) -> bool {
+    username.trim().eq_ignore_ascii_case(SERVER_USERNAME)
+}
+
+/// The name a client asked for without its invisible characters and surrounding spaces,
+/// or why it can't be used
+pub(crate) fn sanitize(raw: &str) -> Result<String, String> {
+    let username: String = raw.chars().filter(|c| !is_invisible(*c)).collect();
+    let username = username.trim();
+    if username.is_empty() {
+        return Err("The username is empty.".to_string());
+    }
+    if username.chars().count() > MAX_LENGTH {
+        return Err(format!(
+            "Usernames are {MAX_LENGTH} characters long at most."
+        ));
+    }
+    if is_reserved(username) {
+        return Err("This username is reserved.".to_string());
+    }
+    Ok(username.to_string())
+}
+
+/// Names `name-2`, `name-3`... a user can take in a room where their name is taken, `name`
+/// being cut for them to stay within `MAX_LENGTH`
+pub(crate) fn alternatives(username: &str) -> Vec<String> {
+    (2..)
+        .take(MAX_ALTERNATIVES)
+        .filter_map(|n| {
+            let suffix = format!("-{n}");
+            let base: String = username.chars().take(MAX_LENGTH - suffix.len()).collect();
+            sanitize(&format!("{base}{suffix}")).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alternatives, is_reserved, sanitize, MAX_ALTERNATIVES, MAX_LENGTH};
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("  alice ").unwrap(), "alice");
+        assert_eq!(sanitize("al\u{0}ice\n").unwrap(), "alice");
+        assert_eq!(sanitize("Zoë").unwrap(), "Zoë");
+        assert!(sanitize("").is_err());
+        assert!(sanitize("\u{200B}\t").is_err());
+        assert!(sanitize(&"a".repeat(MAX_LENGTH + 1)).is_err());
+        assert!(sanitize(&"é".repeat(MAX_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let names = alternatives("alice");
+        assert_eq!(names.len(), MAX_ALTERNATIVES);
+        assert_eq!(names[0], "alice-2");
+        assert_eq!(names[MAX_ALTERNATIVES - 1], "alice-21");
+
+        let long = "é".repeat(MAX_LENGTH);
+        for name in alternatives(&long) {
+            assert_eq!(sanitize(&name).unwrap(), name);
+        }
+        assert_eq!(
+            alternatives(&long)[0],
+            format!("{}-2", "é".repeat(MAX_LENGTH - 2))
+        );
+    }
+
+    #[test]
+    fn test_server_is_reserved() {
+        assert!(is_reserved("Server"));
+        assert!(is_reserved("server "));
+        for impersonation in ["SERVER", "Ser\u{200B}ver", "\u{202E}Server"] {
+            assert!(sanitize(impersonation).is_err(), "{impersonation:?}");
+        }
+    }
+}
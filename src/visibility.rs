@@ -0,0 +1,221 @@
+use crate::{auth, check_room_owner, ensure_room_loaded, members, AppState, CustomError};
+use anyhow::{bail, Result};
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use ts_rs::TS;
+
+/// Who can find and join a room
+#[derive(TS, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub(crate) enum Visibility {
+    /// Listed for everyone
+    #[default]
+    Public,
+    /// Joinable by anyone knowing its URL, only listed for its owners and members
+    Unlisted,
+    /// Only joinable by its owners and members, or with its password
+    Private,
+}
+
+impl Visibility {
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Unlisted => "unlisted",
+            Self::Private => "private",
+        }
+    }
+
+    pub(crate) fn parse(visibility: &str) -> Result<Self> {
+        match visibility {
+            "public" => Ok(Self::Public),
+            "unlisted" => Ok(Self::Unlisted),
+            "private" => Ok(Self::Private),
+            _ => bail!("Unknown room visibility {visibility}"),
+        }
+    }
+
+    /// Whether the room appears in the rooms lists of the users who are not its members
+    pub(crate) const fn is_listed(self) -> bool {
+        matches!(self, Self::Public)
+    }
+}
+
+/// Visibility of a room, as answered by `GET /api/rooms/:room_id/visibility`
+#[derive(TS, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[ts(export)]
+pub(crate) struct RoomVisibility {
+    pub(crate) visibility: Visibility,
+    /// Whether a password lets non-members join the room when it is private
+    pub(crate) password: bool,
+}
+
+/// Body of `PUT /api/rooms/:room_id/visibility`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct VisibilityRequest {
+    visibility: Visibility,
+    /// Password of a private room, none to only let its members in
+    #[serde(default)]
+    #[ts(optional)]
+    password: Option<String>,
+}
+
+/// Visibility of a room and the hash of its password
+pub(crate) async fn load(db: &SqlitePool, room_id: &str) -> Result<(Visibility, Option<String>)> {
+    let row = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT visibility, password_hash FROM rooms WHERE room_id = ?",
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await?;
+    match row {
+        Some((visibility, password_hash)) => Ok((Visibility::parse(&visibility)?, password_hash)),
+        None => Ok((Visibility::Public, None)),
+    }
+}
+
+/// Whether a room appears in the rooms lists of everyone
+pub(crate) async fn is_listed(db: &SqlitePool, room_id: &str) -> bool {
+    match load(db, room_id).await {
+        Ok((visibility, _)) => visibility.is_listed(),
+        Err(e) => {
+            eprintln!("Failed to read visibility of room {room_id}: {e:#}");
+            false
+        }
+    }
+}
+
+/// Get the visibility of a room
+pub(crate) async fn get_visibility(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    let mut rooms = state.rooms.lock().await;
+    let loaded = ensure_room_loaded(&state, &mut rooms, &room_id).await;
+    drop(rooms);
+    if !loaded {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+
+    let (visibility, password_hash) = load(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to read room visibility: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": RoomVisibility {
+            visibility,
+            password: password_hash.is_some(),
+        }
+    })))
+}
+
+/// Change who can find and join a room
+pub(crate) async fn set_visibility(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<VisibilityRequest>,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    if body.password.is_some() && body.visibility != Visibility::Private {
+        return Err(CustomError::bad_request(
+            "Only private rooms have a password.",
+        ));
+    }
+    let password_hash = match body.password {
+        Some(password) if password.is_empty() => {
+            return Err(CustomError::bad_request("The password can't be empty."));
+        }
+        Some(password) => Some(auth::hash_password(password).await?),
+        None => None,
+    };
+
+    let mut rooms = state.rooms.lock().await;
+    if !ensure_room_loaded(&state, &mut rooms, &room_id).await {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    check_room_owner(&state, &headers, &room_id).await?;
+    // Anyone could lock the others out of an ownerless room
+    if body.visibility != Visibility::Public
+        && members::owners(&state.db, &room_id).await.is_empty()
+    {
+        return Err(CustomError::bad_request("Claim the room before hiding it."));
+    }
+
+    let content = rooms[&room_id].content_rx.borrow().clone();
+    drop(rooms);
+    if let Err(e) = sqlx::query(
+        r"
+        INSERT INTO rooms (room_id, content, visibility, password_hash) VALUES (?, ?, ?, ?)
+        ON CONFLICT (room_id) DO UPDATE
+        SET visibility = excluded.visibility, password_hash = excluded.password_hash
+        ",
+    )
+    .bind(&room_id)
+    .bind(content)
+    .bind(body.visibility.as_str())
+    .bind(&password_hash)
+    .execute(db)
+    .await
+    {
+        eprintln!("Failed to store room visibility in database: {e}");
+        return Err(auth::internal_error());
+    }
+
+    println!("Room {room_id} is now {}", body.visibility.as_str());
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": RoomVisibility {
+            visibility: body.visibility,
+            password: password_hash.is_some(),
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, Visibility};
+    use crate::database;
+
+    #[tokio::test]
+    async fn test_load() {
+        let db = database::memory().await.unwrap();
+        sqlx::query("INSERT INTO rooms (room_id, content) VALUES ('open', '')")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO rooms (room_id, content, visibility, password_hash) VALUES ('secret', '', 'private', 'hash')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(load(&db, "open").await.unwrap(), (Visibility::Public, None));
+        assert_eq!(
+            load(&db, "secret").await.unwrap(),
+            (Visibility::Private, Some("hash".to_string()))
+        );
+        // Rooms without a row are in memory only, and public
+        assert_eq!(load(&db, "new").await.unwrap(), (Visibility::Public, None));
+        assert!(
+            sqlx::query("UPDATE rooms SET visibility = 'hidden' WHERE room_id = 'open'")
+                .execute(&db)
+                .await
+                .is_err()
+        );
+    }
+}
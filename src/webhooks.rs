@@ -0,0 +1,485 @@
+use crate::metrics::to_hex;
+use crate::{admin, auth, members};
+use crate::{ensure_room_loaded, unix_timestamp, AppState, CustomError};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Webhooks a room can have besides the global ones
+const MAX_ROOM_WEBHOOKS: usize = 10;
+
+/// Edits of a room are reported at most once per delay
+const CONTENT_UPDATED_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Deliveries taking longer are abandoned
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Room event posted to the webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WebhookEvent {
+    RoomCreated,
+    RoomDeleted,
+    /// Sent a few seconds after an edit, for all the edits made meanwhile
+    ContentUpdated,
+    UserJoined,
+}
+
+impl WebhookEvent {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::RoomCreated => "room_created",
+            Self::RoomDeleted => "room_deleted",
+            Self::ContentUpdated => "content_updated",
+            Self::UserJoined => "user_joined",
+        }
+    }
+}
+
+/// Webhook registered on a room, as listed by `GET /api/rooms/:room_id/webhooks`
+#[derive(TS, sqlx::FromRow, Debug, Clone, PartialEq, Eq, Serialize)]
+#[ts(export)]
+pub(crate) struct RoomWebhook {
+    #[ts(type = "number")]
+    pub(crate) id: i64,
+    pub(crate) url: String,
+    #[ts(type = "number")]
+    pub(crate) created_at: i64,
+}
+
+/// Body of `POST /api/rooms/:room_id/webhooks`
+#[derive(TS, Debug, Deserialize)]
+#[ts(export)]
+pub(crate) struct WebhookRequest {
+    url: String,
+}
+
+/// HMAC-SHA256 of `body` keyed by `secret`, hex encoded.
+/// Deliveries carry it in the `X-Partage-Signature` header as `sha256=<signature>`.
+pub(crate) fn sign(secret: &str, body: &[u8]) -> String {
+    const BLOCK_SIZE: usize = 64;
+    let mut key = [0_u8; BLOCK_SIZE];
+    if secret.len() > BLOCK_SIZE {
+        key[..32].copy_from_slice(&Sha256::digest(secret.as_bytes()));
+    } else {
+        key[..secret.len()].copy_from_slice(secret.as_bytes());
+    }
+    let inner = Sha256::new()
+        .chain_update(key.map(|byte| byte ^ 0x36))
+        .chain_update(body)
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(key.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize();
+    to_hex(&outer)
+}
+
+/// Whether an address can be reached from the internet, unlike the loopback, link-local and
+/// private ones, which would let room webhooks call the services next to the server
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => {
+                let segments = ip.segments();
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7
+                    || segments[0] & 0xfe00 == 0xfc00
+                    // Link-local, fe80::/10
+                    || segments[0] & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // Shared address space, 100.64.0.0/10
+        || (first == 100 && second & 0xc0 == 64)
+        || first == 0)
+}
+
+/// Only plain web URLs of public hosts can be called, gives the host and the address to call.
+/// Host names are resolved, none of their addresses may be loopback, link-local or private.
+async fn validate_url(url: &str) -> Result<(String, SocketAddr), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| "Invalid webhook URL.".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("Webhook URLs must use http or https.".to_string());
+    }
+    let private = || Err("Webhook URLs must point to a public host.".to_string());
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let Some(host) = parsed.host_str() else {
+        return Err("Invalid webhook URL.".to_string());
+    };
+    let addresses: Vec<IpAddr> = match host.trim_matches(['[', ']']).parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            if domain == "localhost" || domain.ends_with(".localhost") {
+                return private();
+            }
+            let resolved = tokio::net::lookup_host((domain.as_str(), port))
+                .await
+                .map_err(|_| format!("The host {domain} can't be resolved."))?;
+            resolved.map(|address| address.ip()).collect()
+        }
+    };
+    if addresses.is_empty() || !addresses.iter().copied().all(is_public) {
+        return private();
+    }
+    Ok((host.to_string(), SocketAddr::new(addresses[0], port)))
+}
+
+/// Deliveries aren't redirected, a public host could send them to a private one
+fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder().redirect(reqwest::redirect::Policy::none())
+}
+
+/// Client delivering to the address a room webhook resolves to now, checked again as its host
+/// may point to a private address since the webhook was added
+async fn pinned_client(url: &str) -> Result<reqwest::Client, String> {
+    let (host, address) = validate_url(url).await?;
+    client_builder()
+        .resolve(&host, address)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Debug)]
+struct Inner {
+    http: reqwest::Client,
+    db: SqlitePool,
+    /// Called for the events of every room
+    urls: Vec<String>,
+    /// Key of the delivery signatures, deliveries are unsigned without it
+    secret: Option<String>,
+    /// Rooms whose `content_updated` event waits for the end of the debounce delay
+    pending_updates: Mutex<HashSet<String>>,
+}
+
+/// Posts room events to the global webhooks and to those of each room
+#[derive(Debug, Clone)]
+pub(crate) struct Webhooks {
+    inner: Arc<Inner>,
+}
+
+impl Webhooks {
+    pub(crate) fn new(db: SqlitePool, urls: Vec<String>, secret: Option<String>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                http: client_builder()
+                    .build()
+                    .expect("Failed to build the webhooks HTTP client"),
+                db,
+                urls,
+                secret,
+                pending_updates: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    /// Post an event of a room to its webhooks, in the background
+    pub(crate) async fn emit(&self, event: WebhookEvent, room_id: &str, data: serde_json::Value) {
+        let room_urls: Vec<String> = match list(&self.inner.db, room_id).await {
+            Ok(webhooks) => webhooks.into_iter().map(|webhook| webhook.url).collect(),
+            Err(e) => {
+                eprintln!("Failed to list webhooks of room {room_id}: {e:#}");
+                Vec::new()
+            }
+        };
+        if self.inner.urls.is_empty() && room_urls.is_empty() {
+            return;
+        }
+
+        let body = json!({
+            "event": event,
+            "room": room_id,
+            "timestamp": unix_timestamp(),
+            "data": data,
+        })
+        .to_string();
+        let signature = self
+            .inner
+            .secret
+            .as_deref()
+            .map(|secret| format!("sha256={}", sign(secret, body.as_bytes())));
+        // The global webhooks are configured by the administrator, those of the rooms are checked
+        let urls = self
+            .inner
+            .urls
+            .iter()
+            .cloned()
+            .map(|url| (url, false))
+            .chain(room_urls.into_iter().map(|url| (url, true)));
+        for (url, room_webhook) in urls {
+            let http = if room_webhook {
+                match pinned_client(&url).await {
+                    Ok(http) => http,
+                    Err(e) => {
+                        eprintln!("Not delivering webhook to {url}: {e}");
+                        continue;
+                    }
+                }
+            } else {
+                self.inner.http.clone()
+            };
+            let mut request = http
+                .post(&url)
+                .timeout(DELIVERY_TIMEOUT)
+                .header("content-type", "application/json")
+                .header("x-partage-event", event.as_str())
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header("x-partage-signature", signature);
+            }
+            tokio::spawn(async move {
+                let delivered = request
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status);
+                if let Err(e) = delivered {
+                    eprintln!("Failed to deliver webhook to {url}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Report an edit of a room, grouped with those of the next few seconds
+    pub(crate) fn content_updated(&self, room_id: &str) {
+        if !self
+            .inner
+            .pending_updates
+            .lock()
+            .unwrap()
+            .insert(room_id.to_string())
+        {
+            return;
+        }
+        let webhooks = self.clone();
+        let room_id = room_id.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(CONTENT_UPDATED_DEBOUNCE).await;
+            webhooks
+                .inner
+                .pending_updates
+                .lock()
+                .unwrap()
+                .remove(&room_id);
+            webhooks
+                .emit(WebhookEvent::ContentUpdated, &room_id, json!({}))
+                .await;
+        });
+    }
+}
+
+/// Webhooks of a room, oldest first
+async fn list(db: &SqlitePool, room_id: &str) -> Result<Vec<RoomWebhook>> {
+    Ok(sqlx::query_as::<_, RoomWebhook>(
+        "SELECT id, url, created_at FROM room_webhooks WHERE room_id = ? ORDER BY id",
+    )
+    .bind(room_id)
+    .fetch_all(db)
+    .await?)
+}
+
+/// Forget the webhooks of a deleted room
+pub(crate) async fn delete_room(db: &SqlitePool, room_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM room_webhooks WHERE room_id = ?")
+        .bind(room_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Load the room and make sure the request comes from an owner of the room or an admin.
+/// Unlike the rest of the API, ownerless rooms are not open to everyone, as webhooks make the
+/// server send requests.
+async fn check_room(
+    state: &AppState,
+    headers: &HeaderMap,
+    room_id: &str,
+) -> Result<(), CustomError> {
+    let mut rooms = state.rooms.lock().await;
+    let loaded = ensure_room_loaded(state, &mut rooms, room_id).await;
+    drop(rooms);
+    if !loaded {
+        return Err(CustomError::not_found("Room not found."));
+    }
+    if admin::is_admin(state, headers) {
+        return Ok(());
+    }
+    let owners = members::owners(&state.db, room_id).await;
+    match auth::current_user(state, headers).await {
+        Some(user) if owners.contains(&user.id) => Ok(()),
+        Some(_) => Err(CustomError::new(
+            StatusCode::FORBIDDEN,
+            "Only the owner of the room or an admin can manage its webhooks.",
+        )),
+        None => Err(CustomError::new(
+            StatusCode::UNAUTHORIZED,
+            "Log in as the owner of the room to manage its webhooks.",
+        )),
+    }
+}
+
+/// List the webhooks of a room
+pub(crate) async fn list_webhooks(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+    let webhooks = list(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to list webhooks: {e:#}");
+        auth::internal_error()
+    })?;
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": webhooks
+    })))
+}
+
+/// Register a webhook called for the events of a room
+pub(crate) async fn add_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<WebhookRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+    validate_url(&body.url)
+        .await
+        .map_err(CustomError::bad_request)?;
+
+    let webhooks = list(db, &room_id).await.map_err(|e| {
+        eprintln!("Failed to list webhooks: {e:#}");
+        auth::internal_error()
+    })?;
+    if webhooks.len() >= MAX_ROOM_WEBHOOKS {
+        return Err(CustomError::bad_request(format!(
+            "A room has at most {MAX_ROOM_WEBHOOKS} webhooks."
+        )));
+    }
+
+    let webhook = sqlx::query_as::<_, RoomWebhook>(
+        "INSERT INTO room_webhooks (room_id, url, created_at) VALUES (?, ?, ?) RETURNING id, url, created_at",
+    )
+    .bind(&room_id)
+    .bind(&body.url)
+    .bind(unix_timestamp())
+    .fetch_one(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Failed to store webhook: {e}");
+        auth::internal_error()
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "type": "success",
+            "value": webhook
+        })),
+    ))
+}
+
+/// Stop calling a webhook of a room
+pub(crate) async fn remove_webhook(
+    State(state): State<Arc<AppState>>,
+    Path((room_id, webhook_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, CustomError> {
+    let db = &state.db;
+    check_room(&state, &headers, &room_id).await?;
+
+    let removed = sqlx::query("DELETE FROM room_webhooks WHERE id = ? AND room_id = ?")
+        .bind(webhook_id)
+        .bind(&room_id)
+        .execute(db)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to remove webhook: {e}");
+            auth::internal_error()
+        })?
+        .rows_affected();
+    if removed == 0 {
+        return Err(CustomError::not_found("Webhook not found."));
+    }
+
+    Ok(Json(json!({
+        "type": "success",
+        "value": webhook_id
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_public, sign, validate_url};
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_url() {
+        assert!(validate_url("https://93.184.215.14/hook").await.is_ok());
+        assert!(validate_url("http://[2606:4700::1111]/hook").await.is_ok());
+        assert!(validate_url("http://localhost:8080/hook").await.is_err());
+        assert!(validate_url("http://api.localhost./hook").await.is_err());
+        assert!(validate_url("http://127.0.0.1:8080/hook").await.is_err());
+        assert!(validate_url("http://[::1]/hook").await.is_err());
+        assert!(validate_url("http://169.254.169.254/latest/meta-data")
+            .await
+            .is_err());
+        assert!(validate_url("http://192.168.1.1/hook").await.is_err());
+        assert!(validate_url("ftp://example.com").await.is_err());
+        assert!(validate_url("not a url").await.is_err());
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["8.8.8.8", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "10.0.0.1",
+            "172.16.0.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
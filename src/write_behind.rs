@@ -0,0 +1,536 @@
+use crate::mirror::Mirror;
+use crate::ot::{self, Sequencers};
+use crate::room_handle::RoomHandle;
+use crate::sequence::RoomSender;
+use crate::storage::{self, Storage};
+use crate::{
+    persisted_message, persistence_message, supervisor, unix_timestamp, PersistenceHealth,
+    DEFAULT_PERSIST_INTERVAL, PERSIST_INTERVAL,
+};
+use anyhow::Result;
+use futures::stream::{self, BoxStream, SelectAll};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+/// Content of a document to write to the [`Storage`], also the snapshot uploaded to the mirror
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Write {
+    pub room_id: String,
+    /// `None` for the main document of the room
+    pub doc_id: Option<String>,
+    /// The whole content of the document
+    pub content: String,
+}
+
+impl Write {
+    /// Writes of the main content of a room and of its other documents
+    pub(crate) fn room<'a>(
+        room_id: &str,
+        content: &str,
+        documents: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Vec<Self> {
+        let main = Self {
+            room_id: room_id.to_string(),
+            doc_id: None,
+            content: content.to_string(),
+        };
+        std::iter::once(main)
+            .chain(documents.into_iter().map(|(doc_id, content)| Self {
+                room_id: room_id.to_string(),
+                doc_id: Some(doc_id.clone()),
+                content: content.clone(),
+            }))
+            .collect()
+    }
+}
+
+/// Members of a room, told how the writes of its documents go
+#[derive(Debug, Clone)]
+pub(crate) struct Listeners {
+    pub(crate) tx: RoomSender,
+    /// Set while the content of the room can't be written
+    pub(crate) persistence_degraded: Arc<AtomicBool>,
+    /// Revisions of the documents, acknowledged once written
+    pub(crate) sequencers: Sequencers,
+}
+
+/// Document of a loaded room, written by the actor when it changes
+#[derive(Debug)]
+struct Tracked {
+    room_id: String,
+    doc_id: Option<String>,
+    content_rx: watch::Receiver<String>,
+    listeners: Listeners,
+    unsaved: Arc<AtomicBool>,
+    /// Cancelled when the room or the document stopped, it is not written past that
+    stopped: CancellationToken,
+    /// Content last written, `None` until the actor first saw the document
+    written: Option<String>,
+    /// Changed since the last successful write
+    dirty: bool,
+    /// Successful writes of the document since it is tracked
+    writes: u64,
+    health: PersistenceHealth,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    /// Kept here rather than in the actor, so that it gets them back when restarted
+    tracked: Mutex<HashMap<u64, Tracked>>,
+    next_id: AtomicU64,
+    /// Notified when a document is tracked
+    registered: Notify,
+    /// Documents written since the server started
+    written: AtomicU64,
+    /// Held while documents are written, see [`WriteBehind::pause`]
+    writing: tokio::sync::Mutex<()>,
+}
+
+/// Writes the documents of the loaded rooms to their storage, in one write per
+/// `PERSIST_INTERVAL_SECONDS` for all the documents that changed meanwhile.
+/// Rooms only tell it when they change, so idle rooms cost nothing and SQLite sees one writer.
+#[derive(Clone)]
+pub(crate) struct WriteBehind {
+    /// Gets the settings and saved versions of the rooms written
+    db: SqlitePool,
+    storage: Arc<dyn Storage>,
+    /// Gets a snapshot of every document written
+    mirror: Option<Mirror>,
+    shared: Arc<Shared>,
+}
+
+/// What happened to a tracked document
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    Changed,
+    Stopped,
+}
+
+impl WriteBehind {
+    /// Start the actor writing to `storage`, restarted if it panics
+    pub(crate) fn spawn(db: SqlitePool, storage: Arc<dyn Storage>, mirror: Option<Mirror>) -> Self {
+        let write_behind = Self {
+            db,
+            storage,
+            mirror,
+            shared: Arc::new(Shared::default()),
+        };
+        let actor = write_behind.clone();
+        supervisor::spawn_supervised("write-behind".to_string(), move |restarts| {
+            actor.clone().run(restarts)
+        });
+        write_behind
+    }
+
+    /// Write a document of a room whenever it changes, until `handle` stops.
+    /// The members of the room are told of the revisions written, when its content can't be
+    /// saved, and when it can again.
+    /// Returns the flag set while the document has changes not written yet.
+    pub(crate) fn track(
+        &self,
+        room_id: &str,
+        doc_id: Option<&str>,
+        content_rx: &watch::Receiver<String>,
+        listeners: &Listeners,
+        handle: &RoomHandle,
+    ) -> Arc<AtomicBool> {
+        let unsaved = Arc::new(AtomicBool::new(false));
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.tracked.lock().unwrap().insert(
+            id,
+            Tracked {
+                room_id: room_id.to_string(),
+                doc_id: doc_id.map(str::to_string),
+                content_rx: content_rx.clone(),
+                listeners: listeners.clone(),
+                unsaved: unsaved.clone(),
+                stopped: handle.stopped(),
+                written: None,
+                dirty: false,
+                writes: 0,
+                health: PersistenceHealth::default(),
+            },
+        );
+        self.shared.registered.notify_one();
+        unsaved
+    }
+
+    /// Storage the rooms are written to
+    pub(crate) const fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    /// Write `writes` with `storage::store`, then mirror them
+    pub(crate) async fn write(&self, writes: &[Write]) -> Result<()> {
+        let writing = self.shared.writing.lock().await;
+        let result = self.store(writes).await;
+        drop(writing);
+        result
+    }
+
+    /// Wait for the writes in flight, and hold the next ones off until the guard is dropped
+    pub(crate) async fn pause(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.shared.writing.lock().await
+    }
+
+    async fn store(&self, writes: &[Write]) -> Result<()> {
+        storage::store(&self.db, self.storage.as_ref(), writes).await?;
+        self.shared.written.fetch_add(
+            u64::try_from(writes.len()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        if let Some(mirror) = &self.mirror {
+            for write in writes {
+                mirror.snapshot(write);
+            }
+        }
+        Ok(())
+    }
+
+    /// Documents written since the server started
+    pub(crate) fn written(&self) -> u64 {
+        self.shared.written.load(Ordering::Relaxed)
+    }
+
+    /// Writes of the documents of each loaded room since it was loaded, by room id
+    pub(crate) fn room_writes(&self) -> HashMap<String, u64> {
+        let mut writes = HashMap::new();
+        let tracked = self.shared.tracked.lock().unwrap();
+        for document in tracked.values() {
+            *writes.entry(document.room_id.clone()).or_default() += document.writes;
+        }
+        drop(tracked);
+        writes
+    }
+
+    /// Documents of a room still tracked
+    #[cfg(test)]
+    pub(crate) fn tracked(&self, room_id: &str) -> usize {
+        self.shared
+            .tracked
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|tracked| tracked.room_id == room_id)
+            .count()
+    }
+
+    async fn run(self, restarts: u32) {
+        let mut interval = time::interval(
+            PERSIST_INTERVAL
+                .get()
+                .copied()
+                .unwrap_or(DEFAULT_PERSIST_INTERVAL),
+        );
+        let mut watched = HashSet::new();
+        let mut events = SelectAll::new();
+        // The panic may have lost a change, everything is written again
+        self.watch_new(&mut watched, &mut events, restarts > 0);
+        loop {
+            tokio::select! {
+                () = self.shared.registered.notified() => {
+                    self.watch_new(&mut watched, &mut events, false);
+                }
+                Some((id, event)) = events.next() => self.handle(id, event),
+                _ = interval.tick() => self.flush().await,
+            }
+        }
+    }
+
+    /// Follow the changes of the documents tracked since the last call
+    fn watch_new(
+        &self,
+        watched: &mut HashSet<u64>,
+        events: &mut SelectAll<BoxStream<'static, (u64, Event)>>,
+        rewrite: bool,
+    ) {
+        let mut tracked = self.shared.tracked.lock().unwrap();
+        for (&id, document) in tracked.iter_mut() {
+            if !watched.insert(id) {
+                continue;
+            }
+            let mut content_rx = document.content_rx.clone();
+            if document.written.is_none() {
+                // Just loaded or created, the content is already stored
+                document.written = Some(content_rx.borrow_and_update().clone());
+            }
+            document.dirty |= rewrite;
+            events.push(changes(id, content_rx, document.stopped.clone()));
+        }
+        watched.retain(|id| tracked.contains_key(id));
+    }
+
+    fn handle(&self, id: u64, event: Event) {
+        let mut tracked = self.shared.tracked.lock().unwrap();
+        match event {
+            Event::Changed => {
+                if let Some(document) = tracked.get_mut(&id) {
+                    document.dirty = true;
+                    document.unsaved.store(true, Ordering::Relaxed);
+                }
+            }
+            // Written on their own when stopped, see `flush_room`
+            Event::Stopped => {
+                tracked.remove(&id);
+            }
+        }
+        drop(tracked);
+    }
+
+    /// Write every document that changed since the last flush
+    async fn flush(&self) {
+        let mut batch = Vec::new();
+        let mut writes = Vec::new();
+        // Taken before reading the contents, a room burning meanwhile waits for their write
+        let writing = self.shared.writing.lock().await;
+        // Scoped rather than dropped, for the future to stay `Send`
+        {
+            let mut tracked = self.shared.tracked.lock().unwrap();
+            for (&id, document) in tracked.iter_mut() {
+                if !document.dirty || document.stopped.is_cancelled() {
+                    continue;
+                }
+                let content = document.content_rx.borrow().clone();
+                if document.written.as_ref() == Some(&content) {
+                    document.dirty = false;
+                    document.unsaved.store(false, Ordering::Relaxed);
+                    continue;
+                }
+                batch.push(id);
+                writes.push(Write {
+                    room_id: document.room_id.clone(),
+                    doc_id: document.doc_id.clone(),
+                    content,
+                });
+            }
+        }
+        if writes.is_empty() {
+            return;
+        }
+
+        // Failed writes are retried on the next flush
+        let result = self.store(&writes).await;
+        drop(writing);
+        if let Err(e) = &result {
+            eprintln!(
+                "Failed to write {} documents to storage: {e:#}",
+                writes.len()
+            );
+        }
+
+        let mut written = Vec::new();
+        {
+            let mut tracked = self.shared.tracked.lock().unwrap();
+            for (id, write) in batch.into_iter().zip(writes) {
+                let Some(document) = tracked.get_mut(&id) else {
+                    continue;
+                };
+                if result.is_ok() {
+                    // Changed again meanwhile if it differs from the current content
+                    document.dirty = *document.content_rx.borrow() != write.content;
+                    document.unsaved.store(document.dirty, Ordering::Relaxed);
+                    written.push((document.listeners.clone(), document.dirty, write.clone()));
+                    document.written = Some(write.content);
+                    document.writes += 1;
+                }
+                if let Some(degraded) = document.health.record(result.is_ok()) {
+                    if degraded {
+                        eprintln!("Persistence degraded for room {}", document.room_id);
+                    } else {
+                        println!("Persistence restored for room {}", document.room_id);
+                    }
+                    document
+                        .listeners
+                        .persistence_degraded
+                        .store(degraded, Ordering::Relaxed);
+                    let _ = document.listeners.tx.send(persistence_message(degraded));
+                }
+            }
+        }
+
+        let now = unix_timestamp();
+        for (listeners, dirty, write) in written {
+            let sequencers = listeners.sequencers.lock().await;
+            let revision =
+                ot::revision_of(&sequencers, write.doc_id.as_deref(), &write.content, !dirty);
+            drop(sequencers);
+            // Otherwise the clients weren't told the revision of the content written
+            if let Some(revision) = revision {
+                let _ = listeners
+                    .tx
+                    .send(persisted_message(write.doc_id, revision, now));
+            }
+        }
+    }
+}
+
+/// Changes of a document, until it stops or its room is dropped
+fn changes(
+    id: u64,
+    content_rx: watch::Receiver<String>,
+    stopped: CancellationToken,
+) -> BoxStream<'static, (u64, Event)> {
+    stream::unfold(Some(content_rx), move |content_rx| {
+        let stopped = stopped.clone();
+        async move {
+            let mut content_rx = content_rx?;
+            tokio::select! {
+                () = stopped.cancelled() => Some(((id, Event::Stopped), None)),
+                changed = content_rx.changed() => match changed {
+                    Ok(()) => Some(((id, Event::Changed), Some(content_rx))),
+                    Err(_) => Some(((id, Event::Stopped), None)),
+                },
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Listeners, Write, WriteBehind};
+    use crate::database;
+    use crate::ot::Sequencers;
+    use crate::room_handle::RoomHandle;
+    use crate::sequence::RoomSender;
+    use crate::storage::SqliteStorage;
+    use sqlx::SqlitePool;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::watch;
+    use tokio::time::{self, Duration};
+
+    async fn test_db() -> SqlitePool {
+        database::memory().await.unwrap()
+    }
+
+    async fn stored(db: &SqlitePool, room_id: &str) -> Option<String> {
+        sqlx::query_scalar::<_, String>("SELECT content FROM rooms WHERE room_id = ?")
+            .bind(room_id)
+            .fetch_optional(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_changed_rooms_are_written_together() {
+        let db = test_db().await;
+        let write_behind =
+            WriteBehind::spawn(db.clone(), Arc::new(SqliteStorage::new(db.clone())), None);
+        let listeners = Listeners {
+            tx: RoomSender::new(16),
+            persistence_degraded: Arc::new(AtomicBool::new(false)),
+            sequencers: Sequencers::default(),
+        };
+        let handles: Vec<RoomHandle> = (0..3).map(|_| RoomHandle::default()).collect();
+        let senders: Vec<_> = handles
+            .iter()
+            .enumerate()
+            .map(|(i, handle)| {
+                let (content_tx, content_rx) = watch::channel(String::new());
+                let unsaved =
+                    write_behind.track(&format!("room{i}"), None, &content_rx, &listeners, handle);
+                (content_tx, unsaved)
+            })
+            .collect();
+        time::sleep(Duration::from_millis(50)).await;
+
+        senders[0].0.send_replace("first".to_string());
+        senders[2].0.send_replace("third".to_string());
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(senders[0].1.load(Ordering::Relaxed));
+        assert!(!senders[1].1.load(Ordering::Relaxed));
+
+        time::timeout(Duration::from_secs(5), async {
+            while stored(&db, "room2").await.is_none() {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(stored(&db, "room0").await.as_deref(), Some("first"));
+        assert_eq!(stored(&db, "room1").await, None);
+        assert!(!senders[0].1.load(Ordering::Relaxed));
+        assert_eq!(write_behind.room_writes().get("room0"), Some(&1));
+        assert_eq!(write_behind.room_writes().get("room1"), Some(&0));
+        assert_eq!(write_behind.written(), 2);
+
+        // Stopped rooms are forgotten, and never written again
+        handles[0].shutdown();
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(write_behind.tracked("room0"), 0);
+        assert_eq!(write_behind.tracked("room2"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_acknowledged() {
+        let db = test_db().await;
+        let write_behind =
+            WriteBehind::spawn(db.clone(), Arc::new(SqliteStorage::new(db.clone())), None);
+        let listeners = Listeners {
+            tx: RoomSender::new(16),
+            persistence_degraded: Arc::new(AtomicBool::new(false)),
+            sequencers: Sequencers::default(),
+        };
+        let mut rx = listeners.tx.subscribe();
+        let handle = RoomHandle::default();
+        let (content_tx, content_rx) = watch::channel(String::new());
+        write_behind.track("room", Some("notes"), &content_rx, &listeners, &handle);
+        time::sleep(Duration::from_millis(50)).await;
+
+        content_tx.send_replace("saved".to_string());
+        let message = time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(message["type"], "persisted");
+        assert_eq!(message["doc_id"], "notes");
+        assert_eq!(message["revision"], 0);
+        assert!(message["value"].as_str().unwrap().parse::<i64>().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_written_in_one_transaction() {
+        let db = test_db().await;
+        let write_behind =
+            WriteBehind::spawn(db.clone(), Arc::new(SqliteStorage::new(db.clone())), None);
+        let writes = [
+            Write {
+                room_id: "room".to_string(),
+                doc_id: None,
+                content: "main".to_string(),
+            },
+            Write {
+                room_id: "room".to_string(),
+                doc_id: Some("notes".to_string()),
+                content: "notes".to_string(),
+            },
+        ];
+        write_behind.write(&writes).await.unwrap();
+        assert_eq!(stored(&db, "room").await.as_deref(), Some("main"));
+
+        // A failed write leaves nothing behind
+        sqlx::query("DROP TABLE documents")
+            .execute(&db)
+            .await
+            .unwrap();
+        let writes = [
+            Write {
+                room_id: "other".to_string(),
+                doc_id: None,
+                content: "main".to_string(),
+            },
+            writes[1].clone(),
+        ];
+        assert!(write_behind.write(&writes).await.is_err());
+        assert_eq!(stored(&db, "other").await, None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::error_code::ErrorCode;
+use crate::rooms::RoomState;
+use crate::{frozen_notice, unix_timestamp, SocketMessage, SocketMessageType};
+use serde_json::json;
+
+/// Write lock of a room ("presenter mode"): while a member holds it, the others can't edit
+/// the room
+#[derive(Debug, Default)]
+pub(crate) struct WriteLock {
+    holder: Option<String>,
+}
+
+impl WriteLock {
+    pub(crate) fn holder(&self) -> Option<&str> {
+        self.holder.as_deref()
+    }
+
+    /// Holder of the lock, if it keeps `username` from editing
+    pub(crate) fn locking_out(&self, username: &str) -> Option<&str> {
+        self.holder().filter(|holder| *holder != username)
+    }
+
+    /// Take the lock if nobody holds it.
+    /// Returns whether `username` holds it now.
+    pub(crate) fn request(&mut self, username: &str) -> bool {
+        let holder = self.holder.get_or_insert_with(|| username.to_string());
+        *holder == username
+    }
+
+    /// Hand the lock over to `to`, or release it if `None`, as its holder
+    pub(crate) fn grant(&mut self, username: &str, to: Option<String>) -> Result<(), String> {
+        if self.holder.as_deref() != Some(username) {
+            return Err("Only the holder of the write lock can hand it over.".to_string());
+        }
+        self.holder = to;
+        Ok(())
+    }
+
+    /// Release the lock if `username` holds it.
+    /// Returns whether it did.
+    pub(crate) fn release(&mut self, username: &str) -> bool {
+        let held = self.holder.as_deref() == Some(username);
+        if held {
+            self.holder = None;
+        }
+        held
+    }
+}
+
+/// Tells the members who holds the write lock, nobody if `holder` is `None`
+pub(crate) fn grant_message(holder: Option<String>) -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::GrantLock,
+        value: holder,
+    })
+    .to_string()
+}
+
+/// Asks the holder of the write lock to hand it over to `username`
+fn request_message(username: &str) -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::RequestLock,
+        username: username.to_string(),
+    })
+    .to_string()
+}
+
+pub(crate) fn locked_notice(holder: &str) -> String {
+    format!("{holder} is presenting, the room is read-only for the others.")
+}
+
+impl RoomState {
+    /// Give the write lock to `username` if it is free, or ask its holder for it
+    pub(crate) async fn request_lock(&self, username: &str) {
+        let granted = self.write_lock.lock().await.request(username);
+        let _ = self.tx.send(if granted {
+            grant_message(Some(username.to_string()))
+        } else {
+            request_message(username)
+        });
+    }
+
+    /// Hand the write lock over to `to`, a member of the room, or release it if `None`
+    pub(crate) async fn grant_lock(
+        &self,
+        username: &str,
+        to: Option<String>,
+    ) -> Result<(), String> {
+        if let Some(to) = &to {
+            if !self.users.lock().await.contains(to) {
+                return Err(format!("{to} is not in this room."));
+            }
+        }
+        self.write_lock.lock().await.grant(username, to.clone())?;
+        let _ = self.tx.send(grant_message(to));
+        Ok(())
+    }
+
+    /// Release the write lock if `username` holds it, once they left the room
+    pub(crate) async fn release_lock(&self, username: &str) {
+        if self.write_lock.lock().await.release(username) {
+            let _ = self.tx.send(grant_message(None));
+        }
+    }
+
+    /// Holder of the write lock, if someone other than `username` holds it
+    pub(crate) async fn locked_by_other(&self, username: &str) -> Option<String> {
+        self.write_lock
+            .lock()
+            .await
+            .locking_out(username)
+            .map(ToString::to_string)
+    }
+
+    /// Why `username` can't edit the room now: it is frozen, or someone else is presenting
+    pub(crate) async fn edit_refusal(&self, username: &str) -> Option<(ErrorCode, String)> {
+        let frozen_until = self
+            .freeze_schedule
+            .lock()
+            .await
+            .frozen_until(unix_timestamp());
+        if let Some(until) = frozen_until {
+            return Some((ErrorCode::RoomFrozen, frozen_notice(until)));
+        }
+        self.locked_by_other(username)
+            .await
+            .map(|holder| (ErrorCode::RoomLocked, locked_notice(&holder)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteLock;
+
+    #[test]
+    fn test_request_and_grant() {
+        let mut lock = WriteLock::default();
+        assert_eq!(lock.locking_out("alice"), None);
+
+        assert!(lock.request("alice"));
+        assert!(lock.request("alice"));
+        assert!(!lock.request("bob"));
+        assert_eq!(lock.holder(), Some("alice"));
+        assert_eq!(lock.locking_out("bob"), Some("alice"));
+        assert_eq!(lock.locking_out("alice"), None);
+
+        assert!(lock.grant("bob", None).is_err());
+        lock.grant("alice", Some("bob".to_string())).unwrap();
+        assert_eq!(lock.holder(), Some("bob"));
+        lock.grant("bob", None).unwrap();
+        assert_eq!(lock.holder(), None);
+    }
+
+    #[test]
+    fn test_release() {
+        let mut lock = WriteLock::default();
+        assert!(!lock.release("alice"));
+        lock.request("alice");
+        assert!(!lock.release("bob"));
+        assert!(lock.release("alice"));
+        assert_eq!(lock.holder(), None);
+    }
+}
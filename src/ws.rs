@@ -0,0 +1,1549 @@
+use crate::archive::{self, ArchivedJoin};
+use crate::auth::{self, AuthUser};
+use crate::burn::BurnAfter;
+use crate::client_ip::ClientIp;
+use crate::compat::{self, ClientMessage};
+use crate::documents::{self, MAIN_DOCUMENT};
+use crate::encryption::{self, EncryptionParams};
+use crate::error_code::ErrorCode;
+use crate::heartbeat::Heartbeat;
+use crate::line_claims::LineRange;
+use crate::members::{self, Access};
+use crate::metrics::RoomActivity;
+use crate::outbound::Outbound;
+use crate::protocol::{self, Capability, Subprotocol, Wire};
+use crate::ranges;
+use crate::resume::ResumeInfo;
+use crate::room_users::RoomUsers;
+use crate::rooms::{restore_room, RoomState};
+use crate::sequence::{Delivery, Missed, RoomSender};
+use crate::sessions::{self, StoredSession};
+use crate::storage::store_new_room;
+use crate::trace::Direction;
+use crate::webhooks::WebhookEvent;
+use crate::write_lock;
+use crate::{
+    admission, freeze, ot, room_id, supervisor, trash, unix_timestamp, username, visibility,
+    AppState, CustomError, SocketMessage, SocketMessageType,
+};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Notify};
+use tokio::time::Instant;
+use tracing::Instrument;
+use ts_rs::TS;
+
+/// Handler
+pub(crate) async fn handler(
+    ws: WebSocketUpgrade,
+    ClientIp(ip): ClientIp,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    if state.draining.is_cancelled() {
+        return retry_later(&state, "Server shutting down, try again later.");
+    }
+    // Spread reconnection storms instead of accepting everyone at once
+    if !state.upgrade_gate.admit().await {
+        println!("Too many connections, rejecting upgrade from {ip}");
+        return retry_later(&state, "Server overloaded, try again later.");
+    }
+
+    // The session cookie is sent with the upgrade request
+    let identity = match auth::authenticate(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            println!("Failed to authenticate upgrade from {ip}: {e:#}");
+            return CustomError::new(StatusCode::UNAUTHORIZED, "Authentication failed.")
+                .into_response();
+        }
+    };
+    let subprotocol = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Subprotocol::select);
+    let ws = match subprotocol {
+        Some(subprotocol) => ws.protocols([subprotocol.name()]),
+        None => ws,
+    };
+    let subprotocol = subprotocol.unwrap_or_default();
+    ws.on_upgrade(move |socket| handle_socket(socket, state, ip, identity, subprotocol))
+        .into_response()
+}
+
+/// Refuse an upgrade, telling the client to retry after a jittered delay
+fn retry_later(state: &AppState, error: &str) -> Response {
+    let retry_after = admission::reconnect_delay(state.config.reconnect_jitter);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(
+            header::RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string(),
+        )],
+        Json(json!({
+            "error": error,
+            "retry_after_ms": retry_after.as_millis(),
+        })),
+    )
+        .into_response()
+}
+
+/// Close frame telling the client to reconnect later, with a jittered delay in the reason
+fn restart_close_frame(state: &AppState) -> Message {
+    let retry_after = admission::reconnect_delay(state.config.reconnect_jitter);
+    Message::Close(Some(CloseFrame {
+        code: close_code::RESTART,
+        reason: json!({ "retry_after_ms": retry_after.as_millis() })
+            .to_string()
+            .into(),
+    }))
+}
+
+/// Close frame sent to clients disconnected through the admin API
+fn disconnected_close_frame() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::POLICY,
+        reason: "Disconnected by an administrator.".into(),
+    }))
+}
+
+/// Message sent to kicked clients before their close frame, so they don't reconnect
+fn kicked_message() -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::Kicked,
+        value: Some("Kicked from the room by an administrator.".to_string()),
+    })
+    .to_string()
+}
+
+/// Close frame sent to clients speaking an unsupported protocol version
+fn unsupported_protocol_close_frame() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::PROTOCOL,
+        reason: "Unsupported protocol version.".into(),
+    }))
+}
+
+/// Answer the keepalive of browsers, which cannot send ping frames: a binary frame holding
+/// the ping opcode is answered by one holding the pong opcode.
+/// Native ping frames are answered by the WebSocket layer itself.
+fn send_pong_frame(sender: &Outbound, b: &[u8]) {
+    if b.first() == Some(&0x9) {
+        sender.send(Message::Binary(vec![0xA]));
+    }
+}
+
+/// Close frame sent to clients whose join message was rejected
+fn rejected_close_frame(reason: String) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::POLICY,
+        reason: reason.into(),
+    }))
+}
+
+/// Message telling a client what went wrong
+fn error_message(code: ErrorCode, error: impl Into<String>) -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::Error,
+        value: Some(error.into()),
+        code: Some(code),
+    })
+    .to_string()
+}
+
+/// Tell a client why it cannot join, then close its connection
+async fn reject(sender: &Outbound, code: ErrorCode, error: String) {
+    sender.send(Message::Text(error_message(code, error.clone())));
+    sender.close(rejected_close_frame(error)).await;
+}
+
+/// Log the close frame of a client, unless it just left
+fn log_client_close(frame: Option<&CloseFrame<'_>>) {
+    if let Some(frame) =
+        frame.filter(|frame| ![close_code::NORMAL, close_code::AWAY].contains(&frame.code))
+    {
+        println!(
+            "Client closed its connection: {} {}",
+            frame.code, frame.reason
+        );
+    }
+}
+
+/// First message of a client, joining a room
+#[derive(TS, Deserialize, Debug)]
+#[ts(export)]
+pub(crate) struct Connect {
+    pub(crate) username: String,
+    /// Id of the room
+    pub(crate) channel: String,
+    /// Create the room end-to-end encrypted, or make sure it is
+    #[serde(default)]
+    #[ts(optional)]
+    pub(crate) encryption: Option<EncryptionParams>,
+    /// Missing for clients predating the handshake, which get no `hello`
+    #[serde(default)]
+    #[ts(optional)]
+    pub(crate) protocol_version: Option<u32>,
+    #[serde(default)]
+    #[ts(type = "Array<string> | undefined")]
+    pub(crate) capabilities: Vec<String>,
+    /// Token of a previous connection to the room, with the `resume` capability
+    #[serde(default)]
+    #[ts(optional)]
+    pub(crate) resume: Option<String>,
+    /// Password of a private room, for those who are not its members
+    #[serde(default)]
+    #[ts(optional)]
+    pub(crate) password: Option<String>,
+}
+
+/// Value of `presence` messages, the users of the room sorted
+#[derive(TS, Serialize, Debug)]
+#[ts(export)]
+pub(crate) struct Presence(Vec<String>);
+
+/// Message announcing the highlighting language of a room, none if unset
+pub(crate) fn syntax_language_message(language: Option<String>) -> String {
+    json!(SocketMessage! {
+        message_type: SocketMessageType::LanguageChanged,
+        value: language,
+    })
+    .to_string()
+}
+
+/// Message announcing that a room became read-only until `until`, or writable again
+pub(crate) fn freeze_message(until: Option<i64>) -> serde_json::Value {
+    match until {
+        Some(until) => json!(SocketMessage! {
+            message_type: SocketMessageType::Freeze,
+            value: Some(frozen_notice(until)),
+        }),
+        None => json!(SocketMessage! {
+            message_type: SocketMessageType::Unfreeze,
+        }),
+    }
+}
+
+pub(crate) fn frozen_notice(until: i64) -> String {
+    format!(
+        "Room is read-only until {} UTC.",
+        freeze::format_time(until)
+    )
+}
+
+/// Message telling the members of a room whether its content is currently saved
+pub(crate) fn persistence_message(degraded: bool) -> String {
+    if degraded {
+        json!(SocketMessage! {
+            message_type: SocketMessageType::PersistenceDegraded,
+            value: Some("Changes can't be saved right now, they will be lost if the server restarts.".to_string()),
+        })
+    } else {
+        json!(SocketMessage! {
+            message_type: SocketMessageType::PersistenceRestored,
+        })
+    }
+    .to_string()
+}
+
+/// Whole state of a room, sent to the clients that missed some of its messages
+async fn room_state_messages(
+    state: &AppState,
+    room_id: &str,
+    operations: bool,
+    multi_document: bool,
+) -> Vec<String> {
+    let mut messages = resync_messages(state, room_id).await;
+    if operations {
+        messages.extend(ot::snapshot_messages(state, room_id, multi_document).await);
+    }
+    messages
+}
+
+/// Message telling the members of a room that a revision of a document was written to the
+/// database at the Unix timestamp `at`
+pub(crate) fn persisted_message(doc_id: Option<String>, revision: u64, at: i64) -> String {
+    json!(SocketMessage! {
+        doc_id: doc_id,
+        message_type: SocketMessageType::Persisted,
+        value: Some(at.to_string()),
+        revision: Some(revision),
+    })
+    .to_string()
+}
+
+/// Error sent to clients sending too many messages
+fn rate_limited_message() -> String {
+    error_message(ErrorCode::RateLimited, "Too many messages, slow down.")
+}
+
+/// Handle sending and receiving messages.
+/// `identity` is the logged in account, if any.
+#[tracing::instrument(
+    name = "websocket",
+    skip_all,
+    fields(%ip, room_id = tracing::field::Empty, username = tracing::field::Empty)
+)]
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    ip: IpAddr,
+    identity: Option<AuthUser>,
+    subprotocol: Subprotocol,
+) {
+    let (sink, mut receiver) = socket.split();
+    let sender = Outbound::spawn(sink);
+
+    // Counted until the connection closes, whether it joins a room or not
+    let Some(_open) = state.connections.open(
+        ip,
+        state.config.max_connections,
+        state.config.max_connections_per_ip,
+    ) else {
+        reject(
+            &sender,
+            ErrorCode::TooManyConnections,
+            "Too many connections, try again later.".to_string(),
+        )
+        .await;
+        return;
+    };
+
+    let mut username = String::new();
+    let mut channel = String::new();
+    let content;
+    let frozen_until;
+    let persistence_degraded;
+    let syntax_language;
+    let lock_holder;
+    let line_claims;
+    let mut encrypted = false;
+    let mut hello = None;
+    let mut wire = Wire::default();
+    let mut multi_document = false;
+    let mut operations = false;
+    let mut fetches_ranges = false;
+    let mut access = Access::Open;
+    let document_contents;
+    let mut revisions = HashMap::new();
+    let resumed;
+    let mut resume_token = None;
+    let mut first_connection = false;
+    // The client read a content that burns after reading, it is wiped once the client leaves
+    let mut burns_on_leave = false;
+    let mut tx = None::<RoomSender>;
+    let mut activity = None::<Arc<RoomActivity>>;
+
+    while let Some(Ok(msg)) = receiver.next().await {
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Binary(b) => {
+                send_pong_frame(&sender, &b);
+                continue;
+            }
+            Message::Close(frame) => {
+                log_client_close(frame.as_ref());
+                return;
+            }
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+
+        if !state.ws_rate_limiter.check(ip) {
+            sender.send(Message::Text(rate_limited_message()));
+            continue;
+        }
+
+        let mut connect: Connect = match serde_json::from_str(&text) {
+            Ok(connect) => connect,
+            Err(err) => {
+                eprintln!("Invalid connect message ({} bytes): {err}", text.len());
+                reject(&sender, ErrorCode::InvalidJson, "Invalid JSON".to_string()).await;
+                return;
+            }
+        };
+        connect.channel = match room_id::normalize(&connect.channel, state.config.slugify_room_ids)
+        {
+            Ok(channel) => channel,
+            Err(e) => {
+                reject(&sender, ErrorCode::InvalidRoomId, e).await;
+                return;
+            }
+        };
+        connect.username = match username::sanitize(&connect.username) {
+            Ok(username) => username,
+            Err(e) => {
+                reject(&sender, ErrorCode::InvalidUsername, e).await;
+                return;
+            }
+        };
+        // Lasts until the client is in the room, or refused
+        let _join = tracing::info_span!("join", room_id = %connect.channel);
+
+        if let Some(version) = connect.protocol_version {
+            match protocol::negotiate(version, &connect.capabilities) {
+                Ok(negotiated) => hello = Some(negotiated),
+                Err(e) => {
+                    sender.send(Message::Text(error_message(ErrorCode::ProtocolMismatch, e)));
+                    sender.close(unsupported_protocol_close_frame()).await;
+                    return;
+                }
+            }
+        }
+
+        // Reconnecting clients get their name back and skip the contents they already have
+        let resumable = hello
+            .as_ref()
+            .is_some_and(|hello| hello.has(Capability::Resume));
+        resumed = connect
+            .resume
+            .as_deref()
+            .filter(|_| resumable)
+            .and_then(|token| {
+                state.resume_sessions.take(
+                    token,
+                    &connect.channel,
+                    identity.as_ref().map(|account| account.id),
+                )
+            });
+        if let Some(resumed) = &resumed {
+            connect.username.clone_from(&resumed.username);
+        }
+
+        // Logged in users always use their account name, anonymous users can't take the
+        // name of an account, nor get one when theirs is taken in the room.
+        // Checked before locking the rooms, not to keep the other joins waiting on the database.
+        let mut alternatives = Vec::new();
+        if let Some(account) = &identity {
+            connect.username.clone_from(&account.username);
+        } else if state.config.require_auth {
+            reject(
+                &sender,
+                ErrorCode::Unauthorized,
+                "Authentication required.".to_string(),
+            )
+            .await;
+            return;
+        } else {
+            alternatives = username::alternatives(&connect.username);
+            let names: Vec<String> = std::iter::once(connect.username.clone())
+                .chain(alternatives.iter().cloned())
+                .collect();
+            match auth::registered(&state, &names).await {
+                Ok(registered) if registered.contains(&connect.username) => {
+                    reject(
+                        &sender,
+                        ErrorCode::UsernameTaken,
+                        "This username belongs to an account, log in to use it.".to_string(),
+                    )
+                    .await;
+                    return;
+                }
+                Ok(registered) => alternatives.retain(|name| !registered.contains(name)),
+                Err(e) => {
+                    eprintln!("Failed to read users from database: {e}");
+                    reject(
+                        &sender,
+                        ErrorCode::Internal,
+                        "Failed to check the username, try again later.".to_string(),
+                    )
+                    .await;
+                    return;
+                }
+            }
+        }
+
+        if let Some(Err(e)) = connect.encryption.as_ref().map(EncryptionParams::validate) {
+            reject(&sender, ErrorCode::InvalidEncryption, e).await;
+            return;
+        }
+
+        // Private rooms only let their members in, and only their editors write
+        access = members::access(
+            &state.db,
+            &connect.channel,
+            identity.as_ref().map(|account| account.id),
+            connect.password.as_deref(),
+        )
+        .await;
+        if access == Access::Denied {
+            reject(
+                &sender,
+                ErrorCode::Forbidden,
+                "This room is private.".to_string(),
+            )
+            .await;
+            return;
+        }
+        // Joining would create a new room under the id of the trashed one
+        if trash::is_trashed(&state.db, &connect.channel).await {
+            reject(
+                &sender,
+                ErrorCode::RoomTrashed,
+                "This room is in the trash, restore it first.".to_string(),
+            )
+            .await;
+            return;
+        }
+        if archive::is_archived(&state.db, &connect.channel).await {
+            match state.config.archived_join {
+                ArchivedJoin::Reject => {
+                    reject(
+                        &sender,
+                        ErrorCode::RoomArchived,
+                        "This room is archived, unarchive it first.".to_string(),
+                    )
+                    .await;
+                    return;
+                }
+                ArchivedJoin::Unarchive => {
+                    if let Err(e) = archive::unarchive(&state.db, &connect.channel).await {
+                        eprintln!("Failed to unarchive room: {e:#}");
+                        reject(
+                            &sender,
+                            ErrorCode::Internal,
+                            "Failed to unarchive the room.".to_string(),
+                        )
+                        .await;
+                        return;
+                    }
+                    println!("Room {} unarchived on join", connect.channel);
+                }
+            }
+        }
+
+        {
+            channel.clone_from(&connect.channel);
+
+            // The room is loaded or created without holding the rooms, the database may be
+            // slow, and whether another client did it meanwhile is checked again after
+            let mut prepared = None;
+            let mut rooms = loop {
+                let rooms = state.rooms.lock().await;
+                if prepared.is_some() || rooms.contains_key(&connect.channel) {
+                    break rooms;
+                }
+                drop(rooms);
+                if let Some(room_state) = restore_room(&state, &connect.channel).await {
+                    prepared = Some((room_state, false));
+                    continue;
+                }
+                if let Err(refusal) = state
+                    .room_creation
+                    .check(&state.db, ip, &connect.channel)
+                    .await
+                {
+                    reject(&sender, refusal.code(), refusal.message().to_string()).await;
+                    return;
+                }
+                // Rooms created by an account belong to it
+                let owner_id = identity.as_ref().map(|account| account.id);
+                store_new_room(
+                    &state.db,
+                    &connect.channel,
+                    owner_id,
+                    connect.encryption.as_ref(),
+                )
+                .await;
+                let room_state = RoomState::new(&connect.channel, &state.write_behind)
+                    .with_encryption(connect.encryption.clone());
+                // Encrypted rooms only ever hold ciphertext
+                if let (Some(content), None) =
+                    (&state.config.default_room_content, &connect.encryption)
+                {
+                    let _ = room_state.content_tx.send(content.clone());
+                }
+                prepared = Some((room_state, true));
+            };
+            let mut created = false;
+            let room: &RoomState = match prepared {
+                Some((room_state, new)) => match rooms.entry(connect.channel.clone()) {
+                    Entry::Vacant(entry) => {
+                        created = new;
+                        entry.insert(room_state)
+                    }
+                    Entry::Occupied(entry) => entry.into_mut(),
+                },
+                None => &rooms[&connect.channel],
+            };
+
+            // Never let a client believe it writes to an encrypted room when it doesn't
+            if connect.encryption.is_some() && room.encryption.is_none() {
+                drop(rooms);
+                reject(
+                    &sender,
+                    ErrorCode::EncryptionMismatch,
+                    "This room already exists and is not encrypted.".to_string(),
+                )
+                .await;
+                return;
+            }
+
+            if room.closing_at.lock().await.is_some() {
+                drop(rooms);
+                reject(
+                    &sender,
+                    ErrorCode::RoomClosing,
+                    "This room is being deleted.".to_string(),
+                )
+                .await;
+                return;
+            }
+
+            tx = Some(room.tx.clone());
+            activity = Some(room.activity.clone());
+
+            // Add the user to the room, under another name if theirs is taken
+            let mut users = room.users.lock().await;
+            let resolved =
+                resolve_username(&users, &connect.username, &alternatives, identity.is_none());
+            let Some(resolved) = resolved else {
+                drop(users);
+                drop(rooms);
+                reject(
+                    &sender,
+                    ErrorCode::UsernameTaken,
+                    "This username is taken in this room.".to_string(),
+                )
+                .await;
+                return;
+            };
+            // Another tab of someone already in the room doesn't take a place
+            let max_users = state.config.max_room_users;
+            if max_users > 0 && !users.contains(&resolved) && users.len() >= max_users {
+                drop(users);
+                drop(rooms);
+                reject(
+                    &sender,
+                    ErrorCode::RoomFull,
+                    "This room is full.".to_string(),
+                )
+                .await;
+                return;
+            }
+            first_connection = users.join(&resolved);
+            drop(users);
+            username = resolved;
+            tracing::Span::current()
+                .record("room_id", channel.as_str())
+                .record("username", username.as_str());
+            content = room.content_rx.borrow().clone();
+            frozen_until = room
+                .freeze_schedule
+                .lock()
+                .await
+                .frozen_until(unix_timestamp());
+            persistence_degraded = room.persistence_degraded.load(Ordering::Relaxed);
+            syntax_language = room.syntax_language.lock().await.clone();
+            lock_holder = room
+                .write_lock
+                .lock()
+                .await
+                .holder()
+                .map(ToString::to_string);
+            line_claims = room.line_claim_messages().await;
+            encrypted = room.encryption.is_some();
+            multi_document = hello
+                .as_ref()
+                .is_some_and(|hello| hello.has(Capability::Documents));
+            operations = hello
+                .as_ref()
+                .is_some_and(|hello| hello.has(Capability::OperationalTransform));
+            fetches_ranges = hello
+                .as_ref()
+                .is_some_and(|hello| hello.has(Capability::Ranges));
+            document_contents = if multi_document {
+                let mut contents: Vec<_> = room
+                    .documents
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(doc_id, document)| {
+                        (doc_id.clone(), document.content_rx.borrow().clone())
+                    })
+                    .collect();
+                contents.sort();
+                contents
+            } else {
+                Vec::new()
+            };
+            burns_on_leave = room.burns_after(BurnAfter::Read).await && !room.is_empty().await;
+            // What the edits of the client are made on
+            revisions.insert(None, room.revision(None).await);
+            for (doc_id, _) in &document_contents {
+                revisions.insert(
+                    Some(doc_id.clone()),
+                    room.revision(Some(doc_id.as_str())).await,
+                );
+            }
+
+            drop(rooms);
+            if created {
+                state
+                    .webhooks
+                    .emit(WebhookEvent::RoomCreated, &channel, json!({}))
+                    .await;
+            }
+        }
+
+        if tx.is_some() && !username.is_empty() {
+            // Unlisted and private rooms stay out of the rooms lists
+            if visibility::is_listed(&state.db, &channel).await {
+                let rooms = state.rooms.lock().await;
+                for (room_name, room_state) in rooms.iter() {
+                    if room_name != &channel {
+                        let _ = room_state.tx.send(
+                            json!(SocketMessage! {
+                                message_type: SocketMessageType::UpdateRoomsList,
+                            })
+                            .to_string(),
+                        );
+                    }
+                }
+            }
+
+            // Always JSON, the client only knows the wire format once it read it
+            if let Some(hello) = &hello {
+                sender.send(Message::Text(
+                    json!(SocketMessage! {
+                        message_type: SocketMessageType::Hello,
+                        value: serde_json::to_string(hello).ok(),
+                    })
+                    .to_string(),
+                ));
+            }
+
+            wire = Wire::negotiated(hello.as_ref());
+            if hello
+                .as_ref()
+                .is_some_and(|hello| hello.has(Capability::Resume))
+            {
+                let token = state.resume_sessions.issue(
+                    &username,
+                    &channel,
+                    identity.as_ref().map(|account| account.id),
+                );
+                let info = ResumeInfo {
+                    token: token.clone(),
+                    resumed: resumed.is_some(),
+                };
+                sender.send(
+                    wire.frame(
+                        json!(SocketMessage! {
+                            message_type: SocketMessageType::Resume,
+                            value: serde_json::to_string(&info).ok(),
+                        })
+                        .to_string(),
+                    ),
+                );
+                resume_token = Some(token);
+            }
+            let already_has = |doc_id: Option<&str>, content: &str| {
+                resumed
+                    .as_ref()
+                    .is_some_and(|resumed| resumed.has(doc_id, content))
+            };
+            // Only the size of large documents is sent, the client fetching what it needs
+            let lazy = |content: &str| fetches_ranges && ranges::is_lazy(content);
+            if let Some(token) = &resume_token {
+                if !lazy(&content) {
+                    state.resume_sessions.delivered(token, None, &content);
+                }
+                for (doc_id, content) in &document_contents {
+                    if !lazy(content) {
+                        state
+                            .resume_sessions
+                            .delivered(token, Some(doc_id.clone()), content);
+                    }
+                }
+            }
+
+            // Send the user the current room content
+            if lazy(&content) {
+                sender.send(wire.frame(ranges::size_message(
+                    None,
+                    revisions.get(&None).copied(),
+                    content.len(),
+                )));
+            } else if !already_has(None, &content) {
+                sender.send(
+                    wire.frame(
+                        json!(SocketMessage! {
+                            message_type: SocketMessageType::content(encrypted),
+                            value: Some(content),
+                            revision: revisions.get(&None).copied(),
+                            username: "Server".to_string(),
+                        })
+                        .to_string(),
+                    ),
+                );
+            }
+            for (doc_id, content) in document_contents {
+                if already_has(Some(&doc_id), &content) {
+                    continue;
+                }
+                let revision = revisions.get(&Some(doc_id.clone())).copied();
+                if lazy(&content) {
+                    sender.send(wire.frame(ranges::size_message(
+                        Some(doc_id),
+                        revision,
+                        content.len(),
+                    )));
+                    continue;
+                }
+                sender.send(
+                    wire.frame(
+                        json!(SocketMessage! {
+                            doc_id: Some(doc_id),
+                            message_type: SocketMessageType::content(encrypted),
+                            value: Some(content),
+                            revision: revision,
+                            username: "Server".to_string(),
+                        })
+                        .to_string(),
+                    ),
+                );
+            }
+            // The revisions to make the first operations on
+            if operations {
+                for snapshot in ot::snapshot_messages(&state, &channel, multi_document).await {
+                    sender.send(wire.frame(snapshot));
+                }
+            }
+
+            if frozen_until.is_some() {
+                sender.send(wire.frame(freeze_message(frozen_until).to_string()));
+            }
+            if persistence_degraded {
+                sender.send(wire.frame(persistence_message(true)));
+            }
+            if syntax_language.is_some() {
+                sender.send(wire.frame(syntax_language_message(syntax_language)));
+            }
+            if lock_holder.is_some() {
+                sender.send(wire.frame(write_lock::grant_message(lock_holder)));
+            }
+            for claim in line_claims {
+                if multi_document || !documents::is_document_message(&claim) {
+                    sender.send(wire.frame(claim));
+                }
+            }
+            if let Some(announcement) = state.announcement.active() {
+                sender.send(wire.frame(announcement.socket_message()));
+            }
+            if username != connect.username {
+                sender.send(
+                    wire.frame(
+                        json!(SocketMessage! {
+                            message_type: SocketMessageType::UsernameAssigned,
+                            value: Some(username.clone()),
+                        })
+                        .to_string(),
+                    ),
+                );
+            }
+
+            break;
+        }
+        println!("Failed to connect to room!");
+        reject(
+            &sender,
+            ErrorCode::Internal,
+            "Failed to connect to room!".to_string(),
+        )
+        .await;
+        return;
+    }
+
+    let (Some(tx), Some(activity)) = (tx, activity) else {
+        println!("Failed to connect to room!");
+        return;
+    };
+
+    let mut rx = tx.subscribe();
+    let connected_at = unix_timestamp();
+    let (connection_id, disconnect) =
+        state
+            .connections
+            .register(&username, &channel, ip, connected_at);
+    let session_id = i64::try_from(connection_id).unwrap_or(i64::MAX);
+    let session = StoredSession {
+        connection_id: session_id,
+        room_id: channel.clone(),
+        username: username.clone(),
+        connected_at,
+    };
+    if let Err(e) = sessions::store(&state.db, &session).await {
+        eprintln!("Failed to record session: {e:#}");
+    }
+
+    // Other tabs of the same account already announced it
+    if first_connection {
+        state
+            .webhooks
+            .emit(
+                WebhookEvent::UserJoined,
+                &channel,
+                json!({ "username": username }),
+            )
+            .await;
+        let _ = tx.send(
+            json!(SocketMessage! {
+                message_type: SocketMessageType::Join,
+                username: username.clone(),
+            })
+            .to_string(),
+        );
+        if let Some(presence) = presence_message(&state, &channel).await {
+            let _ = tx.send(presence);
+        }
+    }
+
+    // Clients acknowledging the messages are resynced once they handled those before the ones
+    // they missed, operations can't be applied past a gap though
+    let delivery = Arc::new(std::sync::Mutex::new(Delivery::default()));
+    let resync = Arc::new(Notify::new());
+
+    let mut recv_messages = {
+        let sender = sender.clone();
+        let state = state.clone();
+        let channel = channel.clone();
+        let resume_token = resume_token.clone();
+        let delivery = delivery.clone();
+        let resync = resync.clone();
+        let task = async move {
+            'receive: loop {
+                let messages = tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok(msg) => {
+                            delivery.lock().unwrap().received(&msg);
+                            vec![msg]
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            state.saturation_metrics.record_lagged(skipped);
+                            let missed = delivery.lock().unwrap().missed(operations);
+                            match missed {
+                                // The client missed messages, send it the whole state of the room
+                                Missed::Resync => {
+                                    room_state_messages(&state, &channel, operations, multi_document)
+                                        .await
+                                }
+                                Missed::AwaitAck => continue 'receive,
+                                Missed::TooSlow => {
+                                    state.saturation_metrics.record_slow_consumer();
+                                    break 'receive;
+                                }
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break 'receive,
+                    },
+                    // It handled the messages before those it missed
+                    () = resync.notified() => {
+                        room_state_messages(&state, &channel, operations, multi_document).await
+                    }
+                };
+                for msg in messages {
+                    if !multi_document && documents::is_document_message(&msg) {
+                        continue;
+                    }
+                    if operations {
+                        // Sent as an operation instead, by the first client following them
+                        if let Some(doc_id) = ot::content_message_document(&msg) {
+                            let doc_id = Some(doc_id.as_str()).filter(|id| *id != MAIN_DOCUMENT);
+                            let rooms = state.rooms.lock().await;
+                            if let Some(room) = rooms.get(&channel) {
+                                room.catch_up_operations(doc_id).await;
+                            }
+                            drop(rooms);
+                            continue;
+                        }
+                    } else if ot::is_operation_message(&msg) {
+                        continue;
+                    }
+                    state
+                        .traces
+                        .record(&channel, connection_id, Direction::Sent, &msg);
+                    let queued = match &resume_token {
+                        // Only what reached the socket counts as delivered
+                        Some(token) => {
+                            let (state, token, sent) = (state.clone(), token.clone(), msg.clone());
+                            sender.send_then(wire.frame(msg), move || {
+                                state.resume_sessions.sent(&token, &sent);
+                            })
+                        }
+                        None => sender.send(wire.frame(msg)),
+                    };
+                    if !queued {
+                        break 'receive;
+                    }
+                }
+            }
+        };
+        tokio::spawn(task.in_current_span())
+    };
+
+    let heartbeat = Arc::new(Heartbeat::default());
+    let mut ping_client = {
+        let heartbeat = heartbeat.clone();
+        let sender = sender.clone();
+        let interval = state.config.heartbeat_interval;
+        let max_missed = state.config.heartbeat_max_missed;
+        tokio::spawn(async move {
+            match interval {
+                Some(interval) => heartbeat.run(&sender, interval, max_missed).await,
+                None => std::future::pending().await,
+            }
+        })
+    };
+
+    let mut send_messages = {
+        let sender = sender.clone();
+        let tx = tx.clone();
+        let name = username.clone();
+        let channel = channel.clone();
+        let state = state.clone();
+        let task = async move {
+            // Revision and content of the last edit of each document, `None` for the main one
+            let mut last_edits = HashMap::<Option<String>, (u64, String)>::new();
+            // End of the mute the client was last told about
+            let mut muted_notice = None;
+            while let Some(Ok(msg)) = receiver.next().await {
+                heartbeat.alive();
+                let text = match msg {
+                    Message::Text(text) => text,
+                    Message::Binary(b) => {
+                        send_pong_frame(&sender, &b);
+                        continue;
+                    }
+                    Message::Close(frame) => {
+                        log_client_close(frame.as_ref());
+                        break;
+                    }
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                };
+                state
+                    .traces
+                    .record(&channel, connection_id, Direction::Received, &text);
+
+                if u64::try_from(text.len()).unwrap_or(u64::MAX) > state.config.max_body_size {
+                    sender.send(wire.frame(error_message(
+                        ErrorCode::PayloadTooLarge,
+                        "This message is larger than the server accepts.",
+                    )));
+                    continue;
+                }
+                let decoded = compat::decode(
+                    subprotocol,
+                    multi_document,
+                    operations,
+                    fetches_ranges,
+                    text,
+                );
+                // Acknowledgements are no edits, they are neither muted nor rate limited
+                if let Ok(ClientMessage::Ack { seq }) = decoded {
+                    if delivery.lock().unwrap().ack(seq) {
+                        resync.notify_one();
+                    }
+                    continue;
+                }
+
+                // Muted clients are told once, then their frames are dropped until the mute ends
+                if let Some(until) =
+                    state
+                        .connections
+                        .muted_until(&channel, &name, unix_timestamp())
+                {
+                    if muted_notice != Some(until) {
+                        muted_notice = Some(until);
+                        sender.send(wire.frame(error_message(
+                            ErrorCode::Muted,
+                            format!("You are muted until {} UTC.", freeze::format_time(until)),
+                        )));
+                    }
+                    continue;
+                }
+
+                // Drop the frame, the next one carries the whole content anyway
+                if !state.ws_rate_limiter.check(ip) {
+                    sender.send(wire.frame(rate_limited_message()));
+                    continue;
+                }
+                activity.record_message();
+
+                let message = match decoded {
+                    Ok(message) => message,
+                    Err(e) => {
+                        sender.send(wire.frame(error_message(ErrorCode::InvalidJson, e)));
+                        continue;
+                    }
+                };
+                let reads = matches!(
+                    message,
+                    ClientMessage::GetPresence | ClientMessage::GetRange { .. }
+                );
+                if !access.can_edit() && !reads {
+                    sender.send(wire.frame(error_message(
+                        ErrorCode::Forbidden,
+                        "Viewers can't edit this room.",
+                    )));
+                    continue;
+                }
+                let (scope, mut text, base_revision) = match message {
+                    ClientMessage::Edit {
+                        doc_id,
+                        value,
+                        revision,
+                    } => (doc_id, value, revision),
+                    ClientMessage::Op(operation) => {
+                        let doc_id = operation.doc_id.clone();
+                        let rooms = state.rooms.lock().await;
+                        let Some(room) = rooms.get(&channel) else {
+                            continue;
+                        };
+                        let result = room
+                            .submit_operation(&state, &channel, operation, &name)
+                            .await;
+                        let revision = room.revision(doc_id.as_deref()).await;
+                        drop(rooms);
+                        match result {
+                            // For the clients not following the operations
+                            Ok(content) => {
+                                let _ = tx.send(
+                                    json!(SocketMessage! {
+                                        doc_id: doc_id,
+                                        message_type: SocketMessageType::Message,
+                                        value: Some(content),
+                                        revision: Some(revision),
+                                        username: name.clone(),
+                                    })
+                                    .to_string(),
+                                );
+                            }
+                            Err(rejected) => {
+                                sender.send(wire.frame(error_message(
+                                    ErrorCode::OperationRejected,
+                                    rejected.error,
+                                )));
+                                sender.send(wire.frame(rejected.snapshot));
+                            }
+                        }
+                        continue;
+                    }
+                    ClientMessage::GetPresence => {
+                        if let Some(presence) = presence_message(&state, &channel).await {
+                            sender.send(wire.frame(presence));
+                        }
+                        continue;
+                    }
+                    ClientMessage::RequestLock => {
+                        let rooms = state.rooms.lock().await;
+                        if let Some(room) = rooms.get(&channel) {
+                            room.request_lock(&name).await;
+                        }
+                        drop(rooms);
+                        continue;
+                    }
+                    ClientMessage::GrantLock { value } => {
+                        let rooms = state.rooms.lock().await;
+                        let result = match rooms.get(&channel) {
+                            Some(room) => room.grant_lock(&name, value).await,
+                            None => Ok(()),
+                        };
+                        drop(rooms);
+                        if let Err(e) = result {
+                            sender.send(wire.frame(error_message(ErrorCode::Forbidden, e)));
+                        }
+                        continue;
+                    }
+                    ClientMessage::ClaimLines { doc_id, start, end } => {
+                        let rooms = state.rooms.lock().await;
+                        let result = match rooms.get(&channel) {
+                            Some(room) => {
+                                room.claim_lines(&name, doc_id, LineRange { start, end })
+                                    .await
+                            }
+                            None => Ok(()),
+                        };
+                        drop(rooms);
+                        if let Err(e) = result {
+                            sender.send(wire.frame(error_message(ErrorCode::LineClaimed, e)));
+                        }
+                        continue;
+                    }
+                    ClientMessage::ReleaseLines => {
+                        let rooms = state.rooms.lock().await;
+                        if let Some(room) = rooms.get(&channel) {
+                            room.release_lines(&name).await;
+                        }
+                        drop(rooms);
+                        continue;
+                    }
+                    ClientMessage::Checkpoint { value } => {
+                        let rooms = state.rooms.lock().await;
+                        let result = match rooms.get(&channel) {
+                            Some(room) => room
+                                .save_checkpoint(&state, &channel, &value, Some(&name))
+                                .await
+                                .map(drop),
+                            None => Ok(()),
+                        };
+                        drop(rooms);
+                        if let Err(e) = result {
+                            sender.send(wire.frame(error_message(ErrorCode::InvalidCheckpoint, e)));
+                        }
+                        continue;
+                    }
+                    // Handled once decoded
+                    ClientMessage::Ack { .. } => continue,
+                    ClientMessage::GetRange {
+                        doc_id,
+                        offset,
+                        length,
+                    } => {
+                        let rooms = state.rooms.lock().await;
+                        let Some(room) = rooms.get(&channel) else {
+                            continue;
+                        };
+                        let content = room.content_of(doc_id.as_deref()).await;
+                        let revision = room.revision(doc_id.as_deref()).await;
+                        drop(rooms);
+                        let range = content
+                            .ok_or_else(|| "This document doesn't exist.".to_string())
+                            .and_then(|content| ranges::slice(&content, offset, length));
+                        let reply = match range {
+                            Ok(range) => ranges::range_message(doc_id, revision, &range),
+                            Err(e) => error_message(ErrorCode::InvalidRange, e),
+                        };
+                        sender.send(wire.frame(reply));
+                        continue;
+                    }
+                    ClientMessage::SetLanguage { value } => {
+                        let rooms = state.rooms.lock().await;
+                        let result = match rooms.get(&channel) {
+                            Some(room) => room.set_syntax_language(&state, &channel, value).await,
+                            None => Ok(()),
+                        };
+                        drop(rooms);
+                        if let Err(e) = result {
+                            sender.send(wire.frame(error_message(ErrorCode::InvalidLanguage, e)));
+                        }
+                        continue;
+                    }
+                };
+
+                // The server must never receive the plaintext of an encrypted room
+                if encrypted && !encryption::is_ciphertext(&text) {
+                    sender.send(wire.frame(error_message(
+                        ErrorCode::EncryptionMismatch,
+                        "Encrypted rooms only accept ciphertext.",
+                    )));
+                    continue;
+                }
+
+                // Lasts until the edit is applied and sent to the members
+                let _broadcast = tracing::info_span!(
+                    "broadcast",
+                    room_id = %channel,
+                    doc_id = scope.as_deref(),
+                    bytes = text.len(),
+                    receivers = tx.receiver_count(),
+                );
+
+                // Update the room content
+                let mut conflicts = 0;
+                let mut revision = None;
+                let rooms = state.rooms.lock().await;
+                if let Some(room) = rooms.get(&channel) {
+                    // Refuse edits of a frozen room, or of one someone else presents in, and
+                    // resync the sender
+                    if let Some((code, notice)) = room.edit_refusal(&name).await {
+                        let content = room.content_of(scope.as_deref()).await;
+                        let revision = room.revision(scope.as_deref()).await;
+                        drop(rooms);
+                        sender.send(wire.frame(error_message(code, notice)));
+                        sender.send(
+                            wire.frame(
+                                json!(SocketMessage! {
+                                    doc_id: scope.clone(),
+                                    message_type: SocketMessageType::content(encrypted),
+                                    value: Some(content.unwrap_or_default()),
+                                    revision: Some(revision),
+                                    username: "Server".to_string(),
+                                })
+                                .to_string(),
+                            ),
+                        );
+                        continue;
+                    }
+
+                    // Edits made on an outdated revision are merged with the changes made since
+                    let mut edited = None;
+                    if let (Some(base_revision), false) = (base_revision, encrypted) {
+                        // Its previous edit, if it wasn't sequenced yet as it made this one
+                        let base = last_edits
+                            .get(&scope)
+                            .filter(|(revision, _)| *revision == base_revision)
+                            .map(|(_, content)| content.as_str());
+                        match room
+                            .merge_edit(scope.as_deref(), base_revision, base, &text)
+                            .await
+                        {
+                            Ok(merged) => {
+                                conflicts = merged.conflicts;
+                                edited = Some((
+                                    base_revision,
+                                    std::mem::replace(&mut text, merged.content),
+                                ));
+                            }
+                            Err(e) => {
+                                let content = room.content_of(scope.as_deref()).await;
+                                let revision = room.revision(scope.as_deref()).await;
+                                drop(rooms);
+                                sender.send(wire.frame(error_message(ErrorCode::EditConflict, e)));
+                                sender.send(
+                                    wire.frame(
+                                        json!(SocketMessage! {
+                                            doc_id: scope.clone(),
+                                            message_type: SocketMessageType::Message,
+                                            value: Some(content.unwrap_or_default()),
+                                            revision: Some(revision),
+                                            username: "Server".to_string(),
+                                        })
+                                        .to_string(),
+                                    ),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Refuse edits of lines claimed by someone else, and resync the sender
+                    if let Err(e) = room.edit_lines(&name, scope.as_deref(), &text).await {
+                        let content = room.content_of(scope.as_deref()).await;
+                        let revision = room.revision(scope.as_deref()).await;
+                        drop(rooms);
+                        sender.send(wire.frame(error_message(ErrorCode::LineClaimed, e)));
+                        sender.send(
+                            wire.frame(
+                                json!(SocketMessage! {
+                                    doc_id: scope.clone(),
+                                    message_type: SocketMessageType::content(encrypted),
+                                    value: Some(content.unwrap_or_default()),
+                                    revision: Some(revision),
+                                    username: "Server".to_string(),
+                                })
+                                .to_string(),
+                            ),
+                        );
+                        continue;
+                    }
+
+                    if let Err(e) = room
+                        .update_content(&state, &channel, scope.as_deref(), &text)
+                        .await
+                    {
+                        drop(rooms);
+                        sender.send(wire.frame(error_message(ErrorCode::InvalidDocument, e)));
+                        continue;
+                    }
+                    if let Some(edited) = edited {
+                        last_edits.insert(scope.clone(), edited);
+                    }
+                    revision = Some(room.revision(scope.as_deref()).await);
+                }
+                drop(rooms);
+
+                let _ = tx.send(
+                    json!(SocketMessage! {
+                        doc_id: scope,
+                        message_type: SocketMessageType::content(encrypted),
+                        value: Some(text),
+                        revision: revision,
+                        username: name.clone(),
+                    })
+                    .to_string(),
+                );
+                // The merged content reaches the client with the broadcast
+                if conflicts > 0 {
+                    sender.send(
+                        wire.frame(
+                            json!(SocketMessage! {
+                                message_type: SocketMessageType::MergeConflict,
+                                value: Some(conflicts.to_string()),
+                            })
+                            .to_string(),
+                        ),
+                    );
+                }
+            }
+        };
+        tokio::spawn(task.in_current_span())
+    };
+
+    tokio::select! {
+        result = &mut send_messages => {
+            supervisor::check_join(&format!("sender of connection {connection_id}"), result);
+            recv_messages.abort();
+        }
+        result = &mut recv_messages => {
+            supervisor::check_join(&format!("receiver of connection {connection_id}"), result);
+            send_messages.abort();
+        }
+        result = &mut ping_client => {
+            // The client stopped answering, its connection is most likely gone
+            supervisor::check_join(&format!("heartbeat of connection {connection_id}"), result);
+            send_messages.abort();
+            recv_messages.abort();
+        }
+        () = sender.overflowed() => {
+            // Closed by the writer, which gave up on the frames the client didn't take yet
+            state.saturation_metrics.record_slow_consumer();
+            send_messages.abort();
+            recv_messages.abort();
+        }
+        () = state.shutdown.cancelled() => {
+            send_messages.abort();
+            recv_messages.abort();
+            sender.close(restart_close_frame(&state)).await;
+        }
+        () = disconnect.cancelled() => {
+            send_messages.abort();
+            recv_messages.abort();
+            if state.connections.was_kicked(connection_id) {
+                sender.send(wire.frame(kicked_message()));
+            }
+            sender.close(disconnected_close_frame()).await;
+        }
+    }
+    ping_client.abort();
+    state.connections.unregister(connection_id);
+    if let Err(e) = sessions::remove(&state.db, session_id).await {
+        eprintln!("Failed to remove session: {e:#}");
+    }
+    if let Some(token) = &resume_token {
+        state.resume_sessions.end(token);
+    }
+
+    let mut rooms = state.rooms.lock().await;
+    let room = rooms.get_mut(&channel);
+
+    let mut last_connection = true;
+    if let Some(room) = room {
+        last_connection = room.users.lock().await.leave(&username);
+        if last_connection {
+            room.release_lock(&username).await;
+            room.release_lines(&username).await;
+        }
+        *room.last_activity.lock().await = Instant::now();
+        let everyone_left = room.users.lock().await.is_empty();
+        let burns = burns_on_leave || (everyone_left && room.burns_after(BurnAfter::Leave).await);
+        if burns && !room.is_empty().await {
+            room.burn(&state, &channel).await;
+        }
+    } else {
+        eprintln!("Failed to remove user from room!");
+    }
+
+    drop(rooms);
+
+    // The user is still in the room from another tab
+    if !last_connection {
+        return;
+    }
+    let _ = tx.send(
+        json!(SocketMessage! {
+            message_type: SocketMessageType::Leave,
+            username: username.clone(),
+        })
+        .to_string(),
+    );
+    if let Some(presence) = presence_message(&state, &channel).await {
+        let _ = tx.send(presence);
+    }
+}
+
+/// Messages bringing a client that missed broadcasts back in sync:
+/// the contents of the room and its documents, then its users
+pub(crate) async fn resync_messages(state: &AppState, room_id: &str) -> Vec<String> {
+    let rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get(room_id) else {
+        return Vec::new();
+    };
+    let encrypted = room.encryption.is_some();
+    let content = room.content_rx.borrow().clone();
+    let mut messages = vec![json!(SocketMessage! {
+        message_type: SocketMessageType::content(encrypted),
+        value: Some(content),
+        username: "Server".to_string(),
+    })
+    .to_string()];
+    let documents = room.documents.lock().await;
+    for (doc_id, document) in documents.iter() {
+        let content = document.content_rx.borrow().clone();
+        messages.push(
+            json!(SocketMessage! {
+                doc_id: Some(doc_id.clone()),
+                message_type: SocketMessageType::content(encrypted),
+                value: Some(content),
+                username: "Server".to_string(),
+            })
+            .to_string(),
+        );
+    }
+    drop(documents);
+    drop(rooms);
+
+    messages.extend(presence_message(state, room_id).await);
+    messages
+}
+
+/// Message listing the users of a room, sorted, `None` if the room is not loaded
+async fn presence_message(state: &AppState, room_id: &str) -> Option<String> {
+    let rooms = state.rooms.lock().await;
+    let mut users = rooms
+        .get(room_id)?
+        .users
+        .lock()
+        .await
+        .names()
+        .cloned()
+        .collect::<Vec<_>>();
+    drop(rooms);
+    users.sort();
+
+    Some(
+        json!(SocketMessage! {
+            message_type: SocketMessageType::Presence,
+            value: serde_json::to_string(&Presence(users)).ok(),
+        })
+        .to_string(),
+    )
+}
+
+/// Name a user joins a room under: the one they asked for, or the first of `alternatives`
+/// free in the room if someone there already uses it, `None` if they are all taken.
+/// Accounts keep their name, they may join from several tabs.
+fn resolve_username(
+    users: &RoomUsers,
+    username: &str,
+    alternatives: &[String],
+    anonymous: bool,
+) -> Option<String> {
+    if !anonymous || !users.contains(username) {
+        return Some(username.to_string());
+    }
+    alternatives
+        .iter()
+        .find(|name| !users.contains(name))
+        .cloned()
+}
@@ -0,0 +1,115 @@
+use anyhow::bail;
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use partage::{Authenticator, Config, Handshake, Identity, PartageServer};
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+async fn spawn(app: Router) -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_app_serves_the_default_room() {
+    let addr = spawn(partage::app(Config::default()).await.unwrap()).await;
+
+    let rooms: serde_json::Value = reqwest::get(format!("http://{addr}/api/rooms"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(rooms
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|room| room["id"] == "general"));
+}
+
+/// Trusts the user of an authenticating proxy, refusing the ones it doesn't know
+struct ProxyHeader;
+
+#[async_trait::async_trait]
+impl Authenticator for ProxyHeader {
+    async fn authenticate(&self, handshake: Handshake<'_>) -> anyhow::Result<Identity> {
+        match handshake.headers.get("x-forwarded-user") {
+            None => Ok(Identity::Anonymous),
+            Some(user) if user == "mallory" => bail!("unknown user"),
+            Some(user) => Ok(Identity::Account(user.to_str()?.to_string())),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_authenticator() {
+    let app = PartageServer::builder()
+        .authenticator(ProxyHeader)
+        .build()
+        .router()
+        .await
+        .unwrap();
+    let addr = spawn(app).await;
+    let client = reqwest::Client::new();
+
+    let account: serde_json::Value = client
+        .get(format!("http://{addr}/api/auth/me"))
+        .header("x-forwarded-user", "erin")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(account["username"], "erin");
+    let response = client
+        .get(format!("http://{addr}/api/auth/me"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // The account name is the one vouched for, whatever the client sends
+    let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("x-forwarded-user", "erin".parse().unwrap());
+    let (mut ws, _) = connect_async(request).await.unwrap();
+    let join_msg = json!({ "username": "not_erin", "channel": "general" }).to_string();
+    ws.send(Message::Text(join_msg)).await.unwrap();
+    let _ = ws.next().await.unwrap();
+    let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+    assert_eq!(parsed["type"], "join");
+    assert_eq!(parsed["username"], "erin");
+
+    // Anonymous clients can't take the name of the account
+    let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+    let join_msg = json!({ "username": "erin", "channel": "general" }).to_string();
+    ws.send(Message::Text(join_msg)).await.unwrap();
+    let received = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(&received).unwrap();
+    assert_eq!(parsed["code"], "username-taken");
+
+    // A failed authentication refuses the socket
+    let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("x-forwarded-user", "mallory".parse().unwrap());
+    assert!(connect_async(request).await.is_err());
+}